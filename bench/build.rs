@@ -0,0 +1,19 @@
+extern crate protoc_rust_grpc;
+
+fn main() {
+    protoc_rust_grpc::run(protoc_rust_grpc::Args {
+        out_dir: "src",
+        includes: &["proto"],
+        input: &[
+            "proto/messages.proto",
+            "proto/payloads.proto",
+            "proto/stats.proto",
+            "proto/control.proto",
+            "proto/benchmark_service.proto",
+            "proto/worker_service.proto",
+        ],
+        rust_protobuf: true,
+        ..Default::default()
+    })
+    .expect("protoc-rust-grpc");
+}
@@ -0,0 +1,94 @@
+//! Benchmark client started by `WorkerService::RunClient`.
+
+use std::sync::Arc;
+
+use grpc::Client;
+use grpc::ClientBuilder;
+
+use client::ClosedLoop;
+use client::Workload;
+use control::ClientConfig;
+use control::ClientStatus;
+use cpu_usage::CpuUsage;
+use histogram::Histogram;
+
+/// `host:port` target of benchmark client.
+fn connect(target: &str) -> grpc::Result<Client> {
+    let colon = target
+        .rfind(':')
+        .ok_or(grpc::Error::Other("server target must be host:port"))?;
+    let port = target[colon + 1..]
+        .parse()
+        .map_err(|_| grpc::Error::Other("invalid port in server target"))?;
+    let host = target[..colon]
+        .trim_start_matches('[')
+        .trim_end_matches(']');
+    ClientBuilder::new(host, port).build()
+}
+
+pub struct BenchmarkClient {
+    load: ClosedLoop,
+    cpu_usage: CpuUsage,
+}
+
+impl BenchmarkClient {
+    pub fn start(config: &ClientConfig) -> grpc::Result<BenchmarkClient> {
+        if config.has_security_params() {
+            warn!("TLS is not supported by benchmark client, using plain connections");
+        }
+        if config.get_load_params().has_poisson() {
+            return Err(grpc::Error::Other("only closed loop load is supported"));
+        }
+        if config.get_server_targets().is_empty() {
+            return Err(grpc::Error::Other("no server targets"));
+        }
+
+        let payload = config.get_payload_config();
+        if payload.has_bytebuf_params() || payload.has_complex_params() {
+            return Err(grpc::Error::Other(
+                "only simple proto payloads are supported",
+            ));
+        }
+        let params = payload.get_simple_params();
+
+        // i-th channel connects to i-th target modulo number of targets
+        let targets = config.get_server_targets();
+        let mut clients = Vec::new();
+        for i in 0..config.get_client_channels().max(1) as usize {
+            clients.push(Arc::new(connect(&targets[i % targets.len()])?));
+        }
+
+        let load = ClosedLoop::start(
+            &clients,
+            config.get_outstanding_rpcs_per_channel().max(1) as usize,
+            Workload {
+                rpc_type: config.get_rpc_type(),
+                req_size: params.get_req_size() as usize,
+                resp_size: params.get_resp_size() as usize,
+            },
+            Histogram::from_params(config.get_histogram_params()),
+        )?;
+
+        Ok(BenchmarkClient {
+            load,
+            cpu_usage: CpuUsage::now(),
+        })
+    }
+
+    /// Latencies and CPU usage since start or previous reset.
+    pub fn mark(&mut self, reset: bool) -> ClientStatus {
+        let cpu_usage = CpuUsage::now();
+        let delta = cpu_usage.since(&self.cpu_usage);
+        if reset {
+            self.cpu_usage = cpu_usage;
+        }
+
+        let mut stats = self.load.mark(reset).to_proto();
+        stats.set_time_user(delta.time_user);
+        stats.set_time_system(delta.time_system);
+
+        let mut status = ClientStatus::new();
+        status.set_stats(stats);
+        status
+    }
+}
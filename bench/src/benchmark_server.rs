@@ -0,0 +1,67 @@
+//! Benchmark server started by `WorkerService::RunServer`.
+
+use std::time::Instant;
+
+use grpc::Server;
+
+use client::duration_secs;
+use control::ServerConfig;
+use control::ServerStatus;
+use cpu_usage::cores;
+use cpu_usage::CpuUsage;
+use server::start_server;
+use stats::ServerStats;
+
+pub struct BenchmarkServer {
+    server: Server,
+    start: Instant,
+    cpu_usage: CpuUsage,
+}
+
+impl BenchmarkServer {
+    pub fn start(config: &ServerConfig) -> grpc::Result<BenchmarkServer> {
+        if config.has_security_params() {
+            warn!("TLS is not supported by benchmark server, using plain connections");
+        }
+        if config.get_payload_config().has_bytebuf_params() {
+            return Err(grpc::Error::Other("generic server is not supported"));
+        }
+        let server = start_server(config.get_port() as u16, None)?;
+        Ok(BenchmarkServer {
+            server,
+            start: Instant::now(),
+            cpu_usage: CpuUsage::now(),
+        })
+    }
+
+    /// Status sent in response to setup.
+    pub fn status(&self) -> ServerStatus {
+        let mut status = ServerStatus::new();
+        status.set_port(self.server.local_addr().port().unwrap_or(0) as i32);
+        status.set_cores(cores());
+        status
+    }
+
+    /// Stats since start or previous reset.
+    pub fn mark(&mut self, reset: bool) -> ServerStatus {
+        let now = Instant::now();
+        let cpu_usage = CpuUsage::now();
+        let delta = cpu_usage.since(&self.cpu_usage);
+
+        let mut stats = ServerStats::new();
+        stats.set_time_elapsed(duration_secs(now - self.start));
+        stats.set_time_user(delta.time_user);
+        stats.set_time_system(delta.time_system);
+        stats.set_total_cpu_time(delta.total_cpu_time);
+        stats.set_idle_cpu_time(delta.idle_cpu_time);
+
+        if reset {
+            self.start = now;
+            self.cpu_usage = cpu_usage;
+        }
+
+        let mut status = self.status();
+        status.set_stats(stats);
+        status
+    }
+}
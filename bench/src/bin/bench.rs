@@ -0,0 +1,147 @@
+extern crate clap;
+extern crate env_logger;
+extern crate grpc;
+extern crate grpc_bench;
+
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use clap::App;
+use clap::Arg;
+
+use grpc::stats::StatsCounters;
+use grpc::ClientBuilder;
+
+use grpc_bench::client::duration_secs;
+use grpc_bench::client::ClosedLoop;
+use grpc_bench::client::Workload;
+use grpc_bench::histogram::Histogram;
+use grpc_bench::server::start_server;
+use grpc_bench::RpcType;
+
+/// Run one workload against a local server and print results.
+fn run(
+    port: u16,
+    rpc_type: RpcType,
+    size: usize,
+    outstanding: usize,
+    duration: Duration,
+    client_counters: &StatsCounters,
+    server_counters: &StatsCounters,
+) {
+    let client = ClientBuilder::new("127.0.0.1", port)
+        .stats_handler(Arc::new(client_counters.clone()))
+        .build()
+        .expect("client");
+
+    let workload = Workload {
+        rpc_type,
+        req_size: size,
+        resp_size: size,
+    };
+    let load = ClosedLoop::start(
+        &[Arc::new(client)],
+        outstanding,
+        workload,
+        Histogram::default(),
+    )
+    .expect("start load");
+
+    // warm up
+    thread::sleep(duration / 5);
+    load.mark(true);
+    let client_before = client_counters.snapshot();
+    let server_before = server_counters.snapshot();
+
+    thread::sleep(duration);
+    let report = load.mark(false);
+    let client_delta = client_counters.snapshot().since(&client_before);
+    let server_delta = server_counters.snapshot().since(&server_before);
+    drop(load);
+
+    let elapsed = duration_secs(report.elapsed);
+    let calls = report.latencies.count() as f64;
+    let bytes = (client_delta.bytes_sent + client_delta.bytes_received) as f64;
+    println!(
+        "{:?} size={} qps={:.0} throughput={:.2}MiB/s p50={:.0}us p90={:.0}us p99={:.0}us errors={:?}",
+        rpc_type,
+        size,
+        calls / elapsed,
+        bytes / elapsed / (1024.0 * 1024.0),
+        report.latencies.percentile(50.0) / 1e3,
+        report.latencies.percentile(90.0) / 1e3,
+        report.latencies.percentile(99.0) / 1e3,
+        report.errors,
+    );
+    println!("    client counters: {:?}", client_delta);
+    println!("    server counters: {:?}", server_delta);
+}
+
+fn main() {
+    env_logger::init();
+
+    let options = App::new("grpc-rust benchmark")
+        .about("Measures unary QPS and streaming throughput against a local server")
+        .arg(
+            Arg::with_name("duration")
+                .long("duration")
+                .help("Measurement duration of each workload in seconds")
+                .default_value("5")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("outstanding")
+                .long("outstanding")
+                .help("Number of concurrent calls")
+                .default_value("8")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("sizes")
+                .long("sizes")
+                .help("Comma-separated request and response payload sizes in bytes")
+                .default_value("0,1024,65536,1048576,4194304")
+                .takes_value(true),
+        )
+        .get_matches();
+
+    let duration = Duration::from_secs(
+        options
+            .value_of("duration")
+            .unwrap()
+            .parse()
+            .expect("duration"),
+    );
+    let outstanding: usize = options
+        .value_of("outstanding")
+        .unwrap()
+        .parse()
+        .expect("outstanding");
+    let sizes: Vec<usize> = options
+        .value_of("sizes")
+        .unwrap()
+        .split(',')
+        .map(|s| s.parse().expect("size"))
+        .collect();
+
+    let server_counters = StatsCounters::new();
+    let client_counters = StatsCounters::new();
+
+    let server = start_server(0, Some(Arc::new(server_counters.clone()))).expect("server");
+    let port = server.local_addr().port().expect("port");
+
+    for &rpc_type in &[RpcType::UNARY, RpcType::STREAMING] {
+        for &size in &sizes {
+            run(
+                port,
+                rpc_type,
+                size,
+                outstanding,
+                duration,
+                &client_counters,
+                &server_counters,
+            );
+        }
+    }
+}
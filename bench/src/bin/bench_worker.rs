@@ -0,0 +1,176 @@
+//! Worker for cross-language benchmark driver
+//! (`tools/run_tests/run_performance_tests.py` in grpc repository).
+
+extern crate clap;
+extern crate env_logger;
+extern crate futures;
+extern crate grpc;
+extern crate grpc_bench;
+#[macro_use]
+extern crate log;
+
+use std::sync::mpsc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use clap::App;
+use clap::Arg;
+
+use futures::Stream;
+
+use grpc::*;
+
+use grpc_bench::benchmark_client::BenchmarkClient;
+use grpc_bench::benchmark_server::BenchmarkServer;
+use grpc_bench::cpu_usage::cores;
+use grpc_bench::*;
+
+struct WorkerServiceImpl {
+    quit: Mutex<mpsc::Sender<()>>,
+}
+
+impl WorkerService for WorkerServiceImpl {
+    fn run_server(
+        &self,
+        _ctx: ServerHandlerContext,
+        req: ServerRequest<ServerArgs>,
+        mut resp: ServerResponseSink<ServerStatus>,
+    ) -> grpc::Result<()> {
+        thread::spawn(move || {
+            let mut server = None;
+            for args in req.into_stream().wait() {
+                let args = match args {
+                    Ok(args) => args,
+                    Err(e) => {
+                        warn!("driver stream failed: {:?}", e);
+                        return;
+                    }
+                };
+                let status = if args.has_setup() {
+                    match BenchmarkServer::start(args.get_setup()) {
+                        Ok(s) => {
+                            let status = s.status();
+                            server = Some(s);
+                            status
+                        }
+                        Err(e) => {
+                            let _ = resp.send_grpc_error(GrpcStatus::Internal, e.to_string());
+                            return;
+                        }
+                    }
+                } else {
+                    match server {
+                        Some(ref mut server) => server.mark(args.get_mark().get_reset()),
+                        None => ServerStatus::new(),
+                    }
+                };
+                if resp.send_data(status).is_err() {
+                    return;
+                }
+            }
+            // driver closed the stream
+            drop(server);
+            let _ = resp.send_trailers(Metadata::new());
+        });
+        Ok(())
+    }
+
+    fn run_client(
+        &self,
+        _ctx: ServerHandlerContext,
+        req: ServerRequest<ClientArgs>,
+        mut resp: ServerResponseSink<ClientStatus>,
+    ) -> grpc::Result<()> {
+        thread::spawn(move || {
+            let mut client = None;
+            for args in req.into_stream().wait() {
+                let args = match args {
+                    Ok(args) => args,
+                    Err(e) => {
+                        warn!("driver stream failed: {:?}", e);
+                        return;
+                    }
+                };
+                let status = if args.has_setup() {
+                    match BenchmarkClient::start(args.get_setup()) {
+                        Ok(c) => {
+                            client = Some(c);
+                            ClientStatus::new()
+                        }
+                        Err(e) => {
+                            let _ = resp.send_grpc_error(GrpcStatus::Internal, e.to_string());
+                            return;
+                        }
+                    }
+                } else {
+                    match client {
+                        Some(ref mut client) => client.mark(args.get_mark().get_reset()),
+                        None => ClientStatus::new(),
+                    }
+                };
+                if resp.send_data(status).is_err() {
+                    return;
+                }
+            }
+            // driver closed the stream, stop the load before completing the call
+            drop(client);
+            let _ = resp.send_trailers(Metadata::new());
+        });
+        Ok(())
+    }
+
+    fn core_count(
+        &self,
+        _ctx: ServerHandlerContext,
+        _req: ServerRequestSingle<CoreRequest>,
+        resp: ServerResponseUnarySink<CoreResponse>,
+    ) -> grpc::Result<()> {
+        let mut response = CoreResponse::new();
+        response.set_cores(cores());
+        resp.finish(response)
+    }
+
+    fn quit_worker(
+        &self,
+        _ctx: ServerHandlerContext,
+        _req: ServerRequestSingle<Void>,
+        resp: ServerResponseUnarySink<Void>,
+    ) -> grpc::Result<()> {
+        resp.finish(Void::new())?;
+        let _ = self.quit.lock().unwrap().send(());
+        Ok(())
+    }
+}
+
+fn main() {
+    env_logger::init();
+
+    let options = App::new("grpc-rust benchmark worker")
+        .about("Runs benchmark servers and clients on request of benchmark driver")
+        .arg(
+            Arg::with_name("driver_port")
+                .long("driver_port")
+                .help("Port for communication with driver")
+                .takes_value(true),
+        )
+        .get_matches();
+
+    let driver_port = options
+        .value_of("driver_port")
+        .map(|p| p.parse().expect("driver_port"))
+        .unwrap_or(DEFAULT_DRIVER_PORT);
+
+    let (quit_tx, quit_rx) = mpsc::channel();
+
+    let mut server = ServerBuilder::new_plain();
+    server.http.set_port(driver_port);
+    server.add_service(WorkerServiceServer::new_service_def(WorkerServiceImpl {
+        quit: Mutex::new(quit_tx),
+    }));
+    let _server = server.build().expect("server");
+
+    let _ = quit_rx.recv();
+    // let the response to `QuitWorker` reach the driver
+    thread::sleep(Duration::from_millis(100));
+}
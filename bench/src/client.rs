@@ -0,0 +1,233 @@
+//! Closed-loop load generator for `BenchmarkService`.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+use futures::Future;
+
+use grpc::*;
+
+use benchmark_service_grpc::BenchmarkServiceClient;
+use control::RpcType;
+use histogram::Histogram;
+use messages::Payload;
+use messages::SimpleRequest;
+use stats::ClientStats;
+use stats::RequestResultCount;
+
+/// Calls made by load generator.
+#[derive(Clone, Debug)]
+pub struct Workload {
+    /// Only `UNARY` and `STREAMING` (ping-pong) are supported.
+    pub rpc_type: RpcType,
+    pub req_size: usize,
+    pub resp_size: usize,
+}
+
+impl Workload {
+    fn request(&self) -> SimpleRequest {
+        let mut payload = Payload::new();
+        payload.set_body(vec![0; self.req_size]);
+        let mut req = SimpleRequest::new();
+        req.set_response_size(self.resp_size as i32);
+        req.set_payload(payload);
+        req
+    }
+}
+
+/// Latencies and failures collected by load generator.
+#[derive(Clone, Debug)]
+pub struct LoadReport {
+    /// Latencies of successful calls in nanoseconds.
+    pub latencies: Histogram,
+    pub elapsed: Duration,
+    /// Number of failed calls by status code.
+    pub errors: BTreeMap<i32, i64>,
+}
+
+impl LoadReport {
+    pub fn to_proto(&self) -> ClientStats {
+        let mut stats = ClientStats::new();
+        stats.set_latencies(self.latencies.to_proto());
+        stats.set_time_elapsed(duration_secs(self.elapsed));
+        for (&status_code, &count) in &self.errors {
+            let mut result = RequestResultCount::new();
+            result.set_status_code(status_code);
+            result.set_count(count);
+            stats.mut_request_results().push(result);
+        }
+        stats
+    }
+}
+
+pub fn duration_secs(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + duration.subsec_nanos() as f64 / 1e9
+}
+
+fn error_code(error: &grpc::Error) -> i32 {
+    error.status().code.code() as i32
+}
+
+struct Collected {
+    latencies: Histogram,
+    errors: BTreeMap<i32, i64>,
+    start: Instant,
+}
+
+impl Collected {
+    fn record(&mut self, latency: Duration) {
+        self.latencies.add(duration_secs(latency) * 1e9);
+    }
+
+    fn record_error(&mut self, error: &grpc::Error) {
+        *self.errors.entry(error_code(error)).or_insert(0) += 1;
+    }
+}
+
+/// Each outstanding call is started as soon as previous one finishes.
+///
+/// Calls are made from dedicated threads, one thread per outstanding call.
+/// Load is stopped when this object is dropped.
+pub struct ClosedLoop {
+    stop: Arc<AtomicBool>,
+    threads: Vec<thread::JoinHandle<()>>,
+    collected: Arc<Mutex<Collected>>,
+}
+
+impl ClosedLoop {
+    pub fn start(
+        clients: &[Arc<Client>],
+        outstanding_per_client: usize,
+        workload: Workload,
+        latencies: Histogram,
+    ) -> grpc::Result<ClosedLoop> {
+        match workload.rpc_type {
+            RpcType::UNARY | RpcType::STREAMING => {}
+            _ => return Err(grpc::Error::Other("unsupported rpc type")),
+        }
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let collected = Arc::new(Mutex::new(Collected {
+            latencies,
+            errors: BTreeMap::new(),
+            start: Instant::now(),
+        }));
+
+        let mut threads = Vec::new();
+        for client in clients {
+            for _ in 0..outstanding_per_client {
+                let stub = BenchmarkServiceClient::with_client(client.clone());
+                let workload = workload.clone();
+                let stop = stop.clone();
+                let collected = collected.clone();
+                threads.push(thread::spawn(move || match workload.rpc_type {
+                    RpcType::STREAMING => run_ping_pong(stub, workload, stop, collected),
+                    _ => run_unary(stub, workload, stop, collected),
+                }));
+            }
+        }
+
+        Ok(ClosedLoop {
+            stop,
+            threads,
+            collected,
+        })
+    }
+
+    /// Results collected since start or since previous reset.
+    pub fn mark(&self, reset: bool) -> LoadReport {
+        let mut collected = self.collected.lock().unwrap();
+        let report = LoadReport {
+            latencies: collected.latencies.clone(),
+            elapsed: collected.start.elapsed(),
+            errors: collected.errors.clone(),
+        };
+        if reset {
+            collected.latencies.reset();
+            collected.errors.clear();
+            collected.start = Instant::now();
+        }
+        report
+    }
+}
+
+impl Drop for ClosedLoop {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn run_unary(
+    stub: BenchmarkServiceClient,
+    workload: Workload,
+    stop: Arc<AtomicBool>,
+    collected: Arc<Mutex<Collected>>,
+) {
+    let req = workload.request();
+    while !stop.load(Ordering::SeqCst) {
+        let start = Instant::now();
+        let r = stub
+            .unary_call(RequestOptions::new(), req.clone())
+            .wait_drop_metadata();
+        let mut collected = collected.lock().unwrap();
+        match r {
+            Ok(..) => collected.record(start.elapsed()),
+            Err(e) => collected.record_error(&e),
+        }
+    }
+}
+
+fn run_ping_pong(
+    stub: BenchmarkServiceClient,
+    workload: Workload,
+    stop: Arc<AtomicBool>,
+    collected: Arc<Mutex<Collected>>,
+) {
+    let req = workload.request();
+    'stream: while !stop.load(Ordering::SeqCst) {
+        let (mut req_sink, resp) = match stub.streaming_call(RequestOptions::new()).wait() {
+            Ok(r) => r,
+            Err(e) => {
+                collected.lock().unwrap().record_error(&e);
+                continue;
+            }
+        };
+        let mut resp = resp.wait_drop_metadata();
+
+        while !stop.load(Ordering::SeqCst) {
+            let start = Instant::now();
+            if let Err(e) = req_sink
+                .block_wait()
+                .map_err(grpc::Error::from)
+                .and_then(|()| req_sink.send_data(req.clone()))
+            {
+                collected.lock().unwrap().record_error(&e);
+                continue 'stream;
+            }
+            match resp.next() {
+                Some(Ok(..)) => collected.lock().unwrap().record(start.elapsed()),
+                Some(Err(e)) => {
+                    collected.lock().unwrap().record_error(&e);
+                    continue 'stream;
+                }
+                None => {
+                    warn!("server closed ping-pong stream");
+                    continue 'stream;
+                }
+            }
+        }
+
+        let _ = req_sink.finish();
+        // drain trailers
+        for _ in resp {}
+    }
+}
@@ -0,0 +1,77 @@
+//! CPU usage reported to benchmark driver.
+//!
+//! Read from `/proc` on Linux, zero on other platforms.
+
+use std::fs;
+
+/// Clock ticks per second in `/proc` files (`USER_HZ`), same on all Linux platforms.
+const USER_HZ: f64 = 100.0;
+
+/// CPU times at some moment.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct CpuUsage {
+    /// User time of this process in seconds.
+    pub time_user: f64,
+    /// System time of this process in seconds.
+    pub time_system: f64,
+    /// Total CPU time of the machine in ticks.
+    pub total_cpu_time: u64,
+    /// Idle CPU time of the machine in ticks.
+    pub idle_cpu_time: u64,
+}
+
+/// `utime` and `stime` fields of `/proc/self/stat`.
+fn parse_self_stat(stat: &str) -> Option<(f64, f64)> {
+    // process name may contain spaces, fields are counted after it
+    let fields: Vec<&str> = stat[stat.rfind(')')? + 1..].split_whitespace().collect();
+    let utime: u64 = fields.get(11)?.parse().ok()?;
+    let stime: u64 = fields.get(12)?.parse().ok()?;
+    Some((utime as f64 / USER_HZ, stime as f64 / USER_HZ))
+}
+
+/// Total and idle time from `cpu` line of `/proc/stat`.
+fn parse_stat(stat: &str) -> Option<(u64, u64)> {
+    let line = stat.lines().find(|l| l.starts_with("cpu "))?;
+    let times: Vec<u64> = line
+        .split_whitespace()
+        .skip(1)
+        .map(|t| t.parse().ok())
+        .collect::<Option<_>>()?;
+    Some((times.iter().sum(), *times.get(3)?))
+}
+
+impl CpuUsage {
+    pub fn now() -> CpuUsage {
+        let mut usage = CpuUsage::default();
+        if let Some((user, system)) = fs::read_to_string("/proc/self/stat")
+            .ok()
+            .and_then(|s| parse_self_stat(&s))
+        {
+            usage.time_user = user;
+            usage.time_system = system;
+        }
+        if let Some((total, idle)) = fs::read_to_string("/proc/stat")
+            .ok()
+            .and_then(|s| parse_stat(&s))
+        {
+            usage.total_cpu_time = total;
+            usage.idle_cpu_time = idle;
+        }
+        usage
+    }
+
+    /// Usage change since `earlier`.
+    pub fn since(&self, earlier: &CpuUsage) -> CpuUsage {
+        CpuUsage {
+            time_user: self.time_user - earlier.time_user,
+            time_system: self.time_system - earlier.time_system,
+            total_cpu_time: self.total_cpu_time.saturating_sub(earlier.total_cpu_time),
+            idle_cpu_time: self.idle_cpu_time.saturating_sub(earlier.idle_cpu_time),
+        }
+    }
+}
+
+/// Number of cores reported to benchmark driver.
+pub fn cores() -> i32 {
+    ::num_cpus::get() as i32
+}
@@ -0,0 +1,141 @@
+//! Latency histogram compatible with `grpc/support/histogram.c`.
+
+use stats::HistogramData;
+use stats::HistogramParams;
+
+/// Histogram with exponentially growing buckets.
+#[derive(Clone, Debug)]
+pub struct Histogram {
+    multiplier: f64,
+    one_on_log_multiplier: f64,
+    max_possible: f64,
+    buckets: Vec<u32>,
+    min_seen: f64,
+    max_seen: f64,
+    sum: f64,
+    sum_of_squares: f64,
+    count: f64,
+}
+
+impl Histogram {
+    /// First bucket is `[0, 1 + resolution)`, there are enough buckets to store `max_possible`.
+    pub fn new(resolution: f64, max_possible: f64) -> Histogram {
+        let multiplier = 1.0 + resolution;
+        let one_on_log_multiplier = 1.0 / multiplier.ln();
+        let mut histogram = Histogram {
+            multiplier,
+            one_on_log_multiplier,
+            max_possible,
+            buckets: Vec::new(),
+            min_seen: max_possible,
+            max_seen: 0.0,
+            sum: 0.0,
+            sum_of_squares: 0.0,
+            count: 0.0,
+        };
+        let num_buckets = histogram.bucket_for_unchecked(max_possible) + 1;
+        histogram.buckets = vec![0; num_buckets];
+        histogram
+    }
+
+    pub fn from_params(params: &HistogramParams) -> Histogram {
+        if params.resolution > 0.0 && params.max_possible > 0.0 {
+            Histogram::new(params.resolution, params.max_possible)
+        } else {
+            Histogram::default()
+        }
+    }
+
+    fn bucket_for_unchecked(&self, value: f64) -> usize {
+        (value.ln() * self.one_on_log_multiplier).max(0.0) as usize
+    }
+
+    fn bucket_for(&self, value: f64) -> usize {
+        let bucket = self.bucket_for_unchecked(value.max(1.0).min(self.max_possible));
+        bucket.min(self.buckets.len() - 1)
+    }
+
+    fn bucket_start(&self, bucket: usize) -> f64 {
+        self.multiplier.powi(bucket as i32)
+    }
+
+    pub fn add(&mut self, value: f64) {
+        self.sum += value;
+        self.sum_of_squares += value * value;
+        self.count += 1.0;
+        if value < self.min_seen {
+            self.min_seen = value;
+        }
+        if value > self.max_seen {
+            self.max_seen = value;
+        }
+        let bucket = self.bucket_for(value);
+        self.buckets[bucket] += 1;
+    }
+
+    /// Add values of another histogram with the same parameters.
+    pub fn merge(&mut self, other: &Histogram) {
+        assert_eq!(self.buckets.len(), other.buckets.len());
+        self.sum += other.sum;
+        self.sum_of_squares += other.sum_of_squares;
+        self.count += other.count;
+        self.min_seen = self.min_seen.min(other.min_seen);
+        self.max_seen = self.max_seen.max(other.max_seen);
+        for (a, b) in self.buckets.iter_mut().zip(other.buckets.iter()) {
+            *a += b;
+        }
+    }
+
+    /// Empty histogram with the same parameters.
+    pub fn reset(&mut self) {
+        for bucket in &mut self.buckets {
+            *bucket = 0;
+        }
+        self.min_seen = self.max_possible;
+        self.max_seen = 0.0;
+        self.sum = 0.0;
+        self.sum_of_squares = 0.0;
+        self.count = 0.0;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count as u64
+    }
+
+    /// Approximate value at given percentile, `0` if histogram is empty.
+    pub fn percentile(&self, percentile: f64) -> f64 {
+        if self.count == 0.0 {
+            return 0.0;
+        }
+        let target = self.count * percentile / 100.0;
+        let mut seen = 0.0;
+        for (i, &bucket) in self.buckets.iter().enumerate() {
+            seen += bucket as f64;
+            if seen >= target {
+                return self
+                    .bucket_start(i + 1)
+                    .min(self.max_seen)
+                    .max(self.min_seen);
+            }
+        }
+        self.max_seen
+    }
+
+    pub fn to_proto(&self) -> HistogramData {
+        let mut data = HistogramData::new();
+        data.set_bucket(self.buckets.clone());
+        data.set_min_seen(self.min_seen);
+        data.set_max_seen(self.max_seen);
+        data.set_sum(self.sum);
+        data.set_sum_of_squares(self.sum_of_squares);
+        data.set_count(self.count);
+        data
+    }
+}
+
+impl Default for Histogram {
+    /// Defaults used by benchmark driver: 1% resolution, up to 60 seconds in nanoseconds.
+    fn default() -> Histogram {
+        Histogram::new(0.01, 60e9)
+    }
+}
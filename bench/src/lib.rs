@@ -0,0 +1,33 @@
+extern crate futures;
+extern crate grpc;
+extern crate grpc_protobuf;
+#[macro_use]
+extern crate log;
+extern crate num_cpus;
+extern crate protobuf;
+
+pub mod benchmark_service;
+pub mod benchmark_service_grpc;
+pub mod control;
+pub mod messages;
+pub mod payloads;
+pub mod stats;
+pub mod worker_service;
+pub mod worker_service_grpc;
+
+pub use benchmark_service_grpc::*;
+pub use control::*;
+pub use messages::*;
+pub use payloads::*;
+pub use stats::*;
+pub use worker_service_grpc::*;
+
+pub mod benchmark_client;
+pub mod benchmark_server;
+pub mod client;
+pub mod cpu_usage;
+pub mod histogram;
+pub mod server;
+
+/// Default port of `WorkerService` used by benchmark driver.
+pub const DEFAULT_DRIVER_PORT: u16 = 10400;
@@ -0,0 +1,124 @@
+//! `BenchmarkService` implementation.
+
+use std::sync::Arc;
+
+use futures::stream;
+use futures::Async;
+use futures::Stream;
+
+use grpc::stats::ServerStatsHandler;
+use grpc::*;
+
+use benchmark_service_grpc::BenchmarkService;
+use benchmark_service_grpc::BenchmarkServiceServer;
+use messages::Payload;
+use messages::SimpleRequest;
+use messages::SimpleResponse;
+
+/// Response with payload of requested size.
+fn response_for(req: &SimpleRequest) -> SimpleResponse {
+    let mut payload = Payload::new();
+    payload.set_body(vec![0; req.get_response_size() as usize]);
+    let mut response = SimpleResponse::new();
+    response.set_payload(payload);
+    response
+}
+
+pub struct BenchmarkServiceImpl;
+
+impl BenchmarkService for BenchmarkServiceImpl {
+    fn unary_call(
+        &self,
+        _ctx: ServerHandlerContext,
+        req: ServerRequestSingle<SimpleRequest>,
+        resp: ServerResponseUnarySink<SimpleResponse>,
+    ) -> grpc::Result<()> {
+        resp.finish(response_for(&req.message))
+    }
+
+    fn streaming_call(
+        &self,
+        ctx: ServerHandlerContext,
+        req: ServerRequest<SimpleRequest>,
+        resp: ServerResponseSink<SimpleResponse>,
+    ) -> grpc::Result<()> {
+        ctx.pump(req.into_stream().map(|m| response_for(&m)), resp);
+        Ok(())
+    }
+
+    fn streaming_from_client(
+        &self,
+        _ctx: ServerHandlerContext,
+        req: ServerRequest<SimpleRequest>,
+        resp: ServerResponseUnarySink<SimpleResponse>,
+    ) -> grpc::Result<()> {
+        let mut resp = Some(resp);
+        let mut last = SimpleRequest::new();
+        req.register_stream_handler_basic(move |message| match message {
+            Some(m) => {
+                last = m;
+                Ok(())
+            }
+            None => resp.take().unwrap().finish(response_for(&last)),
+        });
+        Ok(())
+    }
+
+    fn streaming_from_server(
+        &self,
+        ctx: ServerHandlerContext,
+        req: ServerRequestSingle<SimpleRequest>,
+        resp: ServerResponseSink<SimpleResponse>,
+    ) -> grpc::Result<()> {
+        // until client cancels the call
+        ctx.pump(stream::repeat(response_for(&req.message)), resp);
+        Ok(())
+    }
+
+    fn streaming_both_ways(
+        &self,
+        ctx: ServerHandlerContext,
+        req: ServerRequest<SimpleRequest>,
+        mut resp: ServerResponseSink<SimpleResponse>,
+    ) -> grpc::Result<()> {
+        let mut req = req.into_stream();
+        let mut response = None;
+        ctx.spawn_poll_fn(move || loop {
+            match req.poll()? {
+                Async::Ready(Some(m)) => {
+                    response = Some(response_for(&m));
+                    continue;
+                }
+                Async::Ready(None) => {
+                    resp.send_trailers(Metadata::new())?;
+                    return Ok(Async::Ready(()));
+                }
+                Async::NotReady => {}
+            }
+            if let Async::NotReady = resp.poll()? {
+                return Ok(Async::NotReady);
+            }
+            match response {
+                Some(ref response) => resp.send_data(response.clone())?,
+                None => return Ok(Async::NotReady),
+            }
+        });
+        Ok(())
+    }
+}
+
+/// Start benchmark server, port `0` means any free port.
+pub fn start_server(
+    port: u16,
+    stats_handler: Option<Arc<ServerStatsHandler>>,
+) -> grpc::Result<Server> {
+    let mut server = ServerBuilder::new_plain();
+    server.http.set_port(port);
+    if let Some(stats_handler) = stats_handler {
+        server.set_stats_handler(stats_handler);
+    }
+    server.add_service(BenchmarkServiceServer::new_service_def(
+        BenchmarkServiceImpl,
+    ));
+    server.build()
+}
@@ -209,22 +209,91 @@ fn snake_name(name: &str) -> String {
     snake_method_name
 }
 
+/// Name of file-level constant with full method name,
+/// e. g. `METHOD_GREETER_SAY_HELLO` for `SayHello` of `Greeter`.
+fn method_const_name(service_name: &str, method_name: &str) -> String {
+    format!(
+        "METHOD_{}_{}",
+        snake_name(service_name).to_uppercase(),
+        snake_name(method_name).to_uppercase()
+    )
+}
+
+/// Customize generated code.
+#[derive(Debug, Default, Clone)]
+pub struct Customize {
+    /// Expression of type `&'static Marshaller<M>` for all request and response types,
+    /// e. g. `::grpc::marshall::MarshallerBytes`.
+    /// `::grpc_protobuf::MarshallerProtobuf` if not specified.
+    pub marshaller: Option<String>,
+    /// Also generate blocking client, e. g. `GreeterClientSync`.
+    pub sync_client: bool,
+    /// Also generate server trait with methods returning responses, e. g. `GreeterAsync`,
+    /// implementations are registered with `GreeterServer::new_async_service_def`.
+    pub async_server: bool,
+    /// Methods of server trait returning responses return `::grpc::Result` of response,
+    /// so request validation errors can be returned with `?`.
+    pub async_server_result: bool,
+    /// Also generate client trait implemented by client, e. g. `GreeterClientApi`,
+    /// and mock client implementing it with `::grpc::testing::MockMethod`
+    /// for each method, e. g. `GreeterClientMock`.
+    pub mock_client: bool,
+}
+
+impl Customize {
+    /// Parse protoc plugin parameter, e. g. `marshaller=::my_crate::MyMarshaller`.
+    pub fn parse_from_parameter(parameter: &str) -> Result<Customize, String> {
+        let mut r = Customize::default();
+        for nv in parameter.split_whitespace().flat_map(|s| s.split(',')) {
+            if nv.is_empty() {
+                continue;
+            }
+            let (n, v) = match nv.find('=') {
+                Some(eq) => (&nv[..eq], &nv[eq + 1..]),
+                None => (nv, ""),
+            };
+            match n {
+                "marshaller" => r.marshaller = Some(v.to_owned()),
+                "sync_client" => r.sync_client = v != "false",
+                "async_server" => r.async_server = v != "false",
+                "async_server_result" => r.async_server_result = v != "false",
+                "mock_client" => r.mock_client = v != "false",
+                _ => return Err(format!("unknown parameter: {}", n)),
+            }
+        }
+        Ok(r)
+    }
+
+    fn marshaller(&self) -> &str {
+        match self.marshaller {
+            Some(ref m) => m,
+            None => "::grpc_protobuf::MarshallerProtobuf",
+        }
+    }
+}
+
 struct MethodGen<'a> {
     proto: &'a MethodDescriptorProto,
     service_path: String,
+    const_name: String,
     root_scope: &'a RootScope<'a>,
+    customize: &'a Customize,
 }
 
 impl<'a> MethodGen<'a> {
     fn new(
         proto: &'a MethodDescriptorProto,
+        service_name: &str,
         service_path: String,
         root_scope: &'a RootScope<'a>,
+        customize: &'a Customize,
     ) -> MethodGen<'a> {
         MethodGen {
             proto: proto,
+            const_name: method_const_name(service_name, proto.get_name()),
             service_path: service_path,
             root_scope: root_scope,
+            customize,
         }
     }
 
@@ -232,6 +301,35 @@ impl<'a> MethodGen<'a> {
         snake_name(self.proto.get_name())
     }
 
+    // file-level function returning method descriptor
+    fn descriptor_fn_name(&self) -> String {
+        self.const_name.to_lowercase()
+    }
+
+    fn write_descriptor_fn(&self, w: &mut CodeWriter) {
+        w.pub_fn(
+            &format!(
+                "{}() -> ::grpc::rt::ArcOrStatic<::grpc::rt::MethodDescriptor<{}, {}>>",
+                self.descriptor_fn_name(),
+                self.input_message(),
+                self.output_message()
+            ),
+            |w| {
+                self.write_descriptor(w, "::grpc::rt::ArcOrStatic::Static(&", ")");
+            },
+        );
+    }
+
+    // file-level constant with full method name
+    fn write_const(&self, w: &mut CodeWriter) {
+        w.write_line(format!(
+            "pub const {}: &'static str = \"{}/{}\";",
+            self.const_name,
+            self.service_path,
+            self.proto.get_name()
+        ));
+    }
+
     fn input_message(&self) -> String {
         format!(
             "super::{}",
@@ -266,7 +364,7 @@ impl<'a> MethodGen<'a> {
         let return_type = match self.proto.get_client_streaming() {
             false => resp_type,
             true => format!(
-                "impl ::futures::future::Future<Item=(::grpc::ClientRequestSink<{}>, {}), Error=::grpc::Error>",
+                "(::grpc::RequestSink<{}>, {})",
                 self.input_message(),
                 resp_type
             ),
@@ -306,6 +404,23 @@ impl<'a> MethodGen<'a> {
         w.fn_def(&self.server_sig())
     }
 
+    fn server_async_sig(&self) -> String {
+        let resp = match self.customize.async_server_result {
+            false => self.client_resp_type(),
+            true => format!("::grpc::Result<{}>", self.client_resp_type()),
+        };
+        format!(
+            "{}(&self, o: ::grpc::ServerHandlerContext, req: {}) -> {}",
+            self.snake_name(),
+            self.server_req_type(),
+            resp,
+        )
+    }
+
+    fn write_server_async_intf(&self, w: &mut CodeWriter) {
+        w.fn_def(&self.server_async_sig())
+    }
+
     fn streaming_upper(&self) -> &'static str {
         match (
             self.proto.get_client_streaming(),
@@ -332,11 +447,7 @@ impl<'a> MethodGen<'a> {
 
     fn write_client(&self, w: &mut CodeWriter) {
         w.pub_fn(&self.client_sig(), |w| {
-            self.write_descriptor(
-                w,
-                "let descriptor = ::grpc::rt::ArcOrStatic::Static(&",
-                ");",
-            );
+            w.write_line(format!("let descriptor = {}();", self.descriptor_fn_name()));
 
             let req = match self.proto.get_client_streaming() {
                 false => ", req",
@@ -350,6 +461,96 @@ impl<'a> MethodGen<'a> {
         });
     }
 
+    fn write_client_api_intf(&self, w: &mut CodeWriter) {
+        w.fn_def(&self.client_sig());
+    }
+
+    fn write_client_api_impl(&self, client_name: &str, w: &mut CodeWriter) {
+        w.def_fn(&self.client_sig(), |w| {
+            let req = match self.proto.get_client_streaming() {
+                false => ", req",
+                true => "",
+            };
+            w.write_line(format!(
+                "{}::{}(self, o{})",
+                client_name,
+                self.snake_name(),
+                req
+            ));
+        });
+    }
+
+    fn mock_method_type(&self) -> String {
+        let req = match self.proto.get_client_streaming() {
+            false => self.input_message(),
+            true => format!("::grpc::StreamingRequest<{}>", self.input_message()),
+        };
+        format!(
+            "::grpc::testing::MockMethod<{}, {}>",
+            req,
+            self.client_resp_type()
+        )
+    }
+
+    fn write_client_mock_impl(&self, w: &mut CodeWriter) {
+        w.def_fn(&self.client_sig(), |w| {
+            match self.proto.get_client_streaming() {
+                false => w.write_line(format!("self.{}.call(o, req)", self.snake_name())),
+                true => {
+                    w.write_line("let (sink, reqs) = ::grpc::RequestSink::channel();");
+                    w.write_line(format!("(sink, self.{}.call(o, reqs))", self.snake_name()));
+                }
+            }
+        });
+    }
+
+    fn client_sync_sig(&self) -> String {
+        let req_param = match self.proto.get_client_streaming() {
+            false => format!(", req: {}", self.input_message()),
+            true => format!(
+                ", reqs: impl ::std::iter::IntoIterator<Item={}>",
+                self.input_message()
+            ),
+        };
+        let return_type = match self.proto.get_server_streaming() {
+            false => format!("::grpc::Result<{}>", self.output_message()),
+            true => format!(
+                "::grpc::Result<::grpc::GrpcIterator<{}>>",
+                self.output_message()
+            ),
+        };
+        format!(
+            "{}(&self, o: ::grpc::RequestOptions{}) -> {}",
+            self.snake_name(),
+            req_param,
+            return_type,
+        )
+    }
+
+    fn write_client_sync(&self, w: &mut CodeWriter) {
+        w.pub_fn(&self.client_sync_sig(), |w| {
+            let resp = match self.proto.get_client_streaming() {
+                false => format!("self.client.{}(o, req)", self.snake_name()),
+                true => {
+                    w.write_line(format!(
+                        "let (mut sink, resp) = self.client.{}(o);",
+                        self.snake_name()
+                    ));
+                    w.block("for req in reqs {", "}", |w| {
+                        w.write_line("sink.block_wait()?;");
+                        w.write_line("sink.send_data(req)?;");
+                    });
+                    w.write_line("sink.finish()?;");
+                    "resp".to_owned()
+                }
+            };
+            match self.proto.get_server_streaming() {
+                false => w.write_line(format!("{}.wait_drop_metadata()", resp)),
+                true => w.write_line(format!("Ok({}.wait_drop_metadata())", resp)),
+            }
+        });
+    }
+
     fn write_descriptor(&self, w: &mut CodeWriter, before: &str, after: &str) {
         w.block(
             &format!("{}{}", before, "::grpc::rt::MethodDescriptor {"),
@@ -357,24 +558,18 @@ impl<'a> MethodGen<'a> {
             |w| {
                 w.field_entry(
                     "name",
-                    &format!(
-                        "::grpc::rt::StringOrStatic::Static(\"{}/{}\")",
-                        self.service_path,
-                        self.proto.get_name()
-                    ),
+                    &format!("::grpc::rt::StringOrStatic::Static({})", self.const_name),
                 );
                 w.field_entry(
                     "streaming",
                     &format!("::grpc::rt::GrpcStreaming::{}", self.streaming_upper()),
                 );
-                w.field_entry(
-                    "req_marshaller",
-                    "::grpc::rt::ArcOrStatic::Static(&::grpc_protobuf::MarshallerProtobuf)",
-                );
-                w.field_entry(
-                    "resp_marshaller",
-                    "::grpc::rt::ArcOrStatic::Static(&::grpc_protobuf::MarshallerProtobuf)",
+                let marshaller = format!(
+                    "::grpc::rt::ArcOrStatic::Static(&{})",
+                    self.customize.marshaller()
                 );
+                w.field_entry("req_marshaller", &marshaller);
+                w.field_entry("resp_marshaller", &marshaller);
             },
         );
     }
@@ -386,6 +581,7 @@ struct ServiceGen<'a> {
     methods: Vec<MethodGen<'a>>,
     service_path: String,
     _package: String,
+    customize: &'a Customize,
 }
 
 impl<'a> ServiceGen<'a> {
@@ -393,6 +589,7 @@ impl<'a> ServiceGen<'a> {
         proto: &'a ServiceDescriptorProto,
         file: &FileDescriptorProto,
         root_scope: &'a RootScope,
+        customize: &'a Customize,
     ) -> ServiceGen<'a> {
         let service_path = if file.get_package().is_empty() {
             format!("/{}", proto.get_name())
@@ -402,7 +599,15 @@ impl<'a> ServiceGen<'a> {
         let methods = proto
             .get_method()
             .into_iter()
-            .map(|m| MethodGen::new(m, service_path.clone(), root_scope))
+            .map(|m| {
+                MethodGen::new(
+                    m,
+                    proto.get_name(),
+                    service_path.clone(),
+                    root_scope,
+                    customize,
+                )
+            })
             .collect();
 
         ServiceGen {
@@ -411,6 +616,7 @@ impl<'a> ServiceGen<'a> {
             methods,
             service_path,
             _package: file.get_package().to_string(),
+            customize,
         }
     }
 
@@ -419,11 +625,31 @@ impl<'a> ServiceGen<'a> {
         self.proto.get_name()
     }
 
+    // trait name of server returning responses
+    fn server_async_intf_name(&self) -> String {
+        format!("{}Async", self.proto.get_name())
+    }
+
     // client struct name
     fn client_name(&self) -> String {
         format!("{}Client", self.proto.get_name())
     }
 
+    // blocking client struct name
+    fn client_sync_name(&self) -> String {
+        format!("{}ClientSync", self.proto.get_name())
+    }
+
+    // client trait name
+    fn client_api_name(&self) -> String {
+        format!("{}ClientApi", self.proto.get_name())
+    }
+
+    // mock client struct name
+    fn client_mock_name(&self) -> String {
+        format!("{}ClientMock", self.proto.get_name())
+    }
+
     // server struct name
     fn server_name(&self) -> String {
         format!("{}Server", self.proto.get_name())
@@ -441,6 +667,18 @@ impl<'a> ServiceGen<'a> {
         });
     }
 
+    fn write_server_async_intf(&self, w: &mut CodeWriter) {
+        w.pub_trait(&self.server_async_intf_name(), |w| {
+            for (i, method) in self.methods.iter().enumerate() {
+                if i != 0 {
+                    w.write_line("");
+                }
+
+                method.write_server_async_intf(w);
+            }
+        });
+    }
+
     fn write_client_object(&self, grpc_client: &str, w: &mut CodeWriter) {
         w.expr_block(&self.client_name(), |w| {
             w.field_entry("grpc_client", grpc_client);
@@ -474,32 +712,142 @@ impl<'a> ServiceGen<'a> {
         });
     }
 
+    fn write_client_sync(&self, w: &mut CodeWriter) {
+        w.pub_struct(self.client_sync_name(), |w| {
+            w.field_decl("client", &self.client_name());
+        });
+
+        w.write_line("");
+
+        w.impl_for_block("::grpc::ClientStub", self.client_sync_name(), |w| {
+            let sig = "with_client(grpc_client: ::std::sync::Arc<::grpc::Client>) -> Self";
+            w.def_fn(sig, |w| {
+                w.expr_block(&self.client_sync_name(), |w| {
+                    w.field_entry(
+                        "client",
+                        &format!("{}::with_client(grpc_client)", self.client_name()),
+                    );
+                });
+            });
+        });
+
+        w.write_line("");
+
+        w.impl_self_block(self.client_sync_name(), |w| {
+            for (i, method) in self.methods.iter().enumerate() {
+                if i != 0 {
+                    w.write_line("");
+                }
+
+                method.write_client_sync(w);
+            }
+        });
+    }
+
+    fn write_client_api(&self, w: &mut CodeWriter) {
+        w.pub_trait(&self.client_api_name(), |w| {
+            for (i, method) in self.methods.iter().enumerate() {
+                if i != 0 {
+                    w.write_line("");
+                }
+
+                method.write_client_api_intf(w);
+            }
+        });
+
+        w.write_line("");
+
+        w.impl_for_block(self.client_api_name(), self.client_name(), |w| {
+            for (i, method) in self.methods.iter().enumerate() {
+                if i != 0 {
+                    w.write_line("");
+                }
+
+                method.write_client_api_impl(&self.client_name(), w);
+            }
+        });
+    }
+
+    fn write_client_mock(&self, w: &mut CodeWriter) {
+        w.pub_struct(self.client_mock_name(), |w| {
+            for method in &self.methods {
+                w.write_line(format!(
+                    "pub {}: {},",
+                    method.snake_name(),
+                    method.mock_method_type()
+                ));
+            }
+        });
+
+        w.write_line("");
+
+        w.impl_self_block(self.client_mock_name(), |w| {
+            w.pub_fn("new() -> Self", |w| {
+                w.expr_block(&self.client_mock_name(), |w| {
+                    for method in &self.methods {
+                        w.field_entry(
+                            &method.snake_name(),
+                            &format!("::grpc::testing::MockMethod::new({})", method.const_name),
+                        );
+                    }
+                });
+            });
+
+            w.write_line("");
+
+            w.pub_fn("verify(&self)", |w| {
+                for method in &self.methods {
+                    w.write_line(format!("self.{}.verify();", method.snake_name()));
+                }
+            });
+        });
+
+        w.write_line("");
+
+        w.impl_for_block(self.client_api_name(), self.client_mock_name(), |w| {
+            for (i, method) in self.methods.iter().enumerate() {
+                if i != 0 {
+                    w.write_line("");
+                }
+
+                method.write_client_mock_impl(w);
+            }
+        });
+    }
+
     fn write_service_definition(
         &self,
         before: &str,
         after: &str,
         handler: &str,
+        intf_name: &str,
+        new_method: &str,
         w: &mut CodeWriter,
     ) {
         w.block(
-            &format!("{}::grpc::rt::ServerServiceDefinition::new(\"{}\",",
-                before, self.service_path),
+            &format!(
+                "{}::grpc::rt::ServerServiceDefinition::new(\"{}\",",
+                before, self.service_path
+            ),
             &format!("){}", after),
             |w| {
                 w.block("vec![", "],", |w| {
                     for method in &self.methods {
                         w.block("::grpc::rt::ServerMethod::new(", "),", |w| {
-                            method.write_descriptor(w, "::grpc::rt::ArcOrStatic::Static(&", "),");
-                            w.block("{", "},", |w| {
-                                w.write_line(&format!("let handler_copy = {}.clone();", handler));
-                                w.write_line(&format!("::grpc::rt::MethodHandler{}::new(move |ctx, req, resp| (*handler_copy).{}(ctx, req, resp))",
-                                    method.streaming_upper(),
-                                    method.snake_name()));
-                            });
+                            w.write_line(format!("{}(),", method.descriptor_fn_name()));
+                            w.write_line(format!(
+                                "::grpc::rt::MethodHandler{}::{}({}.clone(), {}::{}),",
+                                method.streaming_upper(),
+                                new_method,
+                                handler,
+                                intf_name,
+                                method.snake_name()
+                            ));
                         });
                     }
                 });
-            });
+            },
+        );
     }
 
     fn write_server(&self, w: &mut CodeWriter) {
@@ -513,29 +861,110 @@ impl<'a> ServiceGen<'a> {
             w.pub_fn(&format!("new_service_def<H : {} + 'static + Sync + Send + 'static>(handler: H) -> ::grpc::rt::ServerServiceDefinition", self.server_intf_name()), |w| {
                 w.write_line("let handler_arc = ::std::sync::Arc::new(handler);");
 
-                self.write_service_definition("", "", "handler_arc", w);
+                self.write_service_definition("", "", "handler_arc", self.server_intf_name(), "new_method", w);
             });
+
+            if self.customize.async_server {
+                w.write_line("");
+
+                w.pub_fn(&format!("new_async_service_def<H : {} + 'static + Sync + Send + 'static>(handler: H) -> ::grpc::rt::ServerServiceDefinition", self.server_async_intf_name()), |w| {
+                    w.write_line("let handler_arc = ::std::sync::Arc::new(handler);");
+
+                    self.write_service_definition("", "", "handler_arc", &self.server_async_intf_name(), "new_async_method", w);
+                });
+            }
         });
     }
 
+    fn write_method_consts(&self, w: &mut CodeWriter) {
+        for method in &self.methods {
+            method.write_const(w);
+        }
+    }
+
+    fn write_descriptor_fns(&self, w: &mut CodeWriter) {
+        for method in &self.methods {
+            w.write_line("");
+            method.write_descriptor_fn(w);
+        }
+    }
+
     fn write(&self, w: &mut CodeWriter) {
         w.comment("server interface");
         w.write_line("");
         self.write_server_intf(w);
         w.write_line("");
+        if self.customize.async_server {
+            w.comment("server interface returning responses");
+            w.write_line("");
+            self.write_server_async_intf(w);
+            w.write_line("");
+        }
         w.comment("client");
         w.write_line("");
         self.write_client(w);
         w.write_line("");
+        if self.customize.sync_client {
+            w.comment("blocking client");
+            w.write_line("");
+            self.write_client_sync(w);
+            w.write_line("");
+        }
+        if self.customize.mock_client {
+            w.comment("client trait implemented by client and mock client");
+            w.write_line("");
+            self.write_client_api(w);
+            w.write_line("");
+            w.comment("mock client");
+            w.write_line("");
+            self.write_client_mock(w);
+            w.write_line("");
+        }
         w.comment("server");
         w.write_line("");
         self.write_server(w);
     }
 }
 
+/// Function which creates definitions of all services of a file,
+/// to be registered with a single `ServerBuilder::add_services` call.
+fn write_service_definitions(services: &[ServiceGen], w: &mut CodeWriter) {
+    let type_params: Vec<String> = services
+        .iter()
+        .enumerate()
+        .map(|(i, s)| format!("H{} : {} + 'static + Sync + Send", i, s.server_intf_name()))
+        .collect();
+    let params: Vec<String> = services
+        .iter()
+        .enumerate()
+        .map(|(i, s)| format!("{}_handler: H{}", snake_name(s.server_intf_name()), i))
+        .collect();
+    w.pub_fn(
+        &format!(
+            "service_definitions<{}>({}) -> ::std::vec::Vec<::grpc::rt::ServerServiceDefinition>",
+            type_params.join(", "),
+            params.join(", ")
+        ),
+        |w| {
+            w.write_line("vec![");
+            w.indented(|w| {
+                for s in services {
+                    w.write_line(format!(
+                        "{}::new_service_def({}_handler),",
+                        s.server_name(),
+                        snake_name(s.server_intf_name())
+                    ));
+                }
+            });
+            w.write_line("]");
+        },
+    );
+}
+
 fn gen_file(
     file: &FileDescriptorProto,
     root_scope: &RootScope,
+    customize: &Customize,
 ) -> Option<compiler_plugin::GenResult> {
     if file.get_service().is_empty() {
         return None;
@@ -549,10 +978,36 @@ fn gen_file(
         w.write_generated();
         w.write_line("");
 
-        for service in file.get_service() {
+        let services: Vec<ServiceGen> = file
+            .get_service()
+            .iter()
+            .map(|service| ServiceGen::new(service, file, root_scope, customize))
+            .collect();
+
+        w.write_line("");
+        w.comment("full method names");
+        w.write_line("");
+        for service in &services {
+            service.write_method_consts(&mut w);
+        }
+
+        w.write_line("");
+        w.comment("method descriptors used by clients and servers,");
+        w.comment("e. g. for interceptors, proxies and tests");
+        for service in &services {
+            service.write_descriptor_fns(&mut w);
+        }
+
+        for service in &services {
             w.write_line("");
-            ServiceGen::new(service, file, root_scope).write(&mut w);
+            service.write(&mut w);
         }
+
+        w.write_line("");
+        w.write_line("");
+        w.comment("all services of this file");
+        w.write_line("");
+        write_service_definitions(&services, &mut w);
     }
 
     Some(compiler_plugin::GenResult {
@@ -564,6 +1019,14 @@ fn gen_file(
 pub fn gen(
     file_descriptors: &[FileDescriptorProto],
     files_to_generate: &[String],
+) -> Vec<compiler_plugin::GenResult> {
+    gen_with_customize(file_descriptors, files_to_generate, &Customize::default())
+}
+
+pub fn gen_with_customize(
+    file_descriptors: &[FileDescriptorProto],
+    files_to_generate: &[String],
+    customize: &Customize,
 ) -> Vec<compiler_plugin::GenResult> {
     let files_map: HashMap<&str, &FileDescriptorProto> =
         file_descriptors.iter().map(|f| (f.get_name(), f)).collect();
@@ -581,18 +1044,70 @@ pub fn gen(
             continue;
         }
 
-        results.extend(gen_file(file, &root_scope).into_iter());
+        results.extend(gen_file(file, &root_scope, customize));
     }
 
     results
 }
 
 pub fn protoc_gen_grpc_rust_main() {
-    compiler_plugin::plugin_main(gen);
+    compiler_plugin::plugin_main_2(|r| {
+        let customize = Customize::parse_from_parameter(r.parameter).expect("parse options");
+        gen_with_customize(r.file_descriptors, r.files_to_generate, &customize)
+    });
 }
 
 #[cfg(test)]
 mod test {
+    use super::Customize;
+
+    #[test]
+    fn customize_parse_from_parameter() {
+        assert_eq!(
+            None,
+            Customize::parse_from_parameter("").unwrap().marshaller
+        );
+        assert_eq!(
+            Some("::grpc::marshall::MarshallerBytes".to_owned()),
+            Customize::parse_from_parameter("marshaller=::grpc::marshall::MarshallerBytes")
+                .unwrap()
+                .marshaller
+        );
+        assert!(
+            Customize::parse_from_parameter("sync_client")
+                .unwrap()
+                .sync_client
+        );
+        assert!(
+            Customize::parse_from_parameter("sync_client,async_server")
+                .unwrap()
+                .async_server
+        );
+        assert!(
+            Customize::parse_from_parameter("async_server,async_server_result")
+                .unwrap()
+                .async_server_result
+        );
+        assert!(
+            Customize::parse_from_parameter("mock_client")
+                .unwrap()
+                .mock_client
+        );
+        assert!(Customize::parse_from_parameter("foo=bar").is_err());
+    }
+
+    #[test]
+    fn test_method_const_name() {
+        assert_eq!(
+            "METHOD_GREETER_SAY_HELLO",
+            super::method_const_name("Greeter", "SayHello")
+        );
+        assert_eq!(
+            "METHOD_ROUTE_GUIDE_GET_FEATURE",
+            super::method_const_name("RouteGuide", "GetFeature")
+        );
+    }
+
     #[test]
     fn test_snake_name() {
         let cases = vec![
@@ -0,0 +1,12 @@
+extern crate protoc_rust_grpc;
+
+fn main() {
+    protoc_rust_grpc::run(protoc_rust_grpc::Args {
+        out_dir: "src",
+        includes: &[],
+        input: &["bytestream.proto"],
+        rust_protobuf: true,
+        ..Default::default()
+    })
+    .expect("protoc-rust-grpc");
+}
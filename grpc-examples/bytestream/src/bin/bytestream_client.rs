@@ -0,0 +1,63 @@
+// Upload a file with `Write` and download it back with `Read`
+
+use bytes::Bytes;
+use futures::future::Future;
+use futures::stream::Stream;
+use grpc::prelude::*;
+use grpc::ByteStreamReader;
+use grpc::ByteStreamWriter;
+use grpc::ClientConf;
+use grpc_examples_bytestream::bytestream::ReadRequest;
+use grpc_examples_bytestream::bytestream::WriteRequest;
+use grpc_examples_bytestream::bytestream_grpc::ByteStreamClient;
+use grpc_examples_bytestream::DEFAULT_PORT;
+use std::env;
+use std::fs;
+use std::io::Cursor;
+
+fn main() {
+    env_logger::init().unwrap();
+
+    let path = env::args().nth(1).expect("usage: bytestream_client <file>");
+    let content = fs::read(&path).expect("read file");
+
+    let client =
+        ByteStreamClient::new_plain("127.0.0.1", DEFAULT_PORT, ClientConf::new()).expect("client");
+
+    let (mut req, resp) = client.write(grpc::RequestOptions::new());
+    // resource name is only needed in the first message
+    req.send_data(WriteRequest {
+        resource_name: path.clone(),
+        ..Default::default()
+    })
+    .expect("send_data");
+    let chunks = ByteStreamReader::new(Cursor::new(content.clone())).chunk_size(16 * 1024);
+    for chunk in chunks.wait() {
+        let chunk = chunk.expect("chunk");
+        // wait for flow control window, so the file is not buffered in memory
+        req.block_wait().expect("block_wait");
+        req.send_data(WriteRequest {
+            data: chunk.to_vec(),
+            ..Default::default()
+        })
+        .expect("send_data");
+    }
+    req.finish().expect("finish");
+    let written = resp.drop_metadata().wait().expect("write");
+    println!("written {} bytes to {}", written.committed_size, path);
+
+    let resp = client.read(
+        grpc::RequestOptions::new(),
+        ReadRequest {
+            resource_name: path.clone(),
+            ..Default::default()
+        },
+    );
+    let chunks = resp.drop_metadata().map(|resp| Bytes::from(resp.data));
+    let (read, len) = ByteStreamWriter::new(chunks, Vec::new())
+        .wait()
+        .expect("read");
+    println!("read {} bytes from {}", len, path);
+
+    assert_eq!(content, read, "content read back differs");
+}
@@ -0,0 +1,21 @@
+use grpc_examples_bytestream::bytestream_grpc::ByteStreamServer;
+use grpc_examples_bytestream::server::ByteStreamImpl;
+use grpc_examples_bytestream::DEFAULT_PORT;
+use std::thread;
+
+fn main() {
+    env_logger::init().unwrap();
+
+    let service_def = ByteStreamServer::new_service_def(ByteStreamImpl::default());
+
+    let mut server_builder = grpc::ServerBuilder::new_plain();
+    server_builder.add_service(service_def);
+    server_builder.http.set_port(DEFAULT_PORT);
+    let server = server_builder.build().expect("build");
+
+    println!("server stared on addr {}", server.local_addr());
+
+    loop {
+        thread::park();
+    }
+}
@@ -0,0 +1,11 @@
+extern crate bytes;
+extern crate futures;
+extern crate grpc;
+extern crate grpc_protobuf;
+extern crate protobuf;
+
+pub mod bytestream;
+pub mod bytestream_grpc;
+pub mod server;
+
+pub const DEFAULT_PORT: u16 = 10001;
@@ -0,0 +1,96 @@
+use crate::bytestream::ReadRequest;
+use crate::bytestream::ReadResponse;
+use crate::bytestream::WriteRequest;
+use crate::bytestream::WriteResponse;
+use crate::bytestream_grpc::ByteStream;
+use bytes::Bytes;
+use futures::future;
+use futures::future::Either;
+use futures::future::Future;
+use futures::stream;
+use futures::stream::Stream;
+use grpc::ByteStreamReader;
+use grpc::ByteStreamWriter;
+use grpc::GrpcStatus;
+use grpc::ServerHandlerContext;
+use grpc::ServerRequest;
+use grpc::ServerRequestSingle;
+use grpc::ServerResponseSink;
+use grpc::ServerResponseUnarySink;
+use grpc::Status;
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+/// Resources are kept in memory, so the example has no blocking IO.
+#[derive(Default)]
+pub struct ByteStreamImpl {
+    resources: Arc<Mutex<HashMap<String, Bytes>>>,
+}
+
+impl ByteStream for ByteStreamImpl {
+    fn read(
+        &self,
+        o: ServerHandlerContext,
+        mut req: ServerRequestSingle<ReadRequest>,
+        mut resp: ServerResponseSink<ReadResponse>,
+    ) -> grpc::Result<()> {
+        let req = req.take_message();
+        let data = match self.resources.lock().unwrap().get(&req.resource_name) {
+            Some(data) => data.clone(),
+            None => {
+                return resp.send_grpc_error(
+                    GrpcStatus::NotFound,
+                    format!("resource not found: {}", req.resource_name),
+                );
+            }
+        };
+        let stream = ByteStreamReader::new(Cursor::new(data)).map(|chunk| ReadResponse {
+            data: chunk.to_vec(),
+            ..Default::default()
+        });
+        o.pump(stream, resp);
+        Ok(())
+    }
+
+    fn write(
+        &self,
+        o: ServerHandlerContext,
+        req: ServerRequest<WriteRequest>,
+        resp: ServerResponseUnarySink<WriteResponse>,
+    ) -> grpc::Result<()> {
+        let resources = self.resources.clone();
+        let f = req
+            .into_stream()
+            .into_future()
+            .map_err(|(e, _)| e)
+            .and_then(move |(first, rest)| {
+                let name = match first {
+                    Some(ref first) if !first.resource_name.is_empty() => {
+                        first.resource_name.clone()
+                    }
+                    _ => {
+                        return Either::A(future::err(grpc::Error::Status(Status::new(
+                            GrpcStatus::InvalidArgument,
+                            "resource_name is not set in the first message",
+                        ))));
+                    }
+                };
+                let chunks = stream::iter_ok(first)
+                    .chain(rest)
+                    .map(|req| Bytes::from(req.data));
+                Either::B(
+                    ByteStreamWriter::new(chunks, Vec::new()).map(move |(data, written)| {
+                        resources.lock().unwrap().insert(name, Bytes::from(data));
+                        WriteResponse {
+                            committed_size: written as i64,
+                            ..Default::default()
+                        }
+                    }),
+                )
+            });
+        o.pump_future(f, resp);
+        Ok(())
+    }
+}
@@ -1,15 +1,12 @@
 extern crate futures;
 extern crate grpc;
 extern crate grpc_examples_greeter;
-extern crate httpbis;
 
-extern crate tls_api;
 extern crate tls_api_native_tls;
 
 extern crate env_logger;
 
 use std::env;
-use std::net::SocketAddr;
 use std::sync::Arc;
 
 use grpc_examples_greeter::helloworld::*;
@@ -18,18 +15,13 @@ use grpc_examples_greeter::helloworld_grpc::*;
 use grpc::ClientStub;
 use grpc::ClientStubExt;
 
-use tls_api::TlsConnector;
-use tls_api::TlsConnectorBuilder;
-
-fn test_tls_connector() -> tls_api_native_tls::TlsConnector {
+fn test_tls_conf() -> grpc::ClientTlsConf {
     let root_ca = include_bytes!("../root-ca.der");
-    let root_ca = tls_api::Certificate::from_der(root_ca.to_vec());
 
-    let mut builder = tls_api_native_tls::TlsConnector::builder().unwrap();
-    builder
-        .add_root_certificate(root_ca)
-        .expect("add_root_certificate");
-    builder.build().unwrap()
+    let mut tls_conf = grpc::ClientTlsConf::new();
+    tls_conf.add_root_certificate_der(root_ca.to_vec());
+    tls_conf.sni_host = Some("foobar.com".to_owned());
+    tls_conf
 }
 
 fn is_tls() -> bool {
@@ -52,14 +44,11 @@ fn main() {
     let client_conf = Default::default();
 
     let client = if tls {
-        // This is a bit complicated, because we need to explicitly pass root CA here
-        // because server uses self-signed certificate.
-        // TODO: simplify it
-        let mut tls_option =
-            httpbis::ClientTlsOption::Tls("foobar.com".to_owned(), Arc::new(test_tls_connector()));
+        // Server uses self-signed certificate, so root CA is passed explicitly
         let grpc_client = Arc::new(
             grpc::ClientBuilder::new("::1", port)
-                .explicit_tls(tls_option)
+                .tls_conf::<tls_api_native_tls::TlsConnector>(test_tls_conf())
+                .unwrap()
                 .build()
                 .unwrap(),
         );
@@ -2,24 +2,22 @@ extern crate futures;
 extern crate grpc;
 extern crate grpc_examples_greeter;
 
+use grpc::Channel;
 use grpc::ClientBuilder;
-use grpc::ClientStub;
 
 use grpc_examples_greeter::helloworld::*;
 use grpc_examples_greeter::helloworld_grpc::*;
 
 use std::env;
-use std::sync::Arc;
-
 fn main() {
     let name = env::args()
         .nth(1)
         .map(|s| s.to_owned())
         .unwrap_or_else(|| "world".to_owned());
 
-    let client = Arc::new(ClientBuilder::new("::1", 50051).build().unwrap());
-    let greeter_client = GreeterClient::with_client(client.clone());
-    let greeter_client2 = GreeterClient::with_client(client);
+    let channel: Channel = ClientBuilder::new("::1", 50051).build_channel().unwrap();
+    let greeter_client: GreeterClient = channel.stub();
+    let greeter_client2: GreeterClient = channel.stub();
 
     let mut req = HelloRequest::new();
     req.set_name(name);
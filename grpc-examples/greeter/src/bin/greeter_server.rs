@@ -54,7 +54,7 @@ fn main() {
 
     let mut server = grpc::ServerBuilder::new();
     server.http.set_port(port);
-    server.add_service(GreeterServer::new_service_def(GreeterImpl));
+    server.add_services(service_definitions(GreeterImpl));
     //server.http.set_cpu_pool_threads(4);
     if tls {
         server.http.set_tls(test_tls_acceptor());
@@ -66,10 +66,7 @@ fn run_record_route(client: &RouteGuideClient) {
 
     println!("Traversing {} points.", point_count);
 
-    let (mut req, resp) = client
-        .record_route(grpc::RequestOptions::new())
-        .wait()
-        .unwrap();
+    let (mut req, resp) = client.record_route(grpc::RequestOptions::new());
 
     for _ in 0..point_count {
         let point = random_point();
@@ -105,10 +102,7 @@ fn run_route_chat(client: &RouteGuideClient) {
         new_note(0, 3, "Sixth message"),
     ];
 
-    let (mut req, resp) = client
-        .route_chat(grpc::RequestOptions::new())
-        .wait()
-        .unwrap();
+    let (mut req, resp) = client.route_chat(grpc::RequestOptions::new());
 
     let sender_thread = thread::spawn(move || {
         for note in notes {
@@ -0,0 +1,94 @@
+//! Call unary method of any gRPC server with JSON request,
+//! given descriptors of its `.proto` files.
+//!
+//! ```text
+//! protoc --include_imports --descriptor_set_out=greeter.pb helloworld.proto
+//! grpc-cli localhost:50051 greeter.pb helloworld.Greeter/SayHello '{"name": "world"}'
+//! ```
+
+extern crate grpc;
+extern crate grpc_protobuf;
+extern crate serde_json;
+
+use std::env;
+use std::fs;
+use std::io;
+use std::io::Read;
+use std::process;
+use std::sync::Arc;
+
+use grpc::rt::ArcOrStatic;
+use grpc::rt::GrpcStreaming;
+use grpc::ClientBuilder;
+use grpc::RequestOptions;
+use grpc_protobuf::dynamic::DescriptorPool;
+
+const USAGE: &str = "usage: grpc-cli <host:port> <descriptor-set> <service/method> [json]
+
+Request JSON is read from stdin if not specified.
+Descriptor set must include imports (protoc --include_imports --descriptor_set_out).";
+
+fn die(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1);
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 4 || args.len() > 5 {
+        eprintln!("{}", USAGE);
+        process::exit(2);
+    }
+
+    let (host, port) = match args[1].rfind(':') {
+        Some(pos) => (
+            args[1][..pos].trim_start_matches('[').trim_end_matches(']'),
+            args[1][pos + 1..]
+                .parse::<u16>()
+                .unwrap_or_else(|_| die("invalid port")),
+        ),
+        None => die("address must be host:port"),
+    };
+
+    let descriptor_set =
+        fs::read(&args[2]).unwrap_or_else(|e| die(&format!("failed to read {}: {}", args[2], e)));
+    let pool = Arc::new(
+        DescriptorPool::parse(&descriptor_set)
+            .unwrap_or_else(|e| die(&format!("invalid descriptor set: {}", e))),
+    );
+
+    let method_name = format!("/{}", args[3].trim_start_matches('/'));
+    let method = DescriptorPool::method_descriptor(&pool, &method_name)
+        .unwrap_or_else(|| die(&format!("method {} not found", method_name)));
+    if method.streaming != GrpcStreaming::Unary {
+        die("only unary methods are supported");
+    }
+
+    let json = match args.get(4) {
+        Some(json) => json.clone(),
+        None => {
+            let mut json = String::new();
+            io::stdin()
+                .read_to_string(&mut json)
+                .unwrap_or_else(|e| die(&format!("failed to read stdin: {}", e)));
+            json
+        }
+    };
+    let req: serde_json::Value =
+        serde_json::from_str(&json).unwrap_or_else(|e| die(&format!("invalid JSON: {}", e)));
+
+    let client = ClientBuilder::new(host, port)
+        .build()
+        .unwrap_or_else(|e| die(&format!("failed to create client: {}", e)));
+    let resp = client
+        .call_unary(
+            RequestOptions::new(),
+            req,
+            ArcOrStatic::Arc(Arc::new(method)),
+        )
+        .wait_drop_metadata();
+    match resp {
+        Ok(resp) => println!("{}", serde_json::to_string_pretty(&resp).unwrap()),
+        Err(e) => die(&format!("call failed: {}", e)),
+    }
+}
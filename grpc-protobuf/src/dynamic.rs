@@ -0,0 +1,764 @@
+//! Messages described by `FileDescriptorSet` at runtime.
+//!
+//! Messages are converted between protobuf wire format and proto3 JSON
+//! without generated code, so methods of any service can be called
+//! given only its descriptors (e. g. `protoc --descriptor_set_out`).
+
+use std::collections::HashMap;
+use std::error::Error as std_Error;
+use std::fmt;
+use std::sync::Arc;
+
+use base64;
+use bytes::Bytes;
+use serde_json::Map;
+use serde_json::Value;
+
+use grpc::marshall::Marshaller;
+use grpc::rt::ArcOrStatic;
+use grpc::rt::GrpcStreaming;
+use grpc::rt::MethodDescriptor;
+
+use protobuf::descriptor::DescriptorProto;
+use protobuf::descriptor::EnumDescriptorProto;
+use protobuf::descriptor::FieldDescriptorProto;
+use protobuf::descriptor::FieldDescriptorProto_Label;
+use protobuf::descriptor::FieldDescriptorProto_Type;
+use protobuf::descriptor::FileDescriptorProto;
+use protobuf::descriptor::FileDescriptorSet;
+use protobuf::wire_format::WireType;
+use protobuf::CodedInputStream;
+use protobuf::CodedOutputStream;
+use protobuf::ProtobufError;
+
+/// Message does not match its descriptor.
+#[derive(Debug)]
+struct DynamicError(String);
+
+impl fmt::Display for DynamicError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std_Error for DynamicError {}
+
+fn error(message: String) -> grpc::Error {
+    grpc::Error::Protocol(grpc::ProtocolError::Marshaller(Box::new(DynamicError(
+        message,
+    ))))
+}
+
+fn protobuf_error(e: ProtobufError) -> grpc::Error {
+    grpc::Error::Protocol(grpc::ProtocolError::Marshaller(Box::new(e)))
+}
+
+/// Method found in descriptors.
+#[derive(Debug, Clone)]
+pub struct DynamicMethod {
+    /// Full method name, e. g. `/helloworld.Greeter/SayHello`
+    pub name: String,
+    /// Full name of request message, e. g. `helloworld.HelloRequest`
+    pub input_type: String,
+    /// Full name of response message
+    pub output_type: String,
+    pub streaming: GrpcStreaming,
+}
+
+/// Messages, enums and methods of a set of `.proto` files.
+#[derive(Debug, Default)]
+pub struct DescriptorPool {
+    /// Keyed by full name without leading dot
+    messages: HashMap<String, DescriptorProto>,
+    enums: HashMap<String, EnumDescriptorProto>,
+    methods: HashMap<String, DynamicMethod>,
+}
+
+/// Full name of a type referenced by a field, e. g. `.foo.Bar` -> `foo.Bar`
+fn type_name(field: &FieldDescriptorProto) -> &str {
+    field.get_type_name().trim_start_matches('.')
+}
+
+fn qualified(prefix: &str, name: &str) -> String {
+    if prefix.is_empty() {
+        name.to_owned()
+    } else {
+        format!("{}.{}", prefix, name)
+    }
+}
+
+fn json_name(field: &FieldDescriptorProto) -> &str {
+    if field.get_json_name().is_empty() {
+        field.get_name()
+    } else {
+        field.get_json_name()
+    }
+}
+
+impl DescriptorPool {
+    pub fn new(files: &FileDescriptorSet) -> DescriptorPool {
+        DescriptorPool::from_files(files.get_file())
+    }
+
+    /// Pool of given files, which must include all imported files.
+    pub fn from_files(files: &[FileDescriptorProto]) -> DescriptorPool {
+        let mut pool = DescriptorPool::default();
+        for file in files {
+            pool.add_file(file);
+        }
+        pool
+    }
+
+    /// Add messages, enums and services of a file.
+    ///
+    /// Types referenced by the file are resolved when messages are converted,
+    /// so files can be added in any order.
+    pub fn add_file(&mut self, file: &FileDescriptorProto) {
+        let package = file.get_package();
+        self.add_messages(package, file.get_message_type());
+        self.add_enums(package, file.get_enum_type());
+        for service in file.get_service() {
+            let service_name = qualified(package, service.get_name());
+            for method in service.get_method() {
+                let name = format!("/{}/{}", service_name, method.get_name());
+                let streaming = match (method.get_client_streaming(), method.get_server_streaming())
+                {
+                    (false, false) => GrpcStreaming::Unary,
+                    (true, false) => GrpcStreaming::ClientStreaming,
+                    (false, true) => GrpcStreaming::ServerStreaming,
+                    (true, true) => GrpcStreaming::Bidi,
+                };
+                self.methods.insert(
+                    name.clone(),
+                    DynamicMethod {
+                        name,
+                        input_type: method.get_input_type().trim_start_matches('.').to_owned(),
+                        output_type: method.get_output_type().trim_start_matches('.').to_owned(),
+                        streaming,
+                    },
+                );
+            }
+        }
+    }
+
+    /// Parse serialized `FileDescriptorSet`.
+    ///
+    /// Descriptors of imported files must be included,
+    /// e. g. with `protoc --include_imports --descriptor_set_out=...`.
+    pub fn parse(descriptor_set: &[u8]) -> grpc::Result<DescriptorPool> {
+        let files: FileDescriptorSet =
+            protobuf::parse_from_bytes(descriptor_set).map_err(protobuf_error)?;
+        Ok(DescriptorPool::new(&files))
+    }
+
+    fn add_messages(&mut self, prefix: &str, messages: &[DescriptorProto]) {
+        for message in messages {
+            let name = qualified(prefix, message.get_name());
+            self.add_messages(&name, message.get_nested_type());
+            self.add_enums(&name, message.get_enum_type());
+            self.messages.insert(name, message.clone());
+        }
+    }
+
+    fn add_enums(&mut self, prefix: &str, enums: &[EnumDescriptorProto]) {
+        for e in enums {
+            self.enums
+                .insert(qualified(prefix, e.get_name()), e.clone());
+        }
+    }
+
+    /// Method by full name, e. g. `/helloworld.Greeter/SayHello`.
+    pub fn method(&self, name: &str) -> Option<&DynamicMethod> {
+        self.methods.get(name)
+    }
+
+    /// All methods, in no particular order.
+    pub fn methods(&self) -> impl Iterator<Item = &DynamicMethod> {
+        self.methods.values()
+    }
+
+    /// Descriptor of a method with JSON request and response,
+    /// to be called with `grpc::Client`.
+    pub fn method_descriptor(
+        pool: &Arc<DescriptorPool>,
+        name: &str,
+    ) -> Option<MethodDescriptor<Value, Value>> {
+        let method = pool.method(name)?;
+        Some(MethodDescriptor {
+            name: method.name.clone().into(),
+            streaming: method.streaming,
+            req_marshaller: ArcOrStatic::Arc(Arc::new(MarshallerDynamic::new(
+                pool.clone(),
+                &method.input_type,
+            ))),
+            resp_marshaller: ArcOrStatic::Arc(Arc::new(MarshallerDynamic::new(
+                pool.clone(),
+                &method.output_type,
+            ))),
+        })
+    }
+
+    /// Descriptors of all methods of a service, e. g. `helloworld.Greeter`,
+    /// sorted by name.
+    ///
+    /// Can be used to serve a service without generated code
+    /// with `ServerMethod::new` and `ServerServiceDefinition::new`.
+    pub fn service_method_descriptors(
+        pool: &Arc<DescriptorPool>,
+        service: &str,
+    ) -> Vec<MethodDescriptor<Value, Value>> {
+        let prefix = format!("/{}/", service);
+        let mut names: Vec<&String> = pool
+            .methods
+            .keys()
+            .filter(|name| name.starts_with(&prefix))
+            .collect();
+        names.sort();
+        names
+            .into_iter()
+            .filter_map(|name| DescriptorPool::method_descriptor(pool, name))
+            .collect()
+    }
+
+    /// Whether message with given full name is known, e. g. `helloworld.HelloRequest`.
+    pub fn has_message(&self, type_name: &str) -> bool {
+        self.messages.contains_key(type_name)
+    }
+
+    fn message(&self, type_name: &str) -> grpc::Result<&DescriptorProto> {
+        self.messages
+            .get(type_name)
+            .ok_or_else(|| error(format!("unknown message type {}", type_name)))
+    }
+
+    fn is_map(&self, field: &FieldDescriptorProto) -> bool {
+        field.get_label() == FieldDescriptorProto_Label::LABEL_REPEATED
+            && field.get_field_type() == FieldDescriptorProto_Type::TYPE_MESSAGE
+            && self
+                .messages
+                .get(type_name(field))
+                .map_or(false, |m| m.get_options().get_map_entry())
+    }
+
+    /// Serialize message given in proto3 JSON mapping.
+    pub fn json_to_message(&self, type_name: &str, json: &Value) -> grpc::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        {
+            let mut os = CodedOutputStream::vec(&mut out);
+            self.write_message(type_name, json, &mut os)?;
+            os.flush().map_err(protobuf_error)?;
+        }
+        Ok(out)
+    }
+
+    /// Parse message into proto3 JSON mapping.
+    ///
+    /// Fields with default values are omitted, unknown fields are skipped.
+    pub fn message_to_json(&self, type_name: &str, message: &[u8]) -> grpc::Result<Value> {
+        self.read_message(type_name, message)
+    }
+
+    fn write_message(
+        &self,
+        message_type: &str,
+        json: &Value,
+        os: &mut CodedOutputStream,
+    ) -> grpc::Result<()> {
+        let descriptor = self.message(message_type)?;
+        let object = json
+            .as_object()
+            .ok_or_else(|| error(format!("expecting object for {}", message_type)))?;
+        for (key, value) in object {
+            let field = descriptor
+                .get_field()
+                .iter()
+                .find(|f| json_name(f) == key || f.get_name() == key)
+                .ok_or_else(|| error(format!("unknown field {} in {}", key, message_type)))?;
+            if value.is_null() {
+                continue;
+            }
+            if self.is_map(field) {
+                let entries = value
+                    .as_object()
+                    .ok_or_else(|| error(format!("expecting object for map field {}", key)))?;
+                let entry_type = self.message(type_name(field))?;
+                for (k, v) in entries {
+                    let mut entry = Map::new();
+                    entry.insert(
+                        json_name(&entry_type.get_field()[0]).to_owned(),
+                        k.clone().into(),
+                    );
+                    entry.insert(json_name(&entry_type.get_field()[1]).to_owned(), v.clone());
+                    self.write_field(field, &Value::Object(entry), os)?;
+                }
+            } else if field.get_label() == FieldDescriptorProto_Label::LABEL_REPEATED {
+                let items = value
+                    .as_array()
+                    .ok_or_else(|| error(format!("expecting array for field {}", key)))?;
+                for item in items {
+                    self.write_field(field, item, os)?;
+                }
+            } else {
+                self.write_field(field, value, os)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_field(
+        &self,
+        field: &FieldDescriptorProto,
+        value: &Value,
+        os: &mut CodedOutputStream,
+    ) -> grpc::Result<()> {
+        let number = field.get_number() as u32;
+        let invalid = || {
+            error(format!(
+                "invalid value {} for field {}",
+                value,
+                field.get_name()
+            ))
+        };
+        let r = match field.get_field_type() {
+            FieldDescriptorProto_Type::TYPE_DOUBLE => {
+                let v = json_f64(value).ok_or_else(invalid)?;
+                os.write_tag(number, WireType::WireTypeFixed64)
+                    .and_then(|_| os.write_raw_little_endian64(v.to_bits()))
+            }
+            FieldDescriptorProto_Type::TYPE_FLOAT => {
+                let v = json_f64(value).ok_or_else(invalid)? as f32;
+                os.write_tag(number, WireType::WireTypeFixed32)
+                    .and_then(|_| os.write_raw_little_endian32(v.to_bits()))
+            }
+            FieldDescriptorProto_Type::TYPE_INT64 | FieldDescriptorProto_Type::TYPE_INT32 => {
+                let v = json_i64(value).ok_or_else(invalid)?;
+                os.write_tag(number, WireType::WireTypeVarint)
+                    .and_then(|_| os.write_raw_varint64(v as u64))
+            }
+            FieldDescriptorProto_Type::TYPE_UINT64 | FieldDescriptorProto_Type::TYPE_UINT32 => {
+                let v = json_u64(value).ok_or_else(invalid)?;
+                os.write_tag(number, WireType::WireTypeVarint)
+                    .and_then(|_| os.write_raw_varint64(v))
+            }
+            FieldDescriptorProto_Type::TYPE_SINT64 | FieldDescriptorProto_Type::TYPE_SINT32 => {
+                let v = json_i64(value).ok_or_else(invalid)?;
+                os.write_tag(number, WireType::WireTypeVarint)
+                    .and_then(|_| os.write_raw_varint64(((v << 1) ^ (v >> 63)) as u64))
+            }
+            FieldDescriptorProto_Type::TYPE_FIXED64 => {
+                let v = json_u64(value).ok_or_else(invalid)?;
+                os.write_tag(number, WireType::WireTypeFixed64)
+                    .and_then(|_| os.write_raw_little_endian64(v))
+            }
+            FieldDescriptorProto_Type::TYPE_SFIXED64 => {
+                let v = json_i64(value).ok_or_else(invalid)?;
+                os.write_tag(number, WireType::WireTypeFixed64)
+                    .and_then(|_| os.write_raw_little_endian64(v as u64))
+            }
+            FieldDescriptorProto_Type::TYPE_FIXED32 => {
+                let v = json_u64(value).ok_or_else(invalid)?;
+                os.write_tag(number, WireType::WireTypeFixed32)
+                    .and_then(|_| os.write_raw_little_endian32(v as u32))
+            }
+            FieldDescriptorProto_Type::TYPE_SFIXED32 => {
+                let v = json_i64(value).ok_or_else(invalid)?;
+                os.write_tag(number, WireType::WireTypeFixed32)
+                    .and_then(|_| os.write_raw_little_endian32(v as i32 as u32))
+            }
+            FieldDescriptorProto_Type::TYPE_BOOL => {
+                // map keys are strings
+                let v = value
+                    .as_bool()
+                    .or_else(|| value.as_str().and_then(|s| s.parse().ok()))
+                    .ok_or_else(invalid)?;
+                os.write_tag(number, WireType::WireTypeVarint)
+                    .and_then(|_| os.write_raw_varint64(v as u64))
+            }
+            FieldDescriptorProto_Type::TYPE_STRING => {
+                let v = value.as_str().ok_or_else(invalid)?;
+                os.write_tag(number, WireType::WireTypeLengthDelimited)
+                    .and_then(|_| os.write_string_no_tag(v))
+            }
+            FieldDescriptorProto_Type::TYPE_BYTES => {
+                let v = value
+                    .as_str()
+                    .and_then(|s| base64::decode(s).ok())
+                    .ok_or_else(invalid)?;
+                os.write_tag(number, WireType::WireTypeLengthDelimited)
+                    .and_then(|_| os.write_bytes_no_tag(&v))
+            }
+            FieldDescriptorProto_Type::TYPE_ENUM => {
+                let v = match *value {
+                    Value::String(ref name) => self
+                        .enums
+                        .get(type_name(field))
+                        .and_then(|e| e.get_value().iter().find(|v| v.get_name() == name))
+                        .map(|v| v.get_number() as i64),
+                    _ => json_i64(value),
+                };
+                let v = v.ok_or_else(invalid)?;
+                os.write_tag(number, WireType::WireTypeVarint)
+                    .and_then(|_| os.write_raw_varint64(v as u64))
+            }
+            FieldDescriptorProto_Type::TYPE_MESSAGE => {
+                let mut nested = Vec::new();
+                {
+                    let mut nested_os = CodedOutputStream::vec(&mut nested);
+                    self.write_message(type_name(field), value, &mut nested_os)?;
+                    nested_os.flush().map_err(protobuf_error)?;
+                }
+                os.write_tag(number, WireType::WireTypeLengthDelimited)
+                    .and_then(|_| os.write_bytes_no_tag(&nested))
+            }
+            FieldDescriptorProto_Type::TYPE_GROUP => {
+                return Err(error(format!(
+                    "group field {} is not supported",
+                    field.get_name()
+                )));
+            }
+        };
+        r.map_err(protobuf_error)
+    }
+
+    fn read_message(&self, message_type: &str, message: &[u8]) -> grpc::Result<Value> {
+        let descriptor = self.message(message_type)?;
+        let mut object = Map::new();
+        let mut is = CodedInputStream::from_bytes(message);
+        while !is.eof().map_err(protobuf_error)? {
+            let (number, wire_type) = is.read_tag_unpack().map_err(protobuf_error)?;
+            let field = match descriptor
+                .get_field()
+                .iter()
+                .find(|f| f.get_number() as u32 == number)
+            {
+                Some(field) => field,
+                None => {
+                    skip_field(&mut is, wire_type)?;
+                    continue;
+                }
+            };
+            let key = json_name(field).to_owned();
+
+            if self.is_map(field) {
+                let entry = self.read_value(field, &mut is)?;
+                let k = match entry.get("key") {
+                    Some(&Value::String(ref k)) => k.clone(),
+                    Some(k) => k.to_string(),
+                    // default key
+                    None => String::new(),
+                };
+                let v = entry.get("value").cloned().unwrap_or(Value::Null);
+                let map = object
+                    .entry(key)
+                    .or_insert_with(|| Value::Object(Map::new()));
+                if let Value::Object(ref mut map) = *map {
+                    map.insert(k, v);
+                }
+            } else if field.get_label() == FieldDescriptorProto_Label::LABEL_REPEATED {
+                let mut items = Vec::new();
+                if wire_type == WireType::WireTypeLengthDelimited && is_packable(field) {
+                    let packed = is.read_bytes().map_err(protobuf_error)?;
+                    let mut packed_is = CodedInputStream::from_bytes(&packed);
+                    while !packed_is.eof().map_err(protobuf_error)? {
+                        items.push(self.read_value(field, &mut packed_is)?);
+                    }
+                } else {
+                    items.push(self.read_value(field, &mut is)?);
+                }
+                let array = object
+                    .entry(key)
+                    .or_insert_with(|| Value::Array(Vec::new()));
+                if let Value::Array(ref mut array) = *array {
+                    array.extend(items);
+                }
+            } else {
+                let value = self.read_value(field, &mut is)?;
+                object.insert(key, value);
+            }
+        }
+        Ok(Value::Object(object))
+    }
+
+    fn read_value(
+        &self,
+        field: &FieldDescriptorProto,
+        is: &mut CodedInputStream,
+    ) -> grpc::Result<Value> {
+        let value = match field.get_field_type() {
+            FieldDescriptorProto_Type::TYPE_DOUBLE => {
+                f64::from_bits(is.read_raw_little_endian64().map_err(protobuf_error)?).into()
+            }
+            FieldDescriptorProto_Type::TYPE_FLOAT => {
+                (f32::from_bits(is.read_raw_little_endian32().map_err(protobuf_error)?) as f64)
+                    .into()
+            }
+            // 64-bit integers are strings in proto3 JSON mapping
+            FieldDescriptorProto_Type::TYPE_INT64 => {
+                (is.read_raw_varint64().map_err(protobuf_error)? as i64)
+                    .to_string()
+                    .into()
+            }
+            FieldDescriptorProto_Type::TYPE_UINT64 => is
+                .read_raw_varint64()
+                .map_err(protobuf_error)?
+                .to_string()
+                .into(),
+            FieldDescriptorProto_Type::TYPE_SINT64 => {
+                zigzag(is.read_raw_varint64().map_err(protobuf_error)?)
+                    .to_string()
+                    .into()
+            }
+            FieldDescriptorProto_Type::TYPE_FIXED64 => is
+                .read_raw_little_endian64()
+                .map_err(protobuf_error)?
+                .to_string()
+                .into(),
+            FieldDescriptorProto_Type::TYPE_SFIXED64 => {
+                (is.read_raw_little_endian64().map_err(protobuf_error)? as i64)
+                    .to_string()
+                    .into()
+            }
+            FieldDescriptorProto_Type::TYPE_INT32 => {
+                (is.read_raw_varint64().map_err(protobuf_error)? as i32).into()
+            }
+            FieldDescriptorProto_Type::TYPE_UINT32 => {
+                (is.read_raw_varint64().map_err(protobuf_error)? as u32).into()
+            }
+            FieldDescriptorProto_Type::TYPE_SINT32 => {
+                (zigzag(is.read_raw_varint64().map_err(protobuf_error)?) as i32).into()
+            }
+            FieldDescriptorProto_Type::TYPE_FIXED32 => is
+                .read_raw_little_endian32()
+                .map_err(protobuf_error)?
+                .into(),
+            FieldDescriptorProto_Type::TYPE_SFIXED32 => {
+                (is.read_raw_little_endian32().map_err(protobuf_error)? as i32).into()
+            }
+            FieldDescriptorProto_Type::TYPE_BOOL => {
+                (is.read_raw_varint64().map_err(protobuf_error)? != 0).into()
+            }
+            FieldDescriptorProto_Type::TYPE_STRING => {
+                is.read_string().map_err(protobuf_error)?.into()
+            }
+            FieldDescriptorProto_Type::TYPE_BYTES => {
+                base64::encode(&is.read_bytes().map_err(protobuf_error)?).into()
+            }
+            FieldDescriptorProto_Type::TYPE_ENUM => {
+                let number = is.read_raw_varint64().map_err(protobuf_error)? as i32;
+                let name = self
+                    .enums
+                    .get(type_name(field))
+                    .and_then(|e| e.get_value().iter().find(|v| v.get_number() == number))
+                    .map(|v| v.get_name().to_owned());
+                match name {
+                    Some(name) => name.into(),
+                    // value added in newer version of the enum
+                    None => number.into(),
+                }
+            }
+            FieldDescriptorProto_Type::TYPE_MESSAGE => {
+                let nested = is.read_bytes().map_err(protobuf_error)?;
+                self.read_message(type_name(field), &nested)?
+            }
+            FieldDescriptorProto_Type::TYPE_GROUP => {
+                return Err(error(format!(
+                    "group field {} is not supported",
+                    field.get_name()
+                )));
+            }
+        };
+        Ok(value)
+    }
+}
+
+/// Repeated fields of these types may be encoded as packed.
+fn is_packable(field: &FieldDescriptorProto) -> bool {
+    match field.get_field_type() {
+        FieldDescriptorProto_Type::TYPE_STRING
+        | FieldDescriptorProto_Type::TYPE_BYTES
+        | FieldDescriptorProto_Type::TYPE_MESSAGE
+        | FieldDescriptorProto_Type::TYPE_GROUP => false,
+        _ => true,
+    }
+}
+
+fn zigzag(n: u64) -> i64 {
+    ((n >> 1) as i64) ^ -((n & 1) as i64)
+}
+
+fn skip_field(is: &mut CodedInputStream, wire_type: WireType) -> grpc::Result<()> {
+    match wire_type {
+        WireType::WireTypeVarint => is.read_raw_varint64().map(|_| ()),
+        WireType::WireTypeFixed64 => is.read_raw_little_endian64().map(|_| ()),
+        WireType::WireTypeFixed32 => is.read_raw_little_endian32().map(|_| ()),
+        WireType::WireTypeLengthDelimited => is.read_bytes().map(|_| ()),
+        WireType::WireTypeStartGroup | WireType::WireTypeEndGroup => {
+            return Err(error("groups are not supported".to_owned()));
+        }
+    }
+    .map_err(protobuf_error)
+}
+
+/// Integers may be given as JSON numbers or strings.
+fn json_i64(value: &Value) -> Option<i64> {
+    match *value {
+        Value::Number(ref n) => n.as_i64(),
+        Value::String(ref s) => s.parse().ok(),
+        _ => None,
+    }
+}
+
+fn json_u64(value: &Value) -> Option<u64> {
+    match *value {
+        Value::Number(ref n) => n.as_u64(),
+        Value::String(ref s) => s.parse().ok(),
+        _ => None,
+    }
+}
+
+fn json_f64(value: &Value) -> Option<f64> {
+    match *value {
+        Value::Number(ref n) => n.as_f64(),
+        Value::String(ref s) => match &s[..] {
+            "NaN" => Some(std::f64::NAN),
+            "Infinity" => Some(std::f64::INFINITY),
+            "-Infinity" => Some(std::f64::NEG_INFINITY),
+            s => s.parse().ok(),
+        },
+        _ => None,
+    }
+}
+
+/// Marshaller of messages of given type as proto3 JSON.
+pub struct MarshallerDynamic {
+    pool: Arc<DescriptorPool>,
+    type_name: String,
+}
+
+impl MarshallerDynamic {
+    /// Marshaller of message with given full name, e. g. `helloworld.HelloRequest`.
+    pub fn new(pool: Arc<DescriptorPool>, type_name: &str) -> MarshallerDynamic {
+        MarshallerDynamic {
+            pool,
+            type_name: type_name.to_owned(),
+        }
+    }
+}
+
+impl Marshaller<Value> for MarshallerDynamic {
+    fn write(&self, m: &Value) -> grpc::Result<Vec<u8>> {
+        self.pool.json_to_message(&self.type_name, m)
+    }
+
+    fn read(&self, buf: Bytes) -> grpc::Result<Value> {
+        self.pool.message_to_json(&self.type_name, &buf)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use protobuf::descriptor::MethodDescriptorProto;
+    use protobuf::descriptor::ServiceDescriptorProto;
+    use protobuf::RepeatedField;
+
+    fn field(
+        name: &str,
+        number: i32,
+        field_type: FieldDescriptorProto_Type,
+        label: FieldDescriptorProto_Label,
+    ) -> FieldDescriptorProto {
+        let mut field = FieldDescriptorProto::new();
+        field.set_name(name.to_owned());
+        field.set_json_name(name.to_owned());
+        field.set_number(number);
+        field.set_field_type(field_type);
+        field.set_label(label);
+        field
+    }
+
+    /// `message Foo { string name = 1; repeated sint64 ids = 2; Foo child = 3; }`
+    fn file() -> FileDescriptorProto {
+        let mut child = field(
+            "child",
+            3,
+            FieldDescriptorProto_Type::TYPE_MESSAGE,
+            FieldDescriptorProto_Label::LABEL_OPTIONAL,
+        );
+        child.set_type_name(".test.Foo".to_owned());
+        let mut foo = DescriptorProto::new();
+        foo.set_name("Foo".to_owned());
+        foo.set_field(RepeatedField::from_vec(vec![
+            field(
+                "name",
+                1,
+                FieldDescriptorProto_Type::TYPE_STRING,
+                FieldDescriptorProto_Label::LABEL_OPTIONAL,
+            ),
+            field(
+                "ids",
+                2,
+                FieldDescriptorProto_Type::TYPE_SINT64,
+                FieldDescriptorProto_Label::LABEL_REPEATED,
+            ),
+            child,
+        ]));
+        let mut file = FileDescriptorProto::new();
+        file.set_package("test".to_owned());
+        file.set_message_type(RepeatedField::from_vec(vec![foo]));
+        file
+    }
+
+    fn pool() -> DescriptorPool {
+        let mut files = FileDescriptorSet::new();
+        files.set_file(RepeatedField::from_vec(vec![file()]));
+        DescriptorPool::new(&files)
+    }
+
+    #[test]
+    fn round_trip() {
+        let pool = pool();
+        let json: Value =
+            serde_json::from_str(r#"{"name": "a", "ids": ["-1", "2"], "child": {"name": "b"}}"#)
+                .unwrap();
+        let message = pool.json_to_message("test.Foo", &json).unwrap();
+        assert_eq!(json, pool.message_to_json("test.Foo", &message).unwrap());
+    }
+
+    #[test]
+    fn unknown_field() {
+        let pool = pool();
+        let json: Value = serde_json::from_str(r#"{"nope": 1}"#).unwrap();
+        assert!(pool.json_to_message("test.Foo", &json).is_err());
+    }
+
+    #[test]
+    fn method_descriptors() {
+        let method = |name: &str, client_streaming| {
+            let mut method = MethodDescriptorProto::new();
+            method.set_name(name.to_owned());
+            method.set_input_type(".test.Foo".to_owned());
+            method.set_output_type(".test.Foo".to_owned());
+            method.set_client_streaming(client_streaming);
+            method
+        };
+        let mut service = ServiceDescriptorProto::new();
+        service.set_name("Svc".to_owned());
+        service.set_method(RepeatedField::from_vec(vec![
+            method("Upload", true),
+            method("Get", false),
+        ]));
+        let mut file = file();
+        file.set_service(RepeatedField::from_vec(vec![service]));
+
+        let pool = Arc::new(DescriptorPool::from_files(&[file]));
+        assert!(pool.has_message("test.Foo"));
+
+        let methods = DescriptorPool::service_method_descriptors(&pool, "test.Svc");
+        let names: Vec<&str> = methods.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(vec!["/test.Svc/Get", "/test.Svc/Upload"], names);
+        assert_eq!(GrpcStreaming::Unary, methods[0].streaming);
+        assert_eq!(GrpcStreaming::ClientStreaming, methods[1].streaming);
+    }
+}
@@ -1,8 +1,15 @@
 //! Implementation of marshaller for protobuf parameter types.
 
+#[cfg(feature = "with-serde")]
+extern crate base64;
 extern crate bytes;
 extern crate grpc;
 extern crate protobuf;
+#[cfg(feature = "with-serde")]
+extern crate serde_json;
+
+#[cfg(feature = "with-serde")]
+pub mod dynamic;
 
 use bytes::Bytes;
 
@@ -16,17 +23,30 @@ pub struct MarshallerProtobuf;
 impl<M: Message> Marshaller<M> for MarshallerProtobuf {
     fn write(&self, m: &M) -> grpc::Result<Vec<u8>> {
         m.write_to_bytes()
-            .map_err(|e| grpc::Error::Marshaller(Box::new(e)))
+            .map_err(|e| grpc::Error::Protocol(grpc::ProtocolError::Marshaller(Box::new(e))))
+    }
+
+    fn write_to_vec(&self, m: &M, out: &mut Vec<u8>) -> grpc::Result<()> {
+        m.write_to_vec(out)
+            .map_err(|e| grpc::Error::Protocol(grpc::ProtocolError::Marshaller(Box::new(e))))
     }
 
     fn read(&self, buf: Bytes) -> grpc::Result<M> {
-        // TODO: make protobuf simple
-        let mut is = CodedInputStream::from_carllerche_bytes(&buf);
         let mut r: M = M::new();
-        r.merge_from(&mut is)
-            .map_err(|e| grpc::Error::Marshaller(Box::new(e)))?;
+        if !buf.is_empty() {
+            // TODO: make protobuf simple
+            let mut is = CodedInputStream::from_carllerche_bytes(&buf);
+            r.merge_from(&mut is)
+                .map_err(|e| grpc::Error::Protocol(grpc::ProtocolError::Marshaller(Box::new(e))))?;
+        }
         r.check_initialized()
-            .map_err(|e| grpc::Error::Marshaller(Box::new(e)))?;
+            .map_err(|e| grpc::Error::Protocol(grpc::ProtocolError::Marshaller(Box::new(e))))?;
         Ok(r)
     }
+
+    /// Messages of types without fields, e. g. `google.protobuf.Empty`,
+    /// are empty unless they carry unknown fields.
+    fn is_empty(&self, m: &M) -> bool {
+        m.descriptor().fields().is_empty() && m.get_unknown_fields().iter().next().is_none()
+    }
 }
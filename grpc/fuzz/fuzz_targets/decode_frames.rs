@@ -0,0 +1,48 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate bytes;
+extern crate grpc;
+
+use bytes::Bytes;
+
+use grpc::framing::decode_frames;
+use grpc::framing::GrpcFrameDecoder;
+
+fuzz_target!(|data: &[u8]| {
+    let frames = decode_frames(data);
+
+    // decoding the same input pushed in two chunks must give the same result
+    let split = data
+        .first()
+        .map(|&b| b as usize % (data.len() + 1))
+        .unwrap_or(0);
+    let mut decoder = GrpcFrameDecoder::new();
+    let mut chunked = Vec::new();
+    let mut result = Ok(());
+    for chunk in &[&data[..split], &data[split..]] {
+        decoder.push(Bytes::from(*chunk));
+        loop {
+            match decoder.next_frame() {
+                Ok(Some(frame)) => chunked.push(frame),
+                Ok(None) => break,
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+        if result.is_err() {
+            break;
+        }
+    }
+    let result = result.and_then(|()| decoder.finish());
+
+    match frames {
+        Ok(frames) => {
+            assert!(result.is_ok());
+            assert_eq!(frames, chunked);
+        }
+        Err(_) => assert!(result.is_err()),
+    }
+});
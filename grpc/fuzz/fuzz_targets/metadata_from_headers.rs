@@ -0,0 +1,35 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate bytes;
+extern crate grpc;
+extern crate httpbis;
+
+use bytes::Bytes;
+
+use grpc::Metadata;
+
+// Input is a list of `name:value` lines. HPACK decoding itself is done by `httpbis`
+// and should be fuzzed there, so headers are constructed from decoded strings.
+fuzz_target!(|data: &[u8]| {
+    let headers = data
+        .split(|&b| b == b'\n')
+        .filter_map(|line| {
+            let colon = line.iter().skip(1).position(|&b| b == b':')? + 1;
+            let name = String::from_utf8_lossy(&line[..colon]).to_ascii_lowercase();
+            let value = Bytes::from(&line[colon + 1..]);
+            Some(httpbis::Header::new(Bytes::from(name), value))
+        })
+        .collect();
+
+    if let Ok(metadata) = Metadata::from_headers(httpbis::Headers::from_vec(headers)) {
+        // encoding of decoded metadata must decode to the same entries
+        let entries = metadata.entries.clone();
+        let again = Metadata::from_headers(metadata.into_headers()).expect("round trip");
+        assert_eq!(entries.len(), again.entries.len());
+        for (a, b) in entries.iter().zip(&again.entries) {
+            assert_eq!(a.key.as_str(), b.key.as_str());
+            assert_eq!(a.value, b.value);
+        }
+    }
+});
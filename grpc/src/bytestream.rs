@@ -0,0 +1,189 @@
+//! Chunked byte streams over streaming methods, similar to `google.bytestream`.
+//!
+//! Server streaming method sends data with `ByteStreamReader`,
+//! client streaming method receives it with `ByteStreamWriter`, or vice versa.
+//! Messages are mapped to and from chunks with `Stream::map`.
+
+use std::io;
+
+use bytes::Bytes;
+
+use futures::Async;
+use futures::Future;
+use futures::Poll;
+use futures::Stream;
+
+use tokio_io::AsyncRead;
+use tokio_io::AsyncWrite;
+
+use error::Error;
+
+const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Stream of chunks read from `AsyncRead`.
+///
+/// Each chunk except the last one is exactly `chunk_size` bytes,
+/// empty input produces no chunks.
+pub struct ByteStreamReader<R> {
+    read: R,
+    buf: Vec<u8>,
+    filled: usize,
+    eof: bool,
+}
+
+impl<R: AsyncRead> ByteStreamReader<R> {
+    pub fn new(read: R) -> ByteStreamReader<R> {
+        ByteStreamReader {
+            read,
+            buf: vec![0; DEFAULT_CHUNK_SIZE],
+            filled: 0,
+            eof: false,
+        }
+    }
+
+    /// Size of data in a single message, 64 KiB by default.
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.buf.resize(chunk_size.max(1), 0);
+        self
+    }
+
+    fn take_chunk(&mut self) -> Bytes {
+        let chunk = Bytes::from(&self.buf[..self.filled]);
+        self.filled = 0;
+        chunk
+    }
+}
+
+impl<R: AsyncRead> Stream for ByteStreamReader<R> {
+    type Item = Bytes;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Bytes>, Error> {
+        loop {
+            if self.eof {
+                if self.filled == 0 {
+                    return Ok(Async::Ready(None));
+                }
+                return Ok(Async::Ready(Some(self.take_chunk())));
+            }
+
+            let filled = self.filled;
+            match try_ready!(self.read.poll_read(&mut self.buf[filled..])) {
+                0 => self.eof = true,
+                len => self.filled += len,
+            }
+
+            if self.filled == self.buf.len() {
+                return Ok(Async::Ready(Some(self.take_chunk())));
+            }
+        }
+    }
+}
+
+/// Future which writes all chunks of the stream to `AsyncWrite`.
+///
+/// Resolves to the writer and the number of bytes written
+/// after the stream ends and the writer is flushed.
+pub struct ByteStreamWriter<S, W> {
+    stream: S,
+    write: Option<W>,
+    chunk: Bytes,
+    written: u64,
+}
+
+impl<S, W> ByteStreamWriter<S, W>
+where
+    S: Stream<Item = Bytes, Error = Error>,
+    W: AsyncWrite,
+{
+    pub fn new(stream: S, write: W) -> ByteStreamWriter<S, W> {
+        ByteStreamWriter {
+            stream,
+            write: Some(write),
+            chunk: Bytes::new(),
+            written: 0,
+        }
+    }
+}
+
+impl<S, W> Future for ByteStreamWriter<S, W>
+where
+    S: Stream<Item = Bytes, Error = Error>,
+    W: AsyncWrite,
+{
+    type Item = (W, u64);
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<(W, u64), Error> {
+        loop {
+            {
+                let write = self.write.as_mut().expect("poll after completion");
+                while !self.chunk.is_empty() {
+                    let len = try_ready!(write.poll_write(&self.chunk));
+                    if len == 0 {
+                        return Err(io::Error::new(
+                            io::ErrorKind::WriteZero,
+                            "write zero bytes into writer",
+                        )
+                        .into());
+                    }
+                    self.chunk.advance(len);
+                    self.written += len as u64;
+                }
+            }
+
+            match try_ready!(self.stream.poll()) {
+                Some(chunk) => self.chunk = chunk,
+                None => {
+                    try_ready!(self.write.as_mut().unwrap().poll_flush());
+                    let write = self.write.take().unwrap();
+                    return Ok(Async::Ready((write, self.written)));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::Cursor;
+
+    use futures::stream;
+
+    #[test]
+    fn chunks() {
+        let chunks = ByteStreamReader::new(Cursor::new(b"abcdefghij".to_vec()))
+            .chunk_size(4)
+            .collect()
+            .wait()
+            .unwrap();
+        assert_eq!(
+            vec![
+                Bytes::from_static(b"abcd"),
+                Bytes::from_static(b"efgh"),
+                Bytes::from_static(b"ij"),
+            ],
+            chunks
+        );
+
+        let chunks = ByteStreamReader::new(Cursor::new(Vec::new()))
+            .collect()
+            .wait()
+            .unwrap();
+        assert!(chunks.is_empty());
+    }
+
+    #[test]
+    fn write_chunks() {
+        let chunks = stream::iter_ok(vec![
+            Bytes::from_static(b"abc"),
+            Bytes::new(),
+            Bytes::from_static(b"de"),
+        ]);
+        let (write, written) = ByteStreamWriter::new(chunks, Vec::new()).wait().unwrap();
+        assert_eq!(b"abcde".to_vec(), write);
+        assert_eq!(5, written);
+    }
+}
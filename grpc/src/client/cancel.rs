@@ -0,0 +1,144 @@
+//! Cancellation of calls which response is dropped before it is complete.
+
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use bytes::Bytes;
+
+use futures::Future;
+use futures::Poll;
+use futures::Stream;
+
+use httpbis;
+use httpbis::ErrorCode;
+
+use error::Error;
+use resp::StreamingResponse;
+use result;
+use stream_item::GrpcStreamWithTrailingMetadata;
+
+/// Request side of HTTP/2 stream of a call, shared by request sink
+/// and response, so the stream can be reset when response is dropped.
+#[derive(Clone)]
+pub(crate) struct SharedClientRequest(Arc<Mutex<httpbis::ClientRequest>>);
+
+impl SharedClientRequest {
+    pub fn new(req: httpbis::ClientRequest) -> SharedClientRequest {
+        SharedClientRequest(Arc::new(Mutex::new(req)))
+    }
+
+    pub fn state(&self) -> httpbis::SenderState {
+        self.0.lock().unwrap().state()
+    }
+
+    pub fn poll(&mut self) -> Poll<(), httpbis::StreamDead> {
+        self.0.lock().unwrap().poll()
+    }
+
+    pub fn send_data(&mut self, data: Bytes) -> result::Result<()> {
+        self.0.lock().unwrap().send_data(data)?;
+        Ok(())
+    }
+
+    pub fn close(&mut self) -> result::Result<()> {
+        self.0.lock().unwrap().close()?;
+        Ok(())
+    }
+
+    /// Reset the stream with `CANCEL`, so server stops handling the call.
+    fn cancel(&self) {
+        if let Err(e) = self.0.lock().unwrap().reset(ErrorCode::Cancel) {
+            debug!("failed to cancel call: {:?}", e);
+        }
+    }
+}
+
+/// Set when server finished the response stream: sent trailers or reset
+/// the stream, or when the connection failed, so the stream needs no reset.
+#[derive(Clone, Default)]
+pub(crate) struct PeerFinished(Arc<AtomicBool>);
+
+impl PeerFinished {
+    pub fn set(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    fn is_set(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Resets the stream when dropped, unless server finished the response.
+///
+/// Response failed with an error produced by the client, e. g.
+/// `DEADLINE_EXCEEDED` or too large message, is not finished by server,
+/// so the stream is reset too.
+struct CancelGuard {
+    req: SharedClientRequest,
+    peer_finished: PeerFinished,
+}
+
+impl Drop for CancelGuard {
+    fn drop(&mut self) {
+        if !self.peer_finished.is_set() {
+            debug!("response dropped before server finished it, canceling call");
+            self.req.cancel();
+        }
+    }
+}
+
+/// Future or stream which holds `CancelGuard` while it is alive.
+struct WithCancelGuard<S> {
+    inner: S,
+    _guard: Arc<CancelGuard>,
+}
+
+impl<F: Future<Error = Error>> Future for WithCancelGuard<F> {
+    type Item = F::Item;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<F::Item, Error> {
+        self.inner.poll()
+    }
+}
+
+impl<S: Stream<Error = Error>> Stream for WithCancelGuard<S> {
+    type Item = S::Item;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<S::Item>, Error> {
+        self.inner.poll()
+    }
+}
+
+/// Cancel the call when response is dropped before it is complete,
+/// e. g. when another hedged call completes first, or after it failed
+/// with an error produced by the client.
+///
+/// `peer_finished` is set by the response stream, see `http_response_to_grpc_frames`.
+///
+/// Request sink does not keep the call alive: dropping the response
+/// of a streaming call cancels it even if request sink is still in use.
+pub(crate) fn response_with_cancel_on_drop<Resp: Send + 'static>(
+    resp: StreamingResponse<Resp>,
+    req: SharedClientRequest,
+    peer_finished: PeerFinished,
+) -> StreamingResponse<Resp> {
+    let guard = Arc::new(CancelGuard { req, peer_finished });
+    let stream_guard = guard.clone();
+    StreamingResponse::new(
+        WithCancelGuard {
+            inner: resp.0,
+            _guard: guard,
+        }
+        .map(move |(metadata, stream)| {
+            let stream = WithCancelGuard {
+                inner: stream.0,
+                _guard: stream_guard,
+            };
+            (metadata, GrpcStreamWithTrailingMetadata::new(stream))
+        }),
+    )
+}
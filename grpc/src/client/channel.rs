@@ -0,0 +1,55 @@
+//! Connection shared by client stubs.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use client::Client;
+use client_stub::ClientStub;
+
+/// Client connection which can be shared by stubs of several services.
+///
+/// All stubs created from one channel (and its clones) send their calls
+/// over the same HTTP/2 connection.
+///
+/// ```ignore
+/// let channel = ClientBuilder::new("::1", 50051).build_channel()?;
+/// let greeter: GreeterClient = channel.stub();
+/// let route_guide: RouteGuideClient = channel.stub();
+/// ```
+#[derive(Debug, Clone)]
+pub struct Channel {
+    client: Arc<Client>,
+}
+
+impl Channel {
+    pub fn new(client: Client) -> Channel {
+        Channel::from(Arc::new(client))
+    }
+
+    /// Underlying client, which can be used to make calls without generated stubs.
+    pub fn client(&self) -> &Arc<Client> {
+        &self.client
+    }
+
+    /// Addresses of connections of this channel, see `Client::peer_addrs`.
+    pub fn peer_addrs(&self) -> Vec<SocketAddr> {
+        self.client.peer_addrs()
+    }
+
+    /// Create a stub of generated client using this channel.
+    pub fn stub<C: ClientStub>(&self) -> C {
+        C::with_channel(self)
+    }
+}
+
+impl From<Client> for Channel {
+    fn from(client: Client) -> Channel {
+        Channel::new(client)
+    }
+}
+
+impl From<Arc<Client>> for Channel {
+    fn from(client: Arc<Client>) -> Channel {
+        Channel { client }
+    }
+}
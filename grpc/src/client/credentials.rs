@@ -0,0 +1,236 @@
+//! Per-call credentials.
+
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use bytes::Bytes;
+use futures::future;
+use futures::future::Future;
+use futures::future::Shared;
+
+use error::Error;
+use error::Status;
+use futures_grpc::GrpcFuture;
+use proto::grpc_status::GrpcStatus;
+use Metadata;
+use MetadataKey;
+
+/// Call for which credentials are requested.
+#[derive(Debug, Clone)]
+pub struct CallCredentialsContext<'a> {
+    /// Server `host:port`.
+    pub authority: &'a str,
+    /// Full method name, e. g. `/helloworld.Greeter/SayHello`.
+    pub method: &'a str,
+}
+
+impl<'a> CallCredentialsContext<'a> {
+    /// URL of the called service like `https://example.com/helloworld.Greeter`,
+    /// used as audience of JWT tokens.
+    pub fn service_url(&self) -> String {
+        let service = match self.method.rfind('/') {
+            Some(pos) => &self.method[..pos],
+            None => self.method,
+        };
+        format!("https://{}{}", self.authority, service)
+    }
+}
+
+/// Credentials attached to each call as metadata, e. g. OAuth2 token.
+///
+/// Registered with `ClientBuilder::call_credentials`.
+/// Metadata is requested before headers of each call are sent,
+/// and the call fails if the returned future fails.
+pub trait CallCredentials: Send + Sync + 'static {
+    fn get_metadata(&self, context: &CallCredentialsContext) -> GrpcFuture<Metadata>;
+
+    /// Allow sending credentials over connection without TLS.
+    ///
+    /// Calls with credentials over plain connection fail by default,
+    /// so tokens cannot be intercepted.
+    fn allow_insecure(&self) -> bool {
+        false
+    }
+}
+
+/// Credentials which send given OAuth2 access token
+/// in `authorization: Bearer <token>` header.
+#[derive(Debug, Clone)]
+pub struct BearerTokenCredentials {
+    token: String,
+}
+
+impl BearerTokenCredentials {
+    pub fn new(token: &str) -> BearerTokenCredentials {
+        BearerTokenCredentials {
+            token: token.to_owned(),
+        }
+    }
+}
+
+/// Metadata with `authorization` header for given bearer token.
+pub(crate) fn bearer_token_metadata(token: &str) -> Metadata {
+    let mut metadata = Metadata::new();
+    metadata.add(
+        MetadataKey::from("authorization"),
+        Bytes::from(format!("Bearer {}", token)),
+    );
+    metadata
+}
+
+impl CallCredentials for BearerTokenCredentials {
+    fn get_metadata(&self, _context: &CallCredentialsContext) -> GrpcFuture<Metadata> {
+        Box::new(future::ok(bearer_token_metadata(&self.token)))
+    }
+}
+
+/// Metadata with time after which it is no longer valid.
+#[derive(Debug, Clone)]
+pub struct ExpiringMetadata {
+    pub metadata: Metadata,
+    /// `None` means metadata never expires.
+    pub expires_at: Option<Instant>,
+}
+
+/// Fetch in progress, error is converted to string, because shared error must be `Sync`
+type Fetching = Shared<Box<Future<Item = ExpiringMetadata, Error = String> + Send>>;
+
+enum CacheState {
+    Empty,
+    Fetching(Fetching),
+    Ready(ExpiringMetadata),
+}
+
+/// Credentials which fetch metadata with given function and reuse it until it expires.
+///
+/// Metadata is fetched again when it is about to expire.
+/// Concurrent calls wait for the same fetch, and failed fetch is retried
+/// by the next call. Cached metadata is shared by all calls, so the fetch
+/// function should not depend on the called method.
+pub struct CachingCallCredentials<F> {
+    fetch: F,
+    refresh_before: Duration,
+    state: Arc<Mutex<CacheState>>,
+}
+
+impl<F> CachingCallCredentials<F>
+where
+    F: Fn(&CallCredentialsContext) -> GrpcFuture<ExpiringMetadata> + Send + Sync + 'static,
+{
+    pub fn new(fetch: F) -> CachingCallCredentials<F> {
+        CachingCallCredentials {
+            fetch,
+            refresh_before: Duration::from_secs(60),
+            state: Arc::new(Mutex::new(CacheState::Empty)),
+        }
+    }
+
+    /// Fetch new metadata this long before cached metadata expires (default is one minute).
+    pub fn refresh_before(mut self, refresh_before: Duration) -> Self {
+        self.refresh_before = refresh_before;
+        self
+    }
+}
+
+impl<F> CallCredentials for CachingCallCredentials<F>
+where
+    F: Fn(&CallCredentialsContext) -> GrpcFuture<ExpiringMetadata> + Send + Sync + 'static,
+{
+    fn get_metadata(&self, context: &CallCredentialsContext) -> GrpcFuture<Metadata> {
+        let mut state = self.state.lock().unwrap();
+
+        if let CacheState::Ready(ref cached) = *state {
+            let fresh = cached
+                .expires_at
+                .map_or(true, |t| Instant::now() + self.refresh_before < t);
+            if fresh {
+                return Box::new(future::ok(cached.metadata.clone()));
+            }
+        }
+
+        let fetching = match *state {
+            CacheState::Fetching(ref fetching) => fetching.clone(),
+            CacheState::Empty | CacheState::Ready(..) => {
+                let fetch: Box<Future<Item = _, Error = _> + Send> =
+                    Box::new((self.fetch)(context).map_err(|e| e.to_string()));
+                let fetching = fetch.shared();
+                *state = CacheState::Fetching(fetching.clone());
+                fetching
+            }
+        };
+
+        let state = self.state.clone();
+        Box::new(fetching.then(move |r| {
+            let mut state = state.lock().unwrap();
+            match r {
+                Ok(fetched) => {
+                    *state = CacheState::Ready((*fetched).clone());
+                    Ok(fetched.metadata.clone())
+                }
+                Err(e) => {
+                    *state = CacheState::Empty;
+                    Err(Error::Status(Status::new(
+                        GrpcStatus::Unauthenticated,
+                        format!("failed to fetch credentials: {}", &*e),
+                    )))
+                }
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+
+    fn context() -> CallCredentialsContext<'static> {
+        CallCredentialsContext {
+            authority: "example.com:443",
+            method: "/helloworld.Greeter/SayHello",
+        }
+    }
+
+    #[test]
+    fn service_url() {
+        assert_eq!(
+            "https://example.com:443/helloworld.Greeter",
+            context().service_url()
+        );
+    }
+
+    #[test]
+    fn caching() {
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let fetches_copy = fetches.clone();
+        let credentials = CachingCallCredentials::new(move |_: &CallCredentialsContext| {
+            let n = fetches_copy.fetch_add(1, Ordering::SeqCst);
+            let metadata = bearer_token_metadata(&n.to_string());
+            let r: GrpcFuture<_> = Box::new(future::ok(ExpiringMetadata {
+                metadata,
+                // first token is about to expire
+                expires_at: Some(Instant::now() + Duration::from_secs(n as u64 * 3600)),
+            }));
+            r
+        });
+
+        let token = |credentials: &CachingCallCredentials<_>| {
+            credentials
+                .get_metadata(&context())
+                .wait()
+                .unwrap()
+                .get("authorization")
+                .unwrap()
+                .to_vec()
+        };
+
+        assert_eq!(b"Bearer 0".to_vec(), token(&credentials));
+        assert_eq!(b"Bearer 1".to_vec(), token(&credentials));
+        assert_eq!(b"Bearer 1".to_vec(), token(&credentials));
+        assert_eq!(2, fetches.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,65 @@
+//! Event loop threads shared by connections of a client.
+
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc;
+use std::thread;
+
+use futures::sync::oneshot;
+use tokio_core::reactor::Core;
+use tokio_core::reactor::Remote;
+
+use error::Error;
+use result;
+
+/// Loops spawned for `ClientConf::event_loop_threads`.
+///
+/// Threads exit when this object is dropped.
+pub(crate) struct EventLoops {
+    remotes: Vec<Remote>,
+    next: AtomicUsize,
+    /// Loop exits when its sender is dropped
+    _shutdown: Vec<oneshot::Sender<()>>,
+}
+
+impl EventLoops {
+    /// Threads are named `{thread_name}-{index}`.
+    pub fn spawn(threads: usize, thread_name: &str) -> result::Result<EventLoops> {
+        let mut remotes = Vec::new();
+        let mut shutdown = Vec::new();
+        for i in 0..threads.max(1) {
+            let (remote_tx, remote_rx) = mpsc::channel();
+            let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+            thread::Builder::new()
+                .name(format!("{}-{}", thread_name, i))
+                .spawn(move || {
+                    let mut core = match Core::new() {
+                        Ok(core) => core,
+                        Err(e) => {
+                            let _ = remote_tx.send(Err(e));
+                            return;
+                        }
+                    };
+                    let _ = remote_tx.send(Ok(core.remote()));
+                    // resolved with error when sender is dropped
+                    let _ = core.run(shutdown_rx);
+                })?;
+            let remote = remote_rx
+                .recv()
+                .map_err(|_| Error::Other("event loop thread failed to start"))??;
+            remotes.push(remote);
+            shutdown.push(shutdown_tx);
+        }
+        Ok(EventLoops {
+            remotes,
+            next: AtomicUsize::new(0),
+            _shutdown: shutdown,
+        })
+    }
+
+    /// Loop for a new connection, loops are assigned round robin.
+    pub fn next(&self) -> Remote {
+        let index = self.next.fetch_add(1, Ordering::Relaxed) % self.remotes.len();
+        self.remotes[index].clone()
+    }
+}
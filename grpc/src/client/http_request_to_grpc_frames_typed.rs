@@ -1,19 +1,28 @@
+use client::cancel::SharedClientRequest;
 use client::req_sink::ClientRequestSinkUntyped;
+use common::frame_log::FrameLog;
 use common::sink::SinkCommon;
 use common::sink::SinkCommonUntyped;
 use marshall::Marshaller;
 use or_static::arc::ArcOrStatic;
+use stats::ClientCallStats;
 use ClientRequestSink;
 
 pub(crate) fn http_req_to_grpc_frames_typed<Req: Send + 'static>(
-    http_req: httpbis::ClientRequest,
+    http_req: SharedClientRequest,
     req_marshaller: ArcOrStatic<Marshaller<Req>>,
+    max_message_bytes: Option<usize>,
+    stats: Option<ClientCallStats>,
+    frame_log: Option<FrameLog>,
 ) -> ClientRequestSink<Req> {
     ClientRequestSink {
         common: SinkCommon {
             marshaller: req_marshaller,
             sink: ClientRequestSinkUntyped {
-                common: SinkCommonUntyped { http: http_req },
+                common: SinkCommonUntyped::new(http_req),
+                max_message_bytes,
+                stats,
+                frame_log,
             },
         },
     }
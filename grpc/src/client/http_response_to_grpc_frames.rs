@@ -14,71 +14,250 @@ use bytes::Bytes;
 
 use result;
 
+use client::cancel::PeerFinished;
+use client::pool::PooledConnectionRef;
+use common::frame_log::FrameLog;
 use error::Error;
-use error::GrpcMessageError;
+use error::Status;
 
 use httpbis::DataOrTrailers;
+use httpbis::ErrorCode;
 use httpbis::HttpStreamAfterHeaders;
+use proto::grpc_frame::check_buffered_frame;
 use proto::grpc_frame::parse_grpc_frames_from_bytes;
+use proto::grpc_frame::truncated_frame;
+use proto::grpc_message::decode_grpc_message;
 use proto::grpc_status::GrpcStatus;
+use proto::grpc_web::CONTENT_TYPE_GRPC;
 use proto::headers::HEADER_GRPC_MESSAGE;
 use proto::headers::HEADER_GRPC_STATUS;
 use proto::metadata::Metadata;
 use resp::*;
+use server::drain::DRAINING_METADATA;
+use stats::error_status;
+use stats::ClientCallStats;
 use stream_item::*;
 
+/// gRPC status of response with HTTP status other than 200, see
+/// https://github.com/grpc/grpc/blob/master/doc/http-grpc-status-mapping.md
+fn grpc_status_for_http_status(http_status: u32) -> GrpcStatus {
+    match http_status {
+        400 => GrpcStatus::Internal,
+        401 => GrpcStatus::Unauthenticated,
+        403 => GrpcStatus::PermissionDenied,
+        404 => GrpcStatus::Unimplemented,
+        429 | 502 | 503 | 504 => GrpcStatus::Unavailable,
+        _ => GrpcStatus::Unknown,
+    }
+}
+
+/// Status of failed response from trailers.
+fn trailers_status(headers: &Headers) -> Status {
+    match headers.get_opt_parse::<i32>(HEADER_GRPC_STATUS) {
+        Some(grpc_status) => Status::new(
+            GrpcStatus::from_code_or_unknown(grpc_status as u32),
+            decode_grpc_message(headers.get_opt(HEADER_GRPC_MESSAGE).unwrap_or("")),
+        ),
+        None => Status::new(GrpcStatus::Internal, "missing grpc-status in trailers"),
+    }
+}
+
+/// gRPC status of stream reset by server, see
+/// https://github.com/grpc/grpc/blob/master/doc/PROTOCOL-HTTP2.md#errors
+fn grpc_status_for_error_code(code: ErrorCode) -> GrpcStatus {
+    match code {
+        ErrorCode::RefusedStream => GrpcStatus::Unavailable,
+        ErrorCode::Cancel => GrpcStatus::Cancelled,
+        ErrorCode::EnhanceYourCalm => GrpcStatus::ResourceExhausted,
+        ErrorCode::InadequateSecurity => GrpcStatus::PermissionDenied,
+        _ => GrpcStatus::Internal,
+    }
+}
+
+/// Convert HTTP/2 error of the response.
+///
+/// Reset streams are reported with status corresponding to reset code,
+/// other failures (connection refused or lost, `GOAWAY` received before
+/// the stream was processed) are transport errors, reported as `UNAVAILABLE`.
+fn response_error(e: httpbis::Error) -> Error {
+    match e {
+        httpbis::Error::RstStreamReceived(code) => Error::Status(Status::new(
+            grpc_status_for_error_code(code),
+            format!("stream reset by server: {:?}", code),
+        )),
+        e => Error::from(e),
+    }
+}
+
 fn init_headers_to_metadata(headers: Headers) -> result::Result<Metadata> {
-    if headers.get_opt(":status") != Some("200") {
-        return Err(Error::Other("not 200"));
+    match headers.get_opt_parse(":status") {
+        Some(200) => {}
+        Some(http_status) => {
+            return Err(Error::Status(Status::new(
+                grpc_status_for_http_status(http_status),
+                format!("HTTP status code {}", http_status),
+            )));
+        }
+        None => return Err(Error::Other("missing :status header")),
     }
 
-    // Check gRPC status code and message
-    // TODO: a more detailed error message.
+    // Trailers-Only response: status is in the only HEADERS frame
     if let Some(grpc_status) = headers.get_opt_parse(HEADER_GRPC_STATUS) {
         if grpc_status != GrpcStatus::Ok as i32 {
             let message = headers
                 .get_opt(HEADER_GRPC_MESSAGE)
-                .unwrap_or("unknown error");
-            return Err(Error::GrpcMessage(GrpcMessageError {
-                grpc_status: grpc_status,
-                grpc_message: message.to_owned(),
-            }));
+                .map_or_else(|| "unknown error".to_owned(), decode_grpc_message);
+            return Err(Error::Status(Status::new(
+                GrpcStatus::from_code_or_unknown(grpc_status as u32),
+                message,
+            )));
+        }
+    }
+
+    match headers.get_opt("content-type") {
+        Some(content_type) if content_type.starts_with(CONTENT_TYPE_GRPC) => {}
+        content_type => {
+            return Err(Error::Status(Status::new(
+                GrpcStatus::Unknown,
+                format!("unexpected content-type: {:?}", content_type),
+            )));
         }
     }
 
     Ok(Metadata::from_headers(headers)?)
 }
 
-pub fn http_response_to_grpc_frames(response: httpbis::Response) -> StreamingResponse<Bytes> {
+/// `peer_finished` is set when server ends or resets the stream,
+/// but not when response fails with an error detected by the client.
+///
+/// `connection` is removed from the pool when the call is rejected
+/// by draining server, so new calls are sent to other connections.
+pub(crate) fn http_response_to_grpc_frames(
+    response: httpbis::Response,
+    peer_finished: PeerFinished,
+    connection: PooledConnectionRef,
+    max_buffer_bytes: Option<usize>,
+    stats: Option<ClientCallStats>,
+    frame_log: Option<FrameLog>,
+) -> StreamingResponse<Bytes> {
+    let stats_on_error = stats.clone();
+    let frame_log_on_error = frame_log.clone();
+    let peer_finished_on_error = peer_finished.clone();
     StreamingResponse::new(
         response
             .0
-            .map_err(|e| Error::from(e))
+            .map_err(move |e| {
+                peer_finished_on_error.set();
+                if let Some(frame_log) = frame_log_on_error {
+                    frame_log.error("recv", &e);
+                }
+                response_error(e)
+            })
             .and_then(|(headers, rem)| {
+                if let Some(ref frame_log) = frame_log {
+                    frame_log.headers("recv", &headers);
+                }
+                // Trailers-Only response ends the stream
+                if headers.get_opt(HEADER_GRPC_STATUS).is_some() {
+                    peer_finished.set();
+                }
+                if headers.get_opt(DRAINING_METADATA).is_some() {
+                    debug!("server is draining, removing connection from pool");
+                    connection.remove();
+                }
                 let metadata = init_headers_to_metadata(headers)?;
                 let frames: GrpcStreamWithTrailingMetadata<Bytes> =
-                    GrpcStreamWithTrailingMetadata::new(
-                        GrpcFrameFromHttpFramesStreamResponse::new(rem),
-                    );
+                    GrpcStreamWithTrailingMetadata::new(StatsStream {
+                        stream: GrpcFrameFromHttpFramesStreamResponse::new(
+                            rem,
+                            peer_finished,
+                            max_buffer_bytes,
+                            frame_log,
+                        ),
+                        stats,
+                    });
                 Ok((metadata, frames))
+            })
+            .map_err(move |e| {
+                if let Some(stats) = stats_on_error {
+                    stats.finished(error_status(&e));
+                }
+                e
             }),
     )
 }
 
+/// Report received messages and call completion to stats handler
+struct StatsStream<S> {
+    stream: S,
+    stats: Option<ClientCallStats>,
+}
+
+impl<S> Stream for StatsStream<S>
+where
+    S: Stream<Item = ItemOrMetadata<Bytes>, Error = Error>,
+{
+    type Item = ItemOrMetadata<Bytes>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let r = self.stream.poll();
+        match r {
+            Ok(Async::Ready(Some(ItemOrMetadata::Item(ref message)))) => {
+                if let Some(ref stats) = self.stats {
+                    stats.message_received(message.len());
+                }
+            }
+            Ok(Async::Ready(None)) => {
+                if let Some(stats) = self.stats.take() {
+                    stats.finished(GrpcStatus::Ok);
+                }
+            }
+            Err(ref e) => {
+                if let Some(stats) = self.stats.take() {
+                    stats.finished(error_status(e));
+                }
+            }
+            Ok(Async::Ready(Some(ItemOrMetadata::TrailingMetadata(..)))) | Ok(Async::NotReady) => {}
+        }
+        r
+    }
+}
+
+impl<S> Drop for StatsStream<S> {
+    fn drop(&mut self) {
+        if let Some(stats) = self.stats.take() {
+            stats.finished(GrpcStatus::Cancelled);
+        }
+    }
+}
+
 struct GrpcFrameFromHttpFramesStreamResponse {
     http_stream_stream: HttpStreamAfterHeaders,
+    peer_finished: PeerFinished,
     buf: Bytes,
+    /// Limit of partially received message, see `ClientConf::max_response_buffer_bytes`
+    max_buffer_bytes: Option<usize>,
     parsed_frames: VecDeque<Bytes>,
     error: Option<stream::Once<ItemOrMetadata<Bytes>, Error>>,
+    frame_log: Option<FrameLog>,
 }
 
 impl GrpcFrameFromHttpFramesStreamResponse {
-    pub fn new(http_stream_stream: HttpStreamAfterHeaders) -> Self {
+    pub fn new(
+        http_stream_stream: HttpStreamAfterHeaders,
+        peer_finished: PeerFinished,
+        max_buffer_bytes: Option<usize>,
+        frame_log: Option<FrameLog>,
+    ) -> Self {
         GrpcFrameFromHttpFramesStreamResponse {
             http_stream_stream,
+            peer_finished,
             buf: Bytes::new(),
+            max_buffer_bytes,
             parsed_frames: VecDeque::new(),
             error: None,
+            frame_log,
         }
     }
 }
@@ -103,19 +282,33 @@ impl Stream for GrpcFrameFromHttpFramesStreamResponse {
                 });
 
             if let Some(frame) = self.parsed_frames.pop_front() {
+                if let Some(ref frame_log) = self.frame_log {
+                    frame_log.grpc_frame("recv", frame.len());
+                }
                 return Ok(Async::Ready(Some(ItemOrMetadata::Item(frame))));
             }
 
-            let part_opt = match self.http_stream_stream.poll()? {
-                Async::NotReady => return Ok(Async::NotReady),
-                Async::Ready(part_opt) => part_opt,
+            let part_opt = match self.http_stream_stream.poll() {
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Ok(Async::Ready(part_opt)) => part_opt,
+                Err(e) => {
+                    self.peer_finished.set();
+                    if let Some(ref frame_log) = self.frame_log {
+                        frame_log.error("recv", &e);
+                    }
+                    return Err(response_error(e));
+                }
             };
             let part = match part_opt {
                 None => {
+                    self.peer_finished.set();
+                    if let Some(ref frame_log) = self.frame_log {
+                        frame_log.end_stream("recv");
+                    }
                     if self.buf.is_empty() {
                         return Ok(Async::Ready(None));
                     } else {
-                        self.error = Some(stream::once(Err(Error::Other("partial frame"))));
+                        self.error = Some(stream::once(Err(truncated_frame(&self.buf))));
                         continue;
                     }
                 }
@@ -124,8 +317,12 @@ impl Stream for GrpcFrameFromHttpFramesStreamResponse {
 
             match part {
                 DataOrTrailers::Trailers(headers) => {
+                    self.peer_finished.set();
+                    if let Some(ref frame_log) = self.frame_log {
+                        frame_log.trailers("recv", &headers);
+                    }
                     if !self.buf.is_empty() {
-                        self.error = Some(stream::once(Err(Error::Other("partial frame"))));
+                        self.error = Some(stream::once(Err(truncated_frame(&self.buf))));
                     } else {
                         let grpc_status = headers.get_opt_parse(HEADER_GRPC_STATUS);
                         if grpc_status == Some(GrpcStatus::Ok as i32) {
@@ -133,25 +330,106 @@ impl Stream for GrpcFrameFromHttpFramesStreamResponse {
                                 Metadata::from_headers(headers)?,
                             ))));
                         } else {
-                            self.error = Some(stream::once(Err(
-                                if let Some(message) = headers.get_opt(HEADER_GRPC_MESSAGE) {
-                                    Error::GrpcMessage(GrpcMessageError {
-                                        grpc_status: grpc_status
-                                            .unwrap_or(GrpcStatus::Unknown as i32),
-                                        grpc_message: message.to_owned(),
-                                    })
-                                } else {
-                                    Error::Other("not xxx")
-                                },
-                            )));
+                            self.error =
+                                Some(stream::once(Err(Error::Status(trailers_status(&headers)))));
                         }
                     }
                     continue;
                 }
                 DataOrTrailers::Data(data, ..) => {
+                    if let Some(ref frame_log) = self.frame_log {
+                        frame_log.data("recv", data.len());
+                    }
                     self.buf.extend_from_slice(&data);
+                    if let Some(max_buffer_bytes) = self.max_buffer_bytes {
+                        if let Err(e) = check_buffered_frame(&self.buf, max_buffer_bytes) {
+                            self.buf = Bytes::new();
+                            self.error = Some(stream::once(Err(e)));
+                        }
+                    }
                 }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use httpbis::Header;
+
+    fn status(headers: Vec<Header>) -> GrpcStatus {
+        match init_headers_to_metadata(Headers::from_vec(headers)) {
+            Err(Error::Status(e)) => e.code,
+            r => panic!("expecting grpc error, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn http_status() {
+        assert_eq!(
+            GrpcStatus::Unimplemented,
+            status(vec![Header::new(":status", "404")])
+        );
+        assert_eq!(
+            GrpcStatus::Unknown,
+            status(vec![Header::new(":status", "500")])
+        );
+    }
+
+    #[test]
+    fn trailers_only() {
+        assert_eq!(
+            GrpcStatus::NotFound,
+            status(vec![
+                Header::new(":status", "200"),
+                Header::new("content-type", "application/grpc"),
+                Header::new(HEADER_GRPC_STATUS, "5"),
+            ])
+        );
+    }
+
+    #[test]
+    fn trailers() {
+        let status = trailers_status(&Headers::from_vec(vec![Header::new(
+            HEADER_GRPC_STATUS,
+            "5",
+        )]));
+        assert_eq!(Status::new(GrpcStatus::NotFound, ""), status);
+
+        let status = trailers_status(&Headers::from_vec(Vec::new()));
+        assert_eq!(GrpcStatus::Internal, status.code);
+    }
+
+    #[test]
+    fn reset_stream() {
+        for &(code, status) in &[
+            (ErrorCode::RefusedStream, GrpcStatus::Unavailable),
+            (ErrorCode::Cancel, GrpcStatus::Cancelled),
+            (ErrorCode::EnhanceYourCalm, GrpcStatus::ResourceExhausted),
+            (ErrorCode::InternalError, GrpcStatus::Internal),
+        ] {
+            match response_error(httpbis::Error::RstStreamReceived(code)) {
+                Error::Status(e) => assert_eq!(status, e.code),
+                e => panic!("expecting status, got {:?}", e),
+            }
+        }
+    }
+
+    #[test]
+    fn content_type() {
+        assert_eq!(
+            GrpcStatus::Unknown,
+            status(vec![
+                Header::new(":status", "200"),
+                Header::new("content-type", "text/html"),
+            ])
+        );
+        init_headers_to_metadata(Headers::from_vec(vec![
+            Header::new(":status", "200"),
+            Header::new("content-type", "application/grpc+proto"),
+        ]))
+        .unwrap();
+    }
+}
@@ -1,11 +1,18 @@
-use client::http_response_to_grpc_frames::http_response_to_grpc_frames;
+use bytes::Bytes;
+
+use client::service_config::check_message_size;
 use marshall::Marshaller;
 use or_static::arc::ArcOrStatic;
 use StreamingResponse;
 
+/// Parse messages of response converted with `http_response_to_grpc_frames`.
 pub(crate) fn http_response_to_grpc_frames_typed<Resp: Send>(
-    resp: httpbis::Response,
+    resp: StreamingResponse<Bytes>,
     marshaller: ArcOrStatic<Marshaller<Resp>>,
+    max_message_bytes: Option<usize>,
 ) -> StreamingResponse<Resp> {
-    http_response_to_grpc_frames(resp).and_then_items(move |message| marshaller.read(message))
+    resp.and_then_items(move |message| {
+        check_message_size(message.len(), max_message_bytes)?;
+        marshaller.read(message)
+    })
 }
@@ -0,0 +1,15 @@
+//! Client interceptors.
+
+use req::RequestOptions;
+use result;
+
+/// Hook called before each client call is started.
+///
+/// Registered with `ClientBuilder::interceptor`.
+/// Interceptors are called in registration order.
+pub trait ClientInterceptor: Send + Sync + 'static {
+    /// Inspect or modify request options, e. g. add metadata.
+    ///
+    /// Returning an error fails the call without sending a request.
+    fn intercept(&self, method: &str, options: &mut RequestOptions) -> result::Result<()>;
+}
@@ -1,8 +1,26 @@
+pub(crate) mod cancel;
+pub(crate) mod channel;
+pub mod credentials;
+pub(crate) mod event_loops;
+#[cfg(feature = "with-google-auth")]
+pub mod google_auth;
 pub(crate) mod http_request_to_grpc_frames_typed;
 pub(crate) mod http_response_to_grpc_frames;
 pub(crate) mod http_response_to_grpc_frames_typed;
+pub mod interceptor;
+pub(crate) mod pool;
 pub(crate) mod req_sink;
+pub(crate) mod resolve;
+pub mod service_config;
+pub(crate) mod tls;
 pub(crate) mod types;
+pub(crate) mod upload;
+
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
 
 use bytes::Bytes;
 use tokio_core::reactor::Remote;
@@ -15,24 +33,127 @@ use httpbis::HttpScheme;
 
 use tls_api;
 
+use marshall::encode_message;
+use marshall::MarshallerBytes;
+use method::GrpcStreaming;
 use method::MethodDescriptor;
 
 use result;
 
+use client::cancel::response_with_cancel_on_drop;
+use client::cancel::PeerFinished;
+use client::cancel::SharedClientRequest;
+use client::channel::Channel;
+use client::credentials::CallCredentials;
+use client::credentials::CallCredentialsContext;
+use client::event_loops::EventLoops;
 use client::http_request_to_grpc_frames_typed::http_req_to_grpc_frames_typed;
+use client::http_response_to_grpc_frames::http_response_to_grpc_frames;
 use client::http_response_to_grpc_frames_typed::http_response_to_grpc_frames_typed;
+use client::interceptor::ClientInterceptor;
+use client::pool::response_with_stream_guard;
+use client::pool::start_call_stream;
+use client::pool::ConnectionPool;
+use client::req_sink::start_streaming_call;
 use client::req_sink::ClientRequestSink;
+use client::req_sink::RequestSink;
+use client::resolve::resolve;
+use client::service_config::check_message_size;
+use client::service_config::RetryPolicy;
+use client::service_config::ServiceConfig;
+use client::tls::ClientTlsConf;
+use common::frame_log::FrameLog;
 use error;
 use futures::future;
+use futures::future::Loop;
 use futures::Future;
+use futures_grpc::GrpcFuture;
 use or_static::arc::ArcOrStatic;
-use proto::grpc_frame::write_grpc_frame_to_vec;
+use or_static::string::StringOrStatic;
+use proto::grpc_frame::GRPC_HEADER_LEN;
+use proto::grpc_timeout::HEADER_GRPC_TIMEOUT;
+use proto::headers::user_agent;
+use proto::headers::HEADER_USER_AGENT;
+use proto::metadata::Metadata;
 use req::*;
 use resp::*;
+use stats::error_status;
+use stats::ClientCallStats;
+use stats::ClientStatsHandler;
+use stream_item::GrpcStreamWithTrailingMetadata;
+use timer::deadline_exceeded;
+use timer::sleep;
+use timer::Sleep;
+use timer::WithDeadline;
+use trace::CallId;
 
 #[derive(Default, Debug, Clone)]
 pub struct ClientConf {
     pub http: httpbis::ClientConf,
+    /// Log HEADERS, DATA and reset events of each call
+    /// together with decoded gRPC message boundaries at `info` level.
+    ///
+    /// Meant for diagnosing protocol-level interop problems, very verbose.
+    pub debug_frames: bool,
+    /// Maximum number of HTTP/2 connections to the backend, one by default.
+    ///
+    /// Additional connections are opened only when all connections
+    /// have `max_streams_per_connection` active calls.
+    pub max_connections: Option<usize>,
+    /// Number of active calls on a connection after which the next call
+    /// is sent over a new connection, unlimited by default.
+    ///
+    /// Should not exceed `MAX_CONCURRENT_STREAMS` announced by the server,
+    /// otherwise calls above the server limit wait for a free stream.
+    pub max_streams_per_connection: Option<usize>,
+    /// Fail calls with `UNAVAILABLE` if a new connection does not start
+    /// their stream within this time, by default OS TCP connect timeout is used.
+    ///
+    /// Unlike `RequestOptions::timeout`, limits only waiting for a connection
+    /// until it starts its first stream, so calls to unreachable address
+    /// fail in bounded time even if they have no deadline. Connection
+    /// which timed out is removed from the pool.
+    pub connect_timeout: Option<Duration>,
+    /// Per-method timeouts, message size limits and retry policies.
+    pub service_config: ServiceConfig,
+    /// Run connections on a fixed number of event loop threads,
+    /// assigned to connections round robin.
+    ///
+    /// By default each connection spawns its own event loop thread.
+    /// Ignored when client is built `with_event_loop`.
+    pub event_loop_threads: Option<usize>,
+    /// Dial this host and port instead of the builder address.
+    ///
+    /// Builder host is still used for `:authority` and TLS SNI, e. g. to connect
+    /// to a load balancer or a known IP address of a TLS server.
+    /// Ignored for unix sockets.
+    pub connect_to: Option<(String, u16)>,
+    /// `:authority` sent with each call, `host:port` of the builder by default.
+    pub authority: Option<String>,
+    /// Send generated `CallId` with calls which do not have one
+    /// in `RequestOptions`, and include call id in client log records.
+    pub generate_call_id: bool,
+    /// Fail call with `RESOURCE_EXHAUSTED` when a response message
+    /// with frame header would not fit into this many bytes.
+    ///
+    /// Response messages not yet taken from response stream are bounded
+    /// by flow control window, which is increased as the stream is polled.
+    /// A message larger than the window is buffered until it is complete,
+    /// this limits such buffer. Checked as soon as frame header is received,
+    /// unlike `MethodConfig::max_response_message_bytes`.
+    pub max_response_buffer_bytes: Option<usize>,
+    /// Fail call with `RESOURCE_EXHAUSTED` at once when this many calls
+    /// are already waiting for a connection to start their streams,
+    /// e. g. while the connection is being established or reestablished.
+    ///
+    /// By default pending calls are not limited. Number of pending calls
+    /// is available from `Client::pending_calls`.
+    pub max_pending_calls: Option<usize>,
+    /// Prepended to `user-agent` header sent with each call,
+    /// e. g. `my-app/1.2` to send `my-app/1.2 grpc-rust/<version>`.
+    ///
+    /// `user-agent` set in `RequestOptions::metadata` replaces the header.
+    pub user_agent_prefix: Option<String>,
 }
 
 impl ClientConf {
@@ -58,9 +179,18 @@ pub struct ClientBuilder<'a, T: tls_api::TlsConnector> {
     event_loop: Option<Remote>,
     pub conf: ClientConf,
     tls: Tls<T>,
+    stats_handler: Option<Arc<ClientStatsHandler>>,
+    interceptors: Vec<Arc<ClientInterceptor>>,
+    call_credentials: Option<Arc<CallCredentials>>,
 }
 
 impl<'a, T: tls_api::TlsConnector> ClientBuilder<'a, T> {
+    /// Run client connections on given event loop
+    /// instead of spawning an event loop thread.
+    ///
+    /// Useful to embed client into an application which already runs
+    /// a `tokio-core` event loop, or to run server and client on a single thread
+    /// in tests. Calls make progress only while that loop is run.
     pub fn with_event_loop(mut self, event_loop: Remote) -> Self {
         self.event_loop = Some(event_loop);
         self
@@ -71,8 +201,33 @@ impl<'a, T: tls_api::TlsConnector> ClientBuilder<'a, T> {
         self
     }
 
+    /// Observe calls made by the client.
+    pub fn stats_handler(mut self, handler: Arc<ClientStatsHandler>) -> Self {
+        self.stats_handler = Some(handler);
+        self
+    }
+
+    /// Add interceptor called before each call.
+    pub fn interceptor(mut self, interceptor: Arc<ClientInterceptor>) -> Self {
+        self.interceptors.push(interceptor);
+        self
+    }
+
+    /// Attach metadata produced by given credentials to each call.
+    ///
+    /// Credentials are sent only over TLS connection,
+    /// unless `CallCredentials::allow_insecure` returns `true`.
+    pub fn call_credentials(mut self, credentials: Arc<CallCredentials>) -> Self {
+        self.call_credentials = Some(credentials);
+        self
+    }
+
+    /// Build a client wrapped in `Channel`, to be shared by stubs of several services.
+    pub fn build_channel(self) -> result::Result<Channel> {
+        self.build().map(Channel::new)
+    }
+
     pub fn build(self) -> result::Result<Client> {
-        let mut builder = httpbis::ClientBuilder::<T>::new();
         let mut conf = self.conf;
         conf.http.thread_name = Some(
             conf.http
@@ -80,32 +235,105 @@ impl<'a, T: tls_api::TlsConnector> ClientBuilder<'a, T> {
                 .unwrap_or_else(|| "grpc-client-loop".to_owned()),
         );
         let (host, port) = match self.client_type {
-            ClientBuilderType::Tcp { host, port } => {
-                if self.http_scheme == HttpScheme::Https {
-                    builder.set_tls(host)?;
+            ClientBuilderType::Tcp { host, port } => (host.to_owned(), Some(port)),
+            ClientBuilderType::Unix { socket } => (socket.to_owned(), None),
+        };
+        let authority = match (conf.authority.take(), port) {
+            (Some(authority), _) => authority,
+            (None, Some(port)) => format!("{}:{}", host, port),
+            (None, None) => host.clone(),
+        };
+        let (dial_host, dial_port) = match (conf.connect_to.take(), port) {
+            (Some((dial_host, dial_port)), Some(..)) => (dial_host, Some(dial_port)),
+            _ => (host.clone(), port),
+        };
+        let https = self.http_scheme == HttpScheme::Https;
+        let event_loops = match (&self.event_loop, conf.event_loop_threads) {
+            (None, Some(threads)) => {
+                let thread_name = conf.http.thread_name.as_ref().expect("thread_name");
+                Some(EventLoops::spawn(threads, thread_name)?)
+            }
+            _ => None,
+        };
+        let event_loop = self.event_loop;
+        let http_conf = conf.http.clone();
+        let tls = match self.tls {
+            Tls::Explict(tls) => Some(tls),
+            Tls::Implicit | Tls::None => None,
+        };
+
+        // host resolved on each connection, so DNS changes are picked up
+        let dial_addr = dial_port.map(|port| (dial_host.clone(), port));
+
+        let tls_host = host.clone();
+        // does not block, `httpbis` connects when the first stream is started
+        let new_client = move |event_loop: Option<Remote>,
+                               addr: Option<SocketAddr>|
+              -> result::Result<httpbis::Client> {
+            let mut builder = httpbis::ClientBuilder::<T>::new();
+            match addr {
+                Some(addr) => {
+                    if https {
+                        builder.set_tls(&tls_host)?;
+                    }
+                    builder.set_addr(addr)?;
+                }
+                None => {
+                    builder.set_unix_addr(&dial_host)?;
                 }
-                builder.set_addr((host, port))?;
-                (host, Some(port))
             }
-            ClientBuilderType::Unix { socket } => {
-                builder.set_unix_addr(socket)?;
-                (socket, None)
+            builder.event_loop = event_loop;
+            builder.conf = http_conf.clone();
+            if let Some(ref tls) = tls {
+                builder.tls = tls.clone();
             }
+            builder.build()
         };
-        builder.event_loop = self.event_loop;
-        builder.conf = conf.http;
-        match self.tls {
-            Tls::Explict(tls) => {
-                builder.tls = tls;
+        let new_client = Arc::new(new_client);
+        // called for each connection of the pool
+        let connect = move || -> GrpcFuture<(httpbis::Client, Option<SocketAddr>)> {
+            let event_loop = match event_loops {
+                Some(ref event_loops) => Some(event_loops.next()),
+                None => event_loop.clone(),
+            };
+            match dial_addr {
+                Some((ref host, port)) => {
+                    let new_client = new_client.clone();
+                    Box::new(resolve(host.clone(), port).and_then(move |addr| {
+                        Ok((new_client(event_loop, Some(addr))?, Some(addr)))
+                    }))
+                }
+                None => {
+                    let client = new_client(event_loop, None).map(|client| (client, None));
+                    Box::new(future::result(client))
+                }
             }
-            Tls::Implicit | Tls::None => {}
-        }
+        };
+
+        let pool = Arc::new(ConnectionPool::new(
+            Box::new(connect),
+            conf.max_connections,
+            conf.max_streams_per_connection,
+            conf.max_pending_calls,
+            conf.connect_timeout,
+        ));
 
         Ok(Client {
-            client: ::std::sync::Arc::new(builder.build()?),
-            host: host.to_owned(),
+            pool,
+            authority,
+            host,
             http_scheme: self.http_scheme,
             port,
+            stats_handler: self.stats_handler,
+            interceptors: self.interceptors,
+            call_credentials: self.call_credentials,
+            debug_frames: conf.debug_frames,
+            max_response_buffer_bytes: conf.max_response_buffer_bytes,
+            user_agent: Bytes::from(user_agent(
+                conf.user_agent_prefix.as_ref().map(String::as_str),
+            )),
+            generate_call_id: conf.generate_call_id,
+            service_config: Arc::new(conf.service_config),
         })
     }
 }
@@ -118,6 +346,9 @@ impl<'a> ClientBuilder<'a, tls_api_stub::TlsConnector> {
             event_loop: None,
             conf: Default::default(),
             tls: Tls::None,
+            stats_handler: None,
+            interceptors: Vec::new(),
+            call_credentials: None,
         }
     }
 
@@ -128,6 +359,9 @@ impl<'a> ClientBuilder<'a, tls_api_stub::TlsConnector> {
             event_loop: None,
             conf: Default::default(),
             tls: Tls::None,
+            stats_handler: None,
+            interceptors: Vec::new(),
+            call_credentials: None,
         }
     }
 
@@ -138,6 +372,9 @@ impl<'a> ClientBuilder<'a, tls_api_stub::TlsConnector> {
             event_loop: self.event_loop,
             conf: self.conf,
             tls: Tls::Implicit,
+            stats_handler: self.stats_handler,
+            interceptors: self.interceptors,
+            call_credentials: self.call_credentials,
         }
     }
 
@@ -151,24 +388,91 @@ impl<'a> ClientBuilder<'a, tls_api_stub::TlsConnector> {
             event_loop: self.event_loop,
             conf: self.conf,
             tls: Tls::Explict(tls),
+            stats_handler: self.stats_handler,
+            interceptors: self.interceptors,
+            call_credentials: self.call_credentials,
         }
     }
+
+    /// Use TLS connector configured with given settings.
+    pub fn tls_conf<TLS: tls_api::TlsConnector>(
+        self,
+        tls_conf: ClientTlsConf,
+    ) -> result::Result<ClientBuilder<'a, TLS>> {
+        let domain = match (&tls_conf.sni_host, &self.client_type) {
+            (Some(sni_host), _) => sni_host.clone(),
+            (None, ClientBuilderType::Tcp { host, .. }) => (*host).to_owned(),
+            (None, ClientBuilderType::Unix { .. }) => {
+                return Err(error::Error::Other(
+                    "sni_host must be specified for unix socket",
+                ));
+            }
+        };
+        let connector = tls_conf.build_connector::<TLS>()?;
+        Ok(self.explicit_tls(ClientTlsOption::Tls(domain, Arc::new(connector))))
+    }
+}
+
+/// Fail response with `DEADLINE_EXCEEDED` if it is not complete when `deadline` fires.
+fn response_with_deadline<Resp: Send + 'static>(
+    resp: StreamingResponse<Resp>,
+    deadline: Sleep,
+) -> StreamingResponse<Resp> {
+    let stream_deadline = deadline.clone();
+    StreamingResponse::new(
+        WithDeadline {
+            inner: resp.0,
+            sleep: deadline,
+        }
+        .map(move |(metadata, stream)| {
+            let stream = WithDeadline {
+                inner: stream.0,
+                sleep: stream_deadline,
+            };
+            (metadata, GrpcStreamWithTrailingMetadata::new(stream))
+        }),
+    )
 }
 
 /// gRPC client implementation.
 /// Used by generated code.
-#[derive(Debug)]
+///
+/// Clones share connections.
+#[derive(Clone)]
 pub struct Client {
-    client: ::std::sync::Arc<httpbis::Client>,
+    pool: Arc<ConnectionPool>,
+    /// Sent as `:authority` of each call
+    authority: String,
     host: String,
     http_scheme: HttpScheme,
     port: Option<u16>,
+    stats_handler: Option<Arc<ClientStatsHandler>>,
+    interceptors: Vec<Arc<ClientInterceptor>>,
+    call_credentials: Option<Arc<CallCredentials>>,
+    debug_frames: bool,
+    max_response_buffer_bytes: Option<usize>,
+    /// Value of `user-agent` header
+    user_agent: Bytes,
+    generate_call_id: bool,
+    service_config: Arc<ServiceConfig>,
+}
+
+impl fmt::Debug for Client {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Client")
+            .field("connections", &self.pool.len())
+            .field("authority", &self.authority)
+            .field("host", &self.host)
+            .field("http_scheme", &self.http_scheme)
+            .field("port", &self.port)
+            .finish()
+    }
 }
 
 impl Client {
     fn call_impl<Req, Resp>(
         &self,
-        options: RequestOptions,
+        mut options: RequestOptions,
         req: Option<Req>,
         method: ArcOrStatic<MethodDescriptor<Req, Resp>>,
     ) -> Box<
@@ -179,12 +483,46 @@ impl Client {
         Req: Send + 'static,
         Resp: Send + 'static,
     {
-        let mut authority = self.host.clone();
-        if let Some(port_value) = self.port {
-            authority = format!("{}:{}", authority, port_value);
+        let authority = self.authority.clone();
+
+        for interceptor in &self.interceptors {
+            if let Err(e) = interceptor.intercept(&method.name, &mut options) {
+                return Box::new(future::err(e));
+            }
+        }
+
+        if self.generate_call_id && options.call_id().is_none() {
+            options = options.with_call_id(CallId::generate());
+        }
+        match options.call_id() {
+            Some(call_id) => debug!(
+                "start call {}/{} call_id={}",
+                authority, method.name, call_id
+            ),
+            None => debug!("start call {}/{}", authority, method.name),
         }
 
-        debug!("start call {}/{}", authority, method.name);
+        let credentials_future = match self.call_credentials {
+            Some(ref credentials) => {
+                if self.http_scheme != HttpScheme::Https && !credentials.allow_insecure() {
+                    return Box::new(future::err(error::Error::Other(
+                        "call credentials require TLS connection",
+                    )));
+                }
+                Some(credentials.get_metadata(&CallCredentialsContext {
+                    authority: &authority,
+                    method: &method.name,
+                }))
+            }
+            None => None,
+        };
+
+        let method_config = self.service_config.method_config(&method.name);
+        let max_request_message_bytes = method_config.and_then(|c| c.max_request_message_bytes);
+        let max_response_message_bytes = method_config.and_then(|c| c.max_response_message_bytes);
+        if let Some(timeout) = method_config.and_then(|c| c.timeout) {
+            options.timeout = Some(options.timeout.map_or(timeout, |t| t.min(timeout)));
+        }
 
         if options.cachable {
             // TODO: GET
@@ -207,21 +545,53 @@ impl Client {
             Header::new(Bytes::from_static(b"te"), Bytes::from_static(b"trailers")),
         ]);
 
+        if options.metadata.get(HEADER_USER_AGENT).is_none() {
+            headers.add_header(Header::new(HEADER_USER_AGENT, self.user_agent.clone()));
+        }
+
+        if let Some(grpc_timeout) = options.grpc_timeout() {
+            headers.add_header(Header::new(HEADER_GRPC_TIMEOUT, grpc_timeout));
+        }
+
         headers.extend(options.metadata.into_headers());
 
+        let deadline = options.timeout.map(sleep);
+
+        let stats = self
+            .stats_handler
+            .clone()
+            .map(|handler| ClientCallStats::started(handler, &method.name));
+
         let req_bytes = match req {
-            Some(req) => match method.req_marshaller.write(&req) {
-                Ok(bytes) => {
-                    // TODO: extra allocation
-                    Some(Bytes::from(write_grpc_frame_to_vec(&bytes)))
+            Some(req) => {
+                let frame = encode_message(&*method.req_marshaller, &req).and_then(|frame| {
+                    check_message_size(frame.len() - GRPC_HEADER_LEN, max_request_message_bytes)?;
+                    Ok(frame)
+                });
+                match frame {
+                    Ok(frame) => Some(frame),
+                    Err(e) => {
+                        if let Some(ref stats) = stats {
+                            stats.finished(error_status(&e));
+                        }
+                        return Box::new(future::err(e));
+                    }
                 }
-                Err(e) => return Box::new(future::err(e)),
-            },
+            }
             None => None,
         };
 
+        if let (Some(stats), Some(frame)) = (&stats, &req_bytes) {
+            stats.message_sent(frame.len() - GRPC_HEADER_LEN);
+        }
+
         let end_stream = req_bytes.is_some();
 
+        let frame_log = match self.debug_frames {
+            true => Some(FrameLog::client(&method.name)),
+            false => None,
+        };
+
         //        let request_frames = {
         //            let method = method.clone();
         //            req.0
@@ -231,18 +601,79 @@ impl Client {
         //                }).map_err(|_e| httpbis::Error::Other("grpc error")) // TODO: preserve error
         //        };
 
-        let http_future = self
-            .client
-            .start_request(headers, req_bytes, None, end_stream);
+        let pool = self.pool.clone();
+        let start_frame_log = frame_log.clone();
+        let start_request = move |headers: Headers| {
+            if let Some(ref frame_log) = start_frame_log {
+                frame_log.headers("send", &headers);
+                if let Some(ref frame) = req_bytes {
+                    frame_log.grpc_frame("send", frame.len() - GRPC_HEADER_LEN);
+                    frame_log.data("send", frame.len());
+                    frame_log.end_stream("send");
+                }
+            }
+            start_call_stream(pool, headers, req_bytes, end_stream)
+        };
+
+        let http_future = match credentials_future {
+            Some(credentials_future) => {
+                future::Either::A(credentials_future.and_then(move |credentials| {
+                    headers.extend(credentials.into_headers());
+                    start_request(headers)
+                }))
+            }
+            None => future::Either::B(start_request(headers)),
+        };
 
-        let http_future = http_future.map_err(error::Error::from);
+        // deadline also covers connecting and waiting for a connection
+        let http_future = match deadline.clone() {
+            Some(deadline) => future::Either::A(WithDeadline {
+                inner: http_future,
+                sleep: deadline,
+            }),
+            None => future::Either::B(http_future),
+        };
+
+        let stats_on_error = stats.clone();
+        let http_future = http_future.map_err(move |e| {
+            if let Some(stats) = stats_on_error {
+                stats.finished(error_status(&e));
+            }
+            e
+        });
 
         let req_marshaller = method.req_marshaller.clone();
         let resp_marshaller = method.resp_marshaller.clone();
-
-        Box::new(http_future.map(move |(req, resp)| {
-            let grpc_req = http_req_to_grpc_frames_typed(req, req_marshaller);
-            let grpc_resp = http_response_to_grpc_frames_typed(resp, resp_marshaller);
+        let max_response_buffer_bytes = self.max_response_buffer_bytes;
+
+        Box::new(http_future.map(move |(req, resp, stream_guard)| {
+            let req = SharedClientRequest::new(req);
+            let peer_finished = PeerFinished::default();
+            let grpc_req = http_req_to_grpc_frames_typed(
+                req.clone(),
+                req_marshaller,
+                max_request_message_bytes,
+                stats.clone(),
+                frame_log.clone(),
+            );
+            let grpc_resp = http_response_to_grpc_frames(
+                resp,
+                peer_finished.clone(),
+                stream_guard.connection(),
+                max_response_buffer_bytes,
+                stats,
+                frame_log,
+            );
+            let mut grpc_resp = http_response_to_grpc_frames_typed(
+                grpc_resp,
+                resp_marshaller,
+                max_response_message_bytes,
+            );
+            if let Some(deadline) = deadline {
+                grpc_resp = response_with_deadline(grpc_resp, deadline);
+            }
+            let grpc_resp = response_with_stream_guard(grpc_resp, stream_guard);
+            let grpc_resp = response_with_cancel_on_drop(grpc_resp, req, peer_finished);
             (grpc_req, grpc_resp)
         }))
 
@@ -255,6 +686,21 @@ impl Client {
         //                    }
     }
 
+    /// Addresses the client is connected to, one per connection.
+    ///
+    /// When host resolves to several addresses, connection is made to the first one.
+    /// Empty if connected through unix socket,
+    /// or before the first call, which opens the first connection.
+    pub fn peer_addrs(&self) -> Vec<SocketAddr> {
+        self.pool.peer_addrs()
+    }
+
+    /// Number of calls waiting for a connection to start their streams,
+    /// see `ClientConf::max_pending_calls`.
+    pub fn pending_calls(&self) -> usize {
+        self.pool.pending_calls()
+    }
+
     pub fn call_unary<Req, Resp>(
         &self,
         o: RequestOptions,
@@ -265,12 +711,117 @@ impl Client {
         Req: Send + 'static,
         Resp: Send + 'static,
     {
+        let retry_policy = self
+            .service_config
+            .method_config(&method.name)
+            .and_then(|c| c.retry_policy.clone());
+        if let Some(retry_policy) = retry_policy {
+            return self.call_unary_with_retry(o, req, method, retry_policy);
+        }
         SingleResponse::new(
             self.call_impl(o, Some(req), method)
                 .and_then(|(_req, resp)| resp.single()),
         )
     }
 
+    /// Unary call retried according to service config.
+    ///
+    /// Request timeout limits all attempts together.
+    fn call_unary_with_retry<Req, Resp>(
+        &self,
+        o: RequestOptions,
+        req: Req,
+        method: ArcOrStatic<MethodDescriptor<Req, Resp>>,
+        retry_policy: RetryPolicy,
+    ) -> SingleResponse<Resp>
+    where
+        Req: Send + 'static,
+        Resp: Send + 'static,
+    {
+        // serialized once to be resent by each attempt
+        let req = match method.req_marshaller.write(&req) {
+            Ok(req) => Bytes::from(req),
+            Err(e) => return SingleResponse::err(e),
+        };
+        let method = ArcOrStatic::Arc(Arc::new(MethodDescriptor {
+            name: method.name.clone(),
+            streaming: method.streaming,
+            req_marshaller: ArcOrStatic::Static(&MarshallerBytes),
+            resp_marshaller: method.resp_marshaller.clone(),
+        }));
+        let timeout = self
+            .service_config
+            .method_config(&method.name)
+            .and_then(|c| c.timeout);
+        let timeout = match (o.timeout, timeout) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+        // all attempts are logged with the same call id
+        let o = match self.generate_call_id && o.call_id().is_none() {
+            true => o.with_call_id(CallId::generate()),
+            false => o,
+        };
+
+        let client = self.clone();
+        let attempts = future::loop_fn(
+            1,
+            move |attempt| -> GrpcFuture<Loop<(Metadata, Resp, Metadata), u32>> {
+                let mut o = o.clone();
+                if let Some(deadline) = deadline {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Box::new(future::err(deadline_exceeded()));
+                    }
+                    o.timeout = Some(deadline - now);
+                }
+                let retry_policy = retry_policy.clone();
+                let name = method.name.clone();
+                Box::new(
+                    client
+                        .call_impl(o, Some(req.clone()), method.clone())
+                        .and_then(|(_req, resp)| resp.single().join_metadata_result())
+                        .then(move |r| -> GrpcFuture<_> {
+                            match r {
+                                Ok(r) => Box::new(future::ok(Loop::Break(r))),
+                                Err(e) => {
+                                    let status = e.status().code;
+                                    if !retry_policy.is_retryable(attempt, status) {
+                                        return Box::new(future::err(e));
+                                    }
+                                    debug!("retrying {} failed with {:?}", name, status);
+                                    Box::new(
+                                        sleep(retry_policy.backoff(attempt))
+                                            .then(move |_| Ok(Loop::Continue(attempt + 1))),
+                                    )
+                                }
+                            }
+                        }),
+                )
+            },
+        );
+        SingleResponse::new(attempts.map(|(metadata, resp, trailing_metadata)| {
+            let resp: GrpcFuture<_> = Box::new(future::ok((resp, trailing_metadata)));
+            (metadata, resp)
+        }))
+    }
+
+    /// Unary call returning metadata and message in a single struct.
+    pub fn call_unary_full<Req, Resp>(
+        &self,
+        o: RequestOptions,
+        req: Req,
+        method: ArcOrStatic<MethodDescriptor<Req, Resp>>,
+    ) -> GrpcFuture<UnaryResponse<Resp>>
+    where
+        Req: Send + 'static,
+        Resp: Send + 'static,
+    {
+        self.call_unary(o, req, method).join_full()
+    }
+
     pub fn call_server_streaming<Req, Resp>(
         &self,
         o: RequestOptions,
@@ -288,29 +839,52 @@ impl Client {
         )
     }
 
+    /// Call is started when either request sink or response is polled,
+    /// messages sent before that are buffered.
     pub fn call_client_streaming<Req, Resp>(
         &self,
         o: RequestOptions,
         method: ArcOrStatic<MethodDescriptor<Req, Resp>>,
-    ) -> impl Future<Item = (ClientRequestSink<Req>, SingleResponse<Resp>), Error = error::Error>
+    ) -> (RequestSink<Req>, SingleResponse<Resp>)
     where
         Req: Send + 'static,
         Resp: Send + 'static,
     {
-        self.call_impl(o, None, method)
-            .map(|(req, resp)| (req, resp.single()))
+        let (req, resp) = start_streaming_call(self.call_impl(o, None, method));
+        (req, resp.single())
     }
 
+    /// Call is started when either request sink or response is polled,
+    /// messages sent before that are buffered.
     pub fn call_bidi<Req, Resp>(
         &self,
         o: RequestOptions,
         method: ArcOrStatic<MethodDescriptor<Req, Resp>>,
-    ) -> impl Future<Item = (ClientRequestSink<Req>, StreamingResponse<Resp>), Error = error::Error>
+    ) -> (RequestSink<Req>, StreamingResponse<Resp>)
     where
         Req: Send + 'static,
         Resp: Send + 'static,
     {
-        self.call_impl(o, None, method)
+        start_streaming_call(self.call_impl(o, None, method))
+    }
+
+    /// Call arbitrary method sending and receiving serialized messages.
+    ///
+    /// `method` is a full method name like `/helloworld.Greeter/SayHello`.
+    /// Useful for proxies which forward calls without knowing message types.
+    pub fn call_raw(
+        &self,
+        o: RequestOptions,
+        method: &str,
+    ) -> impl Future<Item = (ClientRequestSink<Bytes>, StreamingResponse<Bytes>), Error = error::Error>
+    {
+        let method = MethodDescriptor {
+            name: StringOrStatic::from(method),
+            streaming: GrpcStreaming::Bidi,
+            req_marshaller: ArcOrStatic::Static(&MarshallerBytes),
+            resp_marshaller: ArcOrStatic::Static(&MarshallerBytes),
+        };
+        self.call_impl(o, None, ArcOrStatic::Arc(Arc::new(method)))
     }
 }
 
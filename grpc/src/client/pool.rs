@@ -0,0 +1,601 @@
+//! HTTP/2 connections of a client.
+
+use std::net::SocketAddr;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::Weak;
+use std::time::Duration;
+
+use bytes::Bytes;
+
+use futures::future;
+use futures::sync::oneshot;
+use futures::Future;
+use futures::Poll;
+use futures::Stream;
+
+use httpbis;
+use httpbis::Headers;
+
+use error::Error;
+use error::Status;
+use futures_grpc::GrpcFuture;
+use proto::grpc_status::GrpcStatus;
+use resp::StreamingResponse;
+use result;
+use stream_item::GrpcStreamWithTrailingMetadata;
+use timer;
+use timer::WithDeadline;
+
+/// Opens new connection to the backend, resolves to connection and address
+/// it is dialed to (unless connected through unix socket).
+///
+/// Must not block: it is called on the thread which starts the call,
+/// which may be an event loop thread.
+pub(crate) type Connect =
+    Box<Fn() -> GrpcFuture<(httpbis::Client, Option<SocketAddr>)> + Send + Sync>;
+
+struct PooledConnection {
+    client: Arc<httpbis::Client>,
+    peer_addr: Option<SocketAddr>,
+    active_streams: Arc<AtomicUsize>,
+    /// A stream has been started on the connection
+    established: Arc<AtomicBool>,
+}
+
+impl PooledConnection {
+    /// Count a new call as active on this connection.
+    fn start_stream(&self, pool: &Arc<ConnectionPool>) -> (Arc<httpbis::Client>, StreamGuard) {
+        self.active_streams.fetch_add(1, Ordering::SeqCst);
+        (
+            self.client.clone(),
+            StreamGuard {
+                active_streams: self.active_streams.clone(),
+                established: self.established.clone(),
+                connection: PooledConnectionRef {
+                    pool: Arc::downgrade(pool),
+                    client: Arc::downgrade(&self.client),
+                },
+            },
+        )
+    }
+}
+
+/// Connection of a call, which does not keep the connection or the pool alive.
+#[derive(Clone)]
+pub(crate) struct PooledConnectionRef {
+    pool: Weak<ConnectionPool>,
+    client: Weak<httpbis::Client>,
+}
+
+impl PooledConnectionRef {
+    /// Stop assigning new calls to the connection, see `ConnectionPool::remove`.
+    pub fn remove(&self) {
+        if let (Some(pool), Some(client)) = (self.pool.upgrade(), self.client.upgrade()) {
+            pool.remove(&client);
+        }
+    }
+}
+
+struct Connections {
+    open: Vec<PooledConnection>,
+    /// Number of connections being established
+    connecting: usize,
+    /// Calls waiting for the first connection to be established
+    waiting: Vec<oneshot::Sender<::std::result::Result<(), Status>>>,
+}
+
+/// Connections to the same backend.
+///
+/// Calls are sent over the least loaded connection. New connection is opened
+/// when all connections have `max_streams_per_connection` active calls,
+/// until there are `max_connections` connections, or when there are no connections:
+/// on first call, or after all connections failed to start a stream.
+///
+/// Connections are established without holding the lock, calls started
+/// while there is no connection wait for the connection being established.
+pub(crate) struct ConnectionPool {
+    connections: Mutex<Connections>,
+    connect: Connect,
+    max_connections: usize,
+    max_streams_per_connection: Option<usize>,
+    /// Calls waiting for a connection to start their stream
+    pending_calls: Arc<AtomicUsize>,
+    max_pending_calls: Option<usize>,
+    /// Limit of the first stream start on a connection, see `ClientConf::connect_timeout`
+    connect_timeout: Option<Duration>,
+}
+
+impl ConnectionPool {
+    /// Create a pool, the first connection is opened on first call.
+    pub fn new(
+        connect: Connect,
+        max_connections: Option<usize>,
+        max_streams_per_connection: Option<usize>,
+        max_pending_calls: Option<usize>,
+        connect_timeout: Option<Duration>,
+    ) -> ConnectionPool {
+        ConnectionPool {
+            connections: Mutex::new(Connections {
+                open: Vec::new(),
+                connecting: 0,
+                waiting: Vec::new(),
+            }),
+            connect,
+            max_connections: max_connections.unwrap_or(1).max(1),
+            max_streams_per_connection,
+            pending_calls: Arc::new(AtomicUsize::new(0)),
+            max_pending_calls,
+            connect_timeout,
+        }
+    }
+
+    /// Number of open connections.
+    pub fn len(&self) -> usize {
+        self.connections.lock().unwrap().open.len()
+    }
+
+    /// Addresses of open connections.
+    pub fn peer_addrs(&self) -> Vec<SocketAddr> {
+        let connections = self.connections.lock().unwrap();
+        connections
+            .open
+            .iter()
+            .filter_map(|c| c.peer_addr)
+            .collect()
+    }
+
+    /// Number of calls waiting for a connection to start their stream.
+    pub fn pending_calls(&self) -> usize {
+        self.pending_calls.load(Ordering::SeqCst)
+    }
+
+    /// Count the call as pending until returned guard is dropped,
+    /// or fail with `RESOURCE_EXHAUSTED` if `max_pending_calls` calls are already pending.
+    fn start_pending(&self) -> result::Result<PendingCallGuard> {
+        let pending = self.pending_calls.fetch_add(1, Ordering::SeqCst);
+        let guard = PendingCallGuard {
+            pending_calls: self.pending_calls.clone(),
+        };
+        match self.max_pending_calls {
+            Some(max) if pending >= max => Err(Error::Status(Status::new(
+                GrpcStatus::ResourceExhausted,
+                format!("too many calls waiting for connection: {}", max),
+            ))),
+            _ => Ok(guard),
+        }
+    }
+
+    /// Stop assigning new calls to the connection.
+    ///
+    /// Calls in flight hold the connection until they complete.
+    /// Connection is removed after it failed to start a stream, or when
+    /// a call on it is rejected by draining server. HTTP/2 `GOAWAY`
+    /// itself is handled by `httpbis`, which does not report it to the pool.
+    pub fn remove(&self, client: &Arc<httpbis::Client>) {
+        let mut connections = self.connections.lock().unwrap();
+        connections.open.retain(|c| !Arc::ptr_eq(&c.client, client));
+    }
+
+    /// Pick a connection for a new call, opening a new connection if needed.
+    ///
+    /// Call is counted as active on the connection until returned guard is dropped.
+    pub fn pick(pool: &Arc<ConnectionPool>) -> GrpcFuture<(Arc<httpbis::Client>, StreamGuard)> {
+        let mut connections = pool.connections.lock().unwrap();
+
+        let least_loaded = connections
+            .open
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, c)| c.active_streams.load(Ordering::SeqCst))
+            .map(|(i, c)| (i, c.active_streams.load(Ordering::SeqCst)));
+
+        match least_loaded {
+            Some((index, active_streams)) => {
+                let full = match pool.max_streams_per_connection {
+                    Some(max) => active_streams >= max,
+                    None => false,
+                };
+                let opened = connections.open.len() + connections.connecting;
+                if !full || opened >= pool.max_connections {
+                    // when the pool is exhausted, the call is queued by `httpbis`
+                    // on the least loaded connection
+                    return Box::new(future::ok(connections.open[index].start_stream(pool)));
+                }
+                debug!(
+                    "all {} connections are full, opening new connection",
+                    connections.open.len()
+                );
+            }
+            None if connections.connecting > 0 => {
+                let (tx, rx) = oneshot::channel();
+                connections.waiting.push(tx);
+                let pool = pool.clone();
+                return Box::new(
+                    rx.map_err(|_| Error::Other("connection pool is gone"))
+                        .and_then(move |connected| -> GrpcFuture<_> {
+                            match connected {
+                                Ok(()) => ConnectionPool::pick(&pool),
+                                Err(status) => Box::new(future::err(Error::Status(status))),
+                            }
+                        }),
+                );
+            }
+            None => debug!("no open connections, connecting"),
+        }
+
+        connections.connecting += 1;
+        drop(connections);
+
+        let guard = ConnectingGuard { pool: pool.clone() };
+        Box::new((pool.connect)().then(move |connected| match connected {
+            Ok((client, peer_addr)) => {
+                let connection = PooledConnection {
+                    client: Arc::new(client),
+                    peer_addr,
+                    active_streams: Arc::new(AtomicUsize::new(0)),
+                    established: Arc::new(AtomicBool::new(false)),
+                };
+                let picked = connection.start_stream(&guard.pool);
+                guard.pool.connections.lock().unwrap().open.push(connection);
+                // waiting calls pick the new connection
+                drop(guard);
+                Ok(picked)
+            }
+            Err(e) => {
+                let status = e.status();
+                let waiting: Vec<_> = {
+                    let mut connections = guard.pool.connections.lock().unwrap();
+                    connections.waiting.drain(..).collect()
+                };
+                for tx in waiting {
+                    let _ = tx.send(Err(status.clone()));
+                }
+                Err(e)
+            }
+        }))
+    }
+}
+
+/// Connection is counted as being established until this object is dropped.
+///
+/// Calls waiting for the connection are woken up to pick a connection again,
+/// so they do not wait forever if the call which opened the connection was dropped.
+struct ConnectingGuard {
+    pool: Arc<ConnectionPool>,
+}
+
+impl Drop for ConnectingGuard {
+    fn drop(&mut self) {
+        let waiting: Vec<_> = {
+            let mut connections = self.pool.connections.lock().unwrap();
+            connections.connecting -= 1;
+            connections.waiting.drain(..).collect()
+        };
+        for tx in waiting {
+            let _ = tx.send(Ok(()));
+        }
+    }
+}
+
+/// Result of starting a stream: started stream, or connection which failed
+/// to start it with the error of `httpbis`.
+type Started = ::std::result::Result<
+    (httpbis::ClientRequest, httpbis::Response, StreamGuard),
+    (Arc<httpbis::Client>, httpbis::Error),
+>;
+
+/// Start HTTP/2 stream of a call on a picked connection.
+///
+/// Until a stream is started on the connection, the start is limited by
+/// `connect_timeout`: when it expires, the connection is removed from the pool
+/// and the call fails with `UNAVAILABLE`.
+fn start_stream(
+    pool: &Arc<ConnectionPool>,
+    client: Arc<httpbis::Client>,
+    guard: StreamGuard,
+    headers: Headers,
+    body: Option<Bytes>,
+    end_stream: bool,
+) -> GrpcFuture<Started> {
+    let established = guard.established.load(Ordering::SeqCst);
+    let started = client.start_request(headers, body, None, end_stream).then({
+        let client = client.clone();
+        move |r| -> result::Result<Started> {
+            Ok(match r {
+                Ok((req, resp)) => {
+                    guard.established.store(true, Ordering::SeqCst);
+                    Ok((req, resp, guard))
+                }
+                Err(e) => Err((client, e)),
+            })
+        }
+    });
+    match pool.connect_timeout {
+        Some(timeout) if !established => {
+            let pool = pool.clone();
+            Box::new(
+                WithDeadline {
+                    inner: started,
+                    sleep: timer::sleep(timeout),
+                }
+                // `started` does not fail, so the error is the expired timeout
+                .map_err(move |_| {
+                    debug!("connection not established in {:?}", timeout);
+                    pool.remove(&client);
+                    Error::Status(Status::new(
+                        GrpcStatus::Unavailable,
+                        format!("connection not established in {:?}", timeout),
+                    ))
+                }),
+            )
+        }
+        _ => Box::new(started),
+    }
+}
+
+/// Start HTTP/2 stream of a call on a connection of the pool.
+///
+/// When the connection fails to start the stream, e. g. because it is
+/// closed after `GOAWAY`, the call was not sent: the connection is removed
+/// from the pool, so calls in flight on it complete but new calls
+/// are not assigned to it, and the call is started once more on another
+/// connection, opened if needed.
+///
+/// Connections of a grpc-rust server are also removed when a call on them
+/// is rejected because the server is draining, see `Server::set_draining`.
+///
+/// Call is counted as pending until the stream is started, e. g. while
+/// connection is being established, and fails at once if there are
+/// already `max_pending_calls` pending calls.
+pub(crate) fn start_call_stream(
+    pool: Arc<ConnectionPool>,
+    headers: Headers,
+    body: Option<Bytes>,
+    end_stream: bool,
+) -> GrpcFuture<(httpbis::ClientRequest, httpbis::Response, StreamGuard)> {
+    let pending = match pool.start_pending() {
+        Ok(pending) => pending,
+        Err(e) => return Box::new(future::err(e)),
+    };
+    let retry_headers = headers.clone();
+    let retry_body = body.clone();
+    let retry_pool = pool.clone();
+    Box::new(
+        ConnectionPool::pick(&pool)
+            .and_then(move |(client, guard)| {
+                start_stream(&pool, client, guard, headers, body, end_stream)
+            })
+            .and_then(move |started| -> GrpcFuture<_> {
+                let (client, e) = match started {
+                    Ok(started) => return Box::new(future::ok(started)),
+                    Err(e) => e,
+                };
+                debug!("connection failed to start stream, retrying call: {}", e);
+                let pool = retry_pool;
+                pool.remove(&client);
+                Box::new(
+                    ConnectionPool::pick(&pool)
+                        .and_then(move |(client, guard)| {
+                            start_stream(
+                                &pool,
+                                client,
+                                guard,
+                                retry_headers,
+                                retry_body,
+                                end_stream,
+                            )
+                        })
+                        .and_then(|started| started.map_err(|(_, e)| Error::from(e))),
+                )
+            })
+            .then(move |r| {
+                drop(pending);
+                r
+            }),
+    )
+}
+
+/// Call is counted as pending until this object is dropped.
+struct PendingCallGuard {
+    pending_calls: Arc<AtomicUsize>,
+}
+
+impl Drop for PendingCallGuard {
+    fn drop(&mut self) {
+        self.pending_calls.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Call is counted as active on its connection until this object is dropped.
+pub(crate) struct StreamGuard {
+    active_streams: Arc<AtomicUsize>,
+    established: Arc<AtomicBool>,
+    connection: PooledConnectionRef,
+}
+
+impl StreamGuard {
+    /// Connection the call is sent over.
+    pub fn connection(&self) -> PooledConnectionRef {
+        self.connection.clone()
+    }
+}
+
+impl Drop for StreamGuard {
+    fn drop(&mut self) {
+        self.active_streams.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Future or stream which holds `StreamGuard` while it is alive.
+struct WithStreamGuard<S> {
+    inner: S,
+    _guard: Arc<StreamGuard>,
+}
+
+impl<F: Future<Error = Error>> Future for WithStreamGuard<F> {
+    type Item = F::Item;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<F::Item, Error> {
+        self.inner.poll()
+    }
+}
+
+impl<S: Stream<Error = Error>> Stream for WithStreamGuard<S> {
+    type Item = S::Item;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<S::Item>, Error> {
+        self.inner.poll()
+    }
+}
+
+/// Keep call counted as active on its connection until response is dropped.
+pub(crate) fn response_with_stream_guard<Resp: Send + 'static>(
+    resp: StreamingResponse<Resp>,
+    guard: StreamGuard,
+) -> StreamingResponse<Resp> {
+    let guard = Arc::new(guard);
+    let stream_guard = guard.clone();
+    StreamingResponse::new(
+        WithStreamGuard {
+            inner: resp.0,
+            _guard: guard,
+        }
+        .map(move |(metadata, stream)| {
+            let stream = WithStreamGuard {
+                inner: stream.0,
+                _guard: stream_guard,
+            };
+            (metadata, GrpcStreamWithTrailingMetadata::new(stream))
+        }),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use tls_api_stub;
+
+    /// Connections are established lazily, so the address does not need to be reachable.
+    fn client() -> result::Result<(httpbis::Client, Option<SocketAddr>)> {
+        let mut builder = httpbis::ClientBuilder::<tls_api_stub::TlsConnector>::new();
+        builder.set_addr(("127.0.0.1", 1))?;
+        Ok((builder.build()?, None))
+    }
+
+    fn connect() -> GrpcFuture<(httpbis::Client, Option<SocketAddr>)> {
+        Box::new(future::result(client()))
+    }
+
+    #[test]
+    fn opens_connections_when_full() {
+        let pool = Arc::new(ConnectionPool::new(
+            Box::new(connect),
+            Some(2),
+            Some(1),
+            None,
+            None,
+        ));
+        assert_eq!(0, pool.len());
+
+        let (_, a) = ConnectionPool::pick(&pool).wait().unwrap();
+        assert_eq!(1, pool.len());
+        let (_, b) = ConnectionPool::pick(&pool).wait().unwrap();
+        assert_eq!(2, pool.len());
+        // pool is exhausted
+        let (_, c) = ConnectionPool::pick(&pool).wait().unwrap();
+        assert_eq!(2, pool.len());
+
+        drop((a, b, c));
+        let (_, _d) = ConnectionPool::pick(&pool).wait().unwrap();
+        assert_eq!(2, pool.len());
+    }
+
+    #[test]
+    fn reconnects_after_remove() {
+        let pool = Arc::new(ConnectionPool::new(
+            Box::new(connect),
+            None,
+            None,
+            None,
+            None,
+        ));
+        let (first, _guard) = ConnectionPool::pick(&pool).wait().unwrap();
+
+        pool.remove(&first);
+        assert_eq!(0, pool.len());
+
+        let (second, _guard) = ConnectionPool::pick(&pool).wait().unwrap();
+        assert_eq!(1, pool.len());
+        assert!(!Arc::ptr_eq(&first, &second));
+    }
+
+    #[test]
+    fn remove_by_connection_of_call() {
+        let pool = Arc::new(ConnectionPool::new(
+            Box::new(connect),
+            None,
+            None,
+            None,
+            None,
+        ));
+        let (_, guard) = ConnectionPool::pick(&pool).wait().unwrap();
+        let connection = guard.connection();
+        assert_eq!(1, pool.len());
+
+        connection.remove();
+        assert_eq!(0, pool.len());
+
+        // does nothing when the pool is gone
+        drop(pool);
+        connection.remove();
+    }
+
+    #[test]
+    fn calls_wait_for_connection_being_established() {
+        static CONNECTS: AtomicUsize = AtomicUsize::new(0);
+
+        fn counting_connect() -> GrpcFuture<(httpbis::Client, Option<SocketAddr>)> {
+            CONNECTS.fetch_add(1, Ordering::SeqCst);
+            connect()
+        }
+
+        let pool = Arc::new(ConnectionPool::new(
+            Box::new(counting_connect),
+            None,
+            None,
+            None,
+            None,
+        ));
+        // second call waits for the connection opened by the first one
+        let first = ConnectionPool::pick(&pool);
+        let second = ConnectionPool::pick(&pool);
+        assert_eq!(1, CONNECTS.load(Ordering::SeqCst));
+
+        let (first, _guard) = first.wait().unwrap();
+        let (second, _guard) = second.wait().unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(1, CONNECTS.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn max_pending_calls() {
+        let pool = ConnectionPool::new(Box::new(connect), None, None, Some(1), None);
+
+        let first = pool.start_pending().unwrap();
+        assert_eq!(1, pool.pending_calls());
+        let e = pool.start_pending().err().expect("RESOURCE_EXHAUSTED");
+        assert_eq!(GrpcStatus::ResourceExhausted, Status::from(e).code);
+        assert_eq!(1, pool.pending_calls());
+
+        drop(first);
+        assert_eq!(0, pool.pending_calls());
+        let _second = pool.start_pending().unwrap();
+    }
+}
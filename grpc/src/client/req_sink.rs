@@ -1,17 +1,45 @@
+use std::sync::Arc;
+use std::sync::Mutex;
+
 use bytes::Bytes;
+use client::service_config::check_message_size;
 use client::types::ClientTypes;
+use common::frame_log::FrameLog;
 use common::sink::SinkCommon;
 use common::sink::SinkCommonUntyped;
 use common::sink::SinkUntyped;
+use common::sink::WriteFlags;
+use error;
+use error::Error;
+use error::Status;
 use futures::future;
 use futures::future::Future;
+use futures::sink::Sink;
+use futures::stream::Stream;
+use futures::sync::mpsc;
+use futures::sync::oneshot;
+use futures::task;
+use futures::task::Task;
+use futures::Async;
+use futures::AsyncSink;
 use futures::Poll;
+use futures::StartSend;
+use futures_grpc::GrpcFuture;
 use httpbis;
 use httpbis::StreamDead;
+use proto::grpc_frame::GRPC_HEADER_LEN;
+use req::StreamingRequest;
+use resp::StreamingResponse;
 use result;
+use stats::ClientCallStats;
 
 pub struct ClientRequestSinkUntyped {
     pub(crate) common: SinkCommonUntyped<ClientTypes>,
+    /// Set by `MethodConfig::max_request_message_bytes`
+    pub(crate) max_message_bytes: Option<usize>,
+    pub(crate) stats: Option<ClientCallStats>,
+    /// Set if `ClientConf::debug_frames` is enabled
+    pub(crate) frame_log: Option<FrameLog>,
 }
 
 impl SinkUntyped for ClientRequestSinkUntyped {
@@ -19,14 +47,29 @@ impl SinkUntyped for ClientRequestSinkUntyped {
         self.common.http.poll()
     }
 
-    fn send_data(&mut self, message: Bytes) -> result::Result<()> {
-        self.common.send_data(message)?;
-        Ok(())
+    fn send_frame(&mut self, frame: Bytes, flags: WriteFlags) -> result::Result<()> {
+        check_message_size(frame.len() - GRPC_HEADER_LEN, self.max_message_bytes)?;
+        if let Some(ref stats) = self.stats {
+            stats.message_sent(frame.len() - GRPC_HEADER_LEN);
+        }
+        if let Some(ref frame_log) = self.frame_log {
+            frame_log.grpc_frame("send", frame.len() - GRPC_HEADER_LEN);
+            frame_log.data("send", frame.len());
+        }
+        self.common.send_frame(frame, flags)
+    }
+
+    fn flush(&mut self) -> result::Result<()> {
+        Ok(self.common.flush()?)
     }
 }
 
 impl ClientRequestSinkUntyped {
     pub fn finish(&mut self) -> result::Result<()> {
+        if let Some(ref frame_log) = self.frame_log {
+            frame_log.end_stream("send");
+        }
+        self.common.flush()?;
         self.common.http.close()?;
         Ok(())
     }
@@ -37,6 +80,7 @@ pub struct ClientRequestSink<Req: Send + 'static> {
 }
 
 impl<Req: Send> ClientRequestSink<Req> {
+    /// Ready when peer flow control window allows sending more data.
     pub fn poll(&mut self) -> Poll<(), httpbis::StreamDead> {
         self.common.poll()
     }
@@ -45,11 +89,347 @@ impl<Req: Send> ClientRequestSink<Req> {
         future::poll_fn(|| self.poll()).wait()
     }
 
+    /// Same as `poll`, but with gRPC error.
+    pub fn poll_ready(&mut self) -> Poll<(), error::Error> {
+        Ok(self.poll()?)
+    }
+
+    /// Enqueue a message.
+    ///
+    /// Message is buffered even if flow control window is exhausted,
+    /// so large request streams should wait for `poll_ready` first
+    /// (or use this sink as `futures::Sink`, e. g. with `send_all`).
     pub fn send_data(&mut self, message: Req) -> result::Result<()> {
         self.common.send_data(message)
     }
 
+    /// Enqueue a message, with `buffer_hint` it may be sent later
+    /// together with following messages.
+    pub fn send_data_with_flags(&mut self, message: Req, flags: WriteFlags) -> result::Result<()> {
+        self.common.send_data_with_flags(message, flags)
+    }
+
+    /// Send messages held back by `WriteFlags::buffer_hint`.
+    pub fn flush(&mut self) -> result::Result<()> {
+        self.common.flush()
+    }
+
+    /// Finish the request, buffered messages are sent first.
     pub fn finish(&mut self) -> result::Result<()> {
         self.common.sink.finish()
     }
 }
+
+/// Messages are accepted only when flow control window is available,
+/// `close` finishes the request.
+///
+/// Messages are sent with `buffer_hint` until `poll_complete`,
+/// so `send_all` packs small messages into fewer DATA frames.
+impl<Req: Send> Sink for ClientRequestSink<Req> {
+    type SinkItem = Req;
+    type SinkError = error::Error;
+
+    fn start_send(&mut self, item: Req) -> StartSend<Req, error::Error> {
+        if let Async::NotReady = self.poll_ready()? {
+            self.flush()?;
+            return Ok(AsyncSink::NotReady(item));
+        }
+        self.send_data_with_flags(item, WriteFlags { buffer_hint: true })?;
+        Ok(AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), error::Error> {
+        self.flush()?;
+        Ok(Async::Ready(()))
+    }
+
+    fn close(&mut self) -> Poll<(), error::Error> {
+        if self.common.sink.common.http.state() != httpbis::SenderState::Done {
+            self.finish()?;
+        }
+        Ok(Async::Ready(()))
+    }
+}
+
+/// Start of a client streaming or bidi call, driven by whichever
+/// of request sink and response is polled first.
+struct CallStart<Req: Send + 'static> {
+    /// Resolved when HTTP/2 stream is open, response is passed through a channel
+    future: Option<GrpcFuture<ClientRequestSink<Req>>>,
+    /// Set when the start is complete
+    sink: Option<Result<ClientRequestSink<Req>, Status>>,
+    /// Error of the start, taken by response
+    error: Option<Error>,
+    /// Messages sent before stream is open
+    pending: Vec<(Req, WriteFlags)>,
+    /// Request was finished before stream is open
+    finish: bool,
+    /// Tasks waiting for the start, other than the task which polled the future
+    waiting: Vec<Task>,
+}
+
+impl<Req: Send> CallStart<Req> {
+    /// Start of a sink which is not connected to a call
+    fn started() -> CallStart<Req> {
+        CallStart {
+            future: None,
+            sink: None,
+            error: None,
+            pending: Vec::new(),
+            finish: false,
+            waiting: Vec::new(),
+        }
+    }
+
+    /// Returns `false` if the call is not started yet.
+    fn poll(&mut self) -> bool {
+        let result = match self.future {
+            Some(ref mut future) => future.poll(),
+            None => return true,
+        };
+        let sink = match result {
+            Ok(Async::NotReady) => {
+                if !self.waiting.iter().any(|task| task.will_notify_current()) {
+                    self.waiting.push(task::current());
+                }
+                return false;
+            }
+            Ok(Async::Ready(sink)) => self.open(sink).map_err(|e| e.status()),
+            Err(e) => {
+                let status = e.status();
+                self.error = Some(e);
+                Err(status)
+            }
+        };
+        self.future = None;
+        self.sink = Some(sink);
+        for task in self.waiting.drain(..) {
+            task.notify();
+        }
+        true
+    }
+
+    /// Send messages accumulated before stream is open.
+    fn open(&mut self, mut sink: ClientRequestSink<Req>) -> result::Result<ClientRequestSink<Req>> {
+        for (message, flags) in self.pending.drain(..) {
+            sink.send_data_with_flags(message, flags)?;
+        }
+        if self.finish {
+            sink.finish()?;
+        }
+        Ok(sink)
+    }
+}
+
+/// Request stream of client streaming or bidi call.
+///
+/// Available right away, so the next message can be chosen after
+/// receiving responses to previous ones. Messages sent before
+/// the call is started are buffered.
+pub struct RequestSink<Req: Send + 'static> {
+    start: Arc<Mutex<CallStart<Req>>>,
+    /// Set for sinks created with `channel`, `None` after `finish`
+    channel: Option<Option<mpsc::UnboundedSender<Req>>>,
+}
+
+impl<Req: Send> RequestSink<Req> {
+    /// Request sink not connected to a call, which passes messages to returned stream.
+    ///
+    /// Useful to implement mock clients, stream ends when sink is finished.
+    pub fn channel() -> (RequestSink<Req>, StreamingRequest<Req>) {
+        let (tx, rx) = mpsc::unbounded();
+        let sink = RequestSink {
+            start: Arc::new(Mutex::new(CallStart::started())),
+            channel: Some(Some(tx)),
+        };
+        let stream = StreamingRequest::new(rx.map_err(|()| Error::Other("sink dropped")));
+        (sink, stream)
+    }
+
+    /// Send message to the stream of `channel` sink.
+    fn send_to_channel(&mut self, message: Req) -> result::Result<()> {
+        match self.channel {
+            Some(Some(ref tx)) => tx
+                .unbounded_send(message)
+                .map_err(|_| Error::Other("request stream is dropped")),
+            _ => Err(Error::Other("request sink is finished")),
+        }
+    }
+
+    /// Ready when the call is started and peer flow control window
+    /// allows sending more data.
+    pub fn poll_ready(&mut self) -> Poll<(), Error> {
+        if self.channel.is_some() {
+            return Ok(Async::Ready(()));
+        }
+        let mut start = self.start.lock().unwrap();
+        if !start.poll() {
+            return Ok(Async::NotReady);
+        }
+        match start.sink {
+            Some(Ok(ref mut sink)) => sink.poll_ready(),
+            Some(Err(ref status)) => Err(Error::Status(status.clone())),
+            None => unreachable!(),
+        }
+    }
+
+    pub fn block_wait(&mut self) -> result::Result<()> {
+        future::poll_fn(|| self.poll_ready()).wait()
+    }
+
+    /// Enqueue a message.
+    ///
+    /// Message is buffered even if the call is not started yet
+    /// or flow control window is exhausted, see `ClientRequestSink::send_data`.
+    pub fn send_data(&mut self, message: Req) -> result::Result<()> {
+        self.send_data_with_flags(message, WriteFlags::default())
+    }
+
+    /// Enqueue a message, see `ClientRequestSink::send_data_with_flags`.
+    pub fn send_data_with_flags(&mut self, message: Req, flags: WriteFlags) -> result::Result<()> {
+        if self.channel.is_some() {
+            return self.send_to_channel(message);
+        }
+        let mut start = self.start.lock().unwrap();
+        match start.sink {
+            Some(Ok(ref mut sink)) => sink.send_data_with_flags(message, flags),
+            Some(Err(ref status)) => Err(Error::Status(status.clone())),
+            None => {
+                start.pending.push((message, flags));
+                Ok(())
+            }
+        }
+    }
+
+    /// Send messages held back by `WriteFlags::buffer_hint`.
+    ///
+    /// Messages sent before the call is started are sent when it starts.
+    pub fn flush(&mut self) -> result::Result<()> {
+        let mut start = self.start.lock().unwrap();
+        match start.sink {
+            Some(Ok(ref mut sink)) => sink.flush(),
+            Some(Err(ref status)) => Err(Error::Status(status.clone())),
+            None => Ok(()),
+        }
+    }
+
+    pub fn finish(&mut self) -> result::Result<()> {
+        if let Some(ref mut tx) = self.channel {
+            tx.take();
+            return Ok(());
+        }
+        let mut start = self.start.lock().unwrap();
+        match start.sink {
+            Some(Ok(ref mut sink)) => sink.finish(),
+            Some(Err(ref status)) => Err(Error::Status(status.clone())),
+            None => {
+                start.finish = true;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Messages are accepted only when the call is started and flow control
+/// window is available, `close` finishes the request.
+///
+/// Like `ClientRequestSink`, messages are buffered until `poll_complete`.
+impl<Req: Send> Sink for RequestSink<Req> {
+    type SinkItem = Req;
+    type SinkError = Error;
+
+    fn start_send(&mut self, item: Req) -> StartSend<Req, Error> {
+        if let Async::NotReady = self.poll_ready()? {
+            self.flush()?;
+            return Ok(AsyncSink::NotReady(item));
+        }
+        self.send_data_with_flags(item, WriteFlags { buffer_hint: true })?;
+        Ok(AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Error> {
+        self.flush()?;
+        Ok(Async::Ready(()))
+    }
+
+    fn close(&mut self) -> Poll<(), Error> {
+        if self.channel.is_some() {
+            self.finish()?;
+            return Ok(Async::Ready(()));
+        }
+        let mut start = self.start.lock().unwrap();
+        match start.sink {
+            Some(Ok(ref mut sink)) => sink.close(),
+            Some(Err(ref status)) => Err(Error::Status(status.clone())),
+            None => {
+                start.finish = true;
+                Ok(Async::Ready(()))
+            }
+        }
+    }
+}
+
+/// Response of a call started with `start_streaming_call`.
+struct ResponseStart<Req: Send + 'static, Resp: Send + 'static> {
+    start: Arc<Mutex<CallStart<Req>>>,
+    resp: oneshot::Receiver<StreamingResponse<Resp>>,
+}
+
+impl<Req: Send, Resp: Send> Future for ResponseStart<Req, Resp> {
+    type Item = StreamingResponse<Resp>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<StreamingResponse<Resp>, Error> {
+        {
+            let mut start = self.start.lock().unwrap();
+            if !start.poll() {
+                return Ok(Async::NotReady);
+            }
+            if let Some(e) = start.error.take() {
+                return Err(e);
+            }
+        }
+        // response is sent before the start is complete
+        Ok(self.resp.poll()?)
+    }
+}
+
+/// Split the start of a call into request sink and response
+/// which can be used independently before the call is started.
+pub(crate) fn start_streaming_call<Req, Resp, F>(
+    future: F,
+) -> (RequestSink<Req>, StreamingResponse<Resp>)
+where
+    Req: Send + 'static,
+    Resp: Send + 'static,
+    F: Future<Item = (ClientRequestSink<Req>, StreamingResponse<Resp>), Error = Error>
+        + Send
+        + 'static,
+{
+    let (resp_tx, resp_rx) = oneshot::channel();
+    let future = future.map(move |(sink, resp)| {
+        // receiver is dropped if response is dropped
+        let _ = resp_tx.send(resp);
+        sink
+    });
+    let start = Arc::new(Mutex::new(CallStart {
+        future: Some(Box::new(future)),
+        sink: None,
+        error: None,
+        pending: Vec::new(),
+        finish: false,
+        waiting: Vec::new(),
+    }));
+    let resp = ResponseStart {
+        start: start.clone(),
+        resp: resp_rx,
+    };
+    let resp = StreamingResponse::new(resp.map(|resp| resp.0).flatten());
+    (
+        RequestSink {
+            start,
+            channel: None,
+        },
+        resp,
+    )
+}
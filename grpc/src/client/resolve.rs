@@ -0,0 +1,58 @@
+//! Name resolution of backend host.
+
+use std::net::SocketAddr;
+use std::net::ToSocketAddrs;
+use std::sync::Once;
+
+use futures_cpupool;
+use futures_cpupool::CpuPool;
+
+use error::Error;
+use futures_grpc::GrpcFuture;
+use result;
+
+/// Thread resolving host names for all clients, started on first use.
+///
+/// Name resolution blocks, so it is not done on event loops
+/// or threads starting calls.
+fn resolver() -> CpuPool {
+    static INIT: Once = Once::new();
+    static mut RESOLVER: Option<CpuPool> = None;
+
+    unsafe {
+        INIT.call_once(|| {
+            RESOLVER = Some(
+                futures_cpupool::Builder::new()
+                    .pool_size(1)
+                    .name_prefix("grpc-resolver-")
+                    .create(),
+            );
+        });
+        RESOLVER.clone().unwrap()
+    }
+}
+
+/// Address to dial for `host`, the first address it resolves to.
+///
+/// Other addresses are not tried: `httpbis` dials a single address.
+pub(crate) fn resolve(host: String, port: u16) -> GrpcFuture<SocketAddr> {
+    Box::new(resolver().spawn_fn(move || -> result::Result<SocketAddr> {
+        match (&host[..], port).to_socket_addrs()?.next() {
+            Some(addr) => Ok(addr),
+            None => Err(Error::Other("host resolved to no addresses")),
+        }
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use futures::Future;
+
+    #[test]
+    fn resolve_ip() {
+        let addr: SocketAddr = "127.0.0.1:80".parse().unwrap();
+        assert_eq!(addr, resolve("127.0.0.1".to_owned(), 80).wait().unwrap());
+    }
+}
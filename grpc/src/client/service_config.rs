@@ -0,0 +1,334 @@
+//! Per-method call settings applied by the client,
+//! see [service config](https://github.com/grpc/grpc/blob/master/doc/service_config.md).
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+#[cfg(feature = "with-serde")]
+use serde_json;
+
+use error::Error;
+#[cfg(feature = "with-serde")]
+use error::ProtocolError;
+use error::Status;
+use proto::grpc_status::GrpcStatus;
+use result;
+
+/// Maximum number of attempts allowed by the service config spec,
+/// larger values are silently capped.
+const MAX_ATTEMPTS_LIMIT: u32 = 5;
+
+/// How failed unary calls are retried.
+///
+/// Request is serialized once and response is buffered, so that a failed
+/// attempt can be retried transparently. Streaming calls are never retried.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// Number of attempts including the original call
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub backoff_multiplier: f64,
+    /// Calls failed with other statuses are not retried
+    pub retryable_status_codes: Vec<GrpcStatus>,
+}
+
+impl RetryPolicy {
+    pub(crate) fn is_retryable(&self, attempt: u32, status: GrpcStatus) -> bool {
+        attempt < self.max_attempts.min(MAX_ATTEMPTS_LIMIT)
+            && self.retryable_status_codes.contains(&status)
+    }
+
+    /// Delay before the attempt following `attempt` (counting from 1).
+    ///
+    /// Unlike other implementations, backoff is not randomized.
+    pub(crate) fn backoff(&self, attempt: u32) -> Duration {
+        let initial = self.initial_backoff.as_secs() as f64
+            + self.initial_backoff.subsec_nanos() as f64 / 1e9;
+        let secs = initial * self.backoff_multiplier.powi(attempt as i32 - 1);
+        let backoff = Duration::from_nanos((secs * 1e9) as u64);
+        backoff.min(self.max_backoff)
+    }
+}
+
+/// Settings of calls of a method.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MethodConfig {
+    /// Used when `RequestOptions::timeout` is not set or is longer.
+    pub timeout: Option<Duration>,
+    /// Fail the call with `RESOURCE_EXHAUSTED` instead of sending larger message.
+    pub max_request_message_bytes: Option<usize>,
+    /// Fail the call with `RESOURCE_EXHAUSTED` when larger message is received.
+    pub max_response_message_bytes: Option<usize>,
+    pub retry_policy: Option<RetryPolicy>,
+}
+
+/// Method configs keyed by method name.
+///
+/// Config of a method is looked up by full method name
+/// (`/helloworld.Greeter/SayHello`), then by service name, then
+/// default config is used.
+#[derive(Debug, Clone, Default)]
+pub struct ServiceConfig {
+    /// Keys are `/service/method`, `/service` or empty string for default config
+    methods: HashMap<String, MethodConfig>,
+}
+
+impl ServiceConfig {
+    pub fn new() -> ServiceConfig {
+        Default::default()
+    }
+
+    /// Config of a method, e. g. `/helloworld.Greeter/SayHello`.
+    pub fn set_method_config(&mut self, method: &str, config: MethodConfig) {
+        self.methods.insert(method.to_owned(), config);
+    }
+
+    /// Config of methods of a service without own config, e. g. `helloworld.Greeter`.
+    pub fn set_service_config(&mut self, service: &str, config: MethodConfig) {
+        self.methods.insert(format!("/{}", service), config);
+    }
+
+    /// Config of methods without own or service config.
+    pub fn set_default_config(&mut self, config: MethodConfig) {
+        self.methods.insert(String::new(), config);
+    }
+
+    /// Config applied to calls of given method.
+    pub fn method_config(&self, method: &str) -> Option<&MethodConfig> {
+        if let Some(config) = self.methods.get(method) {
+            return Some(config);
+        }
+        if let Some(pos) = method.rfind('/') {
+            if let Some(config) = self.methods.get(&method[..pos]) {
+                return Some(config);
+            }
+        }
+        self.methods.get("")
+    }
+
+    /// Parse service config JSON, as published in DNS TXT records.
+    ///
+    /// Only `methodConfig` section is used, load balancing settings are ignored.
+    #[cfg(feature = "with-serde")]
+    pub fn from_json(json: &[u8]) -> result::Result<ServiceConfig> {
+        let value: serde_json::Value = serde_json::from_slice(json)
+            .map_err(|e| Error::Protocol(ProtocolError::Marshaller(Box::new(e))))?;
+        let mut service_config = ServiceConfig::new();
+        let method_configs = match value.get("methodConfig") {
+            Some(method_configs) => method_configs
+                .as_array()
+                .ok_or(Error::Other("methodConfig must be an array"))?,
+            None => return Ok(service_config),
+        };
+        for method_config in method_configs {
+            let config = json::method_config(method_config)?;
+            let names = method_config
+                .get("name")
+                .and_then(|names| names.as_array())
+                .ok_or(Error::Other("methodConfig must have name array"))?;
+            for name in names {
+                let service = name.get("service").and_then(|s| s.as_str());
+                let method = name.get("method").and_then(|m| m.as_str());
+                match (service, method) {
+                    (None, _) | (Some(""), _) => service_config.set_default_config(config.clone()),
+                    (Some(service), None) | (Some(service), Some("")) => {
+                        service_config.set_service_config(service, config.clone())
+                    }
+                    (Some(service), Some(method)) => service_config
+                        .set_method_config(&format!("/{}/{}", service, method), config.clone()),
+                }
+            }
+        }
+        Ok(service_config)
+    }
+}
+
+/// Fail with `RESOURCE_EXHAUSTED` if message is larger than `max`.
+pub(crate) fn check_message_size(size: usize, max: Option<usize>) -> result::Result<()> {
+    match max {
+        Some(max) if size > max => Err(Error::Status(Status::new(
+            GrpcStatus::ResourceExhausted,
+            format!("message size {} exceeds limit {}", size, max),
+        ))),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(feature = "with-serde")]
+mod json {
+    use std::time::Duration;
+
+    use serde_json::Value;
+
+    use super::MethodConfig;
+    use super::RetryPolicy;
+    use error::Error;
+    use proto::grpc_status::GrpcStatus;
+    use result;
+
+    /// Duration in protobuf JSON encoding, e. g. `1.5s`
+    fn duration(value: &Value) -> result::Result<Duration> {
+        let secs = value
+            .as_str()
+            .filter(|s| s.ends_with('s'))
+            .and_then(|s| s[..s.len() - 1].parse::<f64>().ok())
+            .filter(|secs| *secs >= 0.0)
+            .ok_or(Error::Other("invalid duration in service config"))?;
+        Ok(Duration::from_nanos((secs * 1e9) as u64))
+    }
+
+    fn size(value: &Value) -> result::Result<usize> {
+        value
+            .as_u64()
+            .map(|size| size as usize)
+            .ok_or(Error::Other("invalid message size in service config"))
+    }
+
+    fn status(value: &Value) -> result::Result<GrpcStatus> {
+        let status = match *value {
+            Value::String(ref name) => GrpcStatus::from_name(name),
+            Value::Number(ref code) => code.as_u64().and_then(|c| GrpcStatus::from_code(c as u32)),
+            _ => None,
+        };
+        status.ok_or(Error::Other("invalid status code in service config"))
+    }
+
+    fn retry_policy(value: &Value) -> result::Result<RetryPolicy> {
+        let field = |name: &str| {
+            value
+                .get(name)
+                .ok_or(Error::Other("missing field in retryPolicy"))
+        };
+        let max_attempts = field("maxAttempts")?
+            .as_u64()
+            .filter(|n| *n >= 2)
+            .ok_or(Error::Other("retryPolicy.maxAttempts must be at least 2"))?;
+        let backoff_multiplier = field("backoffMultiplier")?
+            .as_f64()
+            .filter(|m| *m > 0.0)
+            .ok_or(Error::Other(
+                "retryPolicy.backoffMultiplier must be positive",
+            ))?;
+        let retryable_status_codes = field("retryableStatusCodes")?
+            .as_array()
+            .ok_or(Error::Other(
+                "retryPolicy.retryableStatusCodes must be an array",
+            ))?
+            .iter()
+            .map(status)
+            .collect::<result::Result<_>>()?;
+        Ok(RetryPolicy {
+            max_attempts: max_attempts as u32,
+            initial_backoff: duration(field("initialBackoff")?)?,
+            max_backoff: duration(field("maxBackoff")?)?,
+            backoff_multiplier,
+            retryable_status_codes,
+        })
+    }
+
+    pub fn method_config(value: &Value) -> result::Result<MethodConfig> {
+        Ok(MethodConfig {
+            timeout: value.get("timeout").map(duration).transpose()?,
+            max_request_message_bytes: value.get("maxRequestMessageBytes").map(size).transpose()?,
+            max_response_message_bytes: value
+                .get("maxResponseMessageBytes")
+                .map(size)
+                .transpose()?,
+            retry_policy: value.get("retryPolicy").map(retry_policy).transpose()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn lookup() {
+        let config = |millis| MethodConfig {
+            timeout: Some(Duration::from_millis(millis)),
+            ..Default::default()
+        };
+        let mut service_config = ServiceConfig::new();
+        service_config.set_method_config("/foo.Bar/Baz", config(1));
+        service_config.set_service_config("foo.Bar", config(2));
+        assert_eq!(
+            Some(&config(1)),
+            service_config.method_config("/foo.Bar/Baz")
+        );
+        assert_eq!(
+            Some(&config(2)),
+            service_config.method_config("/foo.Bar/Qux")
+        );
+        assert_eq!(None, service_config.method_config("/foo.Qux/Baz"));
+
+        service_config.set_default_config(config(3));
+        assert_eq!(
+            Some(&config(3)),
+            service_config.method_config("/foo.Qux/Baz")
+        );
+    }
+
+    #[test]
+    fn backoff() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(300),
+            backoff_multiplier: 2.0,
+            retryable_status_codes: vec![GrpcStatus::Unavailable],
+        };
+        assert_eq!(Duration::from_millis(100), policy.backoff(1));
+        assert_eq!(Duration::from_millis(200), policy.backoff(2));
+        assert_eq!(Duration::from_millis(300), policy.backoff(3));
+
+        assert!(policy.is_retryable(4, GrpcStatus::Unavailable));
+        assert!(!policy.is_retryable(5, GrpcStatus::Unavailable));
+        assert!(!policy.is_retryable(1, GrpcStatus::Internal));
+    }
+
+    #[cfg(feature = "with-serde")]
+    #[test]
+    fn from_json() {
+        let service_config = ServiceConfig::from_json(
+            br#"{
+                "methodConfig": [{
+                    "name": [{"service": "foo.Bar", "method": "Baz"}, {"service": "foo.Qux"}],
+                    "timeout": "1.5s",
+                    "maxResponseMessageBytes": 1024,
+                    "retryPolicy": {
+                        "maxAttempts": 3,
+                        "initialBackoff": "0.1s",
+                        "maxBackoff": "1s",
+                        "backoffMultiplier": 2,
+                        "retryableStatusCodes": ["UNAVAILABLE", 10]
+                    }
+                }]
+            }"#,
+        )
+        .expect("from_json");
+
+        let expected = MethodConfig {
+            timeout: Some(Duration::from_millis(1500)),
+            max_request_message_bytes: None,
+            max_response_message_bytes: Some(1024),
+            retry_policy: Some(RetryPolicy {
+                max_attempts: 3,
+                initial_backoff: Duration::from_millis(100),
+                max_backoff: Duration::from_secs(1),
+                backoff_multiplier: 2.0,
+                retryable_status_codes: vec![GrpcStatus::Unavailable, GrpcStatus::Aborted],
+            }),
+        };
+        assert_eq!(
+            Some(&expected),
+            service_config.method_config("/foo.Bar/Baz")
+        );
+        assert_eq!(
+            Some(&expected),
+            service_config.method_config("/foo.Qux/Any")
+        );
+        assert_eq!(None, service_config.method_config("/foo.Bar/Other"));
+    }
+}
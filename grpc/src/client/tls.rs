@@ -0,0 +1,91 @@
+use tls_api;
+use tls_api::TlsConnector;
+use tls_api::TlsConnectorBuilder;
+
+use error;
+use result;
+
+/// ALPN protocol id of HTTP/2 over TLS.
+pub(crate) const ALPN_H2: &[u8] = b"h2";
+
+/// Client TLS settings.
+///
+/// Used to create a TLS connector instead of relying on
+/// connector defaults, e.g. to trust a self-signed test CA.
+#[derive(Default, Debug, Clone)]
+pub struct ClientTlsConf {
+    /// DER-encoded certificates added to connector root store.
+    pub root_certificates: Vec<Vec<u8>>,
+    /// Host name used for SNI and certificate verification.
+    /// Client builder host is used if not specified.
+    pub sni_host: Option<String>,
+    /// Do not verify that certificate matches host name.
+    /// Must only be used in tests.
+    ///
+    /// The rest of certificate verification is still performed:
+    /// tls-api 0.2 has no portable switch to accept any certificate.
+    /// Use `build_connector_with` to disable it on the underlying builder,
+    /// or trust a self-signed test CA with `add_root_certificate_der`.
+    pub insecure_skip_hostname_verification: bool,
+    /// Advertise `h2` with ALPN, and fail if TLS implementation does not support ALPN.
+    pub require_alpn_h2: bool,
+}
+
+impl ClientTlsConf {
+    pub fn new() -> ClientTlsConf {
+        Default::default()
+    }
+
+    /// Add DER-encoded root certificate.
+    pub fn add_root_certificate_der(&mut self, der: Vec<u8>) -> &mut Self {
+        self.root_certificates.push(der);
+        self
+    }
+
+    /// Build a TLS connector with these settings.
+    pub fn build_connector<C: TlsConnector>(&self) -> result::Result<C> {
+        self.build_connector_with(|_builder: &mut C::Builder| Ok(()))
+    }
+
+    /// Build a TLS connector with these settings, then let `configure`
+    /// change the builder before the connector is built.
+    ///
+    /// Implementation-specific settings are applied to
+    /// `TlsConnectorBuilder::underlying_mut`, e. g. to accept
+    /// any certificate in tests with `tls-api-native-tls`:
+    ///
+    /// ```ignore
+    /// conf.build_connector_with::<tls_api_native_tls::TlsConnector, _>(|builder| {
+    ///     builder.underlying_mut().danger_accept_invalid_certs(true);
+    ///     Ok(())
+    /// })
+    /// ```
+    pub fn build_connector_with<C, F>(&self, configure: F) -> result::Result<C>
+    where
+        C: TlsConnector,
+        F: FnOnce(&mut C::Builder) -> result::Result<()>,
+    {
+        let mut builder = C::builder()?;
+
+        for der in &self.root_certificates {
+            builder.add_root_certificate(tls_api::Certificate::from_der(der.clone()))?;
+        }
+
+        if self.insecure_skip_hostname_verification {
+            builder.set_verify_hostname(false)?;
+        }
+
+        if self.require_alpn_h2 {
+            if !C::supports_alpn() {
+                return Err(error::Error::Other(
+                    "TLS implementation does not support ALPN",
+                ));
+            }
+            builder.set_alpn_protocols(&[ALPN_H2])?;
+        }
+
+        configure(&mut builder)?;
+
+        Ok(builder.build()?)
+    }
+}
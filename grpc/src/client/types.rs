@@ -1,10 +1,11 @@
+use client::cancel::SharedClientRequest;
 use client::req_sink::ClientRequestSinkUntyped;
 use common::types::Types;
 
 pub(crate) struct ClientTypes;
 
 impl Types for ClientTypes {
-    type HttpSink = httpbis::ClientRequest;
+    type HttpSink = SharedClientRequest;
     type SinkUntyped = ClientRequestSinkUntyped;
 }
 
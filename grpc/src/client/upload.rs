@@ -0,0 +1,180 @@
+//! Client streaming uploads resumed after connection failures.
+
+use std::io;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use bytes::Bytes;
+
+use client::Client;
+use error::Error;
+use method::MethodDescriptor;
+use or_static::arc::ArcOrStatic;
+use proto::grpc_status::GrpcStatus;
+use proto::metadata::MetadataKey;
+use req::RequestOptions;
+use result;
+
+/// Metadata with offset of the first byte sent by an upload attempt,
+/// added by default `UploadResume::start_attempt`.
+pub const UPLOAD_OFFSET_METADATA: &str = "x-upload-offset";
+
+const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+/// How client and server of an upload service agree on where
+/// an interrupted upload continues.
+pub trait UploadResume: Send + Sync + 'static {
+    /// Offset of data acknowledged by server, from which the next attempt starts.
+    ///
+    /// Called before retrying an attempt failed with `error`,
+    /// e. g. to call a unary method of the service which returns committed size.
+    fn acknowledged_offset(&self, client: &Client, error: &Error) -> result::Result<u64>;
+
+    /// Tell server where data of the attempt starts,
+    /// by default with `x-upload-offset` metadata.
+    fn start_attempt(&self, offset: u64, options: &mut RequestOptions) {
+        options.metadata.add(
+            MetadataKey::from(UPLOAD_OFFSET_METADATA),
+            Bytes::from(format!("{}", offset)),
+        );
+    }
+}
+
+impl<F> UploadResume for F
+where
+    F: Fn(&Client, &Error) -> result::Result<u64> + Send + Sync + 'static,
+{
+    fn acknowledged_offset(&self, client: &Client, error: &Error) -> result::Result<u64> {
+        self(client, error)
+    }
+}
+
+/// Upload of seekable data with a client streaming method,
+/// retried from the last acknowledged offset when the call fails with `UNAVAILABLE`.
+///
+/// Data is sent in chunks, each wrapped into request message by `make_request`
+/// together with offset of the chunk. Upload must be idempotent on server:
+/// data after acknowledged offset may be sent again.
+pub struct ResumableUpload<Req: Send + 'static, Resp: Send + 'static> {
+    client: Client,
+    method: ArcOrStatic<MethodDescriptor<Req, Resp>>,
+    options: RequestOptions,
+    resume: Arc<UploadResume>,
+    make_request: Arc<Fn(u64, Bytes) -> Req + Send + Sync>,
+    chunk_size: usize,
+    max_attempts: u32,
+    initial_backoff: Duration,
+}
+
+impl<Req: Send + 'static, Resp: Send + 'static> ResumableUpload<Req, Resp> {
+    pub fn new<R, F>(
+        client: &Client,
+        method: ArcOrStatic<MethodDescriptor<Req, Resp>>,
+        resume: R,
+        make_request: F,
+    ) -> ResumableUpload<Req, Resp>
+    where
+        R: UploadResume,
+        F: Fn(u64, Bytes) -> Req + Send + Sync + 'static,
+    {
+        ResumableUpload {
+            client: client.clone(),
+            method,
+            options: RequestOptions::new(),
+            resume: Arc::new(resume),
+            make_request: Arc::new(make_request),
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            initial_backoff: Duration::from_millis(100),
+        }
+    }
+
+    /// Options of each attempt, timeout applies to each attempt separately.
+    pub fn options(mut self, options: RequestOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Maximum size of data sent in a single message, 64 KiB by default.
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    /// Number of attempts including the first one, 5 by default.
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// Delay before the first retry, doubled for each next retry up to 10 seconds.
+    pub fn initial_backoff(mut self, backoff: Duration) -> Self {
+        self.initial_backoff = backoff;
+        self
+    }
+
+    /// Upload all data of `source` from its start.
+    ///
+    /// Blocks current thread, so it must not be called on event loop thread.
+    pub fn upload<S: Read + Seek>(&self, source: &mut S) -> result::Result<Resp> {
+        let mut offset = 0;
+        let mut backoff = self.initial_backoff;
+        let mut attempt = 1;
+        loop {
+            source.seek(SeekFrom::Start(offset))?;
+            let e = match self.attempt(source, offset) {
+                Ok(resp) => return Ok(resp),
+                Err(e) => e,
+            };
+            if attempt >= self.max_attempts || e.status().code != GrpcStatus::Unavailable {
+                return Err(e);
+            }
+            debug!(
+                "upload {} failed at attempt {}, retrying: {}",
+                self.method.name, attempt, e
+            );
+            thread::sleep(backoff);
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+            offset = self.resume.acknowledged_offset(&self.client, &e)?;
+            attempt += 1;
+        }
+    }
+
+    /// Send data of `source` starting at `offset` in a single call
+    fn attempt<S: Read>(&self, source: &mut S, offset: u64) -> result::Result<Resp> {
+        let mut options = self.options.clone();
+        self.resume.start_attempt(offset, &mut options);
+        let (mut sink, resp) = self
+            .client
+            .call_client_streaming(options, self.method.clone());
+
+        let mut buf = vec![0; self.chunk_size];
+        let mut offset = offset;
+        let sent = loop {
+            let len = match source.read(&mut buf) {
+                Ok(0) => break sink.finish(),
+                Ok(len) => len,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+            let message = (self.make_request)(offset, Bytes::from(&buf[..len]));
+            // wait for flow control window, so the data is not buffered in memory
+            let r = sink.block_wait().and_then(|()| sink.send_data(message));
+            if r.is_err() {
+                break r;
+            }
+            offset += len as u64;
+        };
+        match sent {
+            Ok(()) => resp.wait_drop_metadata(),
+            // response has the reason why the call failed
+            Err(e) => Err(resp.wait_drop_metadata().err().unwrap_or(e)),
+        }
+    }
+}
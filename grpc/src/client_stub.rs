@@ -1,3 +1,4 @@
+use client::channel::Channel;
 use client::{Client, ClientBuilder};
 use std::sync::Arc;
 use ClientConf;
@@ -7,6 +8,11 @@ use Result as grpc_Result;
 pub trait ClientStub: Sized {
     /// Create a client stub using given `Client` object.
     fn with_client(grpc_client: Arc<Client>) -> Self;
+
+    /// Create a client stub sharing the connection of given channel.
+    fn with_channel(channel: &Channel) -> Self {
+        Self::with_client(channel.client().clone())
+    }
 }
 
 pub trait ClientStubExt: Sized {
@@ -0,0 +1,123 @@
+//! Wire-level log of a call enabled by `debug_frames` option.
+
+use std::fmt::Write;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use httpbis::Headers;
+
+/// Sequential id of logged calls.
+///
+/// HTTP/2 stream ids are not exposed by `httpbis`,
+/// so events of one call are correlated by this id.
+static NEXT_CALL_ID: AtomicUsize = AtomicUsize::new(1);
+
+/// Logs HTTP/2 and gRPC frame events of a single call at `info` level.
+///
+/// `recv` and `send` are relative to this side of the call.
+#[derive(Clone)]
+pub(crate) struct FrameLog {
+    side: &'static str,
+    call_id: usize,
+    path: Arc<String>,
+}
+
+/// Headers as `name: value` list, values of binary headers are replaced with their length.
+fn format_headers(headers: &Headers) -> String {
+    let mut r = String::new();
+    for header in headers.iter() {
+        if !r.is_empty() {
+            r.push_str(", ");
+        }
+        if header.name().ends_with("-bin") {
+            let _ = write!(r, "{}: <{} bytes>", header.name(), header.value.len());
+        } else {
+            let _ = write!(
+                r,
+                "{}: {}",
+                header.name(),
+                String::from_utf8_lossy(&header.value)
+            );
+        }
+    }
+    r
+}
+
+impl FrameLog {
+    fn new(side: &'static str, path: &str) -> FrameLog {
+        FrameLog {
+            side,
+            call_id: NEXT_CALL_ID.fetch_add(1, Ordering::Relaxed),
+            path: Arc::new(path.to_owned()),
+        }
+    }
+
+    pub fn client(path: &str) -> FrameLog {
+        FrameLog::new("client", path)
+    }
+
+    pub fn server(path: &str) -> FrameLog {
+        FrameLog::new("server", path)
+    }
+
+    fn event(&self, direction: &str, event: &str) {
+        info!(
+            "{} call #{} {}: {} {}",
+            self.side, self.call_id, self.path, direction, event
+        );
+    }
+
+    pub fn headers(&self, direction: &str, headers: &Headers) {
+        self.event(direction, &format!("HEADERS [{}]", format_headers(headers)));
+    }
+
+    /// Trailers or Trailers-Only response, which is HEADERS frame with end of stream flag.
+    pub fn trailers(&self, direction: &str, headers: &Headers) {
+        self.event(
+            direction,
+            &format!("HEADERS end_stream [{}]", format_headers(headers)),
+        );
+    }
+
+    pub fn data(&self, direction: &str, len: usize) {
+        self.event(direction, &format!("DATA len={}", len));
+    }
+
+    /// End of stream flag of the last DATA frame.
+    pub fn end_stream(&self, direction: &str) {
+        self.event(direction, "END_STREAM");
+    }
+
+    /// Boundary of gRPC message decoded from or encoded to DATA frames.
+    pub fn grpc_frame(&self, direction: &str, message_len: usize) {
+        self.event(
+            direction,
+            &format!("gRPC frame message_len={}", message_len),
+        );
+    }
+
+    /// Stream reset by peer or connection failure.
+    pub fn error(&self, direction: &str, error: &::std::fmt::Display) {
+        self.event(direction, &format!("RST_STREAM or error: {}", error));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use httpbis::Header;
+
+    #[test]
+    fn headers() {
+        let headers = Headers::from_vec(vec![
+            Header::new(":status", "200"),
+            Header::new("grpc-trace-bin", "abc"),
+        ]);
+        assert_eq!(
+            ":status: 200, grpc-trace-bin: <3 bytes>",
+            format_headers(&headers)
+        );
+    }
+}
@@ -1,4 +1,5 @@
 use bytes::Bytes;
+use client::cancel::SharedClientRequest;
 use result;
 
 pub(crate) trait HttpSink {
@@ -6,14 +7,13 @@ pub(crate) trait HttpSink {
     fn send_data(&mut self, data: Bytes) -> result::Result<()>;
 }
 
-impl HttpSink for httpbis::ClientRequest {
+impl HttpSink for SharedClientRequest {
     fn state(&self) -> httpbis::SenderState {
-        self.state()
+        SharedClientRequest::state(self)
     }
 
     fn send_data(&mut self, data: Bytes) -> result::Result<()> {
-        self.send_data(data)?;
-        Ok(())
+        SharedClientRequest::send_data(self, data)
     }
 }
 
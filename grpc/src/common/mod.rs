@@ -1,3 +1,4 @@
+pub(crate) mod frame_log;
 pub(crate) mod http_sink;
 pub(crate) mod sink;
 pub(crate) mod types;
@@ -1,3 +1,5 @@
+use std::mem;
+
 use bytes::Bytes;
 use client::types::ClientTypes;
 use common::http_sink::HttpSink;
@@ -5,9 +7,9 @@ use common::types::Types;
 use error;
 use futures::Poll;
 use httpbis;
+use marshall::encode_message;
 use marshall::Marshaller;
 use or_static::arc::ArcOrStatic;
-use proto::grpc_frame::write_grpc_frame_to_vec;
 use result;
 use server::types::ServerTypes;
 
@@ -22,20 +24,66 @@ impl From<httpbis::SendError> for SendError {
     }
 }
 
+/// Messages buffered with `WriteFlags::buffer_hint` are sent
+/// when they exceed default HTTP/2 frame size.
+const MAX_BUFFERED_BYTES: usize = 16384;
+
+/// Options of a message sent to request or response stream.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct WriteFlags {
+    /// Message may be held back to be sent in single DATA frame with following
+    /// messages. Buffered messages are sent with the next message without this flag,
+    /// when the sink is flushed or finished, or when they exceed 16 KiB.
+    ///
+    /// Useful for high-throughput streams of small messages,
+    /// interactive streams should leave it unset.
+    pub buffer_hint: bool,
+}
+
 pub(crate) trait SinkUntyped {
     fn poll(&mut self) -> Poll<(), httpbis::StreamDead>;
-    fn send_data(&mut self, message: Bytes) -> result::Result<()>;
+    /// Send encoded grpc frame.
+    fn send_frame(&mut self, frame: Bytes, flags: WriteFlags) -> result::Result<()>;
+    /// Send frames held back by `WriteFlags::buffer_hint`.
+    fn flush(&mut self) -> result::Result<()>;
 }
 
 pub(crate) struct SinkCommonUntyped<T: Types> {
     pub(crate) http: T::HttpSink,
+    /// Frames sent with `buffer_hint`
+    buffered: Vec<u8>,
 }
 
 impl<T: Types> SinkCommonUntyped<T> {
-    pub fn send_data(&mut self, message: Bytes) -> result::Result<()> {
-        // TODO: allocation
-        self.http
-            .send_data(Bytes::from(write_grpc_frame_to_vec(&message)))?;
+    pub fn new(http: T::HttpSink) -> SinkCommonUntyped<T> {
+        SinkCommonUntyped {
+            http,
+            buffered: Vec::new(),
+        }
+    }
+
+    pub fn send_frame(&mut self, frame: Bytes, flags: WriteFlags) -> result::Result<()> {
+        if flags.buffer_hint {
+            self.buffered.extend_from_slice(&frame);
+            if self.buffered.len() >= MAX_BUFFERED_BYTES {
+                self.flush()?;
+            }
+            return Ok(());
+        }
+        if self.buffered.is_empty() {
+            self.http.send_data(frame)?;
+            return Ok(());
+        }
+        self.buffered.extend_from_slice(&frame);
+        Ok(self.flush()?)
+    }
+
+    /// Send buffered frames.
+    pub fn flush(&mut self) -> Result<(), httpbis::SendError> {
+        if !self.buffered.is_empty() {
+            let data = mem::replace(&mut self.buffered, Vec::new());
+            self.http.send_data(Bytes::from(data))?;
+        }
         Ok(())
     }
 }
@@ -51,11 +99,18 @@ impl<M: 'static, T: Types> SinkCommon<M, T> {
     }
 
     pub fn send_data(&mut self, message: M) -> result::Result<()> {
-        let bytes = self.marshaller.write(&message)?;
-        // TODO: extra allocation
-        self.sink.send_data(Bytes::from(bytes))?;
+        self.send_data_with_flags(message, WriteFlags::default())
+    }
+
+    pub fn send_data_with_flags(&mut self, message: M, flags: WriteFlags) -> result::Result<()> {
+        let frame = encode_message(&*self.marshaller, &message)?;
+        self.sink.send_frame(frame, flags)?;
         Ok(())
     }
+
+    pub fn flush(&mut self) -> result::Result<()> {
+        self.sink.flush()
+    }
 }
 
 fn _assert_types() {
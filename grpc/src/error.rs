@@ -6,8 +6,111 @@ use futures;
 
 use httpbis;
 
+use tls_api;
+
+use proto::grpc_status::GrpcStatus;
 use proto::metadata;
 
+/// Call could not be delivered: connection could not be established or was lost.
+///
+/// Such calls may be retried, so they are reported as `UNAVAILABLE`.
+#[derive(Debug)]
+pub enum TransportError {
+    Io(io::Error),
+    Http(httpbis::Error),
+    Tls(tls_api::Error),
+}
+
+impl fmt::Display for TransportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &TransportError::Io(ref err) => write!(f, "io error: {}", err.description()),
+            &TransportError::Http(ref err) => write!(f, "http error: {}", err.description()),
+            &TransportError::Tls(ref err) => write!(f, "tls error: {}", err),
+        }
+    }
+}
+
+/// Length-prefixed message framing of request or response body is violated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FrameError {
+    /// Flags byte has bits other than the compressed flag set.
+    UnknownFlags(u8),
+    /// Compressed flag is set, but compression is not supported.
+    Compressed,
+    /// Stream ended in the middle of a frame, lengths include 5-byte prefix.
+    Truncated { expected: usize, received: usize },
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &FrameError::UnknownFlags(flags) => {
+                write!(f, "grpc frame has unknown flags: 0x{:02x}", flags)
+            }
+            &FrameError::Compressed => {
+                write!(
+                    f,
+                    "grpc frame is compressed, but compression is not supported"
+                )
+            }
+            &FrameError::Truncated { expected, received } => write!(
+                f,
+                "grpc frame is truncated: expected {} bytes, received {}",
+                expected, received
+            ),
+        }
+    }
+}
+
+/// Peer sent data which cannot be interpreted as gRPC call.
+///
+/// Reported as `INTERNAL`, except truncated frames reported as `DATA_LOSS`.
+#[derive(Debug)]
+pub enum ProtocolError {
+    MetadataDecode(metadata::MetadataDecodeError),
+    Marshaller(Box<dyn std_Error + Send + Sync>),
+    Frame(FrameError),
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &ProtocolError::MetadataDecode(..) => write!(f, "metadata decode error"),
+            &ProtocolError::Marshaller(ref e) => write!(f, "marshaller error: {}", e),
+            &ProtocolError::Frame(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// Call completed with non-OK status.
+///
+/// On client this is status sent by server, on server
+/// status returned from handler is sent to client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Status {
+    pub code: GrpcStatus,
+    /// Content of `grpc-message` header
+    pub message: String,
+}
+
+impl Status {
+    pub fn new<S: Into<String>>(code: GrpcStatus, message: S) -> Status {
+        Status {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}: {}", self.code, self.message)
+    }
+}
+
+/// Status sent by server, as reported before `Status` was introduced.
+#[deprecated(since = "0.8.0", note = "use `Status`, returned in `Error::Status`")]
 #[derive(Debug)]
 pub struct GrpcMessageError {
     pub grpc_status: i32,
@@ -16,27 +119,67 @@ pub struct GrpcMessageError {
     pub grpc_message: String,
 }
 
+#[allow(deprecated)]
+impl From<Status> for GrpcMessageError {
+    fn from(status: Status) -> GrpcMessageError {
+        GrpcMessageError {
+            grpc_status: status.code.code() as i32,
+            grpc_message: status.message,
+        }
+    }
+}
+
+#[allow(deprecated)]
+impl From<GrpcMessageError> for Status {
+    fn from(err: GrpcMessageError) -> Status {
+        Status {
+            code: GrpcStatus::from_code_or_unknown(err.grpc_status as u32),
+            message: err.grpc_message,
+        }
+    }
+}
+
+/// Error of a call or of the server or client operation.
+///
+/// Use `status` to decide how to react to a failed call,
+/// e. g. calls failed with `UNAVAILABLE` are safe to retry.
 #[derive(Debug)]
 pub enum Error {
-    Io(io::Error),
-    Http(httpbis::Error),
-    GrpcMessage(GrpcMessageError),
+    Transport(TransportError),
+    Protocol(ProtocolError),
+    /// Status sent by peer or returned from handler
+    Status(Status),
     Canceled(futures::Canceled),
-    MetadataDecode(metadata::MetadataDecodeError),
     Panic(String),
-    Marshaller(Box<dyn std_Error + Send + Sync>),
     Other(&'static str),
 }
 
+impl Error {
+    /// Status of failed call as seen by the peer, see `From<Error> for Status`.
+    pub fn status(&self) -> Status {
+        match self {
+            &Error::Status(ref status) => status.clone(),
+            &Error::Transport(..) => Status::new(GrpcStatus::Unavailable, self.to_string()),
+            &Error::Protocol(ProtocolError::Frame(FrameError::Truncated { .. })) => {
+                Status::new(GrpcStatus::DataLoss, self.to_string())
+            }
+            &Error::Protocol(..) => Status::new(GrpcStatus::Internal, self.to_string()),
+            &Error::Canceled(..) => Status::new(GrpcStatus::Cancelled, self.to_string()),
+            &Error::Panic(..) => Status::new(GrpcStatus::Internal, self.to_string()),
+            &Error::Other(..) => Status::new(GrpcStatus::Unknown, self.to_string()),
+        }
+    }
+}
+
 impl From<httpbis::SendError> for Error {
     fn from(e: httpbis::SendError) -> Self {
-        Error::Http(httpbis::Error::from(e))
+        Error::from(httpbis::Error::from(e))
     }
 }
 
 impl From<httpbis::StreamDead> for Error {
     fn from(e: httpbis::StreamDead) -> Self {
-        Error::Http(httpbis::Error::from(e))
+        Error::from(httpbis::Error::from(e))
     }
 }
 
@@ -51,27 +194,66 @@ impl std_Error for Error {}
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            &Error::Io(ref err) => write!(f, "io error: {}", err.description()),
-            &Error::Http(ref err) => write!(f, "http error: {}", err.description()),
-            &Error::GrpcMessage(ref err) => write!(f, "grpc message error: {}", err.grpc_message),
-            &Error::MetadataDecode(..) => write!(f, "metadata decode error"),
+            &Error::Transport(ref err) => write!(f, "{}", err),
+            &Error::Protocol(ref err) => write!(f, "{}", err),
+            &Error::Status(ref status) => write!(f, "grpc message error: {}", status.message),
             &Error::Canceled(..) => write!(f, "canceled"),
             &Error::Panic(ref message) => write!(f, "panic: {}", message),
             &Error::Other(ref message) => write!(f, "other error: {}", message),
-            &Error::Marshaller(ref e) => write!(f, "marshaller error: {}", e),
         }
     }
 }
 
+/// Transport failures are mapped to `UNAVAILABLE`, protocol violations to `INTERNAL`,
+/// other errors not carrying status to `UNKNOWN`.
+impl From<Error> for Status {
+    fn from(err: Error) -> Status {
+        match err {
+            Error::Status(status) => status,
+            err => err.status(),
+        }
+    }
+}
+
+impl From<Status> for Error {
+    fn from(status: Status) -> Self {
+        Error::Status(status)
+    }
+}
+
+impl From<TransportError> for Error {
+    fn from(err: TransportError) -> Self {
+        Error::Transport(err)
+    }
+}
+
+impl From<FrameError> for Error {
+    fn from(err: FrameError) -> Self {
+        Error::Protocol(ProtocolError::Frame(err))
+    }
+}
+
+impl From<ProtocolError> for Error {
+    fn from(err: ProtocolError) -> Self {
+        Error::Protocol(err)
+    }
+}
+
 impl From<io::Error> for Error {
     fn from(err: io::Error) -> Self {
-        Error::Io(err)
+        Error::Transport(TransportError::Io(err))
     }
 }
 
 impl From<httpbis::Error> for Error {
     fn from(err: httpbis::Error) -> Self {
-        Error::Http(err)
+        Error::Transport(TransportError::Http(err))
+    }
+}
+
+impl From<tls_api::Error> for Error {
+    fn from(err: tls_api::Error) -> Self {
+        Error::Transport(TransportError::Tls(err))
     }
 }
 
@@ -84,7 +266,7 @@ impl From<futures::Canceled> for Error {
 impl From<Error> for io::Error {
     fn from(err: Error) -> io::Error {
         match err {
-            Error::Io(e) => e,
+            Error::Transport(TransportError::Io(e)) => e,
             _ => io::Error::new(io::ErrorKind::Other, err),
         }
     }
@@ -92,7 +274,7 @@ impl From<Error> for io::Error {
 
 impl From<metadata::MetadataDecodeError> for Error {
     fn from(de: metadata::MetadataDecodeError) -> Self {
-        Error::MetadataDecode(de)
+        Error::Protocol(ProtocolError::MetadataDecode(de))
     }
 }
 
@@ -101,3 +283,37 @@ impl From<Error> for httpbis::Error {
         httpbis::Error::StdError(Box::new(err))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn status() {
+        let e = Error::from(io::Error::new(io::ErrorKind::ConnectionRefused, "refused"));
+        assert_eq!(GrpcStatus::Unavailable, e.status().code);
+
+        let e = Error::Status(Status::new(GrpcStatus::NotFound, "no such thing"));
+        assert_eq!(
+            Status::new(GrpcStatus::NotFound, "no such thing"),
+            Status::from(e)
+        );
+
+        let e = Error::Protocol(ProtocolError::Marshaller(Box::new(
+            String::from_utf8(vec![0xff]).unwrap_err(),
+        )));
+        assert_eq!(GrpcStatus::Internal, e.status().code);
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn grpc_message_error() {
+        let e = GrpcMessageError::from(Status::new(GrpcStatus::NotFound, "no such thing"));
+        assert_eq!(5, e.grpc_status);
+        assert_eq!("no such thing", e.grpc_message);
+        assert_eq!(
+            Status::new(GrpcStatus::NotFound, "no such thing"),
+            Status::from(e)
+        );
+    }
+}
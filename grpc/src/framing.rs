@@ -0,0 +1,15 @@
+//! gRPC message framing.
+//!
+//! Each message on the wire is prefixed with a compression flag byte
+//! and a big-endian `u32` length. These functions and streams
+//! convert between HTTP/2 data and serialized messages without decoding them,
+//! which is useful for building gRPC-aware proxies.
+
+pub use proto::grpc_frame::decode_frames;
+pub use proto::grpc_frame::parse_grpc_frame;
+pub use proto::grpc_frame::write_grpc_frame;
+pub use proto::grpc_frame::GrpcFrameDecoder;
+pub use proto::grpc_frame::GrpcFrameDecoderStream;
+pub use proto::grpc_frame::GrpcFrameEncoderStream;
+pub use proto::grpc_frame::GrpcFrameFromHttpFramesStreamRequest;
+pub use proto::grpc_frame::GRPC_HEADER_LEN;
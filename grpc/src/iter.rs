@@ -1,5 +1,137 @@
+use std::time::Duration;
+use std::time::Instant;
+
+use futures::stream;
+use futures::stream::Stream;
+
+use futures_grpc::GrpcStream;
+use resp::StreamingResponse;
 use result;
+use timer::sleep;
+use timer::WithDeadline;
+use timer::WithItemTimeout;
 
 type BoxIterator<T> = Box<Iterator<Item = T> + Send>;
 
 pub type GrpcIterator<T> = BoxIterator<result::Result<T>>;
+
+/// Blocking iterator over messages of a response, see `StreamingResponse::wait_iter`.
+///
+/// Iteration ends after the first error. Dropping the iterator
+/// before the response is complete cancels the call.
+pub struct WaitIter<T: Send + 'static> {
+    response: Option<StreamingResponse<T>>,
+    started: Instant,
+    timeout: Option<Duration>,
+    item_timeout: Option<Duration>,
+    iter: Option<stream::Wait<GrpcStream<T>>>,
+    done: bool,
+}
+
+impl<T: Send + 'static> WaitIter<T> {
+    pub(crate) fn new(response: StreamingResponse<T>) -> WaitIter<T> {
+        WaitIter {
+            response: Some(response),
+            started: Instant::now(),
+            timeout: None,
+            item_timeout: None,
+            iter: None,
+            done: false,
+        }
+    }
+
+    /// Fail with `DEADLINE_EXCEEDED` if the response is not complete
+    /// in this time since `wait_iter` was called.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Fail with `DEADLINE_EXCEEDED` if the next message or the end of response
+    /// is not received in this time. Time spent by consumer between
+    /// `next` calls is not counted.
+    pub fn item_timeout(mut self, timeout: Duration) -> Self {
+        self.item_timeout = Some(timeout);
+        self
+    }
+
+    fn start(&mut self) -> stream::Wait<GrpcStream<T>> {
+        let mut stream = self.response.take().unwrap().drop_metadata();
+        if let Some(item_timeout) = self.item_timeout {
+            stream = Box::new(WithItemTimeout::new(stream, item_timeout));
+        }
+        if let Some(timeout) = self.timeout {
+            let left = timeout
+                .checked_sub(self.started.elapsed())
+                .unwrap_or_default();
+            stream = Box::new(WithDeadline {
+                inner: stream,
+                sleep: sleep(left),
+            });
+        }
+        stream.wait()
+    }
+}
+
+impl<T: Send + 'static> Iterator for WaitIter<T> {
+    type Item = result::Result<T>;
+
+    fn next(&mut self) -> Option<result::Result<T>> {
+        if self.done {
+            return None;
+        }
+        if self.iter.is_none() {
+            self.iter = Some(self.start());
+        }
+        let r = self.iter.as_mut().unwrap().next();
+        match r {
+            Some(Ok(..)) => {}
+            Some(Err(..)) | None => self.done = true,
+        }
+        r
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use error::Error;
+    use proto::grpc_status::GrpcStatus;
+
+    fn status(r: Option<result::Result<u32>>) -> GrpcStatus {
+        match r {
+            Some(Err(Error::Status(s))) => s.code,
+            r => panic!("{:?}", r),
+        }
+    }
+
+    #[test]
+    fn complete() {
+        let iter = StreamingResponse::iter(vec![1, 2].into_iter()).wait_iter();
+        let messages: result::Result<Vec<u32>> = iter
+            .timeout(Duration::from_secs(10))
+            .item_timeout(Duration::from_secs(10))
+            .collect();
+        assert_eq!(vec![1, 2], messages.unwrap());
+    }
+
+    #[test]
+    fn item_timeout() {
+        let response = StreamingResponse::no_metadata(
+            stream::once(Ok(1)).chain(stream::empty::<u32, Error>()),
+        );
+        let mut iter = response.wait_iter().item_timeout(Duration::from_millis(1));
+        assert_eq!(1, iter.next().unwrap().unwrap());
+        assert_eq!(GrpcStatus::DeadlineExceeded, status(iter.next()));
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn timeout() {
+        let response = StreamingResponse::<u32>::no_metadata(stream::empty());
+        let mut iter = response.wait_iter().timeout(Duration::from_millis(1));
+        assert_eq!(GrpcStatus::DeadlineExceeded, status(iter.next()));
+        assert!(iter.next().is_none());
+    }
+}
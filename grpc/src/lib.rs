@@ -8,8 +8,18 @@ extern crate futures_cpupool;
 extern crate tls_api;
 extern crate tls_api_stub;
 extern crate tokio_core;
+extern crate tokio_io;
 extern crate tokio_tls_api;
 
+#[cfg(feature = "with-google-auth")]
+extern crate ring;
+#[cfg(feature = "with-serde")]
+extern crate serde;
+#[cfg(feature = "with-serde")]
+extern crate serde_json;
+#[cfg(feature = "with-tracing")]
+extern crate tracing;
+
 extern crate httpbis;
 
 mod futures_misc;
@@ -24,12 +34,14 @@ mod proto;
 
 mod assert_types;
 
+mod bytestream;
 mod chars;
 mod or_static;
 mod req;
 mod resp;
 mod result;
 mod stream_item;
+mod timer;
 
 mod error;
 mod futures_grpc;
@@ -37,19 +49,55 @@ mod iter;
 pub mod marshall;
 mod method;
 
+pub mod framing;
+
 pub mod prelude;
 
 pub mod rt;
 
+pub mod stats;
+
+pub mod trace;
+
 pub mod for_test;
 
+pub mod testing;
+
 pub use error::Error;
+pub use error::FrameError;
+#[allow(deprecated)]
 pub use error::GrpcMessageError;
+pub use error::ProtocolError;
+pub use error::Status;
+pub use error::TransportError;
 pub use result::Result;
 
+pub use iter::GrpcIterator;
+pub use iter::WaitIter;
+
+pub use bytestream::ByteStreamReader;
+pub use bytestream::ByteStreamWriter;
+
 pub use stream_item::ItemOrMetadata;
 
+pub use client::channel::Channel;
+pub use client::credentials::BearerTokenCredentials;
+pub use client::credentials::CachingCallCredentials;
+pub use client::credentials::CallCredentials;
+pub use client::credentials::CallCredentialsContext;
+pub use client::credentials::ExpiringMetadata;
+#[cfg(feature = "with-google-auth")]
+pub use client::google_auth;
+pub use client::interceptor::ClientInterceptor;
 pub use client::req_sink::ClientRequestSink;
+pub use client::req_sink::RequestSink;
+pub use client::service_config::MethodConfig;
+pub use client::service_config::RetryPolicy;
+pub use client::service_config::ServiceConfig;
+pub use client::tls::ClientTlsConf;
+pub use client::upload::ResumableUpload;
+pub use client::upload::UploadResume;
+pub use client::upload::UPLOAD_OFFSET_METADATA;
 pub use client::Client;
 pub use client::ClientBuilder;
 pub use client::ClientConf;
@@ -57,25 +105,60 @@ pub use client::ClientConf;
 pub use client_stub::ClientStub;
 pub use client_stub::ClientStubExt;
 
+pub use server::access_log::AccessLogRecord;
+pub use server::access_log::AccessLogger;
+pub use server::access_log::JsonAccessLogger;
+pub use server::access_log::TextAccessLogger;
+pub use server::auth::ServerAuthHandler;
+pub use server::channelz::Channelz;
+pub use server::channelz::MethodCallCounters;
+pub use server::channelz::StreamSnapshot;
+pub use server::channelz::CHANNELZ_SERVICE;
 pub use server::ctx::ServerHandlerContext;
+pub use server::descriptor::ServiceDescriptor;
+pub use server::descriptor::ServiceMethodDescriptor;
+pub use server::drain::DRAINING_METADATA;
+pub use server::events::ServerEvent;
+pub use server::events::ServerEvents;
+pub use server::histograms::HistogramSnapshot;
+pub use server::histograms::MethodHistogramSnapshot;
+pub use server::histograms::MethodHistograms;
+pub use server::in_flight::InFlightRequests;
+pub use server::json_gateway::JsonGateway;
+pub use server::json_gateway::JsonGatewayRoute;
+pub use server::json_gateway::JsonTranscoder;
+pub use server::panic::PanicPolicy;
+pub use server::panic::ServerPanicHandler;
+pub use server::rate_limit::RateLimit;
+pub use server::rate_limit::RateLimiter;
+pub use server::rate_limit::RateLimiterBuilder;
+pub use server::req_body::ServerRequestBody;
+pub use server::req_body::ServerRequestBodyStream;
 pub use server::req_handler::ServerRequest;
 pub use server::req_single::ServerRequestSingle;
 pub use server::req_stream::ServerRequestStream;
 pub use server::resp_sink::ServerResponseSink;
 pub use server::resp_unary_sink::ServerResponseUnarySink;
+pub use server::tls::ServerTlsConf;
+pub use server::validate::RequestValidator;
+pub use server::watchdog::ServerWatchdogHandler;
 pub use server::Server;
 pub use server::ServerBuilder;
 pub use server::ServerConf;
 
 pub use resp::SingleResponse;
 pub use resp::StreamingResponse;
+pub use resp::UnaryResponse;
 
 pub use req::RequestOptions;
 pub use req::StreamingRequest;
 
+pub use common::sink::WriteFlags;
+
 pub use futures_grpc::GrpcFuture;
 pub use futures_grpc::GrpcStream;
 
 pub use proto::grpc_status::GrpcStatus;
 pub use proto::metadata::Metadata;
+pub use proto::metadata::MetadataDecodeError;
 pub use proto::metadata::MetadataKey;
@@ -1,8 +1,158 @@
+//! Conversion of messages to and from bytes.
+//!
+//! Protobuf marshaller is provided by `grpc-protobuf` crate,
+//! marshallers for other payloads can be implemented with `Marshaller` trait.
+
 use bytes::Bytes;
 
+use error::Error;
+use error::ProtocolError;
+use proto::grpc_frame::empty_grpc_frame;
+use proto::grpc_frame::encode_grpc_frame;
 use result;
 
 pub trait Marshaller<M>: Send + Sync + 'static {
     fn write(&self, m: &M) -> result::Result<Vec<u8>>;
+
+    /// Append serialized message to `out`.
+    ///
+    /// gRPC frames are encoded with this function, so marshallers which can
+    /// serialize into existing buffer should override it to avoid a copy.
+    fn write_to_vec(&self, m: &M, out: &mut Vec<u8>) -> result::Result<()> {
+        out.extend_from_slice(&self.write(m)?);
+        Ok(())
+    }
+
+    /// Parse message.
+    ///
+    /// `bytes` is a slice of received data, so messages can
+    /// keep references to it instead of copying.
     fn read(&self, bytes: Bytes) -> result::Result<M>;
+
+    /// Whether the message serializes to zero bytes, if it is known without
+    /// serializing it, e. g. for `google.protobuf.Empty`.
+    ///
+    /// Empty messages are sent as a static frame, without allocation
+    /// and without calling `write_to_vec`.
+    fn is_empty(&self, _m: &M) -> bool {
+        false
+    }
+}
+
+/// Encode message into grpc frame.
+pub(crate) fn encode_message<M>(marshaller: &Marshaller<M>, m: &M) -> result::Result<Bytes> {
+    if marshaller.is_empty(m) {
+        return Ok(empty_grpc_frame());
+    }
+    encode_grpc_frame(|out| marshaller.write_to_vec(m, out))
+}
+
+/// Messages without payload, e. g. requests of methods which take no arguments.
+///
+/// Payload of received messages is ignored, like unknown fields
+/// of protobuf messages are, so it is compatible with `google.protobuf.Empty`.
+pub struct MarshallerEmpty;
+
+impl Marshaller<()> for MarshallerEmpty {
+    fn write(&self, _m: &()) -> result::Result<Vec<u8>> {
+        Ok(Vec::new())
+    }
+
+    fn write_to_vec(&self, _m: &(), _out: &mut Vec<u8>) -> result::Result<()> {
+        Ok(())
+    }
+
+    fn read(&self, _bytes: Bytes) -> result::Result<()> {
+        Ok(())
+    }
+
+    fn is_empty(&self, _m: &()) -> bool {
+        true
+    }
+}
+
+/// Pass message bytes as is.
+pub struct MarshallerBytes;
+
+impl Marshaller<Vec<u8>> for MarshallerBytes {
+    fn write(&self, m: &Vec<u8>) -> result::Result<Vec<u8>> {
+        Ok(m.clone())
+    }
+
+    fn write_to_vec(&self, m: &Vec<u8>, out: &mut Vec<u8>) -> result::Result<()> {
+        out.extend_from_slice(m);
+        Ok(())
+    }
+
+    fn read(&self, bytes: Bytes) -> result::Result<Vec<u8>> {
+        Ok(bytes.as_ref().to_vec())
+    }
+}
+
+impl Marshaller<Bytes> for MarshallerBytes {
+    fn write(&self, m: &Bytes) -> result::Result<Vec<u8>> {
+        Ok(m.as_ref().to_vec())
+    }
+
+    fn write_to_vec(&self, m: &Bytes, out: &mut Vec<u8>) -> result::Result<()> {
+        out.extend_from_slice(m);
+        Ok(())
+    }
+
+    fn read(&self, bytes: Bytes) -> result::Result<Bytes> {
+        Ok(bytes)
+    }
+}
+
+/// UTF-8 string messages.
+pub struct MarshallerString;
+
+impl Marshaller<String> for MarshallerString {
+    fn write(&self, m: &String) -> result::Result<Vec<u8>> {
+        Ok(m.as_bytes().to_vec())
+    }
+
+    fn read(&self, bytes: Bytes) -> result::Result<String> {
+        String::from_utf8(bytes.as_ref().to_vec())
+            .map_err(|e| Error::Protocol(ProtocolError::Marshaller(Box::new(e))))
+    }
+}
+
+/// Messages encoded as JSON with serde.
+#[cfg(feature = "with-serde")]
+pub struct MarshallerSerdeJson;
+
+#[cfg(feature = "with-serde")]
+impl<M> Marshaller<M> for MarshallerSerdeJson
+where
+    M: ::serde::Serialize + ::serde::de::DeserializeOwned,
+{
+    fn write(&self, m: &M) -> result::Result<Vec<u8>> {
+        ::serde_json::to_vec(m).map_err(|e| Error::Protocol(ProtocolError::Marshaller(Box::new(e))))
+    }
+
+    fn write_to_vec(&self, m: &M, out: &mut Vec<u8>) -> result::Result<()> {
+        ::serde_json::to_writer(out, m)
+            .map_err(|e| Error::Protocol(ProtocolError::Marshaller(Box::new(e))))
+    }
+
+    fn read(&self, bytes: Bytes) -> result::Result<M> {
+        ::serde_json::from_slice(&bytes)
+            .map_err(|e| Error::Protocol(ProtocolError::Marshaller(Box::new(e))))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn empty() {
+        let frame = encode_message(&MarshallerEmpty, &()).unwrap();
+        assert_eq!(&b"\x00\x00\x00\x00\x00"[..], frame.as_ref());
+        assert_eq!((), MarshallerEmpty.read(Bytes::new()).unwrap());
+
+        let frame = encode_message(&MarshallerString, &"ab".to_owned()).unwrap();
+        assert_eq!(&b"\x00\x00\x00\x00\x02ab"[..], frame.as_ref());
+    }
 }
@@ -2,6 +2,7 @@ use marshall::*;
 use or_static::arc::ArcOrStatic;
 use or_static::string::StringOrStatic;
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum GrpcStreaming {
     Unary,
     ClientStreaming,
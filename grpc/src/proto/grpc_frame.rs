@@ -1,8 +1,5 @@
-use std::collections::VecDeque;
-
 use bytes::Bytes;
 
-use futures::stream;
 use futures::stream::Stream;
 use futures::Async;
 use futures::Poll;
@@ -10,6 +7,7 @@ use futures::Poll;
 use error::*;
 use httpbis::DataOrTrailers;
 use httpbis::HttpStreamAfterHeaders;
+use proto::grpc_status::GrpcStatus;
 use result;
 
 fn read_u32_be(bytes: &[u8]) -> u32 {
@@ -30,21 +28,71 @@ fn write_u32_be(v: u32) -> [u8; 4] {
 
 pub const GRPC_HEADER_LEN: usize = 5;
 
+/// Uncompressed frame of zero-length message.
+static EMPTY_GRPC_FRAME: [u8; GRPC_HEADER_LEN] = [0; GRPC_HEADER_LEN];
+
+/// Frame of zero-length message, e. g. `google.protobuf.Empty`, without allocation.
+pub(crate) fn empty_grpc_frame() -> Bytes {
+    Bytes::from_static(&EMPTY_GRPC_FRAME)
+}
+
+/// Return message length from frame header
+pub fn parse_grpc_frame_header(header: &[u8]) -> result::Result<usize> {
+    if header.len() < GRPC_HEADER_LEN {
+        return Err(truncated_frame(header));
+    }
+    match header[0] {
+        0 => {}
+        1 => return Err(FrameError::Compressed.into()),
+        flags => return Err(FrameError::UnknownFlags(flags).into()),
+    }
+    Ok(read_u32_be(&header[1..]) as usize)
+}
+
+/// Fail with `RESOURCE_EXHAUSTED` if frame partially received into `buf`
+/// would not fit into `max_bytes`, checked as soon as frame header is received,
+/// so the rest of the frame is not buffered.
+pub(crate) fn check_buffered_frame(buf: &[u8], max_bytes: usize) -> result::Result<()> {
+    if buf.len() < GRPC_HEADER_LEN {
+        return Ok(());
+    }
+    let frame_len = GRPC_HEADER_LEN.saturating_add(read_u32_be(&buf[1..]) as usize);
+    if frame_len > max_bytes {
+        return Err(Error::Status(Status::new(
+            GrpcStatus::ResourceExhausted,
+            format!(
+                "message of {} bytes exceeds receive buffer limit of {} bytes",
+                frame_len, max_bytes
+            ),
+        )));
+    }
+    Ok(())
+}
+
+/// Error for incomplete frame left in the buffer at the end of the stream.
+pub(crate) fn truncated_frame(buf: &[u8]) -> Error {
+    let expected = match buf.len() >= GRPC_HEADER_LEN {
+        true => GRPC_HEADER_LEN.saturating_add(read_u32_be(&buf[1..]) as usize),
+        false => GRPC_HEADER_LEN,
+    };
+    FrameError::Truncated {
+        expected,
+        received: buf.len(),
+    }
+    .into()
+}
+
 /// Return frame len
 pub fn parse_grpc_frame_0(stream: &[u8]) -> result::Result<Option<usize>> {
     if stream.len() < GRPC_HEADER_LEN {
         return Ok(None);
     }
-    let compressed = match stream[0] {
-        0 => false,
-        1 => true,
-        _ => return Err(Error::Other("unknown compression flag")),
+    let len = parse_grpc_frame_header(stream)?;
+    // length prefix is attacker-controlled, may overflow on 32-bit platforms
+    let end = match len.checked_add(GRPC_HEADER_LEN) {
+        Some(end) => end,
+        None => return Ok(None),
     };
-    if compressed {
-        return Err(Error::Other("compression is not implemented"));
-    }
-    let len = read_u32_be(&stream[1..]) as usize;
-    let end = len + GRPC_HEADER_LEN;
     if end > stream.len() {
         return Ok(None);
     }
@@ -66,6 +114,11 @@ pub fn parse_grpc_frame(stream: &[u8]) -> result::Result<Option<(&[u8], usize)>>
 
 pub fn parse_grpc_frame_from_bytes(stream: &mut Bytes) -> result::Result<Option<Bytes>> {
     if let Some(len) = parse_grpc_frame_0(&stream)? {
+        if len == 0 {
+            // empty messages do not keep received buffer alive
+            stream.split_to(GRPC_HEADER_LEN);
+            return Ok(Some(Bytes::new()));
+        }
         let r = stream.slice(GRPC_HEADER_LEN, len + GRPC_HEADER_LEN);
         stream.split_to(len + GRPC_HEADER_LEN);
         Ok(Some(r))
@@ -94,7 +147,7 @@ pub fn parse_grpc_frames_completely(stream: &[u8]) -> result::Result<Vec<&[u8]>>
     while pos < stream.len() {
         let frame_opt = parse_grpc_frame(&stream[pos..])?;
         match frame_opt {
-            None => return Err(Error::Other("not complete frames")),
+            None => return Err(truncated_frame(&stream[pos..])),
             Some((frame, len)) => {
                 r.push(frame);
                 pos += len;
@@ -114,39 +167,194 @@ pub fn parse_grpc_frame_completely(stream: &[u8]) -> result::Result<&[u8]> {
     }
 }
 
-/// Encode data into grpc frame (add frame prefix)
-pub fn write_grpc_frame(stream: &mut Vec<u8>, frame: &[u8]) {
+/// Decode payloads of all frames of complete message stream,
+/// e. g. HTTP/2 request body.
+///
+/// Malformed or truncated input is reported as error, never panics,
+/// so this function is an entry point for fuzzing the frame decoder.
+pub fn decode_frames(data: &[u8]) -> result::Result<Vec<Bytes>> {
+    let mut decoder = GrpcFrameDecoder::new();
+    decoder.push(Bytes::from(data));
+    let mut frames = Vec::new();
+    while let Some(frame) = decoder.next_frame()? {
+        frames.push(frame);
+    }
+    decoder.finish()?;
+    Ok(frames)
+}
+
+/// Encode data into grpc frame with given flags byte
+pub fn write_grpc_frame_with_flags(stream: &mut Vec<u8>, flags: u8, frame: &[u8]) {
     assert!(frame.len() <= u32::max_value() as usize);
     stream.reserve(5 + frame.len());
-    stream.push(0); // compressed flag
+    stream.push(flags);
     stream.extend(&write_u32_be(frame.len() as u32));
     stream.extend(frame);
 }
 
-pub fn write_grpc_frame_to_vec(frame: &[u8]) -> Vec<u8> {
-    let mut r = Vec::new();
-    write_grpc_frame(&mut r, frame);
-    r
+/// Encode grpc frame with payload written by `write` directly after the frame prefix,
+/// so the payload is not copied.
+pub fn encode_grpc_frame<F>(write: F) -> result::Result<Bytes>
+where
+    F: FnOnce(&mut Vec<u8>) -> result::Result<()>,
+{
+    let mut r = vec![0; GRPC_HEADER_LEN];
+    write(&mut r)?;
+    let len = r.len() - GRPC_HEADER_LEN;
+    if len > u32::max_value() as usize {
+        return Err(Error::Other("message is too large"));
+    }
+    r[1..GRPC_HEADER_LEN].copy_from_slice(&write_u32_be(len as u32));
+    Ok(Bytes::from(r))
 }
 
-trait RequestOrResponse {
-    fn need_trailing_header() -> bool;
+/// Encode message into grpc frame
+///
+/// Messages are always sent uncompressed, with flag 0, because compression
+/// is not implemented; size threshold for compression belongs here once it is.
+pub fn write_grpc_frame(stream: &mut Vec<u8>, frame: &[u8]) {
+    write_grpc_frame_with_flags(stream, 0, frame)
 }
 
+/// Incremental decoder of a stream of grpc frames.
+///
+/// Data is fed in chunks as received from transport,
+/// complete frame payloads are returned without copying.
+#[derive(Default, Debug)]
+pub struct GrpcFrameDecoder {
+    buf: Bytes,
+}
+
+impl GrpcFrameDecoder {
+    pub fn new() -> GrpcFrameDecoder {
+        Default::default()
+    }
+
+    /// Append data received from transport.
+    pub fn push(&mut self, data: Bytes) {
+        if self.buf.is_empty() {
+            self.buf = data;
+        } else {
+            self.buf.extend_from_slice(&data);
+        }
+    }
+
+    /// Take next complete frame payload if there is one.
+    pub fn next_frame(&mut self) -> result::Result<Option<Bytes>> {
+        parse_grpc_frame_from_bytes(&mut self.buf)
+    }
+
+    /// Number of bytes of incomplete frame.
+    pub fn buffered(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Check there is no incomplete frame at the end of the stream.
+    pub fn finish(&self) -> result::Result<()> {
+        if self.buf.is_empty() {
+            Ok(())
+        } else {
+            Err(truncated_frame(&self.buf))
+        }
+    }
+}
+
+/// Stream of frame payloads decoded from a stream of data chunks.
+pub struct GrpcFrameDecoderStream<S> {
+    data: S,
+    decoder: GrpcFrameDecoder,
+    done: bool,
+}
+
+impl<S> GrpcFrameDecoderStream<S>
+where
+    S: Stream<Item = Bytes, Error = Error>,
+{
+    pub fn new(data: S) -> GrpcFrameDecoderStream<S> {
+        GrpcFrameDecoderStream {
+            data,
+            decoder: GrpcFrameDecoder::new(),
+            done: false,
+        }
+    }
+}
+
+impl<S> Stream for GrpcFrameDecoderStream<S>
+where
+    S: Stream<Item = Bytes, Error = Error>,
+{
+    type Item = Bytes;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Bytes>, Error> {
+        loop {
+            if self.done {
+                return Ok(Async::Ready(None));
+            }
+
+            if let Some(frame) = self.decoder.next_frame()? {
+                return Ok(Async::Ready(Some(frame)));
+            }
+
+            match try_ready!(self.data.poll()) {
+                Some(data) => self.decoder.push(data),
+                None => {
+                    self.done = true;
+                    self.decoder.finish()?;
+                }
+            }
+        }
+    }
+}
+
+/// Stream of grpc frames encoded from a stream of messages.
+pub struct GrpcFrameEncoderStream<S> {
+    messages: S,
+}
+
+impl<S> GrpcFrameEncoderStream<S>
+where
+    S: Stream<Item = Bytes, Error = Error>,
+{
+    pub fn new(messages: S) -> GrpcFrameEncoderStream<S> {
+        GrpcFrameEncoderStream { messages }
+    }
+}
+
+impl<S> Stream for GrpcFrameEncoderStream<S>
+where
+    S: Stream<Item = Bytes, Error = Error>,
+{
+    type Item = Bytes;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Bytes>, Error> {
+        Ok(Async::Ready(match try_ready!(self.messages.poll()) {
+            Some(message) => {
+                let mut frame = Vec::new();
+                write_grpc_frame(&mut frame, &message);
+                Some(Bytes::from(frame))
+            }
+            None => None,
+        }))
+    }
+}
+
+/// Stream of frame payloads of HTTP/2 request body.
+///
+/// Trailers are ignored.
 pub struct GrpcFrameFromHttpFramesStreamRequest {
     http_stream_stream: HttpStreamAfterHeaders,
-    buf: Bytes,
-    parsed_frames: VecDeque<Bytes>,
-    error: Option<stream::Once<Bytes, Error>>,
+    decoder: GrpcFrameDecoder,
+    done: bool,
 }
 
 impl GrpcFrameFromHttpFramesStreamRequest {
     pub fn new(http_stream_stream: HttpStreamAfterHeaders) -> Self {
         GrpcFrameFromHttpFramesStreamRequest {
             http_stream_stream,
-            buf: Bytes::new(),
-            parsed_frames: VecDeque::new(),
-            error: None,
+            decoder: GrpcFrameDecoder::new(),
+            done: false,
         }
     }
 }
@@ -157,44 +365,21 @@ impl Stream for GrpcFrameFromHttpFramesStreamRequest {
 
     fn poll(&mut self) -> Poll<Option<Bytes>, Error> {
         loop {
-            if let Some(ref mut error) = self.error {
-                return error.poll();
+            if self.done {
+                return Ok(Async::Ready(None));
             }
 
-            self.parsed_frames
-                .extend(match parse_grpc_frames_from_bytes(&mut self.buf) {
-                    Ok(r) => r,
-                    Err(e) => {
-                        self.error = Some(stream::once(Err(e)));
-                        continue;
-                    }
-                });
-
-            if let Some(frame) = self.parsed_frames.pop_front() {
+            if let Some(frame) = self.decoder.next_frame()? {
                 return Ok(Async::Ready(Some(frame)));
             }
 
-            let part_opt = match self.http_stream_stream.poll()? {
-                Async::NotReady => return Ok(Async::NotReady),
-                Async::Ready(part_opt) => part_opt,
-            };
-            let part = match part_opt {
-                None => {
-                    if self.buf.is_empty() {
-                        return Ok(Async::Ready(None));
-                    } else {
-                        self.error = Some(stream::once(Err(Error::Other("partial frame"))));
-                        continue;
-                    }
-                }
-                Some(part) => part,
-            };
-
-            match part {
+            match try_ready!(self.http_stream_stream.poll()) {
                 // unexpected but OK
-                DataOrTrailers::Trailers(..) => (),
-                DataOrTrailers::Data(data, ..) => {
-                    self.buf.extend_from_slice(&data);
+                Some(DataOrTrailers::Trailers(..)) => (),
+                Some(DataOrTrailers::Data(data, ..)) => self.decoder.push(data),
+                None => {
+                    self.done = true;
+                    self.decoder.finish()?;
                 }
             }
         }
@@ -205,6 +390,20 @@ impl Stream for GrpcFrameFromHttpFramesStreamRequest {
 mod test {
     use super::*;
 
+    use futures::stream;
+    use futures::Future;
+
+    #[test]
+    fn check_buffered_frame_limit() {
+        // header is not complete yet
+        check_buffered_frame(b"\0\0\0", 10).unwrap();
+        check_buffered_frame(b"\0\0\0\0\x05ab", 10).unwrap();
+        match check_buffered_frame(b"\0\0\0\0\x06ab", 10) {
+            Err(Error::Status(ref s)) if s.code == GrpcStatus::ResourceExhausted => {}
+            r => panic!("expecting RESOURCE_EXHAUSTED, got {:?}", r),
+        }
+    }
+
     #[test]
     fn test_parse_grpc_frame() {
         assert_eq!(None, parse_grpc_frame(b"").unwrap());
@@ -248,4 +447,79 @@ mod test {
             &b"\x00"[..],
         );
     }
+
+    #[test]
+    fn test_encode_grpc_frame() {
+        let frame = encode_grpc_frame(|out| {
+            out.extend_from_slice(b"world");
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(&b"\x00\x00\x00\x00\x05world"[..], frame.as_ref());
+    }
+    #[test]
+    fn decoder_stream() {
+        let data = stream::iter_ok(vec![
+            Bytes::from_static(b"\0\0\0"),
+            Bytes::from_static(b"\0\x02a"),
+            Bytes::from_static(b"b\0\0\0\0\0"),
+        ]);
+        let frames: Vec<Bytes> = GrpcFrameDecoderStream::new(data).collect().wait().unwrap();
+        assert_eq!(vec![Bytes::from_static(b"ab"), Bytes::new()], frames);
+
+        let data = stream::iter_ok(vec![Bytes::from_static(b"\0\0")]);
+        assert!(GrpcFrameDecoderStream::new(data).collect().wait().is_err());
+    }
+
+    #[test]
+    fn malformed_frames() {
+        fn status(data: &'static [u8]) -> Status {
+            let data = stream::iter_ok(vec![Bytes::from_static(data)]);
+            let e = GrpcFrameDecoderStream::new(data)
+                .collect()
+                .wait()
+                .unwrap_err();
+            Status::from(e)
+        }
+
+        let s = status(b"\x02\0\0\0\0");
+        assert_eq!(GrpcStatus::Internal, s.code);
+        assert_eq!("grpc frame has unknown flags: 0x02", s.message);
+
+        let s = status(b"\x01\0\0\0\0");
+        assert_eq!(GrpcStatus::Internal, s.code);
+
+        let s = status(b"\0\0\0\0\x05abc");
+        assert_eq!(GrpcStatus::DataLoss, s.code);
+        assert_eq!(
+            "grpc frame is truncated: expected 10 bytes, received 8",
+            s.message
+        );
+
+        let s = status(b"\0\0");
+        assert_eq!(GrpcStatus::DataLoss, s.code);
+    }
+
+    #[test]
+    fn decode_frames_malformed() {
+        assert_eq!(
+            vec![Bytes::from_static(b"ab")],
+            decode_frames(b"\0\0\0\0\x02ab").unwrap()
+        );
+        assert!(decode_frames(b"").unwrap().is_empty());
+        assert!(decode_frames(b"\0\xff\xff\xff\xff").is_err());
+        assert!(decode_frames(b"\0\0\0\0\x02abc").is_err());
+        assert!(decode_frames(b"\x80").is_err());
+        assert!(parse_grpc_frame_header(b"\0").is_err());
+    }
+
+    #[test]
+    fn encoder_stream() {
+        let messages = stream::iter_ok(vec![Bytes::from_static(b"ab")]);
+        let frames: Vec<Bytes> = GrpcFrameEncoderStream::new(messages)
+            .collect()
+            .wait()
+            .unwrap();
+        assert_eq!(vec![Bytes::from_static(b"\0\0\0\0\x02ab")], frames);
+    }
 }
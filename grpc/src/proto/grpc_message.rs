@@ -0,0 +1,75 @@
+//! `grpc-message` header encoding, see
+//! https://github.com/grpc/grpc/blob/master/doc/PROTOCOL-HTTP2.md#responses
+
+use std::fmt::Write;
+
+/// Percent-encode status message: bytes of UTF-8 representation
+/// outside of printable ASCII range, and `%` itself, are encoded as `%XX`.
+pub(crate) fn encode_grpc_message(message: &str) -> String {
+    let mut r = String::with_capacity(message.len());
+    for b in message.bytes() {
+        if (0x20..=0x7e).contains(&b) && b != b'%' {
+            r.push(b as char);
+        } else {
+            write!(r, "%{:02X}", b).unwrap();
+        }
+    }
+    r
+}
+
+fn hex_digit(b: u8) -> Option<u8> {
+    (b as char).to_digit(16).map(|d| d as u8)
+}
+
+/// Decode percent-encoded status message.
+///
+/// Malformed sequences are kept as is, and invalid UTF-8 is replaced,
+/// so message of non-conforming peer is not lost.
+pub(crate) fn decode_grpc_message(value: &str) -> String {
+    if !value.contains('%') {
+        return value.to_owned();
+    }
+    let bytes = value.as_bytes();
+    let mut r = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            if let (Some(h), Some(l)) = (hex_digit(bytes[i + 1]), hex_digit(bytes[i + 2])) {
+                r.push(h << 4 | l);
+                i += 3;
+                continue;
+            }
+        }
+        r.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&r).into_owned()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn encode() {
+        assert_eq!("not found", encode_grpc_message("not found"));
+        assert_eq!("100%25", encode_grpc_message("100%"));
+        assert_eq!("a%0Ab", encode_grpc_message("a\nb"));
+        assert_eq!("%D0%BF%D1%80%D0%B8", encode_grpc_message("при"));
+    }
+
+    #[test]
+    fn decode() {
+        assert_eq!("not found", decode_grpc_message("not found"));
+        assert_eq!("100%", decode_grpc_message("100%25"));
+        assert_eq!("при", decode_grpc_message("%D0%BF%D1%80%D0%B8"));
+        assert_eq!("%zz%4", decode_grpc_message("%zz%4"));
+        assert_eq!("\u{FFFD}", decode_grpc_message("%FF"));
+    }
+
+    #[test]
+    fn round_trip() {
+        let message = "ошибка: 50% \"done\"\r\n\t✓";
+        assert_eq!(message, decode_grpc_message(&encode_grpc_message(message)));
+    }
+}
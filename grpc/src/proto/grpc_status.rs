@@ -1,6 +1,6 @@
 // copied from https://github.com/grpc/grpc/blob/master/include/grpc/impl/codegen/status.h
 #[allow(dead_code)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum GrpcStatus {
     /* Not an error; returned on success */
     Ok = 0,
@@ -146,4 +146,28 @@ impl GrpcStatus {
     pub fn from_code_or_unknown(code: u32) -> GrpcStatus {
         GrpcStatus::from_code(code).unwrap_or(GrpcStatus::Unknown)
     }
+
+    /// Find GrpcStatus enum variant by canonical name, e. g. `UNAVAILABLE`
+    pub fn from_name(name: &str) -> Option<GrpcStatus> {
+        Some(match name {
+            "OK" => GrpcStatus::Ok,
+            "CANCELLED" => GrpcStatus::Cancelled,
+            "UNKNOWN" => GrpcStatus::Unknown,
+            "INVALID_ARGUMENT" => GrpcStatus::Argument,
+            "DEADLINE_EXCEEDED" => GrpcStatus::DeadlineExceeded,
+            "NOT_FOUND" => GrpcStatus::NotFound,
+            "ALREADY_EXISTS" => GrpcStatus::AlreadyExists,
+            "PERMISSION_DENIED" => GrpcStatus::PermissionDenied,
+            "UNAUTHENTICATED" => GrpcStatus::Unauthenticated,
+            "RESOURCE_EXHAUSTED" => GrpcStatus::ResourceExhausted,
+            "FAILED_PRECONDITION" => GrpcStatus::FailedPrecondition,
+            "ABORTED" => GrpcStatus::Aborted,
+            "OUT_OF_RANGE" => GrpcStatus::OutOfRange,
+            "UNIMPLEMENTED" => GrpcStatus::Unimplemented,
+            "INTERNAL" => GrpcStatus::Internal,
+            "UNAVAILABLE" => GrpcStatus::Unavailable,
+            "DATA_LOSS" => GrpcStatus::DataLoss,
+            _ => return None,
+        })
+    }
 }
@@ -0,0 +1,81 @@
+//! `grpc-timeout` header encoding.
+
+use std::time::Duration;
+
+pub(crate) static HEADER_GRPC_TIMEOUT: &'static str = "grpc-timeout";
+
+/// Maximum value of `grpc-timeout` is 8 digits.
+const MAX_VALUE: u64 = 99_999_999;
+
+/// Encode timeout in the most precise unit which fits in 8 digits.
+pub(crate) fn encode_grpc_timeout(timeout: Duration) -> String {
+    let nanos = timeout.as_secs() as u128 * 1_000_000_000 + timeout.subsec_nanos() as u128;
+    let units: &[(u128, char)] = &[
+        (1, 'n'),
+        (1_000, 'u'),
+        (1_000_000, 'm'),
+        (1_000_000_000, 'S'),
+        (60_000_000_000, 'M'),
+    ];
+    for &(unit_nanos, unit) in units {
+        // round up, so peer does not time out earlier
+        let value = (nanos + unit_nanos - 1) / unit_nanos;
+        if value <= MAX_VALUE as u128 {
+            return format!("{}{}", value, unit);
+        }
+    }
+    let hours = (nanos + 3_600_000_000_000 - 1) / 3_600_000_000_000;
+    format!("{}H", hours.min(MAX_VALUE as u128))
+}
+
+/// Parse `grpc-timeout` header value.
+pub(crate) fn parse_grpc_timeout(value: &str) -> Option<Duration> {
+    if value.len() < 2 || value.len() > 9 || !value.is_ascii() {
+        return None;
+    }
+    let (digits, unit) = value.split_at(value.len() - 1);
+    if !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let value: u64 = digits.parse().ok()?;
+    Some(match unit {
+        "H" => Duration::from_secs(value * 3600),
+        "M" => Duration::from_secs(value * 60),
+        "S" => Duration::from_secs(value),
+        "m" => Duration::from_millis(value),
+        "u" => Duration::from_micros(value),
+        "n" => Duration::from_nanos(value),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn encode() {
+        assert_eq!("0n", encode_grpc_timeout(Duration::from_secs(0)));
+        assert_eq!("1500000n", encode_grpc_timeout(Duration::from_micros(1500)));
+        assert_eq!("100000u", encode_grpc_timeout(Duration::from_millis(100)));
+        assert_eq!("3600000m", encode_grpc_timeout(Duration::from_secs(3600)));
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(Some(Duration::from_millis(100)), parse_grpc_timeout("100m"));
+        assert_eq!(Some(Duration::from_secs(7200)), parse_grpc_timeout("2H"));
+        assert_eq!(None, parse_grpc_timeout("100"));
+        assert_eq!(None, parse_grpc_timeout("123456789S"));
+        assert_eq!(None, parse_grpc_timeout("-1S"));
+        assert_eq!(None, parse_grpc_timeout("1\u{e9}"));
+    }
+
+    #[test]
+    fn round_trip() {
+        let timeout = Duration::new(12345, 678);
+        let parsed = parse_grpc_timeout(&encode_grpc_timeout(timeout)).unwrap();
+        assert!(parsed >= timeout);
+        assert!(parsed - timeout < Duration::from_millis(1));
+    }
+}
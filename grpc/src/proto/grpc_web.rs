@@ -0,0 +1,250 @@
+//! grpc-web protocol support.
+//!
+//! grpc-web uses the same length-prefixed message framing as gRPC,
+//! but trailers are sent as the last message in the body instead of HTTP/2 trailers,
+//! and `-text` variant encodes the whole body with base64.
+//!
+//! https://github.com/grpc/grpc/blob/master/doc/PROTOCOL-WEB.md
+
+use std::collections::VecDeque;
+
+use base64;
+
+use bytes::Bytes;
+
+use httpbis::Header;
+use httpbis::Headers;
+
+use error::Error;
+use proto::grpc_frame::write_grpc_frame_with_flags;
+use result;
+
+pub(crate) const CONTENT_TYPE_GRPC: &str = "application/grpc";
+pub(crate) const CONTENT_TYPE_GRPC_WEB: &str = "application/grpc-web";
+pub(crate) const CONTENT_TYPE_GRPC_WEB_TEXT: &str = "application/grpc-web-text";
+
+/// First byte of the grpc-web message which contains trailers.
+pub(crate) const GRPC_WEB_TRAILERS_FLAG: u8 = 0x80;
+
+/// Wire protocol of a request.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum GrpcProtocol {
+    Grpc,
+    GrpcWeb,
+    GrpcWebText,
+}
+
+impl GrpcProtocol {
+    /// Detect protocol from request `content-type`.
+    pub fn from_content_type(content_type: &str) -> Option<GrpcProtocol> {
+        // `+proto`, `+json` etc suffix is allowed
+        let base = content_type.split('+').next().unwrap_or("");
+        let base = base.split(';').next().unwrap_or("").trim();
+        match base {
+            CONTENT_TYPE_GRPC => Some(GrpcProtocol::Grpc),
+            CONTENT_TYPE_GRPC_WEB => Some(GrpcProtocol::GrpcWeb),
+            CONTENT_TYPE_GRPC_WEB_TEXT => Some(GrpcProtocol::GrpcWebText),
+            _ => None,
+        }
+    }
+
+    pub fn is_web(&self) -> bool {
+        *self != GrpcProtocol::Grpc
+    }
+
+    /// Response `content-type`.
+    pub fn content_type(&self) -> &'static str {
+        match *self {
+            GrpcProtocol::Grpc => CONTENT_TYPE_GRPC,
+            GrpcProtocol::GrpcWeb => "application/grpc-web+proto",
+            GrpcProtocol::GrpcWebText => "application/grpc-web-text+proto",
+        }
+    }
+
+    /// Encode response body chunk.
+    pub fn encode_body(&self, data: Bytes) -> Bytes {
+        match *self {
+            GrpcProtocol::Grpc | GrpcProtocol::GrpcWeb => data,
+            GrpcProtocol::GrpcWebText => Bytes::from(base64::encode(&data)),
+        }
+    }
+}
+
+/// Encode trailers as grpc-web trailers message (not yet encoded with `encode_body`).
+pub(crate) fn grpc_web_trailers_frame(trailers: &Headers) -> Bytes {
+    let mut block = Vec::new();
+    for header in trailers.iter() {
+        block.extend_from_slice(header.name().to_ascii_lowercase().as_bytes());
+        block.extend_from_slice(b": ");
+        block.extend_from_slice(&header.value);
+        block.extend_from_slice(b"\r\n");
+    }
+
+    let mut frame = Vec::new();
+    write_grpc_frame_with_flags(&mut frame, GRPC_WEB_TRAILERS_FLAG, &block);
+    Bytes::from(frame)
+}
+
+/// Request headers allowed by CORS preflight response when browser
+/// does not list them in `access-control-request-headers`
+const CORS_ALLOWED_HEADERS: &str = "content-type,x-grpc-web,x-user-agent,grpc-timeout";
+
+/// Headers of every response when browsers from `origin` are allowed to call the server,
+/// so grpc-web client can read status of Trailers-Only responses.
+pub(crate) fn cors_headers(origin: &str) -> Headers {
+    Headers::from_vec(vec![
+        Header::new("access-control-allow-origin", origin.to_owned()),
+        Header::new("access-control-expose-headers", "grpc-status,grpc-message"),
+    ])
+}
+
+/// Response to CORS preflight `OPTIONS` request sent by browser before grpc-web call.
+///
+/// Origin is added with `cors_headers` as to any other response.
+pub(crate) fn cors_preflight_message(request_headers: &Headers) -> httpbis::SimpleHttpMessage {
+    let allowed_headers = request_headers
+        .get_opt("access-control-request-headers")
+        .unwrap_or(CORS_ALLOWED_HEADERS);
+    httpbis::SimpleHttpMessage {
+        headers: Headers::from_vec(vec![
+            Header::new(":status", "204"),
+            Header::new("access-control-allow-methods", "POST"),
+            Header::new("access-control-allow-headers", allowed_headers.to_owned()),
+            Header::new("access-control-max-age", "86400"),
+        ]),
+        body: Bytes::new(),
+    }
+}
+
+/// Incremental decoder of `application/grpc-web-text` request body.
+///
+/// Body may be split at arbitrary positions by transport,
+/// and may consist of several concatenated padded base64 chunks.
+///
+/// Flow control window is measured in input bytes, so decoder also tracks
+/// how many input bytes produced the output, see `input_len`.
+#[derive(Default)]
+pub(crate) struct GrpcWebTextDecoder {
+    buf: Vec<u8>,
+    /// Input bytes, including whitespace, which produced no output yet
+    pending_input: usize,
+    /// Lengths of output not yet passed to `input_len`,
+    /// together with lengths of input which produced it
+    output: VecDeque<(usize, usize)>,
+}
+
+impl GrpcWebTextDecoder {
+    pub fn new() -> GrpcWebTextDecoder {
+        Default::default()
+    }
+
+    /// Decode as much as possible of input, keep incomplete quantum for the next call.
+    pub fn decode(&mut self, data: &[u8]) -> result::Result<Bytes> {
+        self.buf
+            .extend(data.iter().filter(|b| !b.is_ascii_whitespace()));
+
+        let complete = self.buf.len() - self.buf.len() % 4;
+
+        let mut r = Vec::new();
+        let mut start = 0;
+        let mut pos = 0;
+        while pos < complete {
+            pos += 4;
+            // padding terminates base64 chunk
+            if self.buf[pos - 1] == b'=' || pos == complete {
+                let decoded = base64::decode(&self.buf[start..pos])
+                    .map_err(|_| Error::Other("invalid grpc-web-text body"))?;
+                r.extend(decoded);
+                start = pos;
+            }
+        }
+
+        self.buf.drain(..complete);
+
+        self.pending_input += data.len();
+        let input = self.pending_input - self.buf.len();
+        self.pending_input = self.buf.len();
+        if input != 0 {
+            self.output.push_back((r.len(), input));
+        }
+
+        Ok(Bytes::from(r))
+    }
+
+    /// Number of input bytes which produced the next `output` bytes of output.
+    ///
+    /// When output chunk produced by single `decode` call is split,
+    /// its input is divided proportionally. Input which produced no output
+    /// (whitespace) is counted with the following output.
+    pub fn input_len(&mut self, mut output: usize) -> usize {
+        let mut input = 0;
+        while let Some(&(chunk_output, chunk_input)) = self.output.front() {
+            if chunk_output <= output {
+                output -= chunk_output;
+                input += chunk_input;
+                self.output.pop_front();
+                continue;
+            }
+            let part = chunk_input * output / chunk_output;
+            self.output[0] = (chunk_output - output, chunk_input - part);
+            input += part;
+            break;
+        }
+        input
+    }
+
+    /// Is there unprocessed input.
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn from_content_type() {
+        assert_eq!(
+            Some(GrpcProtocol::Grpc),
+            GrpcProtocol::from_content_type("application/grpc")
+        );
+        assert_eq!(
+            Some(GrpcProtocol::GrpcWeb),
+            GrpcProtocol::from_content_type("application/grpc-web+proto")
+        );
+        assert_eq!(
+            Some(GrpcProtocol::GrpcWebText),
+            GrpcProtocol::from_content_type("application/grpc-web-text")
+        );
+        assert_eq!(None, GrpcProtocol::from_content_type("text/plain"));
+    }
+
+    #[test]
+    fn text_decoder_split_input() {
+        let mut decoder = GrpcWebTextDecoder::new();
+        assert_eq!(Bytes::from("abc"), decoder.decode(b"YWJj").unwrap());
+        assert_eq!(Bytes::new(), decoder.decode(b"ZG").unwrap());
+        assert!(!decoder.is_empty());
+        assert_eq!(Bytes::from("de"), decoder.decode(b"U=").unwrap());
+        assert!(decoder.is_empty());
+    }
+
+    #[test]
+    fn text_decoder_input_len() {
+        let mut decoder = GrpcWebTextDecoder::new();
+        decoder.decode(b"YWJj\r\nZG").unwrap();
+        decoder.decode(b"U=").unwrap();
+        // "abc" and the following line break
+        assert_eq!(6, decoder.input_len(3));
+        assert_eq!(2, decoder.input_len(1));
+        assert_eq!(2, decoder.input_len(1));
+        assert_eq!(0, decoder.input_len(0));
+    }
+
+    #[test]
+    fn text_decoder_concatenated_chunks() {
+        let mut decoder = GrpcWebTextDecoder::new();
+        assert_eq!(Bytes::from("aba"), decoder.decode(b"YWI=YQ==").unwrap());
+    }
+}
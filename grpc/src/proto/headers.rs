@@ -1,48 +1,122 @@
 use bytes::Bytes;
 use httpbis::Header;
 use httpbis::Headers;
+use proto::grpc_message::encode_grpc_message;
 use proto::grpc_status::GrpcStatus;
+use proto::grpc_web::CONTENT_TYPE_GRPC;
 use Metadata;
 
 pub(crate) static HEADER_GRPC_STATUS: &'static str = "grpc-status";
 pub(crate) static HEADER_GRPC_MESSAGE: &'static str = "grpc-message";
+pub(crate) static HEADER_GRPC_ENCODING: &'static str = "grpc-encoding";
+pub(crate) static HEADER_GRPC_ACCEPT_ENCODING: &'static str = "grpc-accept-encoding";
+pub(crate) static HEADER_USER_AGENT: &'static str = "user-agent";
 
-pub(crate) fn headers_500(grpc_status: GrpcStatus, message: String) -> Headers {
-    Headers::from_vec(vec![
-        Header::new(":status", "500"),
-        Header::new(HEADER_GRPC_STATUS, format!("{}", grpc_status as i32)),
-        Header::new(HEADER_GRPC_MESSAGE, message),
-    ])
+/// Value of `user-agent` sent by client, e. g. `my-app/1.2 grpc-rust/<version>`.
+pub(crate) fn user_agent(prefix: Option<&str>) -> String {
+    let user_agent = concat!("grpc-rust/", env!("CARGO_PKG_VERSION"));
+    match prefix {
+        Some(prefix) => format!("{} {}", prefix, user_agent),
+        None => user_agent.to_owned(),
+    }
+}
+
+/// Message encodings supported by this implementation, value of `grpc-accept-encoding`
+///
+/// Peer's `grpc-accept-encoding` is not parsed: with `identity` as the only
+/// encoding there is nothing to negotiate. Once compression is implemented,
+/// negotiated codec can be cached per connection and passed to handlers.
+pub(crate) static SUPPORTED_GRPC_ENCODINGS: &'static str = "identity";
+
+/// Trailers-Only response: `:status 200` and `content-type`
+/// with status and metadata in the single HEADERS frame.
+pub(crate) fn trailers_only(
+    content_type: &'static str,
+    grpc_status: GrpcStatus,
+    message: String,
+    metadata: Metadata,
+) -> Headers {
+    let mut headers = Headers::from_vec(vec![
+        Header::new(":status", "200"),
+        Header::new("content-type", content_type),
+        Header::new(HEADER_GRPC_STATUS, format!("{}", grpc_status.code())),
+        Header::new(HEADER_GRPC_MESSAGE, encode_grpc_message(&message)),
+    ]);
+    headers.extend(metadata.into_headers());
+    headers
 }
 
-pub(crate) fn headers_200(metadata: Metadata) -> Headers {
+pub(crate) fn headers_200(content_type: &'static str, metadata: Metadata) -> Headers {
     let mut headers = Headers::from_vec(vec![
         // TODO: do not allocate
         Header::new(":status", "200"),
-        Header::new("content-type", "application/grpc"),
-        Header::new(HEADER_GRPC_STATUS, "0"),
+        Header::new("content-type", content_type),
     ]);
     headers.extend(metadata.into_headers());
     headers
 }
 
-/// Create HTTP response for gRPC error
-pub(crate) fn grpc_error_message(message: &str) -> httpbis::SimpleHttpMessage {
+/// Plain HTTP error response to a request which is not a gRPC request
+pub(crate) fn http_error_message(http_status: u16, message: &str) -> httpbis::SimpleHttpMessage {
     let headers = Headers::from_vec(vec![
-        Header::new(":status", "200"),
-        // TODO: alloc
-        Header::new(
-            HEADER_GRPC_STATUS,
-            format!("{}", GrpcStatus::Internal.code()),
-        ),
-        Header::new(HEADER_GRPC_MESSAGE, message.to_owned()),
+        Header::new(":status", format!("{}", http_status)),
+        Header::new("content-type", "text/plain"),
     ]);
+    httpbis::SimpleHttpMessage {
+        headers,
+        body: Bytes::from(format!("{}\n", message)),
+    }
+}
+
+/// Create HTTP response for gRPC error
+pub(crate) fn grpc_error_message(
+    grpc_status: GrpcStatus,
+    message: &str,
+) -> httpbis::SimpleHttpMessage {
+    grpc_error_message_with_metadata(grpc_status, message, Metadata::new())
+}
+
+pub(crate) fn grpc_error_message_with_metadata(
+    grpc_status: GrpcStatus,
+    message: &str,
+    metadata: Metadata,
+) -> httpbis::SimpleHttpMessage {
+    let headers = trailers_only(CONTENT_TYPE_GRPC, grpc_status, message.to_owned(), metadata);
     httpbis::SimpleHttpMessage {
         headers,
         body: Bytes::new(),
     }
 }
 
+/// `UNIMPLEMENTED` response to a request with unsupported `grpc-encoding`,
+/// which lists supported encodings, see
+/// https://github.com/grpc/grpc/blob/master/doc/compression.md
+pub(crate) fn grpc_unsupported_encoding_message(encoding: &str) -> httpbis::SimpleHttpMessage {
+    let mut message = grpc_error_message(
+        GrpcStatus::Unimplemented,
+        &format!("unsupported grpc-encoding: {}", encoding),
+    );
+    message.headers.add_header(Header::new(
+        HEADER_GRPC_ACCEPT_ENCODING,
+        SUPPORTED_GRPC_ENCODINGS,
+    ));
+    message
+}
+
+/// Add headers not already present, so headers set by server or handler take precedence
+pub(crate) fn add_missing_headers(headers: &mut Headers, extra: &Headers) {
+    // checked against headers present before any extra header is added,
+    // so all values of a repeated extra header are added in order
+    let missing: Vec<Header> = extra
+        .iter()
+        .filter(|header| headers.get_opt(header.name()).is_none())
+        .cloned()
+        .collect();
+    for header in missing {
+        headers.add_header(header);
+    }
+}
+
 // Trailers -> Status [Status-Message] *Custom-Metadata
 pub(crate) fn trailers(
     grpc_status: GrpcStatus,
@@ -54,8 +128,43 @@ pub(crate) fn trailers(
         format!("{}", grpc_status as i32),
     )]);
     if let Some(message) = message {
-        headers.add_header(Header::new(HEADER_GRPC_MESSAGE, message));
+        headers.add_header(Header::new(
+            HEADER_GRPC_MESSAGE,
+            encode_grpc_message(&message),
+        ));
     }
     headers.extend(metadata.into_headers());
     headers
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn add_missing_repeated_headers() {
+        let mut headers = Headers::from_vec(vec![Header::new("x-a", "0")]);
+        let extra = Headers::from_vec(vec![
+            Header::new("x-a", "1"),
+            Header::new("x-b", "1"),
+            Header::new("x-b", "2"),
+        ]);
+        add_missing_headers(&mut headers, &extra);
+        let headers: Vec<(&str, &[u8])> =
+            headers.iter().map(|h| (h.name(), &h.value[..])).collect();
+        assert_eq!(
+            vec![("x-a", &b"0"[..]), ("x-b", &b"1"[..]), ("x-b", &b"2"[..])],
+            headers
+        );
+    }
+
+    #[test]
+    fn user_agent_prefix() {
+        let version = env!("CARGO_PKG_VERSION");
+        assert_eq!(format!("grpc-rust/{}", version), user_agent(None));
+        assert_eq!(
+            format!("my-app/1.2 grpc-rust/{}", version),
+            user_agent(Some("my-app/1.2"))
+        );
+    }
+}
@@ -1,3 +1,7 @@
+use std::net::IpAddr;
+use std::net::SocketAddr;
+use std::str;
+
 use base64;
 
 use bytes::Bytes;
@@ -6,6 +10,8 @@ use chars::Chars;
 use httpbis::Header;
 use httpbis::Headers;
 
+use proto::grpc_web::GrpcProtocol;
+
 #[derive(Debug, Clone)]
 pub struct MetadataKey {
     pub name: Chars,
@@ -63,24 +69,52 @@ impl MetadataEntry {
         Header::new(self.key.name.into_inner(), value)
     }
 
-    fn from_header(header: Header) -> Result<Option<MetadataEntry>, MetadataDecodeError> {
+    /// Entries of a header, several if it is a binary header
+    /// with comma-separated values, e. g. joined by a proxy.
+    fn from_header(header: &Header) -> Result<Vec<MetadataEntry>, MetadataDecodeError> {
         if header.name().starts_with(":") {
-            return Ok(None);
+            return Ok(Vec::new());
         }
-        if header.name().starts_with("grpc-") {
-            return Ok(None);
+        // `grpc-trace-bin` is reserved, but passed to application as metadata
+        if header.name().starts_with("grpc-") && header.name() != "grpc-trace-bin" {
+            return Ok(Vec::new());
         }
         let key = MetadataKey {
             name: Chars::from(header.name()),
         };
-        let value = match key.is_bin() {
-            true => Bytes::from(base64::decode(&header.value)?),
-            false => header.value,
-        };
-        Ok(Some(MetadataEntry { key, value }))
+        if !key.is_bin() {
+            // values of ASCII headers may contain commas, so they are not split
+            return Ok(vec![MetadataEntry {
+                key,
+                value: header.value.clone(),
+            }]);
+        }
+        let mut entries = Vec::new();
+        for value in header.value.split(|&b| b == b',') {
+            let value = trim_ascii_whitespace(value);
+            entries.push(MetadataEntry {
+                key: key.clone(),
+                value: Bytes::from(base64::decode(value)?),
+            });
+        }
+        Ok(entries)
     }
 }
 
+fn trim_ascii_whitespace(mut s: &[u8]) -> &[u8] {
+    while let Some((b' ', rem)) = s.split_first() {
+        s = rem;
+    }
+    while let Some((b' ', rem)) = s.split_last() {
+        s = rem;
+    }
+    s
+}
+
+/// Request or response metadata.
+///
+/// Entries keep the order they were added or received in, including
+/// repeated keys, and are sent in the same order, as one header per entry.
 #[derive(Default, Debug, Clone)]
 pub struct Metadata {
     pub entries: Vec<MetadataEntry>,
@@ -94,10 +128,7 @@ impl Metadata {
     pub fn from_headers(headers: Headers) -> Result<Metadata, MetadataDecodeError> {
         let mut r = Metadata::new();
         for h in headers.iter() {
-            // TODO: clone
-            if let Some(e) = MetadataEntry::from_header(h.clone())? {
-                r.entries.push(e);
-            }
+            r.entries.extend(MetadataEntry::from_header(h)?);
         }
         Ok(r)
     }
@@ -121,6 +152,14 @@ impl Metadata {
         None
     }
 
+    /// All values of the key, in the order they were added or received.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a [u8]> + 'a {
+        self.entries
+            .iter()
+            .filter(move |e| e.key.as_str() == name)
+            .map(|e| &e.value[..])
+    }
+
     pub fn extend(&mut self, extend: Metadata) {
         self.entries.extend(extend.entries);
     }
@@ -128,4 +167,155 @@ impl Metadata {
     pub fn add(&mut self, key: MetadataKey, value: Bytes) {
         self.entries.push(MetadataEntry { key, value });
     }
+
+    /// Token of `authorization: Bearer <token>` entry.
+    pub fn authorization_bearer(&self) -> Option<&str> {
+        let value = str::from_utf8(self.get("authorization")?).ok()?.trim();
+        if !value.get(..7)?.eq_ignore_ascii_case("bearer ") {
+            return None;
+        }
+        let token = value[7..].trim_start();
+        if token.is_empty() {
+            return None;
+        }
+        Some(token)
+    }
+
+    /// Addresses of `x-forwarded-for` entries, starting with the original client.
+    ///
+    /// Values which are not IP addresses (e. g. `unknown`) are skipped,
+    /// ports are dropped.
+    pub fn x_forwarded_for(&self) -> Vec<IpAddr> {
+        self.get_all("x-forwarded-for")
+            .filter_map(|value| str::from_utf8(value).ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .filter_map(|addr| {
+                addr.parse::<IpAddr>()
+                    .or_else(|_| addr.parse::<SocketAddr>().map(|a| a.ip()))
+                    .ok()
+            })
+            .collect()
+    }
+
+    /// Subtype of gRPC `content-type`, e. g. `proto` for `application/grpc+proto`.
+    ///
+    /// `None` if `content-type` has no subtype or is not gRPC `content-type`.
+    pub fn content_subtype(&self) -> Option<&str> {
+        let content_type = str::from_utf8(self.get("content-type")?).ok()?;
+        let content_type = content_type.split(';').next().unwrap_or("").trim();
+        let mut parts = content_type.splitn(2, '+');
+        GrpcProtocol::from_content_type(parts.next()?)?;
+        parts.next().filter(|subtype| !subtype.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entries(metadata: &Metadata) -> Vec<(&str, &[u8])> {
+        metadata
+            .entries
+            .iter()
+            .map(|e| (e.key.as_str(), &e.value[..]))
+            .collect()
+    }
+
+    #[test]
+    fn order_and_repeated_keys() {
+        let mut metadata = Metadata::new();
+        metadata.add(MetadataKey::from("x-a"), Bytes::from("2"));
+        metadata.add(MetadataKey::from("x-b"), Bytes::from("1"));
+        metadata.add(MetadataKey::from("x-a"), Bytes::from("1"));
+        metadata.add(MetadataKey::from("x-c-bin"), Bytes::from(&b"\xff"[..]));
+        metadata.add(MetadataKey::from("x-c-bin"), Bytes::from(&b"\x00"[..]));
+
+        let expected = entries(&metadata)
+            .into_iter()
+            .map(|(k, v)| (k.to_owned(), v.to_vec()))
+            .collect::<Vec<_>>();
+
+        let decoded = Metadata::from_headers(metadata.into_headers()).unwrap();
+        let decoded_entries = entries(&decoded)
+            .into_iter()
+            .map(|(k, v)| (k.to_owned(), v.to_vec()))
+            .collect::<Vec<_>>();
+        assert_eq!(expected, decoded_entries);
+        assert_eq!(
+            vec![&b"2"[..], &b"1"[..]],
+            decoded.get_all("x-a").collect::<Vec<_>>()
+        );
+    }
+
+    fn metadata(name: &str, values: &[&str]) -> Metadata {
+        let mut metadata = Metadata::new();
+        for value in values {
+            metadata.add(MetadataKey::from(name.to_owned()), Bytes::from(*value));
+        }
+        metadata
+    }
+
+    #[test]
+    fn authorization_bearer() {
+        let token = |value| {
+            metadata("authorization", &[value])
+                .authorization_bearer()
+                .map(str::to_owned)
+        };
+        assert_eq!(Some("abc".to_owned()), token("Bearer abc"));
+        assert_eq!(Some("abc".to_owned()), token("bearer  abc"));
+        assert_eq!(None, token("Basic YWxhZGRpbg=="));
+        assert_eq!(None, token("Bearer "));
+        assert_eq!(None, Metadata::new().authorization_bearer());
+    }
+
+    #[test]
+    fn x_forwarded_for() {
+        let metadata = metadata(
+            "x-forwarded-for",
+            &["203.0.113.7, unknown", "[2001:db8::1]:443, 10.0.0.1"],
+        );
+        assert_eq!(
+            vec![
+                "203.0.113.7".parse::<IpAddr>().unwrap(),
+                "2001:db8::1".parse().unwrap(),
+                "10.0.0.1".parse().unwrap(),
+            ],
+            metadata.x_forwarded_for()
+        );
+    }
+
+    #[test]
+    fn content_subtype() {
+        let subtype = |value| {
+            metadata("content-type", &[value])
+                .content_subtype()
+                .map(str::to_owned)
+        };
+        assert_eq!(Some("proto".to_owned()), subtype("application/grpc+proto"));
+        assert_eq!(
+            Some("json".to_owned()),
+            subtype("application/grpc-web+json; charset=utf-8")
+        );
+        assert_eq!(None, subtype("application/grpc"));
+        assert_eq!(None, subtype("application/json+proto"));
+    }
+
+    #[test]
+    fn joined_bin_values() {
+        let headers = Headers::from_vec(vec![
+            Header::new("x-c-bin", "/w==, AA=="),
+            Header::new("x-d", "a, b"),
+        ]);
+        let metadata = Metadata::from_headers(headers).unwrap();
+        assert_eq!(
+            vec![
+                ("x-c-bin", &b"\xff"[..]),
+                ("x-c-bin", &b"\x00"[..]),
+                ("x-d", &b"a, b"[..]),
+            ],
+            entries(&metadata)
+        );
+    }
 }
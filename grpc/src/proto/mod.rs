@@ -1,4 +1,7 @@
 pub(crate) mod grpc_frame;
+pub(crate) mod grpc_message;
 pub(crate) mod grpc_status;
+pub(crate) mod grpc_timeout;
+pub(crate) mod grpc_web;
 pub(crate) mod headers;
 pub(crate) mod metadata;
@@ -1,3 +1,6 @@
+use std::time::Duration;
+
+use futures::future::Future;
 use futures::stream;
 use futures::stream::Stream;
 
@@ -8,20 +11,49 @@ use futures::Async;
 use futures::Poll;
 use futures::Sink;
 use futures::StartSend;
+use futures_grpc::GrpcFuture;
 use futures_grpc::GrpcStream;
+use proto::grpc_timeout::encode_grpc_timeout;
 use proto::metadata::Metadata;
+use result;
+use trace::CallId;
 
+/// Options of a single call.
+///
+/// There is no per-call compression setting: messages of all calls
+/// are sent uncompressed, because compression is not implemented.
 #[derive(Debug, Default, Clone)]
 pub struct RequestOptions {
     pub metadata: Metadata,
     // TODO: do not ignore
     pub cachable: bool,
+    /// Fail the call with `DEADLINE_EXCEEDED` if it is not completed in this time.
+    ///
+    /// Timeout is also sent to the server in `grpc-timeout` header.
+    pub timeout: Option<Duration>,
 }
 
 impl RequestOptions {
     pub fn new() -> RequestOptions {
         Default::default()
     }
+
+    /// Send given call id in request metadata, so client and server
+    /// log records of the call can be correlated.
+    pub fn with_call_id(mut self, call_id: CallId) -> RequestOptions {
+        call_id.inject(&mut self.metadata);
+        self
+    }
+
+    /// Call id set with `with_call_id`, if any.
+    pub fn call_id(&self) -> Option<CallId> {
+        CallId::from_metadata(&self.metadata)
+    }
+
+    /// Value of `grpc-timeout` header sent for `timeout`, e. g. `100000u`.
+    pub fn grpc_timeout(&self) -> Option<String> {
+        self.timeout.map(encode_grpc_timeout)
+    }
 }
 
 /// Excluding initial metadata which is passed separately
@@ -68,6 +100,57 @@ impl<T: Send + 'static> StreamingRequest<T> {
     pub fn err(err: Error) -> StreamingRequest<T> {
         StreamingRequest::new(stream::once(Err(err)))
     }
+
+    // getters
+
+    /// Transform messages.
+    pub fn map_items<U, F>(self, f: F) -> StreamingRequest<U>
+    where
+        U: Send + 'static,
+        F: FnMut(T) -> U + Send + 'static,
+    {
+        StreamingRequest::new(self.0.map(f))
+    }
+
+    /// Transform messages with a function which may fail the stream.
+    pub fn and_then_items<U, F>(self, f: F) -> StreamingRequest<U>
+    where
+        U: Send + 'static,
+        F: FnMut(T) -> result::Result<U> + Send + 'static,
+    {
+        StreamingRequest::new(self.0.and_then(f))
+    }
+
+    /// Transform error of the stream, e. g. to add context to it.
+    pub fn map_err<F>(self, f: F) -> StreamingRequest<T>
+    where
+        F: FnMut(Error) -> Error + Send + 'static,
+    {
+        StreamingRequest::new(self.0.map_err(f))
+    }
+
+    /// Accumulate all messages into single value.
+    pub fn fold<A, F>(self, init: A, mut f: F) -> GrpcFuture<A>
+    where
+        A: Send + 'static,
+        F: FnMut(A, T) -> A + Send + 'static,
+    {
+        Box::new(self.0.fold(init, move |acc, item| Ok(f(acc, item))))
+    }
+
+    /// Collect all messages.
+    pub fn collect(self) -> GrpcFuture<Vec<T>> {
+        Box::new(self.0.collect())
+    }
+
+    /// Wait for the only message, fail if stream contains zero or more than one message.
+    pub fn into_single(self) -> GrpcFuture<T> {
+        Box::new(self.collect().and_then(|mut v| match v.len() {
+            0 => Err(Error::Other("no message in request stream")),
+            1 => Ok(v.swap_remove(0)),
+            _ => Err(Error::Other("more than one message in request stream")),
+        }))
+    }
 }
 
 pub struct StreamingRequestSender<T: Send + 'static> {
@@ -109,3 +192,36 @@ impl<T: Send + 'static> Drop for StreamingRequestSender<T> {
         // TODO: cancel
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn fold_and_single() {
+        assert_eq!(
+            6,
+            StreamingRequest::iter(vec![1, 2, 3])
+                .fold(0, |acc, i| acc + i)
+                .wait()
+                .unwrap()
+        );
+        assert_eq!(
+            vec![2, 4],
+            StreamingRequest::iter(vec![1, 2])
+                .map_items(|i| i * 2)
+                .collect()
+                .wait()
+                .unwrap()
+        );
+        assert_eq!(1, StreamingRequest::once(1).into_single().wait().unwrap());
+        assert!(StreamingRequest::<u32>::empty()
+            .into_single()
+            .wait()
+            .is_err());
+        assert!(StreamingRequest::iter(vec![1, 2])
+            .into_single()
+            .wait()
+            .is_err());
+    }
+}
@@ -1,17 +1,37 @@
+use std::sync::Arc;
+use std::sync::Mutex;
+
 use futures::future;
 use futures::future::Future;
 use futures::stream;
 use futures::stream::Stream;
+use futures::task;
+use futures::task::Task;
 
 use error;
+use futures::Async;
 use futures::Poll;
 use futures_grpc::*;
 use iter::*;
+use proto::grpc_status::GrpcStatus;
 use proto::metadata::Metadata;
 use result;
 use stream_item::*;
 
+/// All parts of successful unary call response.
+#[derive(Debug)]
+pub struct UnaryResponse<T> {
+    pub initial_metadata: Metadata,
+    pub message: T,
+    pub trailing_metadata: Metadata,
+    /// Always `Ok`, failed calls result in `Error`.
+    pub status: GrpcStatus,
+}
+
 /// Single message response
+///
+/// Failed call completes with `Error`, status of the call is `Error::status`:
+/// status sent by server, or `UNAVAILABLE` if server could not be reached.
 pub struct SingleResponse<T: Send + 'static>(pub GrpcFuture<(Metadata, GrpcFuture<(T, Metadata)>)>);
 
 impl<T: Send + 'static> SingleResponse<T> {
@@ -93,6 +113,18 @@ impl<T: Send + 'static> SingleResponse<T> {
         }))
     }
 
+    /// Wait for complete response.
+    pub fn join_full(self) -> GrpcFuture<UnaryResponse<T>> {
+        Box::new(self.join_metadata_result().map(
+            |(initial_metadata, message, trailing_metadata)| UnaryResponse {
+                initial_metadata,
+                message,
+                trailing_metadata,
+                status: GrpcStatus::Ok,
+            },
+        ))
+    }
+
     pub fn drop_metadata(self) -> GrpcFuture<T> {
         Box::new(
             self.0
@@ -116,6 +148,79 @@ impl<T: Send + 'static> SingleResponse<T> {
         }))
     }
 
+    /// Transform response message, keep metadata.
+    pub fn map_message<U, F>(self, f: F) -> SingleResponse<U>
+    where
+        U: Send + 'static,
+        F: FnOnce(T) -> U + Send + 'static,
+    {
+        self.and_then_message(move |message| Ok(f(message)))
+    }
+
+    /// Transform response message with a function which may fail the response.
+    pub fn and_then_message<U, F>(self, f: F) -> SingleResponse<U>
+    where
+        U: Send + 'static,
+        F: FnOnce(T) -> result::Result<U> + Send + 'static,
+    {
+        SingleResponse::new(self.0.map(move |(metadata, future)| {
+            let future: GrpcFuture<(U, Metadata)> =
+                Box::new(future.and_then(move |(message, trailing)| {
+                    f(message).map(|message| (message, trailing))
+                }));
+            (metadata, future)
+        }))
+    }
+
+    /// Transform initial metadata.
+    pub fn map_metadata<F>(self, f: F) -> SingleResponse<T>
+    where
+        F: FnOnce(Metadata) -> Metadata + Send + 'static,
+    {
+        self.and_then_metadata(move |metadata| Ok(f(metadata)))
+    }
+
+    /// Transform initial metadata with a function which may fail the response.
+    pub fn and_then_metadata<F>(self, f: F) -> SingleResponse<T>
+    where
+        F: FnOnce(Metadata) -> result::Result<Metadata> + Send + 'static,
+    {
+        SingleResponse::new(
+            self.0
+                .and_then(move |(metadata, future)| f(metadata).map(|metadata| (metadata, future))),
+        )
+    }
+
+    /// Transform trailing metadata.
+    pub fn map_trailing_metadata<F>(self, f: F) -> SingleResponse<T>
+    where
+        F: FnOnce(Metadata) -> Metadata + Send + 'static,
+    {
+        SingleResponse::new(self.0.map(move |(metadata, future)| {
+            let future: GrpcFuture<(T, Metadata)> =
+                Box::new(future.map(move |(message, trailing)| (message, f(trailing))));
+            (metadata, future)
+        }))
+    }
+
+    /// Transform error of the call, whether it fails before
+    /// or after initial metadata is received.
+    pub fn map_err<F>(self, f: F) -> SingleResponse<T>
+    where
+        F: Fn(error::Error) -> error::Error + Send + Sync + 'static,
+    {
+        let f = Arc::new(f);
+        let f_metadata = f.clone();
+        SingleResponse::new(
+            self.0
+                .map(move |(metadata, future)| {
+                    let future: GrpcFuture<(T, Metadata)> = Box::new(future.map_err(move |e| f(e)));
+                    (metadata, future)
+                })
+                .map_err(move |e| f_metadata(e)),
+        )
+    }
+
     pub fn wait(self) -> result::Result<(Metadata, T, Metadata)> {
         self.join_metadata_result().wait()
     }
@@ -123,6 +228,10 @@ impl<T: Send + 'static> SingleResponse<T> {
     pub fn wait_drop_metadata(self) -> result::Result<T> {
         self.wait().map(|(_initial, r, _trailing)| r)
     }
+
+    pub fn wait_full(self) -> result::Result<UnaryResponse<T>> {
+        self.join_full().wait()
+    }
 }
 
 impl<T: Send + 'static> Future for SingleResponse<T> {
@@ -134,6 +243,31 @@ impl<T: Send + 'static> Future for SingleResponse<T> {
     }
 }
 
+impl<T: Send + 'static> From<error::Error> for SingleResponse<T> {
+    fn from(err: error::Error) -> SingleResponse<T> {
+        SingleResponse::err(err)
+    }
+}
+
+impl<T: Send + 'static> From<error::Status> for SingleResponse<T> {
+    fn from(status: error::Status) -> SingleResponse<T> {
+        SingleResponse::err(error::Error::Status(status))
+    }
+}
+
+/// Allows handlers returning responses to fail early with `?`,
+/// e. g. when request validation fails.
+impl<T: Send + 'static, E: Into<error::Error>> From<Result<SingleResponse<T>, E>>
+    for SingleResponse<T>
+{
+    fn from(r: Result<SingleResponse<T>, E>) -> SingleResponse<T> {
+        match r {
+            Ok(r) => r,
+            Err(e) => SingleResponse::err(e.into()),
+        }
+    }
+}
+
 /// Streaming response
 pub struct StreamingResponse<T: Send + 'static>(
     /// Initial metadata, stream of items followed by trailing metadata
@@ -178,6 +312,17 @@ impl<T: Send + 'static> StreamingResponse<T> {
         StreamingResponse::new(future::ok((metadata, boxed)))
     }
 
+    /// Initial metadata known upfront, and stream computed asynchronously.
+    ///
+    /// When used as server response, metadata is sent to client as response headers
+    /// immediately, without waiting for the stream future or the first message.
+    pub fn metadata_then<F>(metadata: Metadata, stream: F) -> StreamingResponse<T>
+    where
+        F: Future<Item = GrpcStream<T>, Error = error::Error> + Send + 'static,
+    {
+        StreamingResponse::metadata_and_stream(metadata, stream.flatten_stream())
+    }
+
     pub fn no_metadata<S>(s: S) -> StreamingResponse<T>
     where
         S: Stream<Item = T, Error = error::Error> + Send + 'static,
@@ -292,6 +437,48 @@ impl<T: Send + 'static> StreamingResponse<T> {
         self.map_stream(move |stream| stream.and_then_items(f))
     }
 
+    /// Transform initial metadata.
+    pub fn map_metadata<F>(self, f: F) -> StreamingResponse<T>
+    where
+        F: FnOnce(Metadata) -> Metadata + Send + 'static,
+    {
+        self.and_then_metadata(move |metadata| Ok(f(metadata)))
+    }
+
+    /// Transform initial metadata with a function which may fail the response.
+    pub fn and_then_metadata<F>(self, f: F) -> StreamingResponse<T>
+    where
+        F: FnOnce(Metadata) -> result::Result<Metadata> + Send + 'static,
+    {
+        StreamingResponse::new(
+            self.0
+                .and_then(move |(metadata, stream)| f(metadata).map(|metadata| (metadata, stream))),
+        )
+    }
+
+    /// Transform trailing metadata.
+    pub fn map_trailing_metadata<F>(self, f: F) -> StreamingResponse<T>
+    where
+        F: FnMut(Metadata) -> Metadata + Send + 'static,
+    {
+        self.map_stream(move |stream| stream.map_trailing_metadata(f))
+    }
+
+    /// Transform error of the call, whether it fails before
+    /// or after initial metadata is received.
+    pub fn map_err<F>(self, f: F) -> StreamingResponse<T>
+    where
+        F: Fn(error::Error) -> error::Error + Send + Sync + 'static,
+    {
+        let f = Arc::new(f);
+        let f_metadata = f.clone();
+        StreamingResponse::new(
+            self.0
+                .map(move |(metadata, stream)| (metadata, stream.map_err(move |e| f(e))))
+                .map_err(move |e| f_metadata(e)),
+        )
+    }
+
     pub fn drop_metadata(self) -> GrpcStream<T> {
         Box::new(
             self.0
@@ -300,6 +487,32 @@ impl<T: Send + 'static> StreamingResponse<T> {
         )
     }
 
+    /// Split into initial metadata and stream of messages followed by trailing metadata.
+    ///
+    /// Either part can be polled first: initial metadata is available
+    /// as soon as response headers are received, before any message.
+    /// When response fails before headers are received, both parts fail.
+    pub fn into_parts(self) -> (GrpcFuture<Metadata>, GrpcStreamWithTrailingMetadata<T>) {
+        let shared = Arc::new(Mutex::new(PartsShared {
+            headers: Some(self.0),
+            metadata: None,
+            stream: None,
+            metadata_task: None,
+            stream_task: None,
+        }));
+        let metadata = InitialMetadataPart {
+            shared: shared.clone(),
+        };
+        let stream = MessagesPart {
+            shared,
+            stream: None,
+        };
+        (
+            Box::new(metadata),
+            GrpcStreamWithTrailingMetadata::new(stream),
+        )
+    }
+
     pub fn into_future(self) -> SingleResponse<Vec<T>> {
         SingleResponse::new(self.0.map(|(initial, stream)| {
             let future: GrpcFuture<(Vec<T>, Metadata)> = stream.collect_with_metadata();
@@ -324,7 +537,256 @@ impl<T: Send + 'static> StreamingResponse<T> {
         Box::new(self.drop_metadata().wait())
     }
 
+    /// Blocking iterator over messages, which fails with `DEADLINE_EXCEEDED`
+    /// when `WaitIter::timeout` or `WaitIter::item_timeout` is exceeded,
+    /// so synchronous consumers do not block forever on a stuck server.
+    pub fn wait_iter(self) -> WaitIter<T> {
+        WaitIter::new(self)
+    }
+
     pub fn collect(self) -> GrpcFuture<(Metadata, Vec<T>, Metadata)> {
         self.into_future().join_metadata_result()
     }
 }
+
+impl<T: Send + 'static> From<error::Error> for StreamingResponse<T> {
+    fn from(err: error::Error) -> StreamingResponse<T> {
+        StreamingResponse::err(err)
+    }
+}
+
+impl<T: Send + 'static> From<error::Status> for StreamingResponse<T> {
+    fn from(status: error::Status) -> StreamingResponse<T> {
+        StreamingResponse::err(error::Error::Status(status))
+    }
+}
+
+/// Allows handlers returning responses to fail early with `?`.
+impl<T: Send + 'static, E: Into<error::Error>> From<Result<StreamingResponse<T>, E>>
+    for StreamingResponse<T>
+{
+    fn from(r: Result<StreamingResponse<T>, E>) -> StreamingResponse<T> {
+        match r {
+            Ok(r) => r,
+            Err(e) => StreamingResponse::err(e.into()),
+        }
+    }
+}
+
+/// State shared by parts of `StreamingResponse::into_parts`.
+///
+/// Headers future is polled by whichever part is polled, and it notifies
+/// only the task which polled it last, so that part wakes the other one.
+struct PartsShared<T: Send + 'static> {
+    headers: Option<GrpcFuture<(Metadata, GrpcStreamWithTrailingMetadata<T>)>>,
+    metadata: Option<result::Result<Metadata>>,
+    stream: Option<result::Result<GrpcStreamWithTrailingMetadata<T>>>,
+    metadata_task: Option<Task>,
+    stream_task: Option<Task>,
+}
+
+impl<T: Send + 'static> PartsShared<T> {
+    fn poll_headers(&mut self) -> Async<()> {
+        let r = match self.headers {
+            Some(ref mut headers) => headers.poll(),
+            None => return Async::Ready(()),
+        };
+        match r {
+            Ok(Async::NotReady) => return Async::NotReady,
+            Ok(Async::Ready((metadata, stream))) => {
+                self.metadata = Some(Ok(metadata));
+                self.stream = Some(Ok(stream));
+            }
+            Err(e) => {
+                // error is not clonable, metadata part gets its status
+                self.metadata = Some(Err(error::Error::Status(e.status())));
+                self.stream = Some(Err(e));
+            }
+        }
+        self.headers = None;
+        self.notify_all();
+        Async::Ready(())
+    }
+
+    fn notify_all(&mut self) {
+        if let Some(task) = self.metadata_task.take() {
+            task.notify();
+        }
+        if let Some(task) = self.stream_task.take() {
+            task.notify();
+        }
+    }
+}
+
+struct InitialMetadataPart<T: Send + 'static> {
+    shared: Arc<Mutex<PartsShared<T>>>,
+}
+
+impl<T: Send + 'static> Future for InitialMetadataPart<T> {
+    type Item = Metadata;
+    type Error = error::Error;
+
+    fn poll(&mut self) -> Poll<Metadata, error::Error> {
+        let mut shared = self.shared.lock().unwrap();
+        if let Async::NotReady = shared.poll_headers() {
+            shared.metadata_task = Some(task::current());
+            return Ok(Async::NotReady);
+        }
+        match shared.metadata.take() {
+            Some(r) => r.map(Async::Ready),
+            None => Err(error::Error::Other("initial metadata is already taken")),
+        }
+    }
+}
+
+impl<T: Send + 'static> Drop for InitialMetadataPart<T> {
+    fn drop(&mut self) {
+        // stream part may wait for headers future notifying this part
+        if let Ok(mut shared) = self.shared.lock() {
+            shared.notify_all();
+        }
+    }
+}
+
+struct MessagesPart<T: Send + 'static> {
+    shared: Arc<Mutex<PartsShared<T>>>,
+    stream: Option<GrpcStreamWithTrailingMetadata<T>>,
+}
+
+impl<T: Send + 'static> Stream for MessagesPart<T> {
+    type Item = ItemOrMetadata<T>;
+    type Error = error::Error;
+
+    fn poll(&mut self) -> Poll<Option<ItemOrMetadata<T>>, error::Error> {
+        if self.stream.is_none() {
+            let mut shared = self.shared.lock().unwrap();
+            if let Async::NotReady = shared.poll_headers() {
+                shared.stream_task = Some(task::current());
+                return Ok(Async::NotReady);
+            }
+            match shared.stream.take() {
+                Some(Ok(stream)) => self.stream = Some(stream),
+                Some(Err(e)) => return Err(e),
+                None => return Ok(Async::Ready(None)),
+            }
+        }
+        self.stream.as_mut().unwrap().0.poll()
+    }
+}
+
+impl<T: Send + 'static> Drop for MessagesPart<T> {
+    fn drop(&mut self) {
+        if let Ok(mut shared) = self.shared.lock() {
+            shared.notify_all();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use proto::metadata::MetadataKey;
+
+    #[test]
+    fn single_combinators() {
+        let mut metadata = Metadata::new();
+        metadata.add(MetadataKey::from("a"), "b".into());
+        let (initial, message, _trailing) = SingleResponse::completed_with_metadata(metadata, 10)
+            .map_message(|m| m + 1)
+            .map_metadata(|mut m| {
+                m.add(MetadataKey::from("c"), "d".into());
+                m
+            })
+            .wait()
+            .unwrap();
+        assert_eq!(11, message);
+        assert_eq!(Some(&b"b"[..]), initial.get("a"));
+        assert_eq!(Some(&b"d"[..]), initial.get("c"));
+
+        let r = SingleResponse::completed(1)
+            .and_then_message(|_| -> result::Result<u32> { Err(error::Error::Other("first")) })
+            .map_err(|_| error::Error::Other("second"))
+            .wait();
+        match r {
+            Err(error::Error::Other("second")) => {}
+            _ => panic!("expecting mapped error"),
+        }
+    }
+
+    #[test]
+    fn streaming_combinators() {
+        let (_initial, items, trailing) = StreamingResponse::completed(vec![1, 2])
+            .map_items(|i| i * 10)
+            .map_trailing_metadata(|mut m| {
+                m.add(MetadataKey::from("t"), "1".into());
+                m
+            })
+            .collect()
+            .wait()
+            .unwrap();
+        assert_eq!(vec![10, 20], items);
+        assert_eq!(Some(&b"1"[..]), trailing.get("t"));
+
+        let r = StreamingResponse::<u32>::err(error::Error::Other("first"))
+            .map_err(|_| error::Error::Other("second"))
+            .collect()
+            .wait();
+        match r {
+            Err(error::Error::Other("second")) => {}
+            _ => panic!("expecting mapped error"),
+        }
+    }
+
+    #[test]
+    fn streaming_into_parts() {
+        let mut metadata = Metadata::new();
+        metadata.add(MetadataKey::from("a"), "b".into());
+
+        let (initial, stream) =
+            StreamingResponse::completed_with_metadata(metadata.clone(), vec![1, 2]).into_parts();
+        assert_eq!(Some(&b"b"[..]), initial.wait().unwrap().get("a"));
+        assert_eq!(vec![1, 2], stream.collect().wait().unwrap());
+
+        // stream part can be consumed first
+        let (initial, stream) =
+            StreamingResponse::completed_with_metadata(metadata, vec![3]).into_parts();
+        assert_eq!(vec![3], stream.collect().wait().unwrap());
+        assert_eq!(Some(&b"b"[..]), initial.wait().unwrap().get("a"));
+
+        let (initial, stream) = StreamingResponse::<u32>::err(error::Error::Status(
+            error::Status::new(GrpcStatus::NotFound, "nope"),
+        ))
+        .into_parts();
+        assert_eq!(
+            GrpcStatus::NotFound,
+            initial.wait().unwrap_err().status().code
+        );
+        assert_eq!(
+            GrpcStatus::NotFound,
+            stream.collect().wait().unwrap_err().status().code
+        );
+    }
+
+    #[test]
+    fn from_result() {
+        fn validate(req: u32) -> Result<u32, error::Status> {
+            match req {
+                0 => Err(error::Status::new(GrpcStatus::InvalidArgument, "zero")),
+                req => Ok(req),
+            }
+        }
+
+        fn handler(req: u32) -> Result<SingleResponse<u32>, error::Status> {
+            Ok(SingleResponse::completed(validate(req)? * 2))
+        }
+
+        let resp: SingleResponse<u32> = handler(2).into();
+        assert_eq!(4, resp.wait_drop_metadata().unwrap());
+        let resp: SingleResponse<u32> = handler(0).into();
+        assert_eq!(
+            GrpcStatus::InvalidArgument,
+            resp.wait_drop_metadata().unwrap_err().status().code
+        );
+    }
+}
@@ -1,11 +1,15 @@
 //! Functions used by generated code, but not exposed in `grpc`.
 
+pub use server::method::AsyncServiceMethod;
 pub use server::method::MethodHandler;
 pub use server::method::MethodHandlerBidi;
 pub use server::method::MethodHandlerClientStreaming;
+pub use server::method::MethodHandlerFn;
 pub use server::method::MethodHandlerServerStreaming;
 pub use server::method::MethodHandlerUnary;
+pub use server::method::MethodHandlerUnaryStreamBody;
 pub use server::method::ServerMethod;
+pub use server::method::ServiceMethod;
 
 pub use method::GrpcStreaming;
 pub use method::GrpcStreamingFlavor;
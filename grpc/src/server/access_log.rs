@@ -0,0 +1,154 @@
+//! Server access log.
+
+use std::io;
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use proto::grpc_status::GrpcStatus;
+use server::json_gateway::json_escape;
+
+/// Information about completed call.
+///
+/// Peer address is not available: HTTP/2 library does not expose it
+/// to request handlers.
+#[derive(Debug, Clone)]
+pub struct AccessLogRecord<'a> {
+    /// Full method name, e. g. `/helloworld.Greeter/SayHello`.
+    pub method: &'a str,
+    /// Call id sent by client or generated by server, see `trace::CallId`.
+    pub call_id: Option<&'a str>,
+    pub status: GrpcStatus,
+    pub duration: Duration,
+    /// Total size of serialized request messages.
+    pub bytes_received: u64,
+    /// Total size of serialized response messages.
+    pub bytes_sent: u64,
+}
+
+/// Receiver of access log records.
+///
+/// Registered with `ServerBuilder::set_access_logger`.
+/// Called from event loop threads, so it should be fast.
+pub trait AccessLogger: Send + Sync + 'static {
+    fn log(&self, record: &AccessLogRecord);
+}
+
+/// Access logger which writes a line per call, like
+/// `/helloworld.Greeter/SayHello Ok 1.204ms in=7 out=13`.
+pub struct TextAccessLogger<W: Write + Send + 'static> {
+    writer: Mutex<W>,
+}
+
+impl<W: Write + Send + 'static> TextAccessLogger<W> {
+    pub fn new(writer: W) -> TextAccessLogger<W> {
+        TextAccessLogger {
+            writer: Mutex::new(writer),
+        }
+    }
+}
+
+impl TextAccessLogger<io::Stderr> {
+    pub fn stderr() -> TextAccessLogger<io::Stderr> {
+        TextAccessLogger::new(io::stderr())
+    }
+}
+
+impl<W: Write + Send + 'static> AccessLogger for TextAccessLogger<W> {
+    fn log(&self, record: &AccessLogRecord) {
+        let mut writer = self.writer.lock().unwrap();
+        let r = writeln!(
+            writer,
+            "{} {:?} {:.3}ms in={} out={}{}{}",
+            record.method,
+            record.status,
+            duration_ms(record.duration),
+            record.bytes_received,
+            record.bytes_sent,
+            if record.call_id.is_some() {
+                " call_id="
+            } else {
+                ""
+            },
+            record.call_id.unwrap_or("")
+        );
+        if let Err(e) = r {
+            warn!("failed to write access log: {}", e);
+        }
+    }
+}
+
+/// Access logger which writes a JSON object per line, like
+/// `{"method":"/helloworld.Greeter/SayHello","status":0,"duration_ms":1.204,"bytes_received":7,"bytes_sent":13}`.
+pub struct JsonAccessLogger<W: Write + Send + 'static> {
+    writer: Mutex<W>,
+}
+
+impl<W: Write + Send + 'static> JsonAccessLogger<W> {
+    pub fn new(writer: W) -> JsonAccessLogger<W> {
+        JsonAccessLogger {
+            writer: Mutex::new(writer),
+        }
+    }
+}
+
+impl<W: Write + Send + 'static> AccessLogger for JsonAccessLogger<W> {
+    fn log(&self, record: &AccessLogRecord) {
+        let line = json_line(record);
+        let mut writer = self.writer.lock().unwrap();
+        if let Err(e) = writer.write_all(line.as_bytes()) {
+            warn!("failed to write access log: {}", e);
+        }
+    }
+}
+
+fn duration_ms(duration: Duration) -> f64 {
+    duration.as_secs() as f64 * 1000.0 + duration.subsec_nanos() as f64 / 1_000_000.0
+}
+
+fn json_line(record: &AccessLogRecord) -> String {
+    let mut r = String::from("{\"method\":\"");
+    r.push_str(&json_escape(record.method));
+    if let Some(call_id) = record.call_id {
+        r.push_str("\",\"call_id\":\"");
+        r.push_str(&json_escape(call_id));
+    }
+    r.push_str(&format!(
+        "\",\"status\":{},\"duration_ms\":{:.3},\"bytes_received\":{},\"bytes_sent\":{}}}\n",
+        record.status.code(),
+        duration_ms(record.duration),
+        record.bytes_received,
+        record.bytes_sent
+    ));
+    r
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn json() {
+        let record = AccessLogRecord {
+            method: "/a\"b",
+            call_id: None,
+            status: GrpcStatus::NotFound,
+            duration: Duration::from_micros(1500),
+            bytes_received: 3,
+            bytes_sent: 4,
+        };
+        assert_eq!(
+            "{\"method\":\"/a\\\"b\",\"status\":5,\"duration_ms\":1.500,\"bytes_received\":3,\"bytes_sent\":4}\n",
+            json_line(&record)
+        );
+
+        let record = AccessLogRecord {
+            call_id: Some("c1"),
+            ..record
+        };
+        assert_eq!(
+            "{\"method\":\"/a\\\"b\",\"call_id\":\"c1\",\"status\":5,\"duration_ms\":1.500,\"bytes_received\":3,\"bytes_sent\":4}\n",
+            json_line(&record)
+        );
+    }
+}
@@ -0,0 +1,30 @@
+//! Authorization of incoming calls.
+
+use error::Error;
+use proto::grpc_status::GrpcStatus;
+use result;
+use Metadata;
+
+/// Hook called before a call is dispatched to the method handler.
+///
+/// Registered with `ServerBuilder::set_auth_handler`. Called from event loop
+/// after request headers are received, so request body is not read
+/// and not parsed for rejected calls.
+///
+/// Peer address and TLS client certificate are not available:
+/// HTTP/2 library does not expose them to request handlers.
+pub trait ServerAuthHandler: Send + Sync + 'static {
+    /// Return error to reject the call.
+    ///
+    /// Status and message of `Error::Status` are sent to the client,
+    /// e. g. `PERMISSION_DENIED`; other errors are reported as `UNAUTHENTICATED`.
+    fn authorize(&self, method: &str, metadata: &Metadata) -> result::Result<()>;
+}
+
+/// Status and message sent to client when call is rejected.
+pub(crate) fn rejection(error: Error) -> (GrpcStatus, String) {
+    match error {
+        Error::Status(status) => (status.code, status.message),
+        e => (GrpcStatus::Unauthenticated, e.to_string()),
+    }
+}
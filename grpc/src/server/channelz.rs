@@ -0,0 +1,396 @@
+//! Per-method call counters and calls in progress, and a debug service exposing them.
+
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::RwLock;
+use std::time::Duration;
+use std::time::Instant;
+
+#[cfg(feature = "with-serde")]
+use bytes::Bytes;
+#[cfg(feature = "with-serde")]
+use serde_json;
+
+#[cfg(feature = "with-serde")]
+use marshall::MarshallerBytes;
+#[cfg(feature = "with-serde")]
+use method::GrpcStreaming;
+#[cfg(feature = "with-serde")]
+use method::MethodDescriptor;
+#[cfg(feature = "with-serde")]
+use or_static::arc::ArcOrStatic;
+#[cfg(feature = "with-serde")]
+use or_static::string::StringOrStatic;
+use proto::grpc_status::GrpcStatus;
+#[cfg(feature = "with-serde")]
+use result;
+#[cfg(feature = "with-serde")]
+use server::ctx::ServerHandlerContext;
+use server::histograms::MAX_METHODS;
+use server::histograms::OTHER_METHODS;
+#[cfg(feature = "with-serde")]
+use server::method::MethodHandlerUnary;
+#[cfg(feature = "with-serde")]
+use server::method::ServerMethod;
+#[cfg(feature = "with-serde")]
+use server::req_single::ServerRequestSingle;
+#[cfg(feature = "with-serde")]
+use server::resp_unary_sink::ServerResponseUnarySink;
+#[cfg(feature = "with-serde")]
+use server::ServerServiceDefinition;
+use trace::CallId;
+
+/// Service of `Channelz::service`
+pub const CHANNELZ_SERVICE: &str = "/grpc.rust.Channelz";
+
+#[cfg(feature = "with-serde")]
+static GET_METHOD_COUNTERS: MethodDescriptor<Bytes, Bytes> = MethodDescriptor {
+    name: StringOrStatic::Static("/grpc.rust.Channelz/GetMethodCounters"),
+    streaming: GrpcStreaming::Unary,
+    req_marshaller: ArcOrStatic::Static(&MarshallerBytes),
+    resp_marshaller: ArcOrStatic::Static(&MarshallerBytes),
+};
+
+#[cfg(feature = "with-serde")]
+static GET_STREAMS: MethodDescriptor<Bytes, Bytes> = MethodDescriptor {
+    name: StringOrStatic::Static("/grpc.rust.Channelz/GetStreams"),
+    streaming: GrpcStreaming::Unary,
+    req_marshaller: ArcOrStatic::Static(&MarshallerBytes),
+    resp_marshaller: ArcOrStatic::Static(&MarshallerBytes),
+};
+
+/// Counters of calls of a method at some moment.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MethodCallCounters {
+    pub calls_started: u64,
+    pub calls_succeeded: u64,
+    /// Calls completed with status other than `OK`, including cancelled calls.
+    pub calls_failed: u64,
+    pub messages_received: u64,
+    pub messages_sent: u64,
+}
+
+impl MethodCallCounters {
+    /// Calls started and not yet completed.
+    ///
+    /// A number growing over time points to stuck streams.
+    pub fn calls_in_progress(&self) -> u64 {
+        self.calls_started
+            .saturating_sub(self.calls_succeeded + self.calls_failed)
+    }
+}
+
+/// Call in progress at some moment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamSnapshot {
+    /// Sequence number of the call on this server
+    pub id: u64,
+    pub method: String,
+    pub call_id: Option<String>,
+    /// Time since request headers were received
+    pub age: Duration,
+    pub messages_received: u64,
+    pub messages_sent: u64,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    calls_started: AtomicUsize,
+    calls_succeeded: AtomicUsize,
+    calls_failed: AtomicUsize,
+    messages_received: AtomicUsize,
+    messages_sent: AtomicUsize,
+}
+
+impl Counters {
+    fn snapshot(&self) -> MethodCallCounters {
+        MethodCallCounters {
+            calls_started: self.calls_started.load(Ordering::Relaxed) as u64,
+            calls_succeeded: self.calls_succeeded.load(Ordering::Relaxed) as u64,
+            calls_failed: self.calls_failed.load(Ordering::Relaxed) as u64,
+            messages_received: self.messages_received.load(Ordering::Relaxed) as u64,
+            messages_sent: self.messages_sent.load(Ordering::Relaxed) as u64,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Stream {
+    method: String,
+    call_id: Option<CallId>,
+    start: Instant,
+    messages_received: AtomicUsize,
+    messages_sent: AtomicUsize,
+}
+
+/// Channelz-style introspection of a server: per-method call counters,
+/// calls in progress, and a debug service serving them.
+///
+/// ```ignore
+/// let channelz = Arc::new(Channelz::new());
+/// server.set_channelz(channelz.clone());
+/// server.add_service(Channelz::service(&channelz));
+/// ```
+///
+/// Calls are observed by the server directly, so a `ServerStatsHandler`
+/// can be set independently.
+///
+/// `/grpc.rust.Channelz/GetMethodCounters` and `/grpc.rust.Channelz/GetStreams`
+/// take any request message and reply with `to_json` and `streams_to_json`.
+/// Listening sockets are returned by `Server::local_addrs`.
+/// Connections are not reported, see `docs/DECLINED.md`.
+///
+/// Calls of methods beyond the first thousand are counted as `other`.
+#[derive(Debug, Default)]
+pub struct Channelz {
+    methods: RwLock<HashMap<String, Arc<Counters>>>,
+    streams: Mutex<HashMap<u64, Arc<Stream>>>,
+    next_stream_id: AtomicUsize,
+}
+
+impl Channelz {
+    pub fn new() -> Channelz {
+        Default::default()
+    }
+
+    fn method(&self, method: &str) -> Arc<Counters> {
+        if let Some(counters) = self.methods.read().unwrap().get(method) {
+            return counters.clone();
+        }
+        let mut methods = self.methods.write().unwrap();
+        let method = match methods.len() < MAX_METHODS || methods.contains_key(method) {
+            true => method,
+            false => OTHER_METHODS,
+        };
+        methods
+            .entry(method.to_owned())
+            .or_insert_with(|| Arc::new(Counters::default()))
+            .clone()
+    }
+
+    /// Register a call; it is listed by `streams` until returned value is dropped.
+    pub(crate) fn call_started(
+        channelz: &Arc<Channelz>,
+        method: &str,
+        call_id: Option<CallId>,
+    ) -> ChannelzCall {
+        let counters = channelz.method(method);
+        counters.calls_started.fetch_add(1, Ordering::Relaxed);
+        let id = channelz.next_stream_id.fetch_add(1, Ordering::Relaxed) as u64;
+        let stream = Arc::new(Stream {
+            method: method.to_owned(),
+            call_id,
+            start: Instant::now(),
+            messages_received: AtomicUsize::new(0),
+            messages_sent: AtomicUsize::new(0),
+        });
+        channelz.streams.lock().unwrap().insert(id, stream.clone());
+        ChannelzCall {
+            channelz: channelz.clone(),
+            id,
+            counters,
+            stream,
+        }
+    }
+
+    /// Counters of methods called so far, by full method name.
+    pub fn snapshot(&self) -> BTreeMap<String, MethodCallCounters> {
+        self.methods
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(method, counters)| (method.clone(), counters.snapshot()))
+            .collect()
+    }
+
+    /// Calls in progress, oldest first.
+    pub fn streams(&self) -> Vec<StreamSnapshot> {
+        let mut streams: Vec<StreamSnapshot> = self
+            .streams
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(&id, stream)| StreamSnapshot {
+                id,
+                method: stream.method.clone(),
+                call_id: stream.call_id.as_ref().map(|c| c.as_str().to_owned()),
+                age: stream.start.elapsed(),
+                messages_received: stream.messages_received.load(Ordering::Relaxed) as u64,
+                messages_sent: stream.messages_sent.load(Ordering::Relaxed) as u64,
+            })
+            .collect();
+        streams.sort_by_key(|s| s.id);
+        streams
+    }
+
+    /// Counters as JSON object keyed by method name.
+    #[cfg(feature = "with-serde")]
+    pub fn to_json(&self) -> String {
+        let methods: serde_json::Map<String, serde_json::Value> = self
+            .snapshot()
+            .into_iter()
+            .map(|(method, c)| {
+                let counters = serde_json::json!({
+                    "calls_started": c.calls_started,
+                    "calls_succeeded": c.calls_succeeded,
+                    "calls_failed": c.calls_failed,
+                    "calls_in_progress": c.calls_in_progress(),
+                    "messages_received": c.messages_received,
+                    "messages_sent": c.messages_sent,
+                });
+                (method, counters)
+            })
+            .collect();
+        serde_json::Value::Object(methods).to_string()
+    }
+
+    /// Calls in progress as JSON array, oldest first.
+    #[cfg(feature = "with-serde")]
+    pub fn streams_to_json(&self) -> String {
+        let streams: Vec<serde_json::Value> = self
+            .streams()
+            .into_iter()
+            .map(|s| {
+                serde_json::json!({
+                    "id": s.id,
+                    "method": s.method,
+                    "call_id": s.call_id,
+                    "age_ms": s.age.as_millis() as u64,
+                    "messages_received": s.messages_received,
+                    "messages_sent": s.messages_sent,
+                })
+            })
+            .collect();
+        serde_json::Value::Array(streams).to_string()
+    }
+
+    /// Debug service replying with counters and calls in progress of this `Channelz`.
+    #[cfg(feature = "with-serde")]
+    pub fn service(channelz: &Arc<Channelz>) -> ServerServiceDefinition {
+        let counters = channelz.clone();
+        let streams = channelz.clone();
+        ServerServiceDefinition::new(
+            CHANNELZ_SERVICE,
+            vec![
+                ServerMethod::new(
+                    ArcOrStatic::Static(&GET_METHOD_COUNTERS),
+                    MethodHandlerUnary::new(
+                        move |_: ServerHandlerContext,
+                              _: ServerRequestSingle<Bytes>,
+                              resp: ServerResponseUnarySink<Bytes>|
+                              -> result::Result<()> {
+                            resp.finish(Bytes::from(counters.to_json()))
+                        },
+                    ),
+                ),
+                ServerMethod::new(
+                    ArcOrStatic::Static(&GET_STREAMS),
+                    MethodHandlerUnary::new(
+                        move |_: ServerHandlerContext,
+                              _: ServerRequestSingle<Bytes>,
+                              resp: ServerResponseUnarySink<Bytes>|
+                              -> result::Result<()> {
+                            resp.finish(Bytes::from(streams.streams_to_json()))
+                        },
+                    ),
+                ),
+            ],
+        )
+    }
+}
+
+/// Call registered in `Channelz`, removed from calls in progress when dropped.
+#[derive(Debug)]
+pub(crate) struct ChannelzCall {
+    channelz: Arc<Channelz>,
+    id: u64,
+    counters: Arc<Counters>,
+    stream: Arc<Stream>,
+}
+
+impl ChannelzCall {
+    pub fn message_received(&self) {
+        self.counters
+            .messages_received
+            .fetch_add(1, Ordering::Relaxed);
+        self.stream
+            .messages_received
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn message_sent(&self) {
+        self.counters.messages_sent.fetch_add(1, Ordering::Relaxed);
+        self.stream.messages_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn finished(&self, status: GrpcStatus) {
+        match status {
+            GrpcStatus::Ok => self
+                .counters
+                .calls_succeeded
+                .fetch_add(1, Ordering::Relaxed),
+            _ => self.counters.calls_failed.fetch_add(1, Ordering::Relaxed),
+        };
+        self.channelz.streams.lock().unwrap().remove(&self.id);
+    }
+}
+
+impl Drop for ChannelzCall {
+    fn drop(&mut self) {
+        // call dropped without response, e. g. rejected before dispatch
+        self.channelz.streams.lock().unwrap().remove(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn counters() {
+        let channelz = Arc::new(Channelz::new());
+        let first = Channelz::call_started(&channelz, "/a/b", None);
+        first.message_received();
+        let second = Channelz::call_started(&channelz, "/a/b", CallId::parse("x1"));
+        second.message_sent();
+        first.finished(GrpcStatus::Ok);
+
+        let b = &channelz.snapshot()["/a/b"];
+        assert_eq!(2, b.calls_started);
+        assert_eq!(1, b.calls_succeeded);
+        assert_eq!(1, b.calls_in_progress());
+        assert_eq!(1, b.messages_received);
+
+        let streams = channelz.streams();
+        assert_eq!(1, streams.len());
+        assert_eq!(Some("x1"), streams[0].call_id.as_ref().map(String::as_str));
+        assert_eq!(1, streams[0].messages_sent);
+
+        drop(second);
+        assert!(channelz.streams().is_empty());
+
+        for i in 0..MAX_METHODS {
+            Channelz::call_started(&channelz, &format!("/a/{}", i), None);
+        }
+        assert_eq!(MAX_METHODS + 1, channelz.snapshot().len());
+        assert!(channelz.snapshot().contains_key(OTHER_METHODS));
+    }
+
+    #[cfg(feature = "with-serde")]
+    #[test]
+    fn json() {
+        let channelz = Arc::new(Channelz::new());
+        let call = Channelz::call_started(&channelz, "/a/\"b\"", None);
+        call.message_received();
+
+        let counters: serde_json::Value = serde_json::from_str(&channelz.to_json()).unwrap();
+        assert_eq!(1, counters["/a/\"b\""]["calls_in_progress"]);
+        let streams: serde_json::Value = serde_json::from_str(&channelz.streams_to_json()).unwrap();
+        assert_eq!("/a/\"b\"", streams[0]["method"]);
+        assert_eq!(1, streams[0]["messages_received"]);
+    }
+}
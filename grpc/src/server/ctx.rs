@@ -1,17 +1,52 @@
+use std::mem;
+use std::str;
+use std::time::Duration;
+use std::time::Instant;
+
 use error;
+use error::Status;
 use futures::future;
 use futures::stream;
 use futures::Async;
 use futures::Poll;
+use futures_cpupool::CpuPool;
+use futures_grpc::GrpcStream;
+use server::panic::PanicGuard;
+use server::resp_sink_untyped::DroppedSink;
+use server::watchdog::Watchdog;
+use stream_item::ItemOrMetadata;
 use tokio_core::reactor::Remote;
+use trace::CallId;
+use trace::TraceContext;
 use Metadata;
+use RequestOptions;
 use ServerResponseSink;
 use ServerResponseUnarySink;
+use SingleResponse;
+use StreamingResponse;
+use WriteFlags;
 
+/// Context of a call passed to method handlers.
+///
+/// Peer address, TLS state and HTTP/2 stream id of the call are not available:
+/// HTTP/2 library does not expose them to request handlers.
 pub struct ServerHandlerContext {
     pub ctx: httpbis::ServerHandlerContext,
     // TODO: move to request
     pub metadata: Metadata,
+    /// Full name of the method being called, e. g. `/helloworld.Greeter/SayHello`
+    pub(crate) method: String,
+    /// Pool assigned to the method with `ServerMethod::with_cpu_pool`,
+    /// or pool of `ServerConf::worker_threads`
+    pub(crate) cpu_pool: Option<CpuPool>,
+    /// Computed from `grpc-timeout` request header
+    pub(crate) deadline: Option<Instant>,
+    /// Applies `ServerConf::panic_policy` to handler invocations
+    pub(crate) panic_guard: PanicGuard,
+    /// Applies `ServerConf::handler_watchdog_timeout` to responses of the handler
+    pub(crate) watchdog: Watchdog,
+    /// Keeps response sink dropped by failing handler function to send its error
+    pub(crate) dropped_sink: DroppedSink,
 }
 
 impl ServerHandlerContext {
@@ -19,21 +54,90 @@ impl ServerHandlerContext {
         self.ctx.loop_remote()
     }
 
-    pub fn spawn_poll_fn<F>(&self, mut f: F)
+    /// Full name of the method being called, e. g. `/helloworld.Greeter/SayHello`.
+    ///
+    /// Useful in fallback handlers which serve arbitrary methods.
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+
+    /// Pool assigned to the method being handled, if any.
+    ///
+    /// Unary and server streaming handlers are invoked on this pool automatically,
+    /// client streaming and bidi handlers may use it to offload work.
+    pub fn cpu_pool(&self) -> Option<&CpuPool> {
+        self.cpu_pool.as_ref()
+    }
+
+    /// Time by which client expects the response, if client specified a timeout.
+    ///
+    /// Server does not cancel the handler when deadline is exceeded,
+    /// but handler may use it to stop the work which is no longer needed.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// `user-agent` sent by client, e. g. `grpc-rust/<version>` or `grpc-go/1.26.0`,
+    /// useful for logging and metrics by client implementation.
+    pub fn user_agent(&self) -> Option<&str> {
+        let user_agent = self.metadata.get("user-agent")?;
+        str::from_utf8(user_agent).ok()
+    }
+
+    /// Trace context propagated by client in request metadata.
+    pub fn trace_context(&self) -> Option<TraceContext> {
+        TraceContext::from_metadata(&self.metadata)
+    }
+
+    /// Call id sent by client, or generated if `ServerConf::generate_call_id` is set.
+    pub fn call_id(&self) -> Option<CallId> {
+        CallId::from_metadata(&self.metadata)
+    }
+
+    /// Options for outgoing calls made while handling this request,
+    /// which continue the request trace and keep the call id, if any.
+    ///
+    /// Timeout is set to the time remaining until `deadline`, so deadline
+    /// shrinks across hops, and call fails with `DEADLINE_EXCEEDED`
+    /// if deadline is already exceeded. When outgoing response is forwarded
+    /// with `pump_response` or `pump_single_response`, outgoing call is also
+    /// canceled when client cancels this request.
+    pub fn outgoing_request_options(&self) -> RequestOptions {
+        let mut options = RequestOptions::new();
+        if let Some(context) = self.trace_context() {
+            context.child().inject(&mut options.metadata);
+        }
+        if let Some(call_id) = self.call_id() {
+            call_id.inject(&mut options.metadata);
+        }
+        if let Some(deadline) = self.deadline {
+            let now = Instant::now();
+            options.timeout = Some(if deadline > now {
+                deadline - now
+            } else {
+                Duration::from_secs(0)
+            });
+        }
+        options
+    }
+
+    pub fn spawn_poll_fn<F>(&self, f: F)
     where
         F: FnMut() -> Poll<(), error::Error> + Send + 'static,
     {
-        self.loop_remote().spawn(move |_handle| {
-            future::poll_fn(move || match f() {
-                Ok(r) => Ok(r),
-                Err(e) => {
-                    warn!("poll_fn returned error: {:?}", e);
-                    Ok(Async::Ready(()))
-                }
-            })
-        })
+        spawn_poll_fn(
+            &self.loop_remote(),
+            self.panic_guard.clone(),
+            self.method.clone(),
+            f,
+        )
     }
 
+    /// Send all stream messages followed by empty trailers.
+    ///
+    /// Stream is polled only when peer flow control window allows sending,
+    /// so fast streams are not buffered in memory. Stream is dropped
+    /// without being polled further when client cancels the request.
     pub fn pump<Resp, S>(&self, mut stream: S, mut dest: ServerResponseSink<Resp>)
     where
         Resp: Send + 'static,
@@ -56,6 +160,9 @@ impl ServerHandlerContext {
         })
     }
 
+    /// Send the message the future resolves to.
+    ///
+    /// Future is dropped without being polled further when client cancels the request.
     pub fn pump_future<Resp, F>(&self, mut future: F, dest: ServerResponseUnarySink<Resp>)
     where
         Resp: Send + 'static,
@@ -63,6 +170,8 @@ impl ServerHandlerContext {
     {
         let mut dest = Some(dest);
         self.spawn_poll_fn(move || loop {
+            // future is dropped when client cancels the request
+            dest.as_mut().unwrap().sink.poll()?;
             match future.poll()? {
                 Async::NotReady => return Ok(Async::NotReady),
                 Async::Ready(m) => {
@@ -72,4 +181,124 @@ impl ServerHandlerContext {
             }
         })
     }
+
+    /// Send a response with metadata: initial metadata as response headers
+    /// as soon as it is available, then messages, then trailing metadata.
+    ///
+    /// Useful when initial metadata is computed asynchronously
+    /// (e. g. after a database lookup), or to forward a response of an outgoing call.
+    /// Failed response is sent to client as error status.
+    ///
+    /// Response is canceled if it makes no progress within watchdog timeout,
+    /// see `ServerConf::handler_watchdog_timeout`.
+    pub fn pump_response<Resp>(
+        &self,
+        response: StreamingResponse<Resp>,
+        dest: ServerResponseSink<Resp>,
+    ) where
+        Resp: Send + 'static,
+    {
+        let response = self.watchdog.watch(&self.method, response);
+        pump_response(
+            &self.loop_remote(),
+            self.panic_guard.clone(),
+            self.method.clone(),
+            response,
+            dest,
+        )
+    }
+
+    /// Send a single message response with metadata, see `pump_response`.
+    pub fn pump_single_response<Resp>(
+        &self,
+        response: SingleResponse<Resp>,
+        dest: ServerResponseUnarySink<Resp>,
+    ) where
+        Resp: Send + 'static,
+    {
+        self.pump_response(response.into_stream(), dest.sink)
+    }
+}
+
+/// Spawn poll function on the event loop, panics of the function
+/// are handled like panics of the handler
+fn spawn_poll_fn<F>(remote: &Remote, panic_guard: PanicGuard, method: String, mut f: F)
+where
+    F: FnMut() -> Poll<(), error::Error> + Send + 'static,
+{
+    remote.spawn(move |_handle| {
+        future::poll_fn(move || match panic_guard.poll(&method, || f()) {
+            Ok(r) => Ok(r),
+            Err(e) => {
+                warn!("poll_fn returned error: {:?}", e);
+                Ok(Async::Ready(()))
+            }
+        })
+    })
+}
+
+/// Implementation of `ServerHandlerContext::pump_response`,
+/// also used by handlers of service methods returning responses.
+pub(crate) fn pump_response<Resp>(
+    remote: &Remote,
+    panic_guard: PanicGuard,
+    method: String,
+    response: StreamingResponse<Resp>,
+    mut dest: ServerResponseSink<Resp>,
+) where
+    Resp: Send + 'static,
+{
+    let mut headers = response.0;
+    let mut stream: Option<GrpcStream<ItemOrMetadata<Resp>>> = None;
+    let mut trailing = Metadata::new();
+    spawn_poll_fn(remote, panic_guard, method, move || loop {
+        if stream.is_none() {
+            // response is dropped when client cancels the request,
+            // which cancels outgoing calls the response is waiting for
+            dest.poll()?;
+            match headers.poll() {
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Ok(Async::Ready((metadata, s))) => {
+                    // headers are sent before the stream is polled,
+                    // so client gets metadata even if the first message is slow
+                    dest.send_metadata(metadata)?;
+                    stream = Some(s.0);
+                }
+                Err(e) => return send_error(&mut dest, e),
+            }
+        }
+        // messages ready at once are packed together,
+        // buffer is flushed before waiting for more
+        if let Async::NotReady = dest.poll()? {
+            dest.flush()?;
+            return Ok(Async::NotReady);
+        }
+        match stream.as_mut().unwrap().poll() {
+            Ok(Async::NotReady) => {
+                dest.flush()?;
+                return Ok(Async::NotReady);
+            }
+            Ok(Async::Ready(Some(ItemOrMetadata::Item(m)))) => {
+                dest.send_data_with_flags(m, WriteFlags { buffer_hint: true })?;
+            }
+            Ok(Async::Ready(Some(ItemOrMetadata::TrailingMetadata(m)))) => {
+                trailing = m;
+            }
+            Ok(Async::Ready(None)) => {
+                dest.send_trailers(mem::replace(&mut trailing, Metadata::new()))?;
+                return Ok(Async::Ready(()));
+            }
+            Err(e) => return send_error(&mut dest, e),
+        }
+    })
+}
+
+/// Complete the response with status of failed handler response.
+fn send_error<Resp: Send>(
+    dest: &mut ServerResponseSink<Resp>,
+    error: error::Error,
+) -> Poll<(), error::Error> {
+    let status = Status::from(error);
+    dest.send_grpc_error(status.code, status.message)?;
+    Ok(Async::Ready(()))
 }
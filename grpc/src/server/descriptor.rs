@@ -0,0 +1,43 @@
+//! Services and methods of a server available at runtime.
+
+use method::GrpcStreaming;
+use server::method::ServerMethod;
+
+/// Method of a service registered in a server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServiceMethodDescriptor {
+    /// Full name of the method, e. g. `/helloworld.Greeter/SayHello`
+    pub name: String,
+    pub streaming: GrpcStreaming,
+}
+
+/// Service registered in a server.
+///
+/// Useful to build documentation endpoints or custom routing
+/// on top of registered services.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServiceDescriptor {
+    /// Path prefix of the service, e. g. `/helloworld.Greeter`
+    pub name: String,
+    /// Methods sorted by name
+    pub methods: Vec<ServiceMethodDescriptor>,
+}
+
+impl ServiceDescriptor {
+    pub(crate) fn new<'a, I>(name: &str, methods: I) -> ServiceDescriptor
+    where
+        I: Iterator<Item = &'a ServerMethod>,
+    {
+        let mut methods: Vec<ServiceMethodDescriptor> = methods
+            .map(|m| ServiceMethodDescriptor {
+                name: m.name().to_owned(),
+                streaming: m.streaming(),
+            })
+            .collect();
+        methods.sort_by(|a, b| a.name.cmp(&b.name));
+        ServiceDescriptor {
+            name: name.to_owned(),
+            methods,
+        }
+    }
+}
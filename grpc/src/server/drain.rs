@@ -0,0 +1,44 @@
+//! Draining mode of server: new calls are rejected, calls in progress complete.
+
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use bytes::Bytes;
+
+use proto::metadata::Metadata;
+use proto::metadata::MetadataKey;
+
+/// Metadata of `UNAVAILABLE` response to a call rejected by draining server,
+/// so clients and proxies know the call may be retried on another backend.
+pub const DRAINING_METADATA: &str = "x-server-draining";
+
+/// Draining flag shared by server and its request handlers
+#[derive(Clone, Default, Debug)]
+pub(crate) struct Draining {
+    draining: Arc<AtomicBool>,
+}
+
+impl Draining {
+    pub fn new() -> Draining {
+        Default::default()
+    }
+
+    pub fn set(&self, draining: bool) {
+        self.draining.store(draining, Ordering::SeqCst);
+    }
+
+    pub fn get(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+}
+
+/// Metadata of response to a call started while server is draining
+pub(crate) fn draining_metadata() -> Metadata {
+    let mut metadata = Metadata::new();
+    metadata.add(
+        MetadataKey::from(DRAINING_METADATA),
+        Bytes::from_static(b"true"),
+    );
+    metadata
+}
@@ -0,0 +1,170 @@
+//! Subscription to server call lifecycle events.
+
+use std::fmt;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use futures::stream::Stream;
+use futures::sync::mpsc;
+use futures::Poll;
+
+use proto::grpc_status::GrpcStatus;
+use trace::CallId;
+
+/// Events buffered per subscriber, further events are dropped
+/// until the subscriber catches up
+pub(crate) const EVENTS_BUFFER: usize = 1024;
+
+/// Call lifecycle event, see `Server::events`.
+///
+/// Connection events and peer addresses are not reported:
+/// connections are accepted and served by `httpbis`,
+/// which does not expose them to request handlers.
+#[derive(Debug, Clone)]
+pub enum ServerEvent {
+    /// Request headers are received and the call is dispatched to a method handler.
+    StreamStarted {
+        /// Full method name, e. g. `/helloworld.Greeter/SayHello`.
+        method: String,
+        call_id: Option<CallId>,
+    },
+    /// Response is completed with given status.
+    StreamFinished {
+        method: String,
+        call_id: Option<CallId>,
+        status: GrpcStatus,
+        duration: Duration,
+    },
+}
+
+/// Stream of events of a server, obtained with `Server::events`.
+///
+/// Up to 1024 events are buffered; events published while the buffer
+/// is full are dropped and counted in `dropped`, so a slow subscriber
+/// does not slow down the server. Drop the stream to unsubscribe.
+/// Stream ends when server is dropped.
+pub struct ServerEvents {
+    rx: mpsc::Receiver<ServerEvent>,
+    dropped: Arc<AtomicUsize>,
+}
+
+impl ServerEvents {
+    /// Number of events dropped because this subscriber did not keep up.
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl Stream for ServerEvents {
+    type Item = ServerEvent;
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Option<ServerEvent>, ()> {
+        self.rx.poll()
+    }
+}
+
+struct Subscriber {
+    tx: mpsc::Sender<ServerEvent>,
+    /// Shared with `ServerEvents::dropped`
+    dropped: Arc<AtomicUsize>,
+}
+
+/// Subscribers of `Server::events`
+#[derive(Clone, Default)]
+pub(crate) struct ServerEventSubscribers {
+    senders: Arc<Mutex<Vec<Subscriber>>>,
+}
+
+impl fmt::Debug for ServerEventSubscribers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ServerEventSubscribers")
+            .field("count", &self.senders.lock().unwrap().len())
+            .finish()
+    }
+}
+
+impl ServerEventSubscribers {
+    pub fn subscribe(&self) -> ServerEvents {
+        let (tx, rx) = mpsc::channel(EVENTS_BUFFER);
+        let dropped = Arc::new(AtomicUsize::new(0));
+        self.senders.lock().unwrap().push(Subscriber {
+            tx,
+            dropped: dropped.clone(),
+        });
+        ServerEvents { rx, dropped }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.senders.lock().unwrap().is_empty()
+    }
+
+    /// Send event to all subscribers, forget subscribers which dropped the stream
+    pub fn publish(&self, event: ServerEvent) {
+        let mut senders = self.senders.lock().unwrap();
+        let subscribers = senders.drain(..).collect::<Vec<_>>();
+        for mut subscriber in subscribers {
+            match subscriber.tx.try_send(event.clone()) {
+                Ok(()) => {}
+                Err(ref e) if e.is_full() => {
+                    subscriber.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+                Err(..) => continue,
+            }
+            senders.push(subscriber);
+        }
+    }
+
+    /// End streams of all subscribers
+    pub fn close(&self) {
+        self.senders.lock().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dropped_subscriber_is_removed() {
+        let subscribers = ServerEventSubscribers::default();
+        let mut events = subscribers.subscribe().wait();
+        drop(subscribers.subscribe());
+
+        subscribers.publish(ServerEvent::StreamStarted {
+            method: "/a/b".to_owned(),
+            call_id: None,
+        });
+        assert_eq!(1, subscribers.senders.lock().unwrap().len());
+        match events.next() {
+            Some(Ok(ServerEvent::StreamStarted { ref method, .. })) => assert_eq!("/a/b", method),
+            r => panic!("unexpected: {:?}", r),
+        }
+
+        subscribers.close();
+        assert!(subscribers.is_empty());
+        assert!(events.next().is_none());
+    }
+
+    #[test]
+    fn slow_subscriber_drops_events() {
+        let subscribers = ServerEventSubscribers::default();
+        let events = subscribers.subscribe();
+
+        for _ in 0..EVENTS_BUFFER + 10 {
+            subscribers.publish(ServerEvent::StreamStarted {
+                method: "/a/b".to_owned(),
+                call_id: None,
+            });
+        }
+        // channel capacity is buffer plus one slot per sender
+        assert_eq!(9, events.dropped());
+        assert_eq!(
+            EVENTS_BUFFER + 1,
+            events.wait().take(EVENTS_BUFFER + 1).count()
+        );
+    }
+}
@@ -0,0 +1,248 @@
+//! Per-method latency and message size histograms collected by server.
+
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::RwLock;
+use std::time::Duration;
+
+/// Calls of methods beyond this number are counted under `OTHER_METHODS`,
+/// because fallback method may be called with arbitrary names
+pub(crate) const MAX_METHODS: usize = 1000;
+
+/// Name under which calls of methods beyond the limit are counted
+pub(crate) const OTHER_METHODS: &str = "other";
+
+/// Latency bucket bounds in microseconds, 1ms to 10s
+static LATENCY_MICROS_BOUNDS: &[u64] = &[
+    1_000, 2_000, 5_000, 10_000, 20_000, 50_000, 100_000, 200_000, 500_000, 1_000_000, 2_000_000,
+    5_000_000, 10_000_000,
+];
+
+/// Message size bucket bounds in bytes, 64B to 4MiB
+static MESSAGE_BYTES_BOUNDS: &[u64] = &[
+    64,
+    256,
+    1 << 10,
+    4 << 10,
+    16 << 10,
+    64 << 10,
+    256 << 10,
+    1 << 20,
+    4 << 20,
+];
+
+/// Histogram with fixed buckets, updated without locks
+#[derive(Debug)]
+struct Histogram {
+    bounds: &'static [u64],
+    /// One more than bounds, the last one counts values above all bounds
+    counts: Vec<AtomicUsize>,
+    sum: AtomicUsize,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [u64]) -> Histogram {
+        Histogram {
+            bounds,
+            counts: (0..bounds.len() + 1).map(|_| AtomicUsize::new(0)).collect(),
+            sum: AtomicUsize::new(0),
+        }
+    }
+
+    fn record(&self, value: u64) {
+        let bucket = match self.bounds.iter().position(|&bound| value <= bound) {
+            Some(bucket) => bucket,
+            None => self.bounds.len(),
+        };
+        self.counts[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(value as usize, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> HistogramSnapshot {
+        let counts: Vec<u64> = self
+            .counts
+            .iter()
+            .map(|c| c.load(Ordering::Relaxed) as u64)
+            .collect();
+        HistogramSnapshot {
+            bounds: self.bounds,
+            count: counts.iter().sum(),
+            counts,
+            sum: self.sum.load(Ordering::Relaxed) as u64,
+        }
+    }
+}
+
+/// Values of a histogram at some moment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistogramSnapshot {
+    /// Inclusive upper bounds of buckets.
+    pub bounds: &'static [u64],
+    /// Number of values in each bucket, the last one counts values above all bounds.
+    pub counts: Vec<u64>,
+    /// Number of recorded values.
+    pub count: u64,
+    /// Sum of recorded values.
+    pub sum: u64,
+}
+
+impl HistogramSnapshot {
+    /// Upper bound of the bucket containing given quantile, e. g. `0.99`.
+    ///
+    /// `None` if there are no values or the quantile is above all bounds.
+    pub fn quantile_upper_bound(&self, quantile: f64) -> Option<u64> {
+        if self.count == 0 {
+            return None;
+        }
+        let rank = ((self.count as f64 * quantile).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (i, &count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return self.bounds.get(i).cloned();
+            }
+        }
+        None
+    }
+}
+
+/// Histograms of calls of a single method
+#[derive(Debug)]
+pub(crate) struct MethodHistogram {
+    latency_micros: Histogram,
+    request_message_bytes: Histogram,
+    response_message_bytes: Histogram,
+}
+
+impl MethodHistogram {
+    fn new() -> MethodHistogram {
+        MethodHistogram {
+            latency_micros: Histogram::new(LATENCY_MICROS_BOUNDS),
+            request_message_bytes: Histogram::new(MESSAGE_BYTES_BOUNDS),
+            response_message_bytes: Histogram::new(MESSAGE_BYTES_BOUNDS),
+        }
+    }
+
+    pub fn message_received(&self, size: usize) {
+        self.request_message_bytes.record(size as u64);
+    }
+
+    pub fn message_sent(&self, size: usize) {
+        self.response_message_bytes.record(size as u64);
+    }
+
+    pub fn finished(&self, duration: Duration) {
+        self.latency_micros
+            .record(duration.as_secs() * 1_000_000 + duration.subsec_micros() as u64);
+    }
+
+    fn snapshot(&self) -> MethodHistogramSnapshot {
+        MethodHistogramSnapshot {
+            latency_micros: self.latency_micros.snapshot(),
+            request_message_bytes: self.request_message_bytes.snapshot(),
+            response_message_bytes: self.response_message_bytes.snapshot(),
+        }
+    }
+}
+
+/// Histograms of calls of a method at some moment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MethodHistogramSnapshot {
+    /// Time from receiving request headers to completing response, in microseconds.
+    pub latency_micros: HistogramSnapshot,
+    /// Serialized size of request messages, excluding gRPC frame headers.
+    pub request_message_bytes: HistogramSnapshot,
+    /// Serialized size of response messages, excluding gRPC frame headers.
+    pub response_message_bytes: HistogramSnapshot,
+}
+
+/// Latency and message size histograms of server methods,
+/// enabled with `ServerConf::method_histograms` and obtained with `Server::method_histograms`.
+///
+/// Histogram of a method is looked up once per call, counters are updated
+/// without locks. Clones share the histograms. Calls of methods beyond
+/// the first thousand, e. g. of the fallback method, are counted as `other`.
+///
+/// Histograms are not served by `Channelz::service`, which reports call counters
+/// and calls in progress.
+#[derive(Debug, Clone, Default)]
+pub struct MethodHistograms {
+    methods: Arc<RwLock<HashMap<String, Arc<MethodHistogram>>>>,
+}
+
+impl MethodHistograms {
+    pub(crate) fn new() -> MethodHistograms {
+        Default::default()
+    }
+
+    /// Histogram to record a call of given method
+    pub(crate) fn method(&self, method: &str) -> Arc<MethodHistogram> {
+        if let Some(histogram) = self.methods.read().unwrap().get(method) {
+            return histogram.clone();
+        }
+        let mut methods = self.methods.write().unwrap();
+        let method = match methods.len() < MAX_METHODS || methods.contains_key(method) {
+            true => method,
+            false => OTHER_METHODS,
+        };
+        methods
+            .entry(method.to_owned())
+            .or_insert_with(|| Arc::new(MethodHistogram::new()))
+            .clone()
+    }
+
+    /// Histograms of methods called so far, by full method name.
+    pub fn snapshot(&self) -> BTreeMap<String, MethodHistogramSnapshot> {
+        self.methods
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(method, histogram)| (method.clone(), histogram.snapshot()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn buckets() {
+        let histogram = Histogram::new(MESSAGE_BYTES_BOUNDS);
+        histogram.record(0);
+        histogram.record(64);
+        histogram.record(65);
+        histogram.record(100 << 20);
+        let snapshot = histogram.snapshot();
+        assert_eq!(4, snapshot.count);
+        assert_eq!(2, snapshot.counts[0]);
+        assert_eq!(1, snapshot.counts[1]);
+        assert_eq!(1, snapshot.counts[MESSAGE_BYTES_BOUNDS.len()]);
+        assert_eq!(Some(64), snapshot.quantile_upper_bound(0.5));
+        assert_eq!(Some(256), snapshot.quantile_upper_bound(0.75));
+        assert_eq!(None, snapshot.quantile_upper_bound(1.0));
+    }
+
+    #[test]
+    fn methods() {
+        let histograms = MethodHistograms::new();
+        histograms.method("/a/b").finished(Duration::from_millis(3));
+        histograms.method("/a/b").message_received(10);
+
+        let snapshot = histograms.snapshot();
+        let b = &snapshot["/a/b"];
+        assert_eq!(1, b.latency_micros.count);
+        assert_eq!(Some(5_000), b.latency_micros.quantile_upper_bound(0.5));
+        assert_eq!(1, b.request_message_bytes.count);
+        assert_eq!(0, b.response_message_bytes.count);
+
+        for i in 0..MAX_METHODS {
+            histograms.method(&format!("/a/{}", i));
+        }
+        assert_eq!(MAX_METHODS + 1, histograms.snapshot().len());
+        assert!(histograms.snapshot().contains_key(OTHER_METHODS));
+    }
+}
@@ -0,0 +1,69 @@
+//! Tracking of requests being processed by server.
+
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+/// Number of requests currently processed by server.
+///
+/// Obtained with `Server::in_flight_requests`, can be cloned and
+/// polled from another thread, e. g. by metrics exporter.
+#[derive(Clone, Default, Debug)]
+pub struct InFlightRequests {
+    count: Arc<AtomicUsize>,
+}
+
+impl InFlightRequests {
+    pub(crate) fn new() -> InFlightRequests {
+        Default::default()
+    }
+
+    /// Current number of requests.
+    pub fn get(&self) -> usize {
+        self.count.load(Ordering::SeqCst)
+    }
+
+    /// Register new request, or return `None` if `limit` is reached.
+    pub(crate) fn try_start(&self, limit: Option<usize>) -> Option<InFlightGuard> {
+        let prev = self.count.fetch_add(1, Ordering::SeqCst);
+        let guard = InFlightGuard {
+            count: self.count.clone(),
+        };
+        match limit {
+            Some(limit) if prev >= limit => {
+                drop(guard);
+                None
+            }
+            _ => Some(guard),
+        }
+    }
+}
+
+/// Request is counted as in-flight until this object is dropped.
+pub(crate) struct InFlightGuard {
+    count: Arc<AtomicUsize>,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.count.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn limit() {
+        let in_flight = InFlightRequests::new();
+        let a = in_flight.try_start(Some(2)).unwrap();
+        let b = in_flight.try_start(Some(2)).unwrap();
+        assert!(in_flight.try_start(Some(2)).is_none());
+        assert_eq!(2, in_flight.get());
+        drop(a);
+        assert!(in_flight.try_start(Some(2)).is_some());
+        drop(b);
+        assert_eq!(0, in_flight.get());
+    }
+}
@@ -0,0 +1,273 @@
+//! Serve unary gRPC methods as HTTP endpoints with JSON bodies.
+
+use std::sync::Arc;
+
+use bytes::Bytes;
+
+use httpbis;
+use httpbis::Header;
+use httpbis::Headers;
+
+use proto::grpc_status::GrpcStatus;
+use proto::grpc_web::GrpcProtocol;
+use proto::headers::add_missing_headers;
+use result;
+use server::ctx::ServerHandlerContext;
+use server::req_handler::RequestLimits;
+use server::req_handler::ServerRequestUntyped;
+use server::resp_sink_untyped::CommonSlot;
+use server::resp_sink_untyped::DroppedSink;
+use server::resp_sink_untyped::ServerResponseUntypedSink;
+use server::ServerServiceDefinition;
+use server::ServerShared;
+use Metadata;
+
+/// Conversion between JSON and serialized messages of a gRPC method.
+///
+/// For protobuf messages this is typically parse/print with protobuf JSON mapping.
+pub trait JsonTranscoder: Send + Sync + 'static {
+    /// Convert JSON request body to serialized request message.
+    /// Body is empty for requests without body (e. g. `GET`).
+    fn request_from_json(&self, json: &[u8]) -> result::Result<Bytes>;
+
+    /// Convert serialized response message to JSON response body.
+    fn response_to_json(&self, message: &[u8]) -> result::Result<Bytes>;
+}
+
+/// Maps HTTP method and path to unary gRPC method.
+pub struct JsonGatewayRoute {
+    /// HTTP method, e. g. `POST`
+    pub http_method: String,
+    /// Full request path without query string, e. g. `/v1/greet`
+    pub path: String,
+    /// gRPC method name, e. g. `/helloworld.Greeter/SayHello`
+    pub grpc_method: String,
+    pub transcoder: Arc<JsonTranscoder>,
+}
+
+/// Route table of HTTP/JSON endpoints.
+///
+/// Registered with `ServerBuilder::add_json_gateway`, requests are dispatched
+/// to method handlers of services registered in the same server.
+#[derive(Default)]
+pub struct JsonGateway {
+    pub(crate) routes: Vec<JsonGatewayRoute>,
+}
+
+impl JsonGateway {
+    pub fn new() -> JsonGateway {
+        Default::default()
+    }
+
+    pub fn add_route(
+        &mut self,
+        http_method: &str,
+        path: &str,
+        grpc_method: &str,
+        transcoder: Arc<JsonTranscoder>,
+    ) {
+        self.routes.push(JsonGatewayRoute {
+            http_method: http_method.to_owned(),
+            path: path.to_owned(),
+            grpc_method: grpc_method.to_owned(),
+            transcoder,
+        });
+    }
+}
+
+/// HTTP status code conventionally used for gRPC status.
+pub(crate) fn http_status_for_grpc_status(status: GrpcStatus) -> u16 {
+    match status {
+        GrpcStatus::Ok => 200,
+        GrpcStatus::Cancelled => 499,
+        GrpcStatus::Unknown => 500,
+        GrpcStatus::Argument => 400,
+        GrpcStatus::DeadlineExceeded => 504,
+        GrpcStatus::NotFound => 404,
+        GrpcStatus::AlreadyExists => 409,
+        GrpcStatus::PermissionDenied => 403,
+        GrpcStatus::Unauthenticated => 401,
+        GrpcStatus::ResourceExhausted => 429,
+        GrpcStatus::FailedPrecondition => 400,
+        GrpcStatus::Aborted => 409,
+        GrpcStatus::OutOfRange => 400,
+        GrpcStatus::Unimplemented => 501,
+        GrpcStatus::Internal => 500,
+        GrpcStatus::Unavailable => 503,
+        GrpcStatus::DataLoss => 500,
+    }
+}
+
+pub(crate) fn json_headers(http_status: u16) -> Headers {
+    Headers::from_vec(vec![
+        Header::new(":status", format!("{}", http_status)),
+        Header::new("content-type", "application/json"),
+    ])
+}
+
+pub(crate) fn json_escape(s: &str) -> String {
+    let mut r = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => r.push_str("\\\""),
+            '\\' => r.push_str("\\\\"),
+            '\n' => r.push_str("\\n"),
+            '\r' => r.push_str("\\r"),
+            '\t' => r.push_str("\\t"),
+            c if (c as u32) < 0x20 => r.push_str(&format!("\\u{:04x}", c as u32)),
+            c => r.push(c),
+        }
+    }
+    r
+}
+
+pub(crate) fn json_error_body(status: GrpcStatus, message: &str) -> Bytes {
+    Bytes::from(format!(
+        "{{\"code\":{},\"message\":\"{}\"}}",
+        status.code(),
+        json_escape(message)
+    ))
+}
+
+fn json_error_message(status: GrpcStatus, message: &str) -> httpbis::SimpleHttpMessage {
+    httpbis::SimpleHttpMessage {
+        headers: json_headers(http_status_for_grpc_status(status)),
+        body: json_error_body(status, message),
+    }
+}
+
+/// `httpbis` handler of JSON gateway requests
+pub(crate) struct JsonGatewayHandler {
+    pub(crate) routes: Vec<JsonGatewayRoute>,
+    pub(crate) services: Vec<Arc<ServerServiceDefinition>>,
+    pub(crate) shared: Arc<ServerShared>,
+}
+
+impl httpbis::ServerHandler for JsonGatewayHandler {
+    fn start_request(
+        &self,
+        context: httpbis::ServerHandlerContext,
+        req: httpbis::ServerRequest,
+        mut resp: httpbis::ServerResponse,
+    ) -> httpbis::Result<()> {
+        let route = {
+            let path = req.headers.path().split('?').next().unwrap_or("");
+            let method = req.headers.method();
+            self.routes
+                .iter()
+                .find(|r| r.http_method == method && r.path == path)
+        };
+
+        let route = match route {
+            Some(route) => route,
+            None => {
+                resp.send_message(
+                    self.shared
+                        .response_message(json_error_message(GrpcStatus::NotFound, "Not found")),
+                )?;
+                return Ok(());
+            }
+        };
+
+        let service = match self
+            .services
+            .iter()
+            .find(|s| s.find_method(&route.grpc_method).is_some())
+        {
+            Some(service) => service,
+            None => {
+                resp.send_message(self.shared.response_message(json_error_message(
+                    GrpcStatus::Unimplemented,
+                    "Unimplemented method",
+                )))?;
+                return Ok(());
+            }
+        };
+
+        // TODO: clone
+        let mut metadata = match Metadata::from_headers(req.headers.clone()) {
+            Ok(metadata) => metadata,
+            Err(_) => {
+                resp.send_message(self.shared.response_message(json_error_message(
+                    GrpcStatus::Argument,
+                    "decode metadata error",
+                )))?;
+                return Ok(());
+            }
+        };
+
+        let in_flight = match self.shared.admit(&route.grpc_method, &metadata) {
+            Ok(in_flight) => in_flight,
+            Err((status, metadata)) => {
+                let mut message = json_error_message(status.code, &status.message);
+                message.headers.extend(metadata.into_headers());
+                resp.send_message(self.shared.response_message(message))?;
+                return Ok(());
+            }
+        };
+
+        let call_id = self.shared.call_id(&mut metadata);
+        let stats = self.shared.call_stats(&route.grpc_method, call_id);
+
+        let req = ServerRequestUntyped {
+            req,
+            protocol: GrpcProtocol::Grpc,
+            json: Some(route.transcoder.clone()),
+            stats: stats.clone(),
+            frame_log: None,
+            limits: RequestLimits::from_conf(&self.shared.conf),
+            loop_remote: context.loop_remote(),
+        };
+
+        let response_headers = self.shared.response_headers.clone();
+        resp.set_drop_callback(move |resp| {
+            let mut message = json_error_message(
+                GrpcStatus::Internal,
+                "grpc server handler did not close the sender",
+            );
+            add_missing_headers(&mut message.headers, &response_headers);
+            Ok(resp.send_message(message)?)
+        });
+
+        let dropped_sink = DroppedSink::default();
+
+        let resp = ServerResponseUntypedSink {
+            common: CommonSlot::new(resp),
+            protocol: GrpcProtocol::Grpc,
+            json: Some(route.transcoder.clone()),
+            response_headers: self.shared.response_headers.clone(),
+            in_flight: Some(in_flight),
+            stats,
+            frame_log: None,
+            dropped: dropped_sink.clone(),
+        };
+
+        let context = ServerHandlerContext {
+            ctx: context,
+            metadata,
+            method: route.grpc_method.clone(),
+            cpu_pool: self.shared.worker_pool.clone(),
+            deadline: None,
+            panic_guard: self.shared.panic_guard(),
+            watchdog: self.shared.watchdog(),
+            dropped_sink,
+        };
+
+        service.handle_method(&route.grpc_method, None, context, req, resp)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn error_body() {
+        assert_eq!(
+            Bytes::from("{\"code\":5,\"message\":\"no \\\"x\\\"\\n\"}"),
+            json_error_body(GrpcStatus::NotFound, "no \"x\"\n")
+        );
+    }
+}
@@ -1,6 +1,14 @@
 use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures_cpupool::CpuPool;
 
 use common::sink::SinkCommon;
+use error;
+use error::Status;
+use marshall::Marshaller;
+use marshall::MarshallerBytes;
 use method::GrpcStreaming;
 use method::GrpcStreamingBidi;
 use method::GrpcStreamingClientStreaming;
@@ -10,14 +18,22 @@ use method::GrpcStreamingUnary;
 use method::MethodDescriptor;
 use or_static::arc::ArcOrStatic;
 use or_static::string::StringOrStatic;
+use resp::SingleResponse;
+use resp::StreamingResponse;
 use result;
+use server::ctx::pump_response;
 use server::ctx::ServerHandlerContext;
+use server::panic::PanicGuard;
+use server::req_body::ServerRequestBody;
 use server::req_handler::ServerRequest;
 use server::req_handler::ServerRequestUnaryHandler;
 use server::req_handler::ServerRequestUntyped;
 use server::req_single::ServerRequestSingle;
 use server::resp_sink::ServerResponseSink;
+use server::resp_sink_untyped::DroppedSink;
 use server::resp_sink_untyped::ServerResponseUntypedSink;
+use server::validate::RequestValidator;
+use server::validate::ValidatingMarshaller;
 use std::marker;
 use ServerResponseUnarySink;
 
@@ -50,6 +66,109 @@ pub struct MethodHandlerBidi<F> {
     f: Arc<F>,
 }
 
+/// Unary method handler which receives serialized request message in chunks.
+///
+/// Useful for very large requests which should not be buffered in memory.
+/// Handler is called when request headers are received.
+pub struct MethodHandlerUnaryStreamBody<F> {
+    f: Arc<F>,
+}
+
+/// Handler function of a method.
+///
+/// Implemented for closures taking context, request and response,
+/// and for service methods bound to the service instance with `new_method` constructors.
+pub trait MethodHandlerFn<Req, Resp>: Send + Sync + 'static {
+    fn call(&self, ctx: ServerHandlerContext, req: Req, resp: Resp) -> result::Result<()>;
+}
+
+impl<F, Req, Resp> MethodHandlerFn<Req, Resp> for F
+where
+    F: Fn(ServerHandlerContext, Req, Resp) -> result::Result<()> + Send + Sync + 'static,
+{
+    fn call(&self, ctx: ServerHandlerContext, req: Req, resp: Resp) -> result::Result<()> {
+        self(ctx, req, resp)
+    }
+}
+
+/// Method of a service implementation shared by all methods of the service,
+/// created by `new_method` constructors of method handlers.
+pub struct ServiceMethod<S, M> {
+    service: Arc<S>,
+    method: M,
+}
+
+impl<S, M, Req, Resp> MethodHandlerFn<Req, Resp> for ServiceMethod<S, M>
+where
+    S: Send + Sync + 'static,
+    M: Fn(&S, ServerHandlerContext, Req, Resp) -> result::Result<()> + Send + Sync + 'static,
+{
+    fn call(&self, ctx: ServerHandlerContext, req: Req, resp: Resp) -> result::Result<()> {
+        (self.method)(&self.service, ctx, req, resp)
+    }
+}
+
+/// Method of a service implementation which returns the response
+/// instead of writing it to response sink,
+/// created by `new_async_method` constructors of method handlers.
+///
+/// Initial metadata of the response is sent as soon as it is available,
+/// before response messages. Method may also return `Result` of the response,
+/// so errors of request validation can be returned with `?`.
+pub struct AsyncServiceMethod<S, M> {
+    service: Arc<S>,
+    method: M,
+}
+
+impl<S, M, Req, Resp, R> MethodHandlerFn<Req, ServerResponseUnarySink<Resp>>
+    for AsyncServiceMethod<S, M>
+where
+    S: Send + Sync + 'static,
+    Resp: Send + 'static,
+    M: Fn(&S, ServerHandlerContext, Req) -> R + Send + Sync + 'static,
+    R: Into<SingleResponse<Resp>>,
+{
+    fn call(
+        &self,
+        ctx: ServerHandlerContext,
+        req: Req,
+        resp: ServerResponseUnarySink<Resp>,
+    ) -> result::Result<()> {
+        let remote = ctx.loop_remote();
+        let watchdog = ctx.watchdog.clone();
+        let panic_guard = ctx.panic_guard.clone();
+        let method = ctx.method.clone();
+        let response = (self.method)(&self.service, ctx, req).into();
+        let response = watchdog.watch(&method, response.into_stream());
+        pump_response(&remote, panic_guard, method, response, resp.sink);
+        Ok(())
+    }
+}
+
+impl<S, M, Req, Resp, R> MethodHandlerFn<Req, ServerResponseSink<Resp>> for AsyncServiceMethod<S, M>
+where
+    S: Send + Sync + 'static,
+    Resp: Send + 'static,
+    M: Fn(&S, ServerHandlerContext, Req) -> R + Send + Sync + 'static,
+    R: Into<StreamingResponse<Resp>>,
+{
+    fn call(
+        &self,
+        ctx: ServerHandlerContext,
+        req: Req,
+        resp: ServerResponseSink<Resp>,
+    ) -> result::Result<()> {
+        let remote = ctx.loop_remote();
+        let watchdog = ctx.watchdog.clone();
+        let panic_guard = ctx.panic_guard.clone();
+        let method = ctx.method.clone();
+        let response = (self.method)(&self.service, ctx, req).into();
+        let response = watchdog.watch(&method, response);
+        pump_response(&remote, panic_guard, method, response, resp);
+        Ok(())
+    }
+}
+
 impl<F> GrpcStreamingFlavor for MethodHandlerUnary<F> {
     type Flavor = GrpcStreamingUnary;
 
@@ -82,6 +201,14 @@ impl<F> GrpcStreamingFlavor for MethodHandlerBidi<F> {
     }
 }
 
+impl<F> GrpcStreamingFlavor for MethodHandlerUnaryStreamBody<F> {
+    type Flavor = GrpcStreamingUnary;
+
+    fn streaming() -> GrpcStreaming {
+        GrpcStreaming::Unary
+    }
+}
+
 impl<F> MethodHandlerUnary<F> {
     pub fn new<Req, Resp>(f: F) -> Self
     where
@@ -150,18 +277,175 @@ impl<F> MethodHandlerBidi<F> {
     }
 }
 
+impl<S, M> MethodHandlerUnary<ServiceMethod<S, M>> {
+    /// Handler which calls a method of shared service implementation,
+    /// e. g. `MethodHandlerUnary::new_method(service.clone(), TestService::unary_call)`.
+    pub fn new_method<Req, Resp>(service: Arc<S>, method: M) -> Self
+    where
+        Req: Send + 'static,
+        Resp: Send + 'static,
+        S: Send + Sync + 'static,
+        M: Fn(
+                &S,
+                ServerHandlerContext,
+                ServerRequestSingle<Req>,
+                ServerResponseUnarySink<Resp>,
+            ) -> result::Result<()>
+            + Send
+            + Sync
+            + 'static,
+    {
+        MethodHandlerUnary {
+            f: Arc::new(ServiceMethod { service, method }),
+        }
+    }
+}
+
+impl<S, M> MethodHandlerClientStreaming<ServiceMethod<S, M>> {
+    /// Handler which calls a method of shared service implementation,
+    /// e. g. `MethodHandlerClientStreaming::new_method(service.clone(), TestService::streaming_input_call)`.
+    pub fn new_method<Req, Resp>(service: Arc<S>, method: M) -> Self
+    where
+        Req: Send + 'static,
+        Resp: Send + 'static,
+        S: Send + Sync + 'static,
+        M: Fn(
+                &S,
+                ServerHandlerContext,
+                ServerRequest<Req>,
+                ServerResponseUnarySink<Resp>,
+            ) -> result::Result<()>
+            + Send
+            + Sync
+            + 'static,
+    {
+        MethodHandlerClientStreaming {
+            f: Arc::new(ServiceMethod { service, method }),
+        }
+    }
+}
+
+impl<S, M> MethodHandlerServerStreaming<ServiceMethod<S, M>> {
+    /// Handler which calls a method of shared service implementation,
+    /// e. g. `MethodHandlerServerStreaming::new_method(service.clone(), TestService::streaming_output_call)`.
+    pub fn new_method<Req, Resp>(service: Arc<S>, method: M) -> Self
+    where
+        Req: Send + 'static,
+        Resp: Send + 'static,
+        S: Send + Sync + 'static,
+        M: Fn(
+                &S,
+                ServerHandlerContext,
+                ServerRequestSingle<Req>,
+                ServerResponseSink<Resp>,
+            ) -> result::Result<()>
+            + Send
+            + Sync
+            + 'static,
+    {
+        MethodHandlerServerStreaming {
+            f: Arc::new(ServiceMethod { service, method }),
+        }
+    }
+}
+
+impl<S, M> MethodHandlerBidi<ServiceMethod<S, M>> {
+    /// Handler which calls a method of shared service implementation,
+    /// e. g. `MethodHandlerBidi::new_method(service.clone(), TestService::full_duplex_call)`.
+    pub fn new_method<Req, Resp>(service: Arc<S>, method: M) -> Self
+    where
+        Req: Send + 'static,
+        Resp: Send + 'static,
+        S: Send + Sync + 'static,
+        M: Fn(
+                &S,
+                ServerHandlerContext,
+                ServerRequest<Req>,
+                ServerResponseSink<Resp>,
+            ) -> result::Result<()>
+            + Send
+            + Sync
+            + 'static,
+    {
+        MethodHandlerBidi {
+            f: Arc::new(ServiceMethod { service, method }),
+        }
+    }
+}
+
+impl<S, M> MethodHandlerUnary<AsyncServiceMethod<S, M>> {
+    /// Handler which calls a method of shared service implementation returning the response,
+    /// e. g. `MethodHandlerUnary::new_async_method(service.clone(), TestServiceAsync::unary_call)`.
+    pub fn new_async_method<Req, Resp, R>(service: Arc<S>, method: M) -> Self
+    where
+        Req: Send + 'static,
+        Resp: Send + 'static,
+        S: Send + Sync + 'static,
+        M: Fn(&S, ServerHandlerContext, ServerRequestSingle<Req>) -> R + Send + Sync + 'static,
+        R: Into<SingleResponse<Resp>>,
+    {
+        MethodHandlerUnary {
+            f: Arc::new(AsyncServiceMethod { service, method }),
+        }
+    }
+}
+
+impl<S, M> MethodHandlerClientStreaming<AsyncServiceMethod<S, M>> {
+    /// Handler which calls a method of shared service implementation returning the response,
+    /// e. g. `MethodHandlerClientStreaming::new_async_method(service.clone(), TestServiceAsync::streaming_input_call)`.
+    pub fn new_async_method<Req, Resp, R>(service: Arc<S>, method: M) -> Self
+    where
+        Req: Send + 'static,
+        Resp: Send + 'static,
+        S: Send + Sync + 'static,
+        M: Fn(&S, ServerHandlerContext, ServerRequest<Req>) -> R + Send + Sync + 'static,
+        R: Into<SingleResponse<Resp>>,
+    {
+        MethodHandlerClientStreaming {
+            f: Arc::new(AsyncServiceMethod { service, method }),
+        }
+    }
+}
+
+impl<S, M> MethodHandlerServerStreaming<AsyncServiceMethod<S, M>> {
+    /// Handler which calls a method of shared service implementation returning the response,
+    /// e. g. `MethodHandlerServerStreaming::new_async_method(service.clone(), TestServiceAsync::streaming_output_call)`.
+    pub fn new_async_method<Req, Resp, R>(service: Arc<S>, method: M) -> Self
+    where
+        Req: Send + 'static,
+        Resp: Send + 'static,
+        S: Send + Sync + 'static,
+        M: Fn(&S, ServerHandlerContext, ServerRequestSingle<Req>) -> R + Send + Sync + 'static,
+        R: Into<StreamingResponse<Resp>>,
+    {
+        MethodHandlerServerStreaming {
+            f: Arc::new(AsyncServiceMethod { service, method }),
+        }
+    }
+}
+
+impl<S, M> MethodHandlerBidi<AsyncServiceMethod<S, M>> {
+    /// Handler which calls a method of shared service implementation returning the response,
+    /// e. g. `MethodHandlerBidi::new_async_method(service.clone(), TestServiceAsync::full_duplex_call)`.
+    pub fn new_async_method<Req, Resp, R>(service: Arc<S>, method: M) -> Self
+    where
+        Req: Send + 'static,
+        Resp: Send + 'static,
+        S: Send + Sync + 'static,
+        M: Fn(&S, ServerHandlerContext, ServerRequest<Req>) -> R + Send + Sync + 'static,
+        R: Into<StreamingResponse<Resp>>,
+    {
+        MethodHandlerBidi {
+            f: Arc::new(AsyncServiceMethod { service, method }),
+        }
+    }
+}
+
 impl<Req, Resp, F> MethodHandler<Req, Resp> for MethodHandlerUnary<F>
 where
     Req: Send + 'static,
     Resp: Send + 'static,
-    F: Fn(
-            ServerHandlerContext,
-            ServerRequestSingle<Req>,
-            ServerResponseUnarySink<Resp>,
-        ) -> result::Result<()>
-        + Send
-        + Sync
-        + 'static,
+    F: MethodHandlerFn<ServerRequestSingle<Req>, ServerResponseUnarySink<Resp>>,
 {
     fn handle(
         &self,
@@ -173,14 +457,7 @@ where
         where
             Req: Send + 'static,
             Resp: Send + 'static,
-            F: Fn(
-                    ServerHandlerContext,
-                    ServerRequestSingle<Req>,
-                    ServerResponseUnarySink<Resp>,
-                ) -> result::Result<()>
-                + Send
-                + Sync
-                + 'static,
+            F: MethodHandlerFn<ServerRequestSingle<Req>, ServerResponseUnarySink<Resp>>,
         {
             ctx: ServerHandlerContext,
             f: Arc<F>,
@@ -192,14 +469,7 @@ where
         where
             Req: Send + 'static,
             Resp: Send + 'static,
-            F: Fn(
-                    ServerHandlerContext,
-                    ServerRequestSingle<Req>,
-                    ServerResponseUnarySink<Resp>,
-                ) -> result::Result<()>
-                + Send
-                + Sync
-                + 'static,
+            F: MethodHandlerFn<ServerRequestSingle<Req>, ServerResponseUnarySink<Resp>>,
         {
             fn grpc_message(&mut self, message: Req) -> result::Result<()> {
                 let HandlerImpl {
@@ -211,7 +481,24 @@ where
                 let metadata = ctx.metadata.clone();
                 let req = ServerRequestSingle { metadata, message };
                 let resp = ServerResponseUnarySink { sink: resp };
-                f(ctx, req, resp)
+                let cpu_pool = ctx.cpu_pool.clone();
+                let panic_guard = ctx.panic_guard.clone();
+                let method = ctx.method.clone();
+                let dropped_sink = ctx.dropped_sink.clone();
+                call_on_cpu_pool(cpu_pool, panic_guard, method, dropped_sink, move || {
+                    f.call(ctx, req, resp)
+                })
+            }
+
+            /// Request could not be decoded, handler is not called
+            fn error(&mut self, error: error::Error) -> result::Result<()> {
+                match self.take() {
+                    Some(HandlerImpl { mut resp, .. }) => {
+                        let status = Status::from(error);
+                        resp.send_grpc_error(status.code, status.message)
+                    }
+                    None => Err(error),
+                }
             }
         }
 
@@ -230,14 +517,7 @@ impl<Req: Send + 'static, Resp: Send + 'static, F> MethodHandler<Req, Resp>
     for MethodHandlerClientStreaming<F>
 where
     Resp: Send + 'static,
-    F: Fn(
-            ServerHandlerContext,
-            ServerRequest<Req>,
-            ServerResponseUnarySink<Resp>,
-        ) -> result::Result<()>
-        + Send
-        + Sync
-        + 'static,
+    F: MethodHandlerFn<ServerRequest<Req>, ServerResponseUnarySink<Resp>>,
 {
     fn handle(
         &self,
@@ -246,7 +526,7 @@ where
         resp: ServerResponseSink<Resp>,
     ) -> result::Result<()> {
         let resp = ServerResponseUnarySink { sink: resp };
-        (self.f)(ctx, req, resp)
+        self.f.call(ctx, req, resp)
     }
 }
 
@@ -254,14 +534,7 @@ impl<Req, Resp, F> MethodHandler<Req, Resp> for MethodHandlerServerStreaming<F>
 where
     Req: Send + 'static,
     Resp: Send + 'static,
-    F: Fn(
-            ServerHandlerContext,
-            ServerRequestSingle<Req>,
-            ServerResponseSink<Resp>,
-        ) -> result::Result<()>
-        + Send
-        + Sync
-        + 'static,
+    F: MethodHandlerFn<ServerRequestSingle<Req>, ServerResponseSink<Resp>>,
 {
     fn handle(
         &self,
@@ -273,14 +546,7 @@ where
         where
             Req: Send + 'static,
             Resp: Send + 'static,
-            F: Fn(
-                    ServerHandlerContext,
-                    ServerRequestSingle<Req>,
-                    ServerResponseSink<Resp>,
-                ) -> result::Result<()>
-                + Send
-                + Sync
-                + 'static,
+            F: MethodHandlerFn<ServerRequestSingle<Req>, ServerResponseSink<Resp>>,
         {
             ctx: ServerHandlerContext,
             f: Arc<F>,
@@ -292,14 +558,7 @@ where
         where
             Req: Send + 'static,
             Resp: Send + 'static,
-            F: Fn(
-                    ServerHandlerContext,
-                    ServerRequestSingle<Req>,
-                    ServerResponseSink<Resp>,
-                ) -> result::Result<()>
-                + Send
-                + Sync
-                + 'static,
+            F: MethodHandlerFn<ServerRequestSingle<Req>, ServerResponseSink<Resp>>,
         {
             fn grpc_message(&mut self, req: Req) -> result::Result<()> {
                 let HandlerImpl {
@@ -313,7 +572,24 @@ where
                     metadata,
                     message: req,
                 };
-                f(ctx, req, resp)
+                let cpu_pool = ctx.cpu_pool.clone();
+                let panic_guard = ctx.panic_guard.clone();
+                let method = ctx.method.clone();
+                let dropped_sink = ctx.dropped_sink.clone();
+                call_on_cpu_pool(cpu_pool, panic_guard, method, dropped_sink, move || {
+                    f.call(ctx, req, resp)
+                })
+            }
+
+            /// Request could not be decoded, handler is not called
+            fn error(&mut self, error: error::Error) -> result::Result<()> {
+                match self.take() {
+                    Some(HandlerImpl { mut resp, .. }) => {
+                        let status = Status::from(error);
+                        resp.send_grpc_error(status.code, status.message)
+                    }
+                    None => Err(error),
+                }
             }
         }
 
@@ -332,10 +608,39 @@ impl<Req, Resp, F> MethodHandler<Req, Resp> for MethodHandlerBidi<F>
 where
     Req: Send + 'static,
     Resp: Send + 'static,
-    F: Fn(ServerHandlerContext, ServerRequest<Req>, ServerResponseSink<Resp>) -> result::Result<()>
-        + Send
-        + Sync
-        + 'static,
+    F: MethodHandlerFn<ServerRequest<Req>, ServerResponseSink<Resp>>,
+{
+    fn handle(
+        &self,
+        ctx: ServerHandlerContext,
+        req: ServerRequest<Req>,
+        resp: ServerResponseSink<Resp>,
+    ) -> result::Result<()> {
+        self.f.call(ctx, req, resp)
+    }
+}
+
+impl<F> MethodHandlerUnaryStreamBody<F> {
+    pub fn new<Resp>(f: F) -> Self
+    where
+        Resp: Send + 'static,
+        F: Fn(
+                ServerHandlerContext,
+                ServerRequestBody,
+                ServerResponseUnarySink<Resp>,
+            ) -> result::Result<()>
+            + Send
+            + 'static,
+    {
+        MethodHandlerUnaryStreamBody { f: Arc::new(f) }
+    }
+}
+
+impl<Req, Resp, F> MethodHandler<Req, Resp> for MethodHandlerUnaryStreamBody<F>
+where
+    Req: Send + 'static,
+    Resp: Send + 'static,
+    F: MethodHandlerFn<ServerRequestBody, ServerResponseUnarySink<Resp>>,
 {
     fn handle(
         &self,
@@ -343,7 +648,19 @@ where
         req: ServerRequest<Req>,
         resp: ServerResponseSink<Resp>,
     ) -> result::Result<()> {
-        (self.f)(ctx, req, resp)
+        let resp = ServerResponseUnarySink { sink: resp };
+        let body = match req.req.into_body_stream() {
+            Ok(body) => body,
+            Err(e) => {
+                let status = e.status();
+                return resp.send_grpc_error(status.code, status.message);
+            }
+        };
+        let req = ServerRequestBody {
+            metadata: ctx.metadata.clone(),
+            body,
+        };
+        self.f.call(ctx, req, resp)
     }
 }
 
@@ -358,6 +675,8 @@ pub(crate) trait MethodHandlerDispatchUntyped {
 
 struct MethodHandlerDispatchImpl<Req: 'static, Resp: 'static> {
     desc: ArcOrStatic<MethodDescriptor<Req, Resp>>,
+    /// Marshaller of `desc`, wrapped to validate messages if method has a validator
+    req_marshaller: ArcOrStatic<Marshaller<Req>>,
     method_handler: Box<MethodHandler<Req, Resp> + Sync + Send>,
 }
 
@@ -374,7 +693,7 @@ where
     ) -> result::Result<()> {
         let req = ServerRequest {
             req,
-            marshaller: self.desc.req_marshaller.clone(),
+            marshaller: self.req_marshaller.clone(),
         };
 
         let resp = ServerResponseSink {
@@ -384,27 +703,23 @@ where
             },
         };
 
-        // TODO: catch unwind for better diag
         self.method_handler.handle(ctx, req, resp)
-        //        let resp = catch_unwind(AssertUnwindSafe(|| {
-        //
-        //        }));
-        //        match resp {
-        //            Ok(resp) => {
-        //                let desc_copy = self.desc.clone();
-        //                resp.and_then_items(move |resp| desc_copy.resp_marshaller.write(&resp))
-        //            }
-        //            Err(e) => {
-        //                let message = any_to_string(e);
-        //                StreamingResponse::err(Error::Panic(message))
-        //            }
-        //        }
     }
 }
 
+static RAW_METHOD: MethodDescriptor<Bytes, Bytes> = MethodDescriptor {
+    name: StringOrStatic::Static("*"),
+    streaming: GrpcStreaming::Bidi,
+    req_marshaller: ArcOrStatic::Static(&MarshallerBytes),
+    resp_marshaller: ArcOrStatic::Static(&MarshallerBytes),
+};
+
 pub struct ServerMethod {
     pub(crate) name: StringOrStatic,
+    pub(crate) streaming: GrpcStreaming,
     pub(crate) dispatch: Box<MethodHandlerDispatchUntyped + Sync + Send>,
+    pub(crate) cpu_pool: Option<CpuPool>,
+    pub(crate) watchdog_timeout: Option<Duration>,
 }
 
 impl ServerMethod {
@@ -419,10 +734,135 @@ impl ServerMethod {
     {
         ServerMethod {
             name: method.name.clone(),
+            streaming: method.streaming,
             dispatch: Box::new(MethodHandlerDispatchImpl {
+                req_marshaller: method.req_marshaller.clone(),
                 desc: method,
                 method_handler: Box::new(handler),
             }),
+            cpu_pool: None,
+            watchdog_timeout: None,
+        }
+    }
+
+    /// Method which checks each request message with `validator`
+    /// after it is parsed, before it is passed to the handler.
+    pub fn new_validated<Req, Resp, H, V>(
+        method: ArcOrStatic<MethodDescriptor<Req, Resp>>,
+        handler: H,
+        validator: V,
+    ) -> ServerMethod
+    where
+        Req: Send + 'static,
+        Resp: Send + 'static,
+        H: MethodHandler<Req, Resp> + 'static + Sync + Send,
+        V: RequestValidator<Req>,
+    {
+        ServerMethod {
+            name: method.name.clone(),
+            streaming: method.streaming,
+            dispatch: Box::new(MethodHandlerDispatchImpl {
+                req_marshaller: ArcOrStatic::Arc(Arc::new(ValidatingMarshaller {
+                    marshaller: method.req_marshaller.clone(),
+                    validator: Arc::new(validator),
+                })),
+                desc: method,
+                method_handler: Box::new(handler),
+            }),
+            cpu_pool: None,
+            watchdog_timeout: None,
+        }
+    }
+
+    /// Method which receives and sends serialized messages of any method,
+    /// to be registered with `ServerBuilder::set_fallback_method`.
+    ///
+    /// Name of the called method is available from `ServerHandlerContext::method`.
+    pub fn raw<F>(handler: F) -> ServerMethod
+    where
+        F: Fn(
+                ServerHandlerContext,
+                ServerRequest<Bytes>,
+                ServerResponseSink<Bytes>,
+            ) -> result::Result<()>
+            + Send
+            + Sync
+            + 'static,
+    {
+        ServerMethod::new(
+            ArcOrStatic::Static(&RAW_METHOD),
+            MethodHandlerBidi::new(handler),
+        )
+    }
+
+    /// Full name of the method, e. g. `/helloworld.Greeter/SayHello`.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn streaming(&self) -> GrpcStreaming {
+        self.streaming
+    }
+
+    /// Invoke handler of this method on given pool instead of event loop.
+    ///
+    /// Useful for CPU-heavy methods which would otherwise block other requests
+    /// served by the same event loop.
+    pub fn with_cpu_pool(mut self, cpu_pool: CpuPool) -> ServerMethod {
+        self.cpu_pool = Some(cpu_pool);
+        self
+    }
+
+    /// Cancel handler responses of this method which make no progress in given time,
+    /// overriding `ServerConf::handler_watchdog_timeout`.
+    pub fn with_watchdog_timeout(mut self, timeout: Duration) -> ServerMethod {
+        self.watchdog_timeout = Some(timeout);
+        self
+    }
+}
+
+/// Call handler function on pool if there is one, or in place otherwise.
+///
+/// Panics of the handler are handled according to panic policy on both paths.
+/// Error returned by the handler is sent to client if the handler dropped
+/// the response sink, otherwise the sink is already completed or owned
+/// by a future which completes it.
+fn call_on_cpu_pool<F>(
+    cpu_pool: Option<CpuPool>,
+    panic_guard: PanicGuard,
+    method: String,
+    dropped_sink: DroppedSink,
+    f: F,
+) -> result::Result<()>
+where
+    F: FnOnce() -> result::Result<()> + Send + 'static,
+{
+    let call = move || {
+        dropped_sink.arm();
+        let r = panic_guard.call(&method, f);
+        let sink = dropped_sink.disarm();
+        match (r, sink) {
+            (Err(e), Some(mut sink)) => {
+                debug!("handler of {} returned error: {:?}", method, e);
+                let status = e.status();
+                sink.send_grpc_error(status.code, status.message)?;
+                Ok(())
+            }
+            (r, _) => r,
+        }
+    };
+    match cpu_pool {
+        Some(cpu_pool) => {
+            cpu_pool
+                .spawn_fn(move || {
+                    if let Err(e) = call() {
+                        warn!("handler returned error: {:?}", e);
+                    }
+                    Ok::<_, ()>(())
+                })
+                .forget();
+            Ok(())
         }
+        None => call(),
     }
 }
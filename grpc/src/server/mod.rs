@@ -1,5 +1,17 @@
+pub(crate) mod access_log;
+pub(crate) mod auth;
+pub(crate) mod channelz;
 pub(crate) mod ctx;
+pub(crate) mod descriptor;
+pub(crate) mod drain;
+pub(crate) mod events;
+pub(crate) mod histograms;
+pub(crate) mod in_flight;
+pub(crate) mod json_gateway;
 pub(crate) mod method;
+pub(crate) mod panic;
+pub(crate) mod rate_limit;
+pub(crate) mod req_body;
 pub(crate) mod req_handler;
 pub(crate) mod req_handler_unary;
 pub(crate) mod req_single;
@@ -7,56 +19,169 @@ pub(crate) mod req_stream;
 pub(crate) mod resp_sink;
 pub(crate) mod resp_sink_untyped;
 pub(crate) mod resp_unary_sink;
+pub(crate) mod routes;
+pub(crate) mod tls;
 pub(crate) mod types;
+pub(crate) mod validate;
+pub(crate) mod watchdog;
 
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
 
+use futures_cpupool;
+use futures_cpupool::CpuPool;
 use httpbis;
+use tokio_core::reactor::Remote;
 
 use result::Result;
 
 use tls_api;
 use tls_api_stub;
 
-use common::sink::SinkCommonUntyped;
+use common::frame_log::FrameLog;
+use error::Error;
+use error::Status;
 use httpbis::AnySocketAddr;
 use proto::grpc_status::GrpcStatus;
+use proto::grpc_timeout::parse_grpc_timeout;
+use proto::grpc_timeout::HEADER_GRPC_TIMEOUT;
+use proto::grpc_web::cors_headers;
+use proto::grpc_web::cors_preflight_message;
+use proto::grpc_web::GrpcProtocol;
+use proto::headers::add_missing_headers;
 use proto::headers::grpc_error_message;
+use proto::headers::grpc_error_message_with_metadata;
+use proto::headers::grpc_unsupported_encoding_message;
+use proto::headers::http_error_message;
+use proto::headers::HEADER_GRPC_ACCEPT_ENCODING;
+use proto::headers::HEADER_GRPC_ENCODING;
+use proto::headers::SUPPORTED_GRPC_ENCODINGS;
 use result;
+use server::access_log::AccessLogger;
+use server::auth::rejection;
+use server::auth::ServerAuthHandler;
+use server::channelz::Channelz;
 use server::ctx::ServerHandlerContext;
+use server::descriptor::ServiceDescriptor;
+use server::drain::draining_metadata;
+use server::drain::Draining;
+use server::events::ServerEventSubscribers;
+use server::events::ServerEvents;
+use server::histograms::MethodHistograms;
+use server::in_flight::InFlightGuard;
+use server::in_flight::InFlightRequests;
+use server::json_gateway::JsonGateway;
+use server::json_gateway::JsonGatewayHandler;
 use server::method::ServerMethod;
+use server::panic::PanicGuard;
+use server::panic::PanicPolicy;
+use server::panic::ServerPanicHandler;
+use server::rate_limit::retry_after_metadata;
+use server::rate_limit::RateLimiter;
+use server::req_handler::RequestLimits;
 use server::req_handler::ServerRequestUntyped;
+use server::resp_sink_untyped::CommonSlot;
+use server::resp_sink_untyped::DroppedSink;
 use server::resp_sink_untyped::ServerResponseUntypedSink;
+use server::routes::ServiceRoutes;
+use server::tls::ServerTlsConf;
+use server::watchdog::ServerWatchdogHandler;
+use server::watchdog::Watchdog;
+use stats::ServerCallStats;
+use stats::ServerStatsHandler;
+use trace::CallId;
 use Metadata;
 
 pub struct ServerServiceDefinition {
     pub prefix: String,
-    pub methods: Vec<ServerMethod>,
+    methods: HashMap<String, ServerMethod>,
+    /// Name of a method registered more than once, reported by `ServerBuilder::build`
+    duplicate_method: Option<String>,
 }
 
 impl ServerServiceDefinition {
     pub fn new(prefix: &str, methods: Vec<ServerMethod>) -> ServerServiceDefinition {
+        let mut map = HashMap::with_capacity(methods.len());
+        let mut duplicate_method = None;
+        for method in methods {
+            if map.contains_key(method.name.as_str()) {
+                duplicate_method = Some(method.name.to_string());
+            }
+            map.insert(method.name.to_string(), method);
+        }
         ServerServiceDefinition {
             prefix: prefix.to_owned(),
-            methods: methods,
+            methods: map,
+            duplicate_method,
         }
     }
 
     pub fn find_method(&self, name: &str) -> Option<&ServerMethod> {
-        self.methods.iter().filter(|m| m.name == name).next()
+        self.methods.get(name)
+    }
+
+    /// Names of all methods of the service.
+    pub fn method_names(&self) -> impl Iterator<Item = &str> {
+        self.methods.keys().map(|n| n.as_str())
+    }
+
+    /// All methods of the service, in no particular order.
+    pub fn methods(&self) -> impl Iterator<Item = &ServerMethod> {
+        self.methods.values()
+    }
+
+    /// Remove method from the service before the service is registered,
+    /// e. g. to disable methods with a feature flag.
+    /// Calls of removed method fail with `UNIMPLEMENTED`.
+    pub fn remove_method(&mut self, name: &str) -> Option<ServerMethod> {
+        self.methods.remove(name)
+    }
+
+    /// Names and streaming flavors of the service and its methods.
+    pub fn descriptor(&self) -> ServiceDescriptor {
+        ServiceDescriptor::new(&self.prefix, self.methods())
+    }
+
+    /// Run methods which names match the predicate on given pool.
+    pub fn set_cpu_pool_for<P>(&mut self, predicate: P, cpu_pool: CpuPool)
+    where
+        P: Fn(&str) -> bool,
+    {
+        for method in self.methods.values_mut() {
+            if predicate(&method.name) {
+                method.cpu_pool = Some(cpu_pool.clone());
+            }
+        }
     }
 
     pub(crate) fn handle_method(
         &self,
         name: &str,
-        ctx: ServerHandlerContext,
+        fallback: Option<&ServerMethod>,
+        mut ctx: ServerHandlerContext,
         req: ServerRequestUntyped,
         mut resp: ServerResponseUntypedSink,
     ) -> result::Result<()> {
-        match self.find_method(name) {
-            Some(method) => method.dispatch.start_request(ctx, req, resp),
+        match self.find_method(name).or(fallback) {
+            Some(method) => {
+                if let Some(ref cpu_pool) = method.cpu_pool {
+                    ctx.cpu_pool = Some(cpu_pool.clone());
+                }
+                if let Some(timeout) = method.watchdog_timeout {
+                    ctx.watchdog.timeout = Some(timeout);
+                }
+                let panic_guard = ctx.panic_guard.clone();
+                panic_guard.call(name, || method.dispatch.start_request(ctx, req, resp))
+            }
             None => {
-                resp.send_grpc_error(GrpcStatus::Unimplemented, "Unimplemented method".to_owned())?;
+                resp.send_grpc_error(
+                    GrpcStatus::Unimplemented,
+                    format!("unknown method {}", name),
+                )?;
                 Ok(())
             }
         }
@@ -64,7 +189,106 @@ impl ServerServiceDefinition {
 }
 
 #[derive(Default, Debug, Clone)]
-pub struct ServerConf {}
+pub struct ServerConf {
+    /// Accept `application/grpc-web` and `application/grpc-web-text` requests
+    /// in addition to plain gRPC, so browsers can call the server without a proxy.
+    ///
+    /// Browsers call servers of other origins only if CORS is allowed
+    /// with `grpc_web_cors_origin`.
+    pub grpc_web: bool,
+    /// Allow grpc-web calls from pages of this origin, e. g. `https://example.com`,
+    /// or `*` for any origin.
+    ///
+    /// CORS preflight `OPTIONS` requests are answered instead of rejected
+    /// with HTTP 405, and responses allow the origin to read `grpc-status`
+    /// and `grpc-message`. Ignored unless `grpc_web` is enabled.
+    pub grpc_web_cors_origin: Option<String>,
+    /// Reject requests with `RESOURCE_EXHAUSTED` when this many requests
+    /// are already processed by the server.
+    ///
+    /// Limit is global, number of streams per connection is limited
+    /// by HTTP/2 settings.
+    pub max_concurrent_requests: Option<usize>,
+    /// Treat requests with any HTTP method and `content-type` as gRPC requests.
+    ///
+    /// By default non-`POST` requests are rejected with HTTP 405,
+    /// and requests without gRPC `content-type` are rejected with HTTP 415.
+    /// Useful for testing with generic HTTP/2 clients.
+    pub permissive_requests: bool,
+    /// Log HEADERS, DATA and reset events of each gRPC request
+    /// together with decoded gRPC message boundaries at `info` level.
+    ///
+    /// Meant for diagnosing protocol-level interop problems, very verbose.
+    pub debug_frames: bool,
+    /// What to do when method handler panics, by default `INTERNAL` is sent to client.
+    pub panic_policy: PanicPolicy,
+    /// Cancel handler response and fail the call with `DEADLINE_EXCEEDED`
+    /// when the response headers or the next message are not produced in this time,
+    /// and notify `ServerWatchdogHandler`. May be overridden per method
+    /// with `ServerMethod::with_watchdog_timeout`.
+    ///
+    /// Applies to responses returned by handlers of generated async services
+    /// and passed to `ServerHandlerContext::pump_response`. Handlers writing
+    /// to response sink directly, and handlers blocking a thread, are not watched.
+    pub handler_watchdog_timeout: Option<Duration>,
+    /// Number of event loop threads accepting and serving connections, one by default.
+    ///
+    /// Each additional loop listens on the same TCP port with `SO_REUSEPORT`,
+    /// so connections are distributed among loops by OS. Only Linux distributes
+    /// connections among sockets sharing a port, so on other systems
+    /// one loop is used. Ignored for unix sockets and when server is run
+    /// on external event loop.
+    pub event_loop_threads: Option<usize>,
+    /// Run method handlers on a pool of this many threads instead of event loop threads.
+    ///
+    /// Methods assigned own pool with `ServerMethod::with_cpu_pool` are run on that pool.
+    pub worker_threads: Option<usize>,
+    /// Bind listening sockets with `SO_REUSEPORT`, so several processes
+    /// can serve the same port, e. g. during a graceful restart.
+    pub reuse_port: bool,
+    /// Fail request with `RESOURCE_EXHAUSTED` when client sends
+    /// more than this many messages in a single request stream.
+    pub max_request_messages: Option<u64>,
+    /// Fail request with `RESOURCE_EXHAUSTED` when a request message
+    /// with frame header would not fit into this many bytes.
+    ///
+    /// Request messages not yet taken by handler are bounded by flow control
+    /// window, because window is increased only as handler takes messages,
+    /// so a slow handler pushes back on the client. A message larger than
+    /// the window is buffered until it is complete, this limits such buffer.
+    /// Checked as soon as frame header is received.
+    ///
+    /// JSON gateway request bodies are buffered whole, they are limited
+    /// to 4 MiB when this is not set.
+    pub max_request_buffer_bytes: Option<usize>,
+    /// Fail request with `DEADLINE_EXCEEDED` when the next request message
+    /// (or the end of request stream) is not received in this time.
+    ///
+    /// Protects from clients which open a stream and then send data
+    /// slowly or not at all, holding server resources indefinitely.
+    ///
+    /// Timer starts when request HEADERS are dispatched to the method handler.
+    pub request_message_timeout: Option<Duration>,
+    /// Attach generated `CallId` to requests which do not have one,
+    /// so every access log record and `ServerHandlerContext::call_id` has an id.
+    pub generate_call_id: bool,
+    /// Headers added to every response, e. g. `server` or security headers.
+    ///
+    /// Headers sent by the server itself or by method handler as metadata take precedence.
+    /// Values of keys ending with `-bin` are base64-encoded like other metadata.
+    pub custom_response_headers: Metadata,
+    /// Collect per-method latency and message size histograms,
+    /// see `Server::method_histograms`.
+    pub method_histograms: bool,
+    /// Log a warning with method name and duration for calls
+    /// which take longer than this to complete.
+    pub slow_call_threshold: Option<Duration>,
+    /// Add `grpc-accept-encoding` header listing supported message encodings
+    /// to every response.
+    ///
+    /// Only `identity` is supported until compression is implemented.
+    pub advertise_accept_encoding: bool,
+}
 
 impl ServerConf {
     pub fn new() -> ServerConf {
@@ -75,6 +299,20 @@ impl ServerConf {
 pub struct ServerBuilder<A: tls_api::TlsAcceptor = tls_api_stub::TlsAcceptor> {
     pub http: httpbis::ServerBuilder<A>,
     pub conf: ServerConf,
+    unix_socket: Option<PathBuf>,
+    services: Vec<ServerServiceDefinition>,
+    json_gateways: Vec<(String, JsonGateway)>,
+    stats_handler: Option<Arc<ServerStatsHandler>>,
+    access_logger: Option<Arc<AccessLogger>>,
+    channelz: Option<Arc<Channelz>>,
+    auth_handler: Option<Arc<ServerAuthHandler>>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    fallback: Option<ServerMethod>,
+    http_fallback: Option<Arc<httpbis::ServerHandler>>,
+    panic_handler: Option<Arc<ServerPanicHandler>>,
+    watchdog_handler: Option<Arc<ServerWatchdogHandler>>,
+    event_loop: Option<Remote>,
+    listeners: Vec<Box<AdditionalListener>>,
 }
 
 impl ServerBuilder<tls_api_stub::TlsAcceptor> {
@@ -88,58 +326,636 @@ impl<A: tls_api::TlsAcceptor> ServerBuilder<A> {
         ServerBuilder {
             http: httpbis::ServerBuilder::new(),
             conf: ServerConf::new(),
+            unix_socket: None,
+            services: Vec::new(),
+            json_gateways: Vec::new(),
+            stats_handler: None,
+            access_logger: None,
+            channelz: None,
+            auth_handler: None,
+            rate_limiter: None,
+            fallback: None,
+            http_fallback: None,
+            panic_handler: None,
+            watchdog_handler: None,
+            event_loop: None,
+            listeners: Vec::new(),
         }
     }
 
+    /// Listen on unix domain socket.
+    ///
+    /// Stale socket file left by previous process is removed,
+    /// and socket file is removed when server is dropped.
     #[cfg(unix)]
-    pub fn new_unix() -> ServerBuilder<A> {
-        ServerBuilder {
-            http: httpbis::ServerBuilder::new(),
-            conf: ServerConf::new(),
+    pub fn set_unix_addr(&mut self, path: &str) -> Result<()> {
+        use std::os::unix::fs::FileTypeExt;
+
+        if let Ok(file_metadata) = fs::symlink_metadata(path) {
+            if file_metadata.file_type().is_socket() {
+                fs::remove_file(path)?;
+            }
         }
+
+        self.http.set_unix_addr(path.to_owned())?;
+        self.unix_socket = Some(PathBuf::from(path));
+        Ok(())
+    }
+
+    /// Serve TLS with acceptor built from server certificate `builder`
+    /// with given settings.
+    pub fn tls_conf(&mut self, tls_conf: ServerTlsConf, builder: A::Builder) -> Result<()> {
+        self.http.set_tls(tls_conf.build_acceptor::<A>(builder)?);
+        Ok(())
     }
 
     pub fn add_service(&mut self, def: ServerServiceDefinition) {
-        self.http.service.set_service(
-            &def.prefix.clone(),
-            Arc::new(GrpcServerHandler {
-                service_definition: Arc::new(def),
-            }),
-        );
+        self.services.push(def);
+    }
+
+    /// Add several services, e. g. all services of a proto file
+    /// returned by generated `service_definitions` function.
+    pub fn add_services(&mut self, defs: Vec<ServerServiceDefinition>) {
+        self.services.extend(defs);
+    }
+
+    /// Observe calls of all services of this server.
+    pub fn set_stats_handler(&mut self, handler: Arc<ServerStatsHandler>) {
+        self.stats_handler = Some(handler);
+    }
+
+    /// Collect call counters and calls in progress of all services of this server,
+    /// see `Channelz`. Does not replace stats handler.
+    pub fn set_channelz(&mut self, channelz: Arc<Channelz>) {
+        self.channelz = Some(channelz);
+    }
+
+    /// Log completed calls of all services of this server.
+    pub fn set_access_logger(&mut self, logger: Arc<AccessLogger>) {
+        self.access_logger = Some(logger);
+    }
+
+    /// Authorize calls of all services of this server before they are dispatched.
+    pub fn set_auth_handler(&mut self, handler: Arc<ServerAuthHandler>) {
+        self.auth_handler = Some(handler);
+    }
+
+    /// Reject calls of all services exceeding rate limits
+    /// with `RESOURCE_EXHAUSTED`, see `RateLimiter`.
+    pub fn set_rate_limiter(&mut self, rate_limiter: RateLimiter) {
+        self.rate_limiter = Some(Arc::new(rate_limiter));
+    }
+
+    /// Handle calls of methods not registered in any service,
+    /// e. g. with a method created by `ServerMethod::raw` to build a proxy.
+    ///
+    /// By default such calls fail with `UNIMPLEMENTED`.
+    pub fn set_fallback_method(&mut self, method: ServerMethod) {
+        self.fallback = Some(method);
+    }
+
+    /// Handle requests which are not gRPC requests, e. g. `GET /healthz`,
+    /// so plain HTTP/2 probes can be served on the same port as gRPC.
+    ///
+    /// Requests without gRPC `content-type` or with method other than `POST`
+    /// are passed to this handler instead of being rejected.
+    pub fn set_http_fallback(&mut self, handler: Arc<httpbis::ServerHandler>) {
+        self.http_fallback = Some(handler);
+    }
+
+    /// Also accept connections on the address of given HTTP server builder,
+    /// e. g. `[::]` in addition to `0.0.0.0`, or a plaintext port of TLS server.
+    ///
+    /// Address, TLS and HTTP settings are taken from `http`,
+    /// while services and other settings of this builder are shared by all listeners.
+    pub fn add_listener<B: tls_api::TlsAcceptor>(&mut self, http: httpbis::ServerBuilder<B>) {
+        self.listeners.push(Box::new(http));
+    }
+
+    /// Run server (and all listeners) on given event loop
+    /// instead of spawning an event loop thread.
+    ///
+    /// Useful to embed server into an application which already runs
+    /// a `tokio-core` event loop, or to run server and client on a single thread
+    /// in tests. Server is not served until that loop is run.
+    pub fn set_event_loop(&mut self, event_loop: Remote) {
+        self.event_loop = Some(event_loop);
+    }
+
+    /// Observe panics of method handlers of all services of this server.
+    pub fn set_panic_handler(&mut self, handler: Arc<ServerPanicHandler>) {
+        self.panic_handler = Some(handler);
+    }
+
+    /// Observe handlers canceled by `ServerConf::handler_watchdog_timeout`.
+    pub fn set_watchdog_handler(&mut self, handler: Arc<ServerWatchdogHandler>) {
+        self.watchdog_handler = Some(handler);
+    }
+
+    /// Serve HTTP/JSON endpoints under given path prefix.
+    pub fn add_json_gateway(&mut self, prefix: &str, gateway: JsonGateway) {
+        self.json_gateways.push((prefix.to_owned(), gateway));
     }
 
     pub fn build(mut self) -> Result<Server> {
-        self.http.conf.thread_name = Some(
-            self.http
-                .conf
-                .thread_name
-                .unwrap_or_else(|| "grpc-server-loop".to_owned()),
-        );
+        let in_flight = InFlightRequests::new();
+        let draining = Draining::new();
+        let events = ServerEventSubscribers::default();
+        let histograms = match self.conf.method_histograms {
+            true => Some(MethodHistograms::new()),
+            false => None,
+        };
+        let worker_pool = self.conf.worker_threads.map(|threads| {
+            futures_cpupool::Builder::new()
+                .pool_size(threads.max(1))
+                .name_prefix("grpc-server-worker-")
+                .create()
+        });
+        let event_loop_threads = match self.event_loop {
+            Some(..) => 1,
+            None if cfg!(target_os = "linux") => self.conf.event_loop_threads.unwrap_or(1).max(1),
+            None => {
+                if self.conf.event_loop_threads.unwrap_or(1) > 1 {
+                    warn!("event_loop_threads is only supported on Linux, using one loop");
+                }
+                1
+            }
+        };
+        let shared = Arc::new(ServerShared {
+            response_headers: Arc::new(response_headers(&self.conf)),
+            conf: self.conf,
+            worker_pool,
+            in_flight: in_flight.clone(),
+            draining: draining.clone(),
+            stats_handler: self.stats_handler,
+            access_logger: self.access_logger,
+            channelz: self.channelz,
+            events: events.clone(),
+            histograms: histograms.clone(),
+            auth_handler: self.auth_handler,
+            rate_limiter: self.rate_limiter,
+            fallback: self.fallback,
+            http_fallback: self.http_fallback,
+            panic_handler: self.panic_handler,
+            watchdog_handler: self.watchdog_handler,
+        });
+        let services: Vec<Arc<ServerServiceDefinition>> =
+            self.services.into_iter().map(Arc::new).collect();
+        let routes = Arc::new(ServiceRoutes::new(shared.clone()));
+        for def in &services {
+            routes.add(def.clone())?;
+        }
+        let handlers = ServerHandlers {
+            root: match shared.fallback {
+                Some(..) => Some(Arc::new(GrpcServerHandler {
+                    service_definition: Arc::new(ServerServiceDefinition::new("/", Vec::new())),
+                    shared: shared.clone(),
+                })),
+                None => None,
+            },
+            routes: routes.clone(),
+            json_gateways: self
+                .json_gateways
+                .into_iter()
+                .map(|(prefix, gateway)| {
+                    let handler = Arc::new(JsonGatewayHandler {
+                        routes: gateway.routes,
+                        services: services.clone(),
+                        shared: shared.clone(),
+                    });
+                    (prefix, handler)
+                })
+                .collect(),
+            http_fallback: shared.http_fallback.clone(),
+            response_headers: shared.response_headers.clone(),
+            event_loop: self.event_loop,
+            reuse_port: shared.conf.reuse_port,
+        };
+
+        if event_loop_threads > 1 {
+            self.http.conf.reuse_port = Some(true);
+        }
+        let http_conf = self.http.conf.clone();
+        let tls = self.http.tls.clone();
+
+        handlers.register(&mut self.http);
+        let server = self.http.build()?;
+
+        let mut listeners = Vec::new();
+        if let AnySocketAddr::Inet(addr) = *server.local_addr() {
+            // additional loops bind the port assigned to the first one,
+            // so it works with port 0 too
+            for _ in 1..event_loop_threads {
+                let mut http = httpbis::ServerBuilder::<A>::new();
+                http.set_addr(addr)?;
+                http.conf = http_conf.clone();
+                http.tls = tls.clone();
+                handlers.register(&mut http);
+                listeners.push(http.build()?);
+            }
+        }
+        for listener in self.listeners {
+            listeners.push(listener.start(&handlers)?);
+        }
 
         Ok(Server {
-            server: self.http.build()?,
+            server,
+            listeners,
+            unix_socket: self.unix_socket,
+            in_flight,
+            draining,
+            events,
+            histograms,
+            routes,
         })
     }
 }
 
+/// Listener added with `ServerBuilder::add_listener`,
+/// type-erased because it may use different TLS acceptor.
+trait AdditionalListener {
+    fn start(self: Box<Self>, handlers: &ServerHandlers) -> Result<httpbis::Server>;
+}
+
+impl<B: tls_api::TlsAcceptor> AdditionalListener for httpbis::ServerBuilder<B> {
+    fn start(self: Box<Self>, handlers: &ServerHandlers) -> Result<httpbis::Server> {
+        let mut http = *self;
+        handlers.register(&mut http);
+        Ok(http.build()?)
+    }
+}
+
+/// Request handlers shared by all listeners of a server
+struct ServerHandlers {
+    /// Handler of paths not matching any service, set if server has fallback method
+    root: Option<Arc<GrpcServerHandler>>,
+    routes: Arc<ServiceRoutes>,
+    json_gateways: Vec<(String, Arc<JsonGatewayHandler>)>,
+    http_fallback: Option<Arc<httpbis::ServerHandler>>,
+    response_headers: Arc<httpbis::Headers>,
+    event_loop: Option<Remote>,
+    /// `ServerConf::reuse_port`, applied to all listeners
+    reuse_port: bool,
+}
+
+impl ServerHandlers {
+    fn register<B: tls_api::TlsAcceptor>(&self, http: &mut httpbis::ServerBuilder<B>) {
+        // gRPC services are looked up in the route table on each request,
+        // so they can be added and removed while server is running
+        http.service.set_service(
+            "/",
+            Arc::new(ServiceRouter {
+                routes: self.routes.clone(),
+                root: self.root.clone(),
+                http_fallback: self.http_fallback.clone(),
+                response_headers: self.response_headers.clone(),
+            }),
+        );
+        for &(ref prefix, ref handler) in &self.json_gateways {
+            http.service.set_service(prefix, handler.clone());
+        }
+
+        if http.event_loop.is_none() {
+            http.event_loop = self.event_loop.clone();
+        }
+
+        if self.reuse_port {
+            http.conf.reuse_port = Some(true);
+        }
+
+        http.conf.thread_name = Some(
+            http.conf
+                .thread_name
+                .take()
+                .unwrap_or_else(|| "grpc-server-loop".to_owned()),
+        );
+    }
+}
+
 #[derive(Debug)]
 pub struct Server {
     server: httpbis::Server,
+    /// Servers of listeners added with `ServerBuilder::add_listener`
+    listeners: Vec<httpbis::Server>,
+    unix_socket: Option<PathBuf>,
+    in_flight: InFlightRequests,
+    draining: Draining,
+    events: ServerEventSubscribers,
+    histograms: Option<MethodHistograms>,
+    routes: Arc<ServiceRoutes>,
 }
 
 impl Server {
+    /// Address of the main listener.
+    ///
+    /// Listening socket is bound when server is built, so when server is configured
+    /// with port 0 (`server.http.set_port(0)`), this returns the port assigned by OS,
+    /// and clients may connect to it right away. This is the recommended way
+    /// to start servers in tests without port conflicts.
     pub fn local_addr(&self) -> &AnySocketAddr {
         self.server.local_addr()
     }
 
+    /// Addresses of all listeners, main listener first.
+    pub fn local_addrs(&self) -> Vec<&AnySocketAddr> {
+        let mut addrs = vec![self.server.local_addr()];
+        addrs.extend(self.listeners.iter().map(|l| l.local_addr()));
+        addrs
+    }
+
+    /// Services registered in this server, sorted by name.
+    pub fn services(&self) -> Vec<ServiceDescriptor> {
+        self.routes.descriptors()
+    }
+
+    /// Register a service while server is running.
+    ///
+    /// Service is available to new requests on all listeners.
+    /// Fails if a service with the same prefix is already registered.
+    /// Service is not served by JSON gateways added with `ServerBuilder::add_json_gateway`.
+    pub fn add_service(&self, def: ServerServiceDefinition) -> Result<()> {
+        self.routes.add(Arc::new(def))
+    }
+
+    /// Unregister a service by its name, e. g. `helloworld.Greeter` or `/helloworld.Greeter`.
+    ///
+    /// New requests to the service are replied with `UNIMPLEMENTED`
+    /// (or dispatched to the fallback method), calls in progress are completed.
+    /// Returns `false` if service is not registered.
+    pub fn remove_service(&self, name: &str) -> bool {
+        if name.starts_with('/') {
+            self.routes.remove(name)
+        } else {
+            self.routes.remove(&format!("/{}", name))
+        }
+    }
+
     pub fn is_alive(&self) -> bool {
-        self.server.is_alive()
+        self.server.is_alive() && self.listeners.iter().all(|l| l.is_alive())
+    }
+
+    /// Counter of requests currently processed by this server.
+    pub fn in_flight_requests(&self) -> InFlightRequests {
+        self.in_flight.clone()
+    }
+
+    /// Reject new calls with `UNAVAILABLE` and `x-server-draining` metadata,
+    /// while calls in progress, including long-lived streams, continue.
+    ///
+    /// Unlike dropping the server, connections stay open, so a load balancer
+    /// can take the backend out of rotation before it is shut down, e. g. when
+    /// it watches health checks served by a handler which checks `is_draining`.
+    /// grpc-rust clients send no more calls over a connection after
+    /// a call on it is rejected, and open a new connection for the next call.
+    /// Draining is stopped with `set_draining(false)`.
+    pub fn set_draining(&self, draining: bool) {
+        self.draining.set(draining);
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.get()
+    }
+
+    /// Subscribe to call lifecycle events, e. g. to build a dashboard.
+    ///
+    /// Only calls started after subscription are reported.
+    pub fn events(&self) -> ServerEvents {
+        self.events.subscribe()
+    }
+
+    /// Latency and message size histograms of methods,
+    /// `None` unless `ServerConf::method_histograms` is enabled.
+    pub fn method_histograms(&self) -> Option<&MethodHistograms> {
+        self.histograms.as_ref()
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.events.close();
+        if let Some(ref path) = self.unix_socket {
+            if let Err(e) = fs::remove_file(path) {
+                warn!("failed to remove unix socket {}: {}", path.display(), e);
+            }
+        }
     }
 }
 
+/// Headers added to every response
+fn response_headers(conf: &ServerConf) -> httpbis::Headers {
+    let mut headers = conf.custom_response_headers.clone().into_headers();
+    if let (true, Some(origin)) = (conf.grpc_web, conf.grpc_web_cors_origin.as_ref()) {
+        headers.extend(cors_headers(origin));
+    }
+    if conf.advertise_accept_encoding {
+        headers.add_header(httpbis::Header::new(
+            HEADER_GRPC_ACCEPT_ENCODING,
+            SUPPORTED_GRPC_ENCODINGS,
+        ));
+    }
+    headers
+}
+
+/// State shared by request handlers of a server
+pub(crate) struct ServerShared {
+    pub conf: ServerConf,
+    /// `ServerConf::custom_response_headers` and `grpc-accept-encoding`
+    pub response_headers: Arc<httpbis::Headers>,
+    /// Pool of `ServerConf::worker_threads`
+    pub worker_pool: Option<CpuPool>,
+    pub in_flight: InFlightRequests,
+    /// Set by `Server::set_draining`
+    pub draining: Draining,
+    pub stats_handler: Option<Arc<ServerStatsHandler>>,
+    pub access_logger: Option<Arc<AccessLogger>>,
+    /// Subscribers of `Server::events`
+    pub events: ServerEventSubscribers,
+    /// Set if `ServerConf::method_histograms` is enabled
+    pub histograms: Option<MethodHistograms>,
+    pub channelz: Option<Arc<Channelz>>,
+    pub auth_handler: Option<Arc<ServerAuthHandler>>,
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+    /// Handler of methods not registered in any service
+    pub fallback: Option<ServerMethod>,
+    /// Handler of requests which are not gRPC requests
+    pub http_fallback: Option<Arc<httpbis::ServerHandler>>,
+    pub panic_handler: Option<Arc<ServerPanicHandler>>,
+    pub watchdog_handler: Option<Arc<ServerWatchdogHandler>>,
+}
+
+impl ServerShared {
+    pub fn call_stats(&self, method: &str, call_id: Option<CallId>) -> Option<ServerCallStats> {
+        if self.stats_handler.is_none()
+            && self.access_logger.is_none()
+            && self.events.is_empty()
+            && self.histograms.is_none()
+            && self.channelz.is_none()
+            && self.conf.slow_call_threshold.is_none()
+        {
+            return None;
+        }
+        Some(ServerCallStats::started(self, method, call_id))
+    }
+
+    /// Call id sent by client, or generated one added to request metadata
+    /// if `ServerConf::generate_call_id` is set
+    pub fn call_id(&self, metadata: &mut Metadata) -> Option<CallId> {
+        match CallId::from_metadata(metadata) {
+            Some(call_id) => Some(call_id),
+            None if self.conf.generate_call_id => {
+                let call_id = CallId::generate();
+                call_id.inject(metadata);
+                Some(call_id)
+            }
+            None => None,
+        }
+    }
+
+    /// Add response headers of the server to a response sent without response sink
+    pub fn response_message(
+        &self,
+        mut message: httpbis::SimpleHttpMessage,
+    ) -> httpbis::SimpleHttpMessage {
+        add_missing_headers(&mut message.headers, &self.response_headers);
+        message
+    }
+
+    pub fn panic_guard(&self) -> PanicGuard {
+        PanicGuard {
+            policy: self.conf.panic_policy,
+            handler: self.panic_handler.clone(),
+        }
+    }
+
+    pub fn watchdog(&self) -> Watchdog {
+        Watchdog {
+            timeout: self.conf.handler_watchdog_timeout,
+            handler: self.watchdog_handler.clone(),
+        }
+    }
+
+    /// Checks of a call before it is dispatched to method handler,
+    /// shared by gRPC and JSON gateway requests: draining, auth handler,
+    /// rate limiter and `ServerConf::max_concurrent_requests`, in this order.
+    ///
+    /// Returns guard of the call counted as in flight, or status and metadata
+    /// to reply with.
+    pub fn admit(
+        &self,
+        method: &str,
+        metadata: &Metadata,
+    ) -> ::std::result::Result<InFlightGuard, (Status, Metadata)> {
+        if self.draining.get() {
+            return Err((
+                Status::new(GrpcStatus::Unavailable, "server is draining"),
+                draining_metadata(),
+            ));
+        }
+
+        if let Some((status, message)) = self.authorize(method, metadata) {
+            return Err((Status::new(status, message), Metadata::new()));
+        }
+
+        if let Some(retry_after) = self.rate_limit(method, metadata) {
+            return Err((
+                Status::new(
+                    GrpcStatus::ResourceExhausted,
+                    format!("rate limit exceeded for {}", method),
+                ),
+                retry_after_metadata(retry_after),
+            ));
+        }
+
+        match self.in_flight.try_start(self.conf.max_concurrent_requests) {
+            Some(in_flight) => Ok(in_flight),
+            None => Err((
+                Status::new(
+                    GrpcStatus::ResourceExhausted,
+                    "too many concurrent requests",
+                ),
+                Metadata::new(),
+            )),
+        }
+    }
+
+    /// Status and message to reply with if the call is rejected by auth handler
+    fn authorize(&self, method: &str, metadata: &Metadata) -> Option<(GrpcStatus, String)> {
+        let auth_handler = self.auth_handler.as_ref()?;
+        match auth_handler.authorize(method, metadata) {
+            Ok(()) => None,
+            Err(e) => {
+                debug!("call of {} rejected: {}", method, e);
+                Some(rejection(e))
+            }
+        }
+    }
+
+    /// Time after which call may be retried if the call exceeds rate limit
+    fn rate_limit(&self, method: &str, metadata: &Metadata) -> Option<Duration> {
+        let rate_limiter = self.rate_limiter.as_ref()?;
+        match rate_limiter.acquire(method, metadata) {
+            Ok(()) => None,
+            Err(retry_after) => {
+                debug!("call of {} exceeds rate limit", method);
+                Some(retry_after)
+            }
+        }
+    }
+}
+
+/// Dispatches requests to services of the route table,
+/// replies `UNIMPLEMENTED` to requests to paths not matching any service
+struct ServiceRouter {
+    routes: Arc<ServiceRoutes>,
+    /// Set if server has fallback method
+    root: Option<Arc<GrpcServerHandler>>,
+    http_fallback: Option<Arc<httpbis::ServerHandler>>,
+    response_headers: Arc<httpbis::Headers>,
+}
+
+impl httpbis::ServerHandler for ServiceRouter {
+    fn start_request(
+        &self,
+        context: httpbis::ServerHandlerContext,
+        req: httpbis::ServerRequest,
+        mut resp: httpbis::ServerResponse,
+    ) -> httpbis::Result<()> {
+        let handler = self
+            .routes
+            .find(req.headers.path())
+            .or_else(|| self.root.clone());
+        if let Some(handler) = handler {
+            return handler.start_request(context, req, resp);
+        }
+        if let Some(ref http_fallback) = self.http_fallback {
+            if !is_grpc_request(&req.headers) {
+                return http_fallback.start_request(context, req, resp);
+            }
+        }
+        debug!("unknown service: {}", req.headers.path());
+        let mut message = grpc_error_message(
+            GrpcStatus::Unimplemented,
+            &format!("unknown service for path {}", req.headers.path()),
+        );
+        add_missing_headers(&mut message.headers, &self.response_headers);
+        resp.send_message(message)?;
+        Ok(())
+    }
+}
+
+/// `POST` request with gRPC or gRPC-Web `content-type`
+fn is_grpc_request(headers: &httpbis::Headers) -> bool {
+    headers.method() == "POST"
+        && headers
+            .get_opt("content-type")
+            .and_then(GrpcProtocol::from_content_type)
+            .is_some()
+}
+
 /// Implementation of gRPC over http2 HttpService
 struct GrpcServerHandler {
     service_definition: Arc<ServerServiceDefinition>,
+    shared: Arc<ServerShared>,
 }
 
 impl httpbis::ServerHandler for GrpcServerHandler {
@@ -152,35 +968,163 @@ impl httpbis::ServerHandler for GrpcServerHandler {
         // TODO: clone
         let path = req.headers.path().to_owned();
 
+        let frame_log = match self.shared.conf.debug_frames {
+            true => Some(FrameLog::server(&path)),
+            false => None,
+        };
+        if let Some(ref frame_log) = frame_log {
+            frame_log.headers("recv", &req.headers);
+        }
+
+        let permissive = self.shared.conf.permissive_requests;
+
+        if self.shared.conf.grpc_web
+            && self.shared.conf.grpc_web_cors_origin.is_some()
+            && req.headers.method() == "OPTIONS"
+        {
+            resp.send_message(
+                self.shared
+                    .response_message(cors_preflight_message(&req.headers)),
+            )?;
+            return Ok(());
+        }
+
+        if let Some(ref http_fallback) = self.shared.http_fallback {
+            if !permissive && !is_grpc_request(&req.headers) {
+                return http_fallback.start_request(context, req, resp);
+            }
+        }
+
+        if !permissive && req.headers.method() != "POST" {
+            resp.send_message(self.shared.response_message(http_error_message(
+                405,
+                "gRPC requests must use POST method",
+            )))?;
+            return Ok(());
+        }
+
+        let protocol = match req
+            .headers
+            .get_opt("content-type")
+            .and_then(GrpcProtocol::from_content_type)
+        {
+            Some(protocol) => protocol,
+            None if permissive => GrpcProtocol::Grpc,
+            None => {
+                resp.send_message(self.shared.response_message(http_error_message(
+                    415,
+                    "content-type must be application/grpc",
+                )))?;
+                return Ok(());
+            }
+        };
+
         // TODO: clone
-        let metadata = match Metadata::from_headers(req.headers.clone()) {
+        let mut metadata = match Metadata::from_headers(req.headers.clone()) {
             Ok(metadata) => metadata,
             Err(_) => {
-                resp.send_message(grpc_error_message("decode metadata error"))?;
+                resp.send_message(self.shared.response_message(grpc_error_message(
+                    GrpcStatus::Internal,
+                    "decode metadata error",
+                )))?;
                 return Ok(());
             }
         };
 
-        let req = ServerRequestUntyped { req };
+        // compression is not implemented, but identity encoding is allowed
+        if let Some(encoding) = req.headers.get_opt(HEADER_GRPC_ENCODING) {
+            if encoding != "identity" {
+                resp.send_message(
+                    self.shared
+                        .response_message(grpc_unsupported_encoding_message(encoding)),
+                )?;
+                return Ok(());
+            }
+        }
+
+        if protocol.is_web() && !self.shared.conf.grpc_web {
+            resp.send_message(self.shared.response_message(grpc_error_message(
+                GrpcStatus::Internal,
+                "grpc-web is not enabled",
+            )))?;
+            return Ok(());
+        }
+
+        let in_flight =
+            match self.shared.admit(&path, &metadata) {
+                Ok(in_flight) => in_flight,
+                Err((status, metadata)) => {
+                    resp.send_message(self.shared.response_message(
+                        grpc_error_message_with_metadata(status.code, &status.message, metadata),
+                    ))?;
+                    return Ok(());
+                }
+            };
+
+        let deadline = req
+            .headers
+            .get_opt(HEADER_GRPC_TIMEOUT)
+            .and_then(parse_grpc_timeout)
+            .map(|timeout| Instant::now() + timeout);
 
+        let call_id = self.shared.call_id(&mut metadata);
+        if let Some(ref call_id) = call_id {
+            debug!("start call {} call_id={}", path, call_id);
+        }
+
+        let stats = self.shared.call_stats(&path, call_id);
+
+        let req = ServerRequestUntyped {
+            req,
+            protocol,
+            json: None,
+            stats: stats.clone(),
+            frame_log: frame_log.clone(),
+            limits: RequestLimits::from_conf(&self.shared.conf),
+            loop_remote: context.loop_remote(),
+        };
+
+        let response_headers = self.shared.response_headers.clone();
         resp.set_drop_callback(move |resp| {
-            Ok(resp.send_message(grpc_error_message(
+            let mut message = grpc_error_message(
+                GrpcStatus::Internal,
                 "grpc server handler did not close the sender",
-            ))?)
+            );
+            add_missing_headers(&mut message.headers, &response_headers);
+            Ok(resp.send_message(message)?)
         });
 
+        let dropped_sink = DroppedSink::default();
+
         let resp = ServerResponseUntypedSink {
-            common: SinkCommonUntyped { http: resp },
+            common: CommonSlot::new(resp),
+            protocol,
+            json: None,
+            response_headers: self.shared.response_headers.clone(),
+            in_flight: Some(in_flight),
+            stats,
+            frame_log,
+            dropped: dropped_sink.clone(),
         };
 
         let context = ServerHandlerContext {
             ctx: context,
             metadata,
+            method: path.clone(),
+            cpu_pool: self.shared.worker_pool.clone(),
+            deadline,
+            panic_guard: self.shared.panic_guard(),
+            watchdog: self.shared.watchdog(),
+            dropped_sink,
         };
 
-        // TODO: catch unwind
-        self.service_definition
-            .handle_method(&path, context, req, resp)?;
+        self.service_definition.handle_method(
+            &path,
+            self.shared.fallback.as_ref(),
+            context,
+            req,
+            resp,
+        )?;
 
         Ok(())
     }
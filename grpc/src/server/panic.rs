@@ -0,0 +1,121 @@
+//! Panics in method handlers.
+
+use std::any::Any;
+use std::panic;
+use std::panic::AssertUnwindSafe;
+use std::process;
+use std::sync::Arc;
+
+use error;
+use futures::Async;
+use futures::Poll;
+use result;
+
+/// What server does when method handler panics.
+///
+/// Panics are caught both in handler functions and while polling
+/// responses passed to `ServerHandlerContext::pump*` and `spawn_poll_fn`.
+///
+/// There is no policy to close the connection of the call: calls sharing
+/// a connection are independent, and a panic leaves no state behind
+/// which would make the connection unusable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PanicPolicy {
+    /// Reply `INTERNAL` to the call and continue serving other calls.
+    RespondInternal,
+    /// Abort the process, for services which prefer to fail fast and be restarted.
+    Abort,
+}
+
+impl Default for PanicPolicy {
+    fn default() -> PanicPolicy {
+        PanicPolicy::RespondInternal
+    }
+}
+
+/// Observer of method handler panics, e. g. to report them to crash reporting service.
+///
+/// Registered with `ServerBuilder::set_panic_handler`. Called before
+/// `ServerConf::panic_policy` is applied, so it is called even if process is aborted.
+///
+/// Panic location and backtrace are printed by the standard panic hook
+/// (with `RUST_BACKTRACE=1`) before this handler is called.
+pub trait ServerPanicHandler: Send + Sync + 'static {
+    fn panicked(&self, method: &str, message: &str);
+}
+
+/// Message passed to `panic!`, if it is a string.
+fn panic_message(payload: &(Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&'static str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "Box<Any>"
+    }
+}
+
+/// Catches panics of handler invocations and applies panic policy.
+#[derive(Clone, Default)]
+pub(crate) struct PanicGuard {
+    pub policy: PanicPolicy,
+    pub handler: Option<Arc<ServerPanicHandler>>,
+}
+
+impl PanicGuard {
+    pub fn call<F>(&self, method: &str, f: F) -> result::Result<()>
+    where
+        F: FnOnce() -> result::Result<()>,
+    {
+        match panic::catch_unwind(AssertUnwindSafe(f)) {
+            Ok(r) => r,
+            Err(payload) => {
+                self.panicked(method, payload);
+                // response sink is dropped while unwinding,
+                // and its drop callback sends `INTERNAL` to client
+                Ok(())
+            }
+        }
+    }
+
+    /// Poll a handler response, panic completes the response,
+    /// and the response sink dropped with it sends `INTERNAL` to client.
+    pub fn poll<F>(&self, method: &str, f: F) -> Poll<(), error::Error>
+    where
+        F: FnOnce() -> Poll<(), error::Error>,
+    {
+        match panic::catch_unwind(AssertUnwindSafe(f)) {
+            Ok(r) => r,
+            Err(payload) => {
+                self.panicked(method, payload);
+                Ok(Async::Ready(()))
+            }
+        }
+    }
+
+    fn panicked(&self, method: &str, payload: Box<Any + Send>) {
+        let message = panic_message(&*payload);
+        error!("handler of {} panicked: {}", method, message);
+        if let Some(ref handler) = self.handler {
+            handler.panicked(method, message);
+        }
+
+        match self.policy {
+            PanicPolicy::Abort => process::abort(),
+            PanicPolicy::RespondInternal => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn message() {
+        let payload = panic::catch_unwind(|| panic!("static")).unwrap_err();
+        assert_eq!("static", panic_message(&*payload));
+        let payload = panic::catch_unwind(|| panic!("formatted {}", 1)).unwrap_err();
+        assert_eq!("formatted 1", panic_message(&*payload));
+    }
+}
@@ -0,0 +1,341 @@
+//! Token bucket rate limiting of incoming calls.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use bytes::Bytes;
+
+use proto::metadata::Metadata;
+use proto::metadata::MetadataKey;
+
+/// Metadata with number of seconds after which rejected call may be retried.
+pub(crate) const HEADER_RETRY_AFTER: &str = "retry-after";
+/// Server pushback in milliseconds, understood by gRPC retry implementations.
+pub(crate) const HEADER_GRPC_RETRY_PUSHBACK_MS: &str = "grpc-retry-pushback-ms";
+
+/// Buckets are pruned when their number exceeds this, and calls
+/// with new keys share an overflow bucket if pruning does not help
+const MAX_BUCKETS: usize = 10_000;
+/// Minimum interval between prunes, so a full map is not scanned on every call
+const PRUNE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Token bucket parameters.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    /// Tokens added to the bucket per second, a call takes one token.
+    pub per_second: f64,
+    /// Bucket capacity, i. e. number of calls allowed in a burst.
+    pub burst: u32,
+}
+
+impl RateLimit {
+    /// Allow `per_second` calls per second with bursts of the same size.
+    ///
+    /// Panics if `per_second` is zero.
+    pub fn per_second(per_second: u32) -> RateLimit {
+        assert!(per_second > 0, "rate limit must allow at least one call");
+        RateLimit {
+            per_second: per_second as f64,
+            burst: per_second,
+        }
+    }
+
+    pub fn with_burst(self, burst: u32) -> RateLimit {
+        RateLimit {
+            burst: burst.max(1),
+            ..self
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, limit: &RateLimit, now: Instant) {
+        if now > self.updated {
+            let elapsed = now - self.updated;
+            let elapsed = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+            self.tokens = (self.tokens + elapsed * limit.per_second).min(limit.burst as f64);
+            self.updated = now;
+        }
+    }
+}
+
+/// Rejects calls exceeding configured rate with `RESOURCE_EXHAUSTED`.
+///
+/// Registered with `ServerBuilder::set_rate_limiter`, checked after
+/// `ServerAuthHandler`, before the call is dispatched to the method handler.
+/// Rejected calls are replied with `retry-after` (seconds) and
+/// `grpc-retry-pushback-ms` metadata.
+///
+/// Peer address is not available to request handlers, so calls
+/// can be keyed by peer only with a metadata value, e. g. API key.
+#[derive(Debug)]
+pub struct RateLimiter {
+    default: Option<RateLimit>,
+    methods: HashMap<String, RateLimit>,
+    key_metadata: Option<String>,
+    buckets: Mutex<Buckets>,
+}
+
+/// Client part of bucket key
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum ClientKey {
+    /// Key metadata is not configured or not sent
+    None,
+    /// Value of key metadata
+    Value(Bytes),
+    /// Shared by new clients when there are too many buckets
+    Overflow,
+}
+
+/// Keyed by method (or `None` for the shared default bucket) and client key
+type BucketKey = (Option<String>, ClientKey);
+
+#[derive(Debug)]
+struct Buckets {
+    map: HashMap<BucketKey, Bucket>,
+    last_prune: Option<Instant>,
+}
+
+/// Builder of `RateLimiter`.
+#[derive(Debug, Default)]
+pub struct RateLimiterBuilder {
+    default: Option<RateLimit>,
+    methods: HashMap<String, RateLimit>,
+    key_metadata: Option<String>,
+}
+
+impl RateLimiterBuilder {
+    pub fn new() -> RateLimiterBuilder {
+        Default::default()
+    }
+
+    /// Limit of all calls of methods without own limit, taken together.
+    ///
+    /// Calls of such methods are not limited if default limit is not set.
+    pub fn default_limit(mut self, limit: RateLimit) -> RateLimiterBuilder {
+        self.default = Some(limit);
+        self
+    }
+
+    /// Limit of calls of given method, e. g. `/helloworld.Greeter/SayHello`.
+    pub fn method(mut self, method: &str, limit: RateLimit) -> RateLimiterBuilder {
+        self.methods.insert(method.to_owned(), limit);
+        self
+    }
+
+    /// Keep separate buckets per value of given request metadata,
+    /// e. g. `x-api-key`, so each client gets own limit.
+    ///
+    /// Calls without this metadata share a bucket.
+    pub fn key_by_metadata(mut self, key: &str) -> RateLimiterBuilder {
+        self.key_metadata = Some(key.to_owned());
+        self
+    }
+
+    pub fn build(self) -> RateLimiter {
+        RateLimiter {
+            default: self.default,
+            methods: self.methods,
+            key_metadata: self.key_metadata,
+            buckets: Mutex::new(Buckets {
+                map: HashMap::new(),
+                last_prune: None,
+            }),
+        }
+    }
+}
+
+impl RateLimiter {
+    pub fn builder() -> RateLimiterBuilder {
+        RateLimiterBuilder::new()
+    }
+
+    /// Take a token for the call, or return time after which a token is available.
+    pub(crate) fn acquire(&self, method: &str, metadata: &Metadata) -> Result<(), Duration> {
+        self.acquire_at(method, metadata, Instant::now())
+    }
+
+    fn acquire_at(&self, method: &str, metadata: &Metadata, now: Instant) -> Result<(), Duration> {
+        let (method, limit) = match self.methods.get(method) {
+            Some(limit) => (Some(method.to_owned()), limit),
+            None => match self.default {
+                Some(ref limit) => (None, limit),
+                None => return Ok(()),
+            },
+        };
+        let client = match self.key_metadata.as_ref().and_then(|key| metadata.get(key)) {
+            Some(value) => ClientKey::Value(Bytes::from(value)),
+            None => ClientKey::None,
+        };
+        let mut key = (method, client);
+
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.map.len() >= MAX_BUCKETS && !buckets.map.contains_key(&key) {
+            let prune = match buckets.last_prune {
+                Some(last_prune) => now >= last_prune + PRUNE_INTERVAL,
+                None => true,
+            };
+            if prune {
+                self.prune(&mut buckets.map, now);
+                buckets.last_prune = Some(now);
+            }
+            if buckets.map.len() >= MAX_BUCKETS {
+                key.1 = ClientKey::Overflow;
+            }
+        }
+        let bucket = buckets.map.entry(key).or_insert_with(|| Bucket {
+            tokens: limit.burst as f64,
+            updated: now,
+        });
+        bucket.refill(limit, now);
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+        if limit.per_second <= 0.0 {
+            return Err(Duration::from_secs(u32::max_value() as u64));
+        }
+        let wait = (1.0 - bucket.tokens) / limit.per_second;
+        Err(Duration::from_nanos((wait * 1e9).ceil() as u64))
+    }
+
+    /// Remove full buckets, they are recreated full when needed
+    fn prune(&self, buckets: &mut HashMap<BucketKey, Bucket>, now: Instant) {
+        buckets.retain(|&(ref method, _), bucket| {
+            let limit = match *method {
+                Some(ref method) => self.methods.get(method),
+                None => self.default.as_ref(),
+            };
+            match limit {
+                Some(limit) => {
+                    bucket.refill(limit, now);
+                    bucket.tokens < limit.burst as f64
+                }
+                None => false,
+            }
+        });
+    }
+}
+
+/// Metadata sent with rejection of a call which may be retried after `retry_after`
+pub(crate) fn retry_after_metadata(retry_after: Duration) -> Metadata {
+    let millis =
+        retry_after.as_secs() * 1000 + (retry_after.subsec_nanos() as u64 + 999_999) / 1_000_000;
+    let mut metadata = Metadata::new();
+    metadata.add(
+        MetadataKey::from(HEADER_RETRY_AFTER),
+        Bytes::from(format!("{}", (millis + 999) / 1000)),
+    );
+    metadata.add(
+        MetadataKey::from(HEADER_GRPC_RETRY_PUSHBACK_MS),
+        Bytes::from(format!("{}", millis)),
+    );
+    metadata
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn token_bucket() {
+        let limiter = RateLimiter::builder()
+            .method("/a/slow", RateLimit::per_second(2))
+            .build();
+        let metadata = Metadata::new();
+        let now = Instant::now();
+
+        assert_eq!(Ok(()), limiter.acquire_at("/a/slow", &metadata, now));
+        assert_eq!(Ok(()), limiter.acquire_at("/a/slow", &metadata, now));
+        assert_eq!(
+            Err(Duration::from_millis(500)),
+            limiter.acquire_at("/a/slow", &metadata, now)
+        );
+        let later = now + Duration::from_millis(500);
+        assert_eq!(Ok(()), limiter.acquire_at("/a/slow", &metadata, later));
+
+        // no default limit
+        for _ in 0..10 {
+            assert_eq!(Ok(()), limiter.acquire_at("/a/other", &metadata, now));
+        }
+    }
+
+    #[test]
+    fn keyed_by_metadata() {
+        let limiter = RateLimiter::builder()
+            .default_limit(RateLimit::per_second(1))
+            .key_by_metadata("x-api-key")
+            .build();
+        let now = Instant::now();
+        let mut a = Metadata::new();
+        a.add(MetadataKey::from("x-api-key"), Bytes::from("a"));
+        let mut b = Metadata::new();
+        b.add(MetadataKey::from("x-api-key"), Bytes::from("b"));
+
+        assert_eq!(Ok(()), limiter.acquire_at("/a/x", &a, now));
+        // default bucket is shared by methods
+        assert!(limiter.acquire_at("/a/y", &a, now).is_err());
+        assert_eq!(Ok(()), limiter.acquire_at("/a/y", &b, now));
+    }
+
+    #[test]
+    fn too_many_keys_share_overflow_bucket() {
+        let limiter = RateLimiter::builder()
+            .default_limit(RateLimit::per_second(1))
+            .key_by_metadata("x-api-key")
+            .build();
+        let now = Instant::now();
+        let key = |i: usize| {
+            let mut metadata = Metadata::new();
+            metadata.add(
+                MetadataKey::from("x-api-key"),
+                Bytes::from(format!("{}", i)),
+            );
+            metadata
+        };
+
+        for i in 0..MAX_BUCKETS {
+            assert_eq!(Ok(()), limiter.acquire_at("/a/x", &key(i), now));
+        }
+        // new keys share a bucket until existing buckets are refilled
+        assert_eq!(Ok(()), limiter.acquire_at("/a/x", &key(MAX_BUCKETS), now));
+        assert!(limiter
+            .acquire_at("/a/x", &key(MAX_BUCKETS + 1), now)
+            .is_err());
+        // known keys keep own buckets
+        assert!(limiter.acquire_at("/a/x", &key(0), now).is_err());
+        assert_eq!(MAX_BUCKETS + 1, limiter.buckets.lock().unwrap().map.len());
+
+        // refilled buckets are pruned
+        let later = now + Duration::from_secs(2);
+        assert_eq!(
+            Ok(()),
+            limiter.acquire_at("/a/x", &key(MAX_BUCKETS + 1), later)
+        );
+        assert_eq!(1, limiter.buckets.lock().unwrap().map.len());
+    }
+
+    #[test]
+    #[should_panic]
+    fn zero_rate() {
+        RateLimit::per_second(0);
+    }
+
+    #[test]
+    fn retry_after() {
+        let metadata = retry_after_metadata(Duration::from_millis(1500));
+        assert_eq!(Some(&b"2"[..]), metadata.get(HEADER_RETRY_AFTER));
+        assert_eq!(
+            Some(&b"1500"[..]),
+            metadata.get(HEADER_GRPC_RETRY_PUSHBACK_MS)
+        );
+    }
+}
@@ -0,0 +1,306 @@
+//! Request body of unary call received as a stream of chunks.
+
+use std::cmp;
+
+use bytes::Bytes;
+use futures::sync::mpsc;
+use futures::Async;
+use futures::Poll;
+use futures::Stream;
+use httpbis::Headers;
+use httpbis::ServerIncreaseInWindow;
+
+use common::frame_log::FrameLog;
+use error;
+use error::Error;
+use error::FrameError;
+use proto::grpc_frame::parse_grpc_frame_header;
+use proto::grpc_frame::GRPC_HEADER_LEN;
+use proto::grpc_web::GrpcWebTextDecoder;
+use result;
+use stats::ServerCallStats;
+use Metadata;
+
+/// Request of `MethodHandlerUnaryStreamBody` handler.
+pub struct ServerRequestBody {
+    pub metadata: Metadata,
+    /// Serialized request message split into chunks as received from network.
+    pub body: ServerRequestBodyStream,
+}
+
+pub(crate) enum BodyToStream {
+    Chunk(Bytes),
+    /// Bytes consumed from network, but not passed to handler (e. g. frame header)
+    Processed(usize),
+    End,
+    Error(error::Error),
+}
+
+/// Stream of serialized message chunks.
+///
+/// Flow control window is increased as chunks are taken from the stream,
+/// so client cannot send more than the window size ahead of handler.
+/// Stream fails if request does not contain exactly one message.
+pub struct ServerRequestBodyStream {
+    pub(crate) rx: mpsc::UnboundedReceiver<BodyToStream>,
+    pub(crate) increase_in_window: ServerIncreaseInWindow,
+}
+
+impl ServerRequestBodyStream {
+    fn processed(&mut self, size: usize) -> result::Result<()> {
+        // TODO: checked cast
+        self.increase_in_window.data_frame_processed(size as u32);
+        self.increase_in_window.increase_window_auto()?;
+        Ok(())
+    }
+}
+
+impl Stream for ServerRequestBodyStream {
+    type Item = Bytes;
+    type Error = error::Error;
+
+    fn poll(&mut self) -> Poll<Option<Bytes>, error::Error> {
+        loop {
+            let item = match self.rx.poll() {
+                Ok(Async::Ready(Some(item))) => item,
+                Ok(Async::Ready(None)) | Err(()) => {
+                    return Err(Error::Other("unexpected EOF"));
+                }
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+            };
+
+            match item {
+                BodyToStream::Chunk(chunk) => {
+                    self.processed(chunk.len())?;
+                    return Ok(Async::Ready(Some(chunk)));
+                }
+                BodyToStream::Processed(size) => {
+                    self.processed(size)?;
+                }
+                BodyToStream::End => return Ok(Async::Ready(None)),
+                BodyToStream::Error(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// Splits request body containing single message into message payload chunks.
+#[derive(Default)]
+struct UnaryBodyParser {
+    /// Partially received frame header
+    header: Vec<u8>,
+    /// Payload bytes of current message not received yet
+    remaining: usize,
+    /// Payload length, known after header is received
+    message_len: Option<usize>,
+}
+
+impl UnaryBodyParser {
+    fn data(&mut self, mut data: Bytes) -> result::Result<Vec<Bytes>> {
+        let mut chunks = Vec::new();
+        while !data.is_empty() {
+            if self.message_len.is_none() {
+                let take = cmp::min(GRPC_HEADER_LEN - self.header.len(), data.len());
+                self.header.extend_from_slice(&data.split_to(take));
+                if self.header.len() == GRPC_HEADER_LEN {
+                    let len = parse_grpc_frame_header(&self.header)?;
+                    self.message_len = Some(len);
+                    self.remaining = len;
+                }
+            } else if self.remaining != 0 {
+                let chunk = data.split_to(cmp::min(self.remaining, data.len()));
+                self.remaining -= chunk.len();
+                chunks.push(chunk);
+            } else {
+                return Err(Error::Other("unary request contains more than one message"));
+            }
+        }
+        Ok(chunks)
+    }
+
+    /// Length of the message if it is completely received.
+    fn end(&self) -> result::Result<usize> {
+        match self.message_len {
+            Some(len) if self.remaining == 0 => Ok(len),
+            Some(len) => Err(FrameError::Truncated {
+                expected: GRPC_HEADER_LEN + len,
+                received: GRPC_HEADER_LEN + len - self.remaining,
+            }
+            .into()),
+            None if self.header.is_empty() => Err(Error::Other("request message is not complete")),
+            None => Err(FrameError::Truncated {
+                expected: GRPC_HEADER_LEN,
+                received: self.header.len(),
+            }
+            .into()),
+        }
+    }
+}
+
+pub(crate) struct UnaryBodyHandler {
+    parser: UnaryBodyParser,
+    web_text_decoder: Option<GrpcWebTextDecoder>,
+    stats: Option<ServerCallStats>,
+    frame_log: Option<FrameLog>,
+    tx: mpsc::UnboundedSender<BodyToStream>,
+    /// Error or end of stream is sent
+    done: bool,
+}
+
+impl UnaryBodyHandler {
+    pub fn new(
+        web_text_decoder: Option<GrpcWebTextDecoder>,
+        stats: Option<ServerCallStats>,
+        frame_log: Option<FrameLog>,
+        tx: mpsc::UnboundedSender<BodyToStream>,
+    ) -> UnaryBodyHandler {
+        UnaryBodyHandler {
+            parser: UnaryBodyParser::default(),
+            web_text_decoder,
+            stats,
+            frame_log,
+            tx,
+            done: false,
+        }
+    }
+
+    fn send(&mut self, item: BodyToStream) {
+        if let BodyToStream::End | BodyToStream::Error(..) = item {
+            self.done = true;
+        }
+        // receiver may be dropped by handler which is not interested in body
+        let _ = self.tx.unbounded_send(item);
+    }
+
+    fn process(&mut self, data: Bytes) -> result::Result<()> {
+        let consumed = data.len();
+        let data = match self.web_text_decoder {
+            Some(ref mut decoder) => decoder.decode(&data)?,
+            None => data,
+        };
+        let had_header = self.parser.message_len.is_some();
+        let mut payload = 0;
+        for chunk in self.parser.data(data)? {
+            payload += chunk.len();
+            self.send(BodyToStream::Chunk(chunk));
+        }
+        if let (false, Some(len), Some(frame_log)) =
+            (had_header, self.parser.message_len, &self.frame_log)
+        {
+            frame_log.grpc_frame("recv", len);
+        }
+        if consumed > payload {
+            self.send(BodyToStream::Processed(consumed - payload));
+        }
+        Ok(())
+    }
+
+    fn end(&mut self) {
+        if self.done {
+            return;
+        }
+        let web_text_incomplete = self
+            .web_text_decoder
+            .as_ref()
+            .map_or(false, |d| !d.is_empty());
+        match self.parser.end() {
+            Ok(..) if web_text_incomplete => {
+                self.send(BodyToStream::Error(Error::Other(
+                    "incomplete grpc-web-text body",
+                )));
+            }
+            Ok(len) => {
+                if let Some(ref stats) = self.stats {
+                    stats.message_received(len);
+                }
+                self.send(BodyToStream::End);
+            }
+            Err(e) => self.send(BodyToStream::Error(e)),
+        }
+    }
+}
+
+impl httpbis::ServerStreamHandler for UnaryBodyHandler {
+    fn data_frame(&mut self, data: Bytes, end_stream: bool) -> httpbis::Result<()> {
+        if let Some(ref frame_log) = self.frame_log {
+            frame_log.data("recv", data.len());
+            if end_stream {
+                frame_log.end_stream("recv");
+            }
+        }
+        if self.done {
+            return Ok(());
+        }
+        if let Err(e) = self.process(data) {
+            self.send(BodyToStream::Error(e));
+            return Ok(());
+        }
+        if end_stream {
+            self.end();
+        }
+        Ok(())
+    }
+
+    fn trailers(&mut self, trailers: Headers) -> httpbis::Result<()> {
+        if let Some(ref frame_log) = self.frame_log {
+            frame_log.trailers("recv", &trailers);
+        }
+        // there are no trailers in gRPC request
+        drop(trailers);
+        self.end();
+        Ok(())
+    }
+
+    fn error(&mut self, error: httpbis::Error) -> httpbis::Result<()> {
+        if let Some(ref frame_log) = self.frame_log {
+            frame_log.error("recv", &error);
+        }
+        if !self.done {
+            self.send(BodyToStream::Error(error.into()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parser_chunks() {
+        let mut parser = UnaryBodyParser::default();
+        assert!(parser
+            .data(Bytes::from_static(b"\0\0\0"))
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            vec![Bytes::from_static(b"ab")],
+            parser.data(Bytes::from_static(b"\0\x05ab")).unwrap()
+        );
+        assert!(parser.end().is_err());
+        assert_eq!(
+            vec![Bytes::from_static(b"cde")],
+            parser.data(Bytes::from_static(b"cde")).unwrap()
+        );
+        assert_eq!(5, parser.end().unwrap());
+    }
+
+    #[test]
+    fn parser_empty_message() {
+        let mut parser = UnaryBodyParser::default();
+        assert!(parser.end().is_err());
+        assert!(parser
+            .data(Bytes::from_static(b"\0\0\0\0\0"))
+            .unwrap()
+            .is_empty());
+        assert_eq!(0, parser.end().unwrap());
+    }
+
+    #[test]
+    fn parser_two_messages() {
+        let mut parser = UnaryBodyParser::default();
+        assert!(parser
+            .data(Bytes::from_static(b"\0\0\0\0\x01a\0\0\0\0\x01b"))
+            .is_err());
+    }
+}
@@ -1,15 +1,40 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::rc::Weak;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
 use bytes::Bytes;
+use common::frame_log::FrameLog;
 use error;
+use error::Status;
+use futures::future;
 use futures::sync::mpsc;
+use futures::Async;
+use futures::Future;
 use httpbis::Headers;
 use httpbis::ServerIncreaseInWindow;
 use marshall::Marshaller;
 use or_static::arc::ArcOrStatic;
+use proto::grpc_frame::check_buffered_frame;
 use proto::grpc_frame::parse_grpc_frame_from_bytes;
+use proto::grpc_frame::truncated_frame;
+use proto::grpc_status::GrpcStatus;
+use proto::grpc_web::GrpcProtocol;
+use proto::grpc_web::GrpcWebTextDecoder;
 use result;
+use server::json_gateway::JsonTranscoder;
+use server::req_body::ServerRequestBodyStream;
+use server::req_body::UnaryBodyHandler;
 use server::req_handler_unary::RequestHandlerUnaryToStream;
 use server::req_stream::ServerRequestStreamSenderHandler;
+use server::ServerConf;
+use stats::ServerCallStats;
 use std::marker;
+use tokio_core::reactor::Handle;
+use tokio_core::reactor::Remote;
+use tokio_core::reactor::Timeout;
 use Metadata;
 use ServerRequestStream;
 
@@ -36,31 +61,121 @@ pub trait ServerRequestUnaryHandler<M>: 'static {
     }
 }
 
+/// Limit of JSON gateway request body when `ServerConf::max_request_buffer_bytes` is not set
+const DEFAULT_MAX_JSON_BODY_BYTES: usize = 4 << 20;
+
+/// Request stream limits configured with `ServerConf`.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct RequestLimits {
+    pub max_messages: Option<u64>,
+    pub max_buffer_bytes: Option<usize>,
+    pub message_timeout: Option<Duration>,
+}
+
+impl RequestLimits {
+    pub fn from_conf(conf: &ServerConf) -> RequestLimits {
+        RequestLimits {
+            max_messages: conf.max_request_messages,
+            max_buffer_bytes: conf.max_request_buffer_bytes,
+            message_timeout: conf.request_message_timeout,
+        }
+    }
+}
+
 struct ServerStreamStreamHandlerUntypedHandler<H: ServerRequestStreamHandlerUntyped> {
     buf: Bytes,
+    /// Set for `application/grpc-web-text` requests
+    web_text_decoder: Option<GrpcWebTextDecoder>,
+    /// Set for JSON gateway requests: transcoder and body received so far
+    json: Option<(Arc<JsonTranscoder>, Vec<u8>)>,
+    stats: Option<ServerCallStats>,
+    frame_log: Option<FrameLog>,
     handler: H,
+    /// Malformed frame was received and reported to handler,
+    /// the rest of the request is ignored
+    failed: bool,
+    /// End of stream is passed to handler
+    finished: bool,
+    limits: RequestLimits,
+    /// Number of messages received so far
+    messages: u64,
+    /// Time of the last message, or of the request start
+    last_message: Instant,
 }
 
 impl<H: ServerRequestStreamHandlerUntyped> ServerStreamStreamHandlerUntypedHandler<H> {
     fn process_buf(&mut self) -> result::Result<()> {
         loop {
             let old_len = self.buf.len();
-            let grpc_message = match parse_grpc_frame_from_bytes(&mut self.buf)? {
-                Some(grpc_message) => grpc_message,
-                None => return Ok(()),
+            let grpc_message = match parse_grpc_frame_from_bytes(&mut self.buf) {
+                Ok(Some(grpc_message)) => grpc_message,
+                Ok(None) => return Ok(()),
+                Err(e) => return self.decode_error(e),
             };
-            let consumed = old_len - self.buf.len();
+            self.messages += 1;
+            self.last_message = Instant::now();
+            if let Some(max_messages) = self.limits.max_messages {
+                if self.messages > max_messages {
+                    return self.decode_error(error::Error::Status(Status::new(
+                        GrpcStatus::ResourceExhausted,
+                        format!("request stream has more than {} messages", max_messages),
+                    )));
+                }
+            }
+
+            let mut consumed = old_len - self.buf.len();
+            if let Some(ref mut decoder) = self.web_text_decoder {
+                // flow control window is measured in base64 encoded bytes
+                consumed = decoder.input_len(consumed);
+            }
+
+            if let Some(ref stats) = self.stats {
+                stats.message_received(grpc_message.len());
+            }
+            if let Some(ref frame_log) = self.frame_log {
+                frame_log.grpc_frame("recv", grpc_message.len());
+            }
 
             // TODO: checked cast
             self.handler.grpc_message(grpc_message, consumed as u32)?;
         }
     }
 
+    /// Whole JSON body received, pass it to handler as single message
+    fn json_end_stream(&mut self) -> result::Result<()> {
+        self.finished = true;
+        let (transcoder, body) = self.json.take().unwrap();
+        let message = transcoder.request_from_json(&body)?;
+        if let Some(ref stats) = self.stats {
+            stats.message_received(message.len());
+        }
+        // TODO: checked cast
+        self.handler.grpc_message(message, body.len() as u32)?;
+        self.handler.end_stream()
+    }
+
+    /// Malformed request is failed by handler with error status
+    /// instead of resetting the stream, so client gets a descriptive error.
+    fn decode_error(&mut self, error: error::Error) -> result::Result<()> {
+        self.failed = true;
+        self.buf = Bytes::new();
+        self.handler.error(error)
+    }
+
     fn end_stream(&mut self) -> result::Result<()> {
+        let web_text_incomplete = self
+            .web_text_decoder
+            .as_ref()
+            .map_or(false, |d| !d.is_empty());
         if !self.buf.is_empty() {
-            return Err(error::Error::Other("not complete frames").into());
+            let e = truncated_frame(&self.buf);
+            return self.decode_error(e);
+        }
+        if web_text_incomplete {
+            return self.decode_error(error::Error::Other("incomplete grpc-web-text body"));
         }
 
+        self.finished = true;
         self.handler.end_stream()?;
         Ok(())
     }
@@ -70,6 +185,44 @@ impl<H: ServerRequestStreamHandlerUntyped> httpbis::ServerStreamHandler
     for ServerStreamStreamHandlerUntypedHandler<H>
 {
     fn data_frame(&mut self, data: Bytes, end_stream: bool) -> httpbis::Result<()> {
+        if let Some(ref frame_log) = self.frame_log {
+            frame_log.data("recv", data.len());
+            if end_stream {
+                frame_log.end_stream("recv");
+            }
+        }
+
+        if self.failed {
+            return Ok(());
+        }
+
+        if let Some((_, ref mut body)) = self.json {
+            body.extend_from_slice(&data);
+            let max_body_bytes = self
+                .limits
+                .max_buffer_bytes
+                .unwrap_or(DEFAULT_MAX_JSON_BODY_BYTES);
+            if body.len() > max_body_bytes {
+                let e = error::Error::Status(Status::new(
+                    GrpcStatus::ResourceExhausted,
+                    "request body exceeds receive buffer limit",
+                ));
+                self.decode_error(e)?;
+                return Ok(());
+            }
+            if end_stream {
+                self.json_end_stream()?;
+            } else {
+                self.handler.buffer_processed(body.len())?;
+            }
+            return Ok(());
+        }
+
+        let data = match self.web_text_decoder {
+            Some(ref mut decoder) => decoder.decode(&data)?,
+            None => data,
+        };
+
         if self.buf.is_empty() {
             self.buf = data;
         } else {
@@ -77,12 +230,22 @@ impl<H: ServerRequestStreamHandlerUntyped> httpbis::ServerStreamHandler
         }
 
         self.process_buf()?;
+        if self.failed {
+            return Ok(());
+        }
 
         if end_stream {
             self.end_stream()?;
             return Ok(());
         }
 
+        if let Some(max_buffer_bytes) = self.limits.max_buffer_bytes {
+            if let Err(e) = check_buffered_frame(&self.buf, max_buffer_bytes) {
+                self.decode_error(e)?;
+                return Ok(());
+            }
+        }
+
         if self.buf.len() != 0 {
             trace!(
                 "buffer processed, still contains {}, calling handler",
@@ -95,37 +258,137 @@ impl<H: ServerRequestStreamHandlerUntyped> httpbis::ServerStreamHandler
     }
 
     fn trailers(&mut self, trailers: Headers) -> httpbis::Result<()> {
+        if let Some(ref frame_log) = self.frame_log {
+            frame_log.trailers("recv", &trailers);
+        }
+
         // there are no trailers in gRPC request
         drop(trailers);
 
-        // trigger error if buf is not empty
-        self.process_buf()?;
+        if self.failed {
+            return Ok(());
+        }
 
-        self.handler.end_stream()?;
+        if self.json.is_some() {
+            self.json_end_stream()?;
+            return Ok(());
+        }
+
+        self.end_stream()?;
 
         Ok(())
     }
 
     fn error(&mut self, error: httpbis::Error) -> httpbis::Result<()> {
+        if let Some(ref frame_log) = self.frame_log {
+            frame_log.error("recv", &error);
+        }
         self.handler.error(error.into())?;
         Ok(())
     }
 }
 
+/// Stream handler shared with the timer of `RequestLimits::message_timeout`.
+///
+/// Both are used only on the event loop thread of the connection.
+struct SharedStreamHandler<H: ServerRequestStreamHandlerUntyped>(
+    Rc<RefCell<ServerStreamStreamHandlerUntypedHandler<H>>>,
+);
+
+impl<H: ServerRequestStreamHandlerUntyped> httpbis::ServerStreamHandler for SharedStreamHandler<H> {
+    fn data_frame(&mut self, data: Bytes, end_stream: bool) -> httpbis::Result<()> {
+        self.0.borrow_mut().data_frame(data, end_stream)
+    }
+
+    fn trailers(&mut self, trailers: Headers) -> httpbis::Result<()> {
+        self.0.borrow_mut().trailers(trailers)
+    }
+
+    fn error(&mut self, error: httpbis::Error) -> httpbis::Result<()> {
+        self.0.borrow_mut().error(error)
+    }
+}
+
+/// Fail the request when no message is received in `timeout` after the previous one.
+///
+/// Timer stops when request stream is complete or handler is dropped.
+fn spawn_message_timer<H>(
+    handle: &Handle,
+    handler: Weak<RefCell<ServerStreamStreamHandlerUntypedHandler<H>>>,
+    timeout: Duration,
+) where
+    H: ServerRequestStreamHandlerUntyped,
+{
+    let mut timer = match Timeout::new(timeout, handle) {
+        Ok(timer) => timer,
+        Err(e) => {
+            warn!("failed to create request message timer: {}", e);
+            return;
+        }
+    };
+    handle.spawn(future::poll_fn(move || loop {
+        match timer.poll() {
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Ok(Async::Ready(())) => {}
+            Err(e) => {
+                warn!("request message timer failed: {}", e);
+                return Ok(Async::Ready(()));
+            }
+        }
+        let handler = match handler.upgrade() {
+            Some(handler) => handler,
+            None => return Ok(Async::Ready(())),
+        };
+        let mut handler = handler.borrow_mut();
+        if handler.failed || handler.finished {
+            return Ok(Async::Ready(()));
+        }
+        let idle = handler.last_message.elapsed();
+        if idle < timeout {
+            timer.reset(Instant::now() + (timeout - idle));
+            continue;
+        }
+        let e = error::Error::Status(Status::new(
+            GrpcStatus::DeadlineExceeded,
+            format!("no request message received in {:?}", timeout),
+        ));
+        if let Err(e) = handler.decode_error(e) {
+            warn!("failed to report request message timeout: {:?}", e);
+        }
+        return Ok(Async::Ready(()));
+    }));
+}
+
 struct ServerRequestStreamHandlerHandler<M: 'static, H: ServerRequestStreamHandler<M>> {
     handler: H,
     marshaller: ArcOrStatic<Marshaller<M>>,
+    /// Message could not be parsed or failed validation,
+    /// the rest of the request is ignored
+    failed: bool,
 }
 
 impl<M, H: ServerRequestStreamHandler<M>> ServerRequestStreamHandlerUntyped
     for ServerRequestStreamHandlerHandler<M, H>
 {
     fn grpc_message(&mut self, message: Bytes, frame_size: u32) -> result::Result<()> {
-        let message = self.marshaller.read(message)?;
+        if self.failed {
+            return Ok(());
+        }
+        let message = match self.marshaller.read(message) {
+            Ok(message) => message,
+            Err(e) => {
+                // reported like malformed frame, so client gets the status
+                self.failed = true;
+                return self.handler.error(e);
+            }
+        };
         self.handler.grpc_message(message, frame_size)
     }
 
     fn end_stream(&mut self) -> result::Result<()> {
+        if self.failed {
+            return Ok(());
+        }
         self.handler.end_stream()
     }
 
@@ -140,6 +403,15 @@ impl<M, H: ServerRequestStreamHandler<M>> ServerRequestStreamHandlerUntyped
 
 pub(crate) struct ServerRequestUntyped<'a> {
     pub(crate) req: httpbis::ServerRequest<'a>,
+    pub(crate) protocol: GrpcProtocol,
+    /// Request body is JSON converted with this transcoder
+    pub(crate) json: Option<Arc<JsonTranscoder>>,
+    pub(crate) stats: Option<ServerCallStats>,
+    /// Set if `ServerConf::debug_frames` is enabled
+    pub(crate) frame_log: Option<FrameLog>,
+    pub(crate) limits: RequestLimits,
+    /// Event loop of the connection, runs request message timer
+    pub(crate) loop_remote: Remote,
 }
 
 impl<'a> ServerRequestUntyped<'a> {
@@ -148,16 +420,64 @@ impl<'a> ServerRequestUntyped<'a> {
         H: ServerRequestStreamHandlerUntyped,
         F: FnOnce(ServerIncreaseInWindow) -> (H, R),
     {
+        let web_text_decoder = match self.protocol {
+            GrpcProtocol::GrpcWebText => Some(GrpcWebTextDecoder::new()),
+            GrpcProtocol::Grpc | GrpcProtocol::GrpcWeb => None,
+        };
+        let json = self.json.map(|transcoder| (transcoder, Vec::new()));
+        let stats = self.stats;
+        let frame_log = self.frame_log;
+        let limits = self.limits;
+        // handlers are registered on the event loop thread
+        let loop_handle = self.loop_remote.handle();
         self.req.register_stream_handler(|increase_in_window| {
             let (handler, r) = handler(increase_in_window);
+            let handler = Rc::new(RefCell::new(ServerStreamStreamHandlerUntypedHandler {
+                buf: Bytes::new(),
+                web_text_decoder,
+                json,
+                stats,
+                frame_log,
+                handler,
+                failed: false,
+                finished: false,
+                limits,
+                messages: 0,
+                last_message: Instant::now(),
+            }));
+            if let (Some(timeout), Some(loop_handle)) = (limits.message_timeout, loop_handle) {
+                spawn_message_timer(&loop_handle, Rc::downgrade(&handler), timeout);
+            }
+            (SharedStreamHandler(handler), r)
+        })
+    }
+
+    /// Receive single message request body in chunks without parsing it.
+    ///
+    /// JSON gateway requests are not supported and fail with `UNIMPLEMENTED`.
+    pub fn into_body_stream(self) -> result::Result<ServerRequestBodyStream> {
+        if self.json.is_some() {
+            return Err(error::Error::Status(Status::new(
+                GrpcStatus::Unimplemented,
+                "method is not available via JSON gateway",
+            )));
+        }
+        let web_text_decoder = match self.protocol {
+            GrpcProtocol::GrpcWebText => Some(GrpcWebTextDecoder::new()),
+            GrpcProtocol::Grpc | GrpcProtocol::GrpcWeb => None,
+        };
+        let stats = self.stats;
+        let frame_log = self.frame_log;
+        Ok(self.req.register_stream_handler(|increase_in_window| {
+            let (tx, rx) = mpsc::unbounded();
             (
-                ServerStreamStreamHandlerUntypedHandler {
-                    buf: Bytes::new(),
-                    handler,
+                UnaryBodyHandler::new(web_text_decoder, stats, frame_log, tx),
+                ServerRequestBodyStream {
+                    rx,
+                    increase_in_window,
                 },
-                r,
             )
-        })
+        }))
     }
 }
 
@@ -185,6 +505,7 @@ impl<'a, M: Send + 'static> ServerRequest<'a, M> {
                 ServerRequestStreamHandlerHandler {
                     handler,
                     marshaller,
+                    failed: false,
                 },
                 r,
             )
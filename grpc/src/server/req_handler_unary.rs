@@ -38,6 +38,10 @@ where
         }
     }
 
+    fn error(&mut self, error: error::Error) -> result::Result<()> {
+        self.handler.error(error)
+    }
+
     fn buffer_processed(&mut self, buffered: usize) -> result::Result<()> {
         // TODO: overflow check
         self.increase_in_window
@@ -1,7 +1,13 @@
 use common::sink::SinkCommon;
+use common::sink::WriteFlags;
+use error;
 use futures::future;
 use futures::future::Future;
+use futures::sink::Sink;
+use futures::Async;
+use futures::AsyncSink;
 use futures::Poll;
+use futures::StartSend;
 use httpbis;
 use httpbis::StreamDead;
 use proto::grpc_status::GrpcStatus;
@@ -14,6 +20,7 @@ pub struct ServerResponseSink<Resp: Send + 'static> {
 }
 
 impl<Resp: Send> ServerResponseSink<Resp> {
+    /// Ready when peer flow control window allows sending more data.
     pub fn poll(&mut self) -> Poll<(), httpbis::StreamDead> {
         self.common.poll()
     }
@@ -27,10 +34,28 @@ impl<Resp: Send> ServerResponseSink<Resp> {
         Ok(())
     }
 
+    /// Enqueue a message.
+    ///
+    /// Message is buffered even if flow control window is exhausted,
+    /// so handlers producing many messages should check `poll` first
+    /// (or use this sink as `futures::Sink` which does that).
     pub fn send_data(&mut self, message: Resp) -> result::Result<()> {
         self.common.send_data(message)
     }
 
+    /// Enqueue a message, with `buffer_hint` it may be sent later
+    /// together with following messages.
+    pub fn send_data_with_flags(&mut self, message: Resp, flags: WriteFlags) -> result::Result<()> {
+        self.common.send_data_with_flags(message, flags)
+    }
+
+    /// Send messages held back by `WriteFlags::buffer_hint`.
+    ///
+    /// Trailers and errors flush buffered messages automatically.
+    pub fn flush(&mut self) -> result::Result<()> {
+        self.common.flush()
+    }
+
     pub fn send_trailers(&mut self, metadata: Metadata) -> result::Result<()> {
         self.common.sink.send_trailers(metadata)?;
         Ok(())
@@ -40,4 +65,47 @@ impl<Resp: Send> ServerResponseSink<Resp> {
         self.common.sink.send_grpc_error(status, message)?;
         Ok(())
     }
+
+    /// Send error status with custom trailing metadata.
+    pub fn send_grpc_error_with_trailers(
+        &mut self,
+        status: GrpcStatus,
+        message: String,
+        metadata: Metadata,
+    ) -> result::Result<()> {
+        self.common
+            .sink
+            .send_grpc_error_with_trailers(status, message, metadata)?;
+        Ok(())
+    }
+}
+
+/// Messages are accepted only when flow control window is available,
+/// `close` sends empty trailers.
+///
+/// Messages are sent with `buffer_hint` until `poll_complete`.
+impl<Resp: Send> Sink for ServerResponseSink<Resp> {
+    type SinkItem = Resp;
+    type SinkError = error::Error;
+
+    fn start_send(&mut self, item: Resp) -> StartSend<Resp, error::Error> {
+        if let Async::NotReady = self.poll()? {
+            self.flush()?;
+            return Ok(AsyncSink::NotReady(item));
+        }
+        self.send_data_with_flags(item, WriteFlags { buffer_hint: true })?;
+        Ok(AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), error::Error> {
+        self.flush()?;
+        Ok(Async::Ready(()))
+    }
+
+    fn close(&mut self) -> Poll<(), error::Error> {
+        if self.common.sink.common.http.state() != httpbis::SenderState::Done {
+            self.send_trailers(Metadata::new())?;
+        }
+        Ok(Async::Ready(()))
+    }
 }
@@ -1,18 +1,113 @@
+use std::ops::Deref;
+use std::ops::DerefMut;
+use std::sync::Arc;
+use std::sync::Mutex;
+
 use bytes::Bytes;
+use common::frame_log::FrameLog;
 use common::sink::SinkCommonUntyped;
 use common::sink::SinkUntyped;
+use common::sink::WriteFlags;
 use futures::Poll;
+use httpbis::Headers;
 use httpbis::SenderState;
+use proto::grpc_frame::GRPC_HEADER_LEN;
 use proto::grpc_status::GrpcStatus;
+use proto::grpc_web::grpc_web_trailers_frame;
+use proto::grpc_web::GrpcProtocol;
+use proto::headers::add_missing_headers;
 use proto::headers::headers_200;
-use proto::headers::headers_500;
 use proto::headers::trailers;
+use proto::headers::trailers_only;
 use result;
+use server::in_flight::InFlightGuard;
+use server::json_gateway::http_status_for_grpc_status;
+use server::json_gateway::json_error_body;
+use server::json_gateway::json_headers;
+use server::json_gateway::JsonTranscoder;
 use server::types::ServerTypes;
+use stats::ServerCallStats;
 use Metadata;
 
 pub(crate) struct ServerResponseUntypedSink {
-    pub common: SinkCommonUntyped<ServerTypes>,
+    pub common: CommonSlot,
+    pub protocol: GrpcProtocol,
+    /// Response is converted to JSON with this transcoder
+    pub json: Option<Arc<JsonTranscoder>>,
+    /// `ServerConf::custom_response_headers`, added to response headers
+    pub response_headers: Arc<Headers>,
+    /// Released when response is complete
+    pub in_flight: Option<InFlightGuard>,
+    /// Set if server has stats handler, taken when response is complete
+    pub stats: Option<ServerCallStats>,
+    /// Set if `ServerConf::debug_frames` is enabled
+    pub frame_log: Option<FrameLog>,
+    /// Keeps the sink if it is dropped while handler function runs
+    pub dropped: DroppedSink,
+}
+
+/// `SinkCommonUntyped` which can be moved out of the sink being dropped
+pub(crate) struct CommonSlot(Option<SinkCommonUntyped<ServerTypes>>);
+
+impl CommonSlot {
+    pub fn new(http: httpbis::ServerResponse) -> CommonSlot {
+        CommonSlot(Some(SinkCommonUntyped::new(http)))
+    }
+}
+
+impl Deref for CommonSlot {
+    type Target = SinkCommonUntyped<ServerTypes>;
+
+    fn deref(&self) -> &SinkCommonUntyped<ServerTypes> {
+        self.0.as_ref().expect("sink is dropped")
+    }
+}
+
+impl DerefMut for CommonSlot {
+    fn deref_mut(&mut self) -> &mut SinkCommonUntyped<ServerTypes> {
+        self.0.as_mut().expect("sink is dropped")
+    }
+}
+
+#[derive(Default)]
+struct DroppedSinkState {
+    /// Handler function is running
+    armed: bool,
+    sink: Option<ServerResponseUntypedSink>,
+}
+
+/// Response sink dropped by handler function before it returned.
+///
+/// Handler takes the sink by value, so when it returns an error
+/// the sink is usually already dropped. The sink is kept here instead
+/// of sending `INTERNAL`, so the status of the error can be sent.
+#[derive(Clone, Default)]
+pub(crate) struct DroppedSink {
+    state: Arc<Mutex<DroppedSinkState>>,
+}
+
+impl DroppedSink {
+    /// Keep sinks dropped from now on
+    pub fn arm(&self) {
+        self.state.lock().unwrap().armed = true;
+    }
+
+    /// Stop keeping dropped sinks, return the sink dropped while armed
+    pub fn disarm(&self) -> Option<ServerResponseUntypedSink> {
+        let mut state = self.state.lock().unwrap();
+        state.armed = false;
+        state.sink.take()
+    }
+
+    /// Keep the sink if armed, otherwise return it back
+    fn keep(&self, sink: ServerResponseUntypedSink) -> Option<ServerResponseUntypedSink> {
+        let mut state = self.state.lock().unwrap();
+        if !state.armed {
+            return Some(sink);
+        }
+        state.sink = Some(sink);
+        None
+    }
 }
 
 impl SinkUntyped for ServerResponseUntypedSink {
@@ -20,11 +115,33 @@ impl SinkUntyped for ServerResponseUntypedSink {
         self.common.http.poll()
     }
 
-    fn send_data(&mut self, message: Bytes) -> result::Result<()> {
+    fn send_frame(&mut self, frame: Bytes, flags: WriteFlags) -> result::Result<()> {
+        if let Some(ref stats) = self.stats {
+            stats.message_sent(frame.len() - GRPC_HEADER_LEN);
+        }
+        if let Some(transcoder) = self.json.clone() {
+            let json = transcoder.response_to_json(&frame[GRPC_HEADER_LEN..])?;
+            if self.common.http.state() == httpbis::SenderState::ExpectingHeaders {
+                let mut headers = json_headers(200);
+                add_missing_headers(&mut headers, &self.response_headers);
+                self.common.http.send_headers(headers)?;
+            }
+            self.common.http.send_data(json)?;
+            return Ok(());
+        }
         if self.common.http.state() == httpbis::SenderState::ExpectingHeaders {
             self.send_metadata(Metadata::new())?;
         }
-        self.common.send_data(message)
+        let body = self.protocol.encode_body(frame);
+        if let Some(ref frame_log) = self.frame_log {
+            frame_log.grpc_frame("send", frame.len() - GRPC_HEADER_LEN);
+            frame_log.data("send", body.len());
+        }
+        self.common.send_frame(body, flags)
+    }
+
+    fn flush(&mut self) -> result::Result<()> {
+        Ok(self.common.flush()?)
     }
 }
 
@@ -37,14 +154,61 @@ impl ServerResponseUntypedSink {
     }
 
     fn do_send_headers(&mut self, metadata: Metadata) -> Result<(), httpbis::SendError> {
-        let headers = headers_200(metadata);
+        if self.json.is_some() {
+            let mut headers = json_headers(200);
+            headers.extend(metadata.into_headers());
+            add_missing_headers(&mut headers, &self.response_headers);
+            return self.common.http.send_headers(headers);
+        }
+        let mut headers = headers_200(self.protocol.content_type(), metadata);
+        add_missing_headers(&mut headers, &self.response_headers);
+        if let Some(ref frame_log) = self.frame_log {
+            frame_log.headers("send", &headers);
+        }
         self.common.http.send_headers(headers)
     }
 
     pub fn send_trailers(&mut self, metadata: Metadata) -> Result<(), httpbis::SendError> {
-        self.common
-            .http
-            .send_trailers(trailers(GrpcStatus::Ok, None, metadata))
+        self.do_send_trailers(GrpcStatus::Ok, trailers(GrpcStatus::Ok, None, metadata))
+    }
+
+    /// Response is complete, release resources and report stats.
+    fn finished(&mut self, grpc_status: GrpcStatus) {
+        self.in_flight.take();
+        if let Some(stats) = self.stats.take() {
+            stats.finished(grpc_status);
+        }
+    }
+
+    fn do_send_trailers(
+        &mut self,
+        grpc_status: GrpcStatus,
+        trailers: Headers,
+    ) -> Result<(), httpbis::SendError> {
+        self.finished(grpc_status);
+        self.common.flush()?;
+        if self.json.is_some() {
+            // status can no longer be changed, just finish the body
+            drop(trailers);
+            return self.common.http.send_data_end_of_stream(Bytes::new());
+        }
+        if self.protocol.is_web() {
+            // grpc-web clients cannot read HTTP trailers,
+            // so trailers are sent as the last message
+            let frame = self
+                .protocol
+                .encode_body(grpc_web_trailers_frame(&trailers));
+            if let Some(ref frame_log) = self.frame_log {
+                frame_log.data("send", frame.len());
+                frame_log.end_stream("send");
+            }
+            self.common.http.send_data_end_of_stream(frame)
+        } else {
+            if let Some(ref frame_log) = self.frame_log {
+                frame_log.trailers("send", &trailers);
+            }
+            self.common.http.send_trailers(trailers)
+        }
     }
 
     pub fn _close(&mut self) -> Result<(), httpbis::SendError> {
@@ -56,13 +220,65 @@ impl ServerResponseUntypedSink {
         grpc_status: GrpcStatus,
         message: String,
     ) -> Result<(), httpbis::SendError> {
-        // TODO: if headers sent, then ...
-        let headers = headers_500(grpc_status, message.to_owned());
+        self.send_grpc_error_with_trailers(grpc_status, message, Metadata::new())
+    }
+
+    pub fn send_grpc_error_with_trailers(
+        &mut self,
+        grpc_status: GrpcStatus,
+        message: String,
+        metadata: Metadata,
+    ) -> Result<(), httpbis::SendError> {
+        self.finished(grpc_status);
+        if self.json.is_some() && self.common.http.state() == SenderState::ExpectingHeaders {
+            let mut headers = json_headers(http_status_for_grpc_status(grpc_status));
+            add_missing_headers(&mut headers, &self.response_headers);
+            self.common.http.send_headers(headers)?;
+            return self
+                .common
+                .http
+                .send_data_end_of_stream(json_error_body(grpc_status, &message));
+        }
 
         if self.common.http.state() == SenderState::ExpectingHeaders {
+            let mut headers =
+                trailers_only(self.protocol.content_type(), grpc_status, message, metadata);
+            add_missing_headers(&mut headers, &self.response_headers);
+            if let Some(ref frame_log) = self.frame_log {
+                frame_log.trailers("send", &headers);
+            }
             self.common.http.send_headers_end_of_stream(headers)
         } else {
-            self.common.http.send_trailers(headers)
+            self.do_send_trailers(grpc_status, trailers(grpc_status, Some(message), metadata))
+        }
+    }
+}
+
+impl Drop for ServerResponseUntypedSink {
+    fn drop(&mut self) {
+        let common = match self.common.0.take() {
+            Some(common) => common,
+            // moved to the sink kept by `DroppedSink`
+            None => return,
+        };
+        if common.http.state() == SenderState::Done {
+            self.finished(GrpcStatus::Internal);
+            return;
+        }
+        let sink = ServerResponseUntypedSink {
+            common: CommonSlot(Some(common)),
+            protocol: self.protocol,
+            json: self.json.take(),
+            response_headers: self.response_headers.clone(),
+            in_flight: self.in_flight.take(),
+            stats: self.stats.take(),
+            frame_log: self.frame_log.take(),
+            dropped: DroppedSink::default(),
+        };
+        if let Some(mut sink) = self.dropped.keep(sink) {
+            // handler did not complete the response, so error is sent by drop callback
+            sink.finished(GrpcStatus::Internal);
+            drop(sink.common.0.take());
         }
     }
 }
@@ -25,4 +25,15 @@ impl<Resp: Send + 'static> ServerResponseUnarySink<Resp> {
     pub fn send_grpc_error(mut self, status: GrpcStatus, message: String) -> result::Result<()> {
         self.sink.send_grpc_error(status, message)
     }
+
+    /// Send error status with custom trailing metadata.
+    pub fn send_grpc_error_with_trailers(
+        mut self,
+        status: GrpcStatus,
+        message: String,
+        metadata: Metadata,
+    ) -> result::Result<()> {
+        self.sink
+            .send_grpc_error_with_trailers(status, message, metadata)
+    }
 }
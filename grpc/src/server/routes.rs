@@ -0,0 +1,106 @@
+//! Route table of gRPC services, which may be changed while server is running.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::RwLock;
+
+use error::Error;
+use result::Result;
+use server::descriptor::ServiceDescriptor;
+use server::GrpcServerHandler;
+use server::ServerServiceDefinition;
+use server::ServerShared;
+
+/// Services of a server by path prefix.
+///
+/// Shared by all listeners of a server, so services added or removed
+/// with `Server::add_service` and `Server::remove_service` are
+/// visible to new requests on all listeners. Calls already dispatched
+/// to a removed service are completed.
+pub(crate) struct ServiceRoutes {
+    shared: Arc<ServerShared>,
+    services: RwLock<HashMap<String, Arc<GrpcServerHandler>>>,
+}
+
+impl ServiceRoutes {
+    pub fn new(shared: Arc<ServerShared>) -> ServiceRoutes {
+        ServiceRoutes {
+            shared,
+            services: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn add(&self, def: Arc<ServerServiceDefinition>) -> Result<()> {
+        if let Some(ref name) = def.duplicate_method {
+            error!("method {} is registered more than once", name);
+            return Err(Error::Other("duplicate method registration"));
+        }
+        let mut services = self.services.write().unwrap();
+        if services.contains_key(&def.prefix) {
+            error!("service {} is registered more than once", def.prefix);
+            return Err(Error::Other("duplicate service registration"));
+        }
+        let handler = Arc::new(GrpcServerHandler {
+            service_definition: def.clone(),
+            shared: self.shared.clone(),
+        });
+        services.insert(def.prefix.clone(), handler);
+        Ok(())
+    }
+
+    /// Returns `false` if no service is registered with given prefix.
+    pub fn remove(&self, prefix: &str) -> bool {
+        self.services.write().unwrap().remove(prefix).is_some()
+    }
+
+    /// Service with longest prefix matching given path.
+    pub fn find(&self, path: &str) -> Option<Arc<GrpcServerHandler>> {
+        let services = self.services.read().unwrap();
+        services
+            .iter()
+            .filter(|&(prefix, _)| prefix_matches(prefix, path))
+            .max_by_key(|&(prefix, _)| prefix.len())
+            .map(|(_, handler)| handler.clone())
+    }
+
+    /// Sorted by name
+    pub fn descriptors(&self) -> Vec<ServiceDescriptor> {
+        let services = self.services.read().unwrap();
+        let mut descriptors: Vec<ServiceDescriptor> = services
+            .values()
+            .map(|handler| handler.service_definition.descriptor())
+            .collect();
+        descriptors.sort_by(|a, b| a.name.cmp(&b.name));
+        descriptors
+    }
+}
+
+impl fmt::Debug for ServiceRoutes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let services = self.services.read().unwrap();
+        f.debug_set().entries(services.keys()).finish()
+    }
+}
+
+/// Prefix matches whole path segments, so `/foo` matches `/foo/bar`, but not `/foobar`
+fn prefix_matches(prefix: &str, path: &str) -> bool {
+    path.starts_with(prefix)
+        && (prefix.ends_with('/')
+            || path.len() == prefix.len()
+            || path.as_bytes()[prefix.len()] == b'/')
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn prefix() {
+        assert!(prefix_matches("/foo", "/foo/bar"));
+        assert!(prefix_matches("/foo", "/foo"));
+        assert!(prefix_matches("/", "/foo/bar"));
+        assert!(!prefix_matches("/foo", "/foobar/baz"));
+        assert!(!prefix_matches("/foo", "/bar/baz"));
+    }
+}
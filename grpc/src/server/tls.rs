@@ -0,0 +1,36 @@
+use tls_api::TlsAcceptor;
+use tls_api::TlsAcceptorBuilder;
+
+use client::tls::ALPN_H2;
+use error;
+use result;
+
+/// Server TLS settings.
+///
+/// Applied to acceptor builder created from server certificate and key
+/// by TLS implementation, e.g. with `TlsAcceptorBuilder::from_pkcs12`.
+#[derive(Default, Debug, Clone)]
+pub struct ServerTlsConf {
+    /// Advertise `h2` with ALPN, and fail if TLS implementation does not support ALPN.
+    pub require_alpn_h2: bool,
+}
+
+impl ServerTlsConf {
+    pub fn new() -> ServerTlsConf {
+        Default::default()
+    }
+
+    /// Build a TLS acceptor with these settings.
+    pub fn build_acceptor<A: TlsAcceptor>(&self, mut builder: A::Builder) -> result::Result<A> {
+        if self.require_alpn_h2 {
+            if !A::supports_alpn() {
+                return Err(error::Error::Other(
+                    "TLS implementation does not support ALPN",
+                ));
+            }
+            builder.set_alpn_protocols(&[ALPN_H2])?;
+        }
+
+        Ok(builder.build()?)
+    }
+}
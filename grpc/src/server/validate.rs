@@ -0,0 +1,67 @@
+//! Validation of request messages before they are passed to method handler.
+
+use std::sync::Arc;
+
+use bytes::Bytes;
+
+use error::Error;
+use error::Status;
+use marshall::Marshaller;
+use or_static::arc::ArcOrStatic;
+use proto::grpc_status::GrpcStatus;
+use result;
+
+/// Check of decoded request message, registered with `ServerMethod::new_validated`.
+///
+/// Called on event loop thread for each request message before it reaches the handler,
+/// e. g. to run generated protoc-gen-validate style checks.
+/// Failed call is replied with `INVALID_ARGUMENT` for unary and server streaming methods;
+/// for client streaming and bidi methods the error is returned from request stream.
+pub trait RequestValidator<Req>: Send + Sync + 'static {
+    /// Return error to reject the message.
+    ///
+    /// `Error::Status` is sent to client as is,
+    /// other errors are sent as `INVALID_ARGUMENT` with error description.
+    fn validate(&self, message: &Req) -> result::Result<()>;
+}
+
+impl<Req, F> RequestValidator<Req> for F
+where
+    F: Fn(&Req) -> result::Result<()> + Send + Sync + 'static,
+{
+    fn validate(&self, message: &Req) -> result::Result<()> {
+        self(message)
+    }
+}
+
+/// Request marshaller which validates parsed messages
+pub(crate) struct ValidatingMarshaller<Req: 'static> {
+    pub marshaller: ArcOrStatic<Marshaller<Req>>,
+    pub validator: Arc<RequestValidator<Req>>,
+}
+
+impl<Req: 'static> Marshaller<Req> for ValidatingMarshaller<Req> {
+    fn write(&self, m: &Req) -> result::Result<Vec<u8>> {
+        self.marshaller.write(m)
+    }
+
+    fn write_to_vec(&self, m: &Req, out: &mut Vec<u8>) -> result::Result<()> {
+        self.marshaller.write_to_vec(m, out)
+    }
+
+    fn is_empty(&self, m: &Req) -> bool {
+        self.marshaller.is_empty(m)
+    }
+
+    fn read(&self, bytes: Bytes) -> result::Result<Req> {
+        let message = self.marshaller.read(bytes)?;
+        match self.validator.validate(&message) {
+            Ok(()) => Ok(message),
+            Err(e @ Error::Status(..)) => Err(e),
+            Err(e) => Err(Error::Status(Status::new(
+                GrpcStatus::Argument,
+                e.to_string(),
+            ))),
+        }
+    }
+}
@@ -0,0 +1,205 @@
+//! Cancellation of method handlers which make no progress.
+
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+use futures::Async;
+use futures::Future;
+use futures::Poll;
+use futures::Stream;
+
+use error::Error;
+use error::Status;
+use proto::grpc_status::GrpcStatus;
+use stream_item::GrpcStreamWithTrailingMetadata;
+use timer::is_expired;
+use timer::sleep;
+use timer::Sleep;
+use StreamingResponse;
+
+/// Observer of handlers canceled by watchdog, e. g. to dump diagnostics
+/// or to count stuck handlers per method.
+///
+/// Registered with `ServerBuilder::set_watchdog_handler`. Called on event loop
+/// when the handler is canceled, before the error is sent to client.
+pub trait ServerWatchdogHandler: Send + Sync + 'static {
+    fn stuck(&self, method: &str, timeout: Duration);
+}
+
+/// Applies `ServerConf::handler_watchdog_timeout` or
+/// `ServerMethod::with_watchdog_timeout` to handler responses.
+#[derive(Clone, Default)]
+pub(crate) struct Watchdog {
+    pub timeout: Option<Duration>,
+    pub handler: Option<Arc<ServerWatchdogHandler>>,
+}
+
+impl Watchdog {
+    /// Fail the response with `DEADLINE_EXCEEDED` if its headers or the next message
+    /// are not produced within the timeout. Time spent waiting for client
+    /// to accept previous messages is not counted.
+    pub fn watch<Resp: Send + 'static>(
+        &self,
+        method: &str,
+        resp: StreamingResponse<Resp>,
+    ) -> StreamingResponse<Resp> {
+        let timeout = match self.timeout {
+            Some(timeout) => timeout,
+            None => return resp,
+        };
+        let stream_watchdog = self.clone();
+        let stream_method = method.to_owned();
+        StreamingResponse::new(
+            WithWatchdog::new(resp.0, method.to_owned(), self.handler.clone(), timeout).map(
+                move |(metadata, stream)| {
+                    let stream = WithWatchdog::new(
+                        stream.0,
+                        stream_method,
+                        stream_watchdog.handler,
+                        timeout,
+                    );
+                    (metadata, GrpcStreamWithTrailingMetadata::new(stream))
+                },
+            ),
+        )
+    }
+}
+
+/// Future or stream which fails when it is not ready for `timeout`.
+struct WithWatchdog<S> {
+    inner: S,
+    method: String,
+    handler: Option<Arc<ServerWatchdogHandler>>,
+    timeout: Duration,
+    /// When the inner future or stream was polled after it was last ready
+    waiting_since: Option<Instant>,
+    sleep: Sleep,
+}
+
+impl<S> WithWatchdog<S> {
+    fn new(
+        inner: S,
+        method: String,
+        handler: Option<Arc<ServerWatchdogHandler>>,
+        timeout: Duration,
+    ) -> WithWatchdog<S> {
+        WithWatchdog {
+            inner,
+            method,
+            handler,
+            timeout,
+            waiting_since: None,
+            sleep: sleep(timeout),
+        }
+    }
+
+    fn ready(&mut self) {
+        self.waiting_since = None;
+    }
+
+    fn not_ready(&mut self) -> Result<(), Error> {
+        let now = Instant::now();
+        let waiting_since = match self.waiting_since {
+            Some(waiting_since) => waiting_since,
+            None => {
+                // timer is restarted lazily, so a fast stream
+                // does not start a timer per message
+                self.waiting_since = Some(now);
+                now
+            }
+        };
+        while is_expired(&mut self.sleep) {
+            let idle = now - waiting_since;
+            if idle >= self.timeout {
+                return Err(self.stuck());
+            }
+            self.sleep = sleep(self.timeout - idle);
+        }
+        Ok(())
+    }
+
+    fn stuck(&self) -> Error {
+        warn!(
+            "handler of {} made no progress in {:?}, canceling it",
+            self.method, self.timeout
+        );
+        if let Some(ref handler) = self.handler {
+            handler.stuck(&self.method, self.timeout);
+        }
+        Error::Status(Status::new(
+            GrpcStatus::DeadlineExceeded,
+            format!("handler made no progress in {:?}", self.timeout),
+        ))
+    }
+}
+
+impl<F: Future<Error = Error>> Future for WithWatchdog<F> {
+    type Item = F::Item;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<F::Item, Error> {
+        match self.inner.poll()? {
+            Async::Ready(r) => {
+                self.ready();
+                Ok(Async::Ready(r))
+            }
+            Async::NotReady => {
+                self.not_ready()?;
+                Ok(Async::NotReady)
+            }
+        }
+    }
+}
+
+impl<S: Stream<Error = Error>> Stream for WithWatchdog<S> {
+    type Item = S::Item;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<S::Item>, Error> {
+        match self.inner.poll()? {
+            Async::Ready(r) => {
+                self.ready();
+                Ok(Async::Ready(r))
+            }
+            Async::NotReady => {
+                self.not_ready()?;
+                Ok(Async::NotReady)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::sync::Mutex;
+
+    use futures::future;
+
+    struct Recorder(Mutex<Vec<String>>);
+
+    impl ServerWatchdogHandler for Recorder {
+        fn stuck(&self, method: &str, _timeout: Duration) {
+            self.0.lock().unwrap().push(method.to_owned());
+        }
+    }
+
+    #[test]
+    fn stuck() {
+        let recorder = Arc::new(Recorder(Mutex::new(Vec::new())));
+        let r = WithWatchdog::new(
+            future::empty::<(), Error>(),
+            "/a.B/C".to_owned(),
+            Some(recorder.clone()),
+            Duration::from_millis(1),
+        )
+        .wait();
+        match r {
+            Err(Error::Status(e)) => assert_eq!(GrpcStatus::DeadlineExceeded, e.code),
+            r => panic!("{:?}", r),
+        }
+        assert_eq!(vec!["/a.B/C".to_owned()], *recorder.0.lock().unwrap());
+    }
+}
@@ -0,0 +1,354 @@
+//! Hooks to observe RPC activity, e. g. to export metrics.
+
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+use error::Error;
+use proto::grpc_status::GrpcStatus;
+use server::access_log::AccessLogRecord;
+use server::access_log::AccessLogger;
+use server::channelz::Channelz;
+use server::channelz::ChannelzCall;
+use server::events::ServerEvent;
+use server::events::ServerEventSubscribers;
+use server::histograms::MethodHistogram;
+use server::ServerShared;
+use trace::CallId;
+
+/// Server RPC events.
+///
+/// Registered with `ServerBuilder::set_stats_handler`.
+/// Functions are called from event loop threads, so they should be fast.
+pub trait ServerStatsHandler: Send + Sync + 'static {
+    /// Request headers received.
+    fn call_started(&self, _method: &str) {}
+
+    /// Request message received, `size` is serialized message size.
+    fn message_received(&self, _method: &str, _size: usize) {}
+
+    /// Response message sent, `size` is serialized message size.
+    fn message_sent(&self, _method: &str, _size: usize) {}
+
+    /// Response completed with given status.
+    fn call_finished(&self, _method: &str, _status: GrpcStatus, _duration: Duration) {}
+}
+
+/// Client RPC events.
+///
+/// Registered with `ClientBuilder::stats_handler`.
+/// Functions are called from event loop threads, so they should be fast.
+pub trait ClientStatsHandler: Send + Sync + 'static {
+    /// Call started.
+    fn call_started(&self, _method: &str) {}
+
+    /// Request message sent, `size` is serialized message size.
+    fn message_sent(&self, _method: &str, _size: usize) {}
+
+    /// Response message received, `size` is serialized message size.
+    fn message_received(&self, _method: &str, _size: usize) {}
+
+    /// Response completed with given status,
+    /// `Cancelled` if response was dropped before completion.
+    fn call_finished(&self, _method: &str, _status: GrpcStatus, _duration: Duration) {}
+}
+
+/// gRPC status corresponding to call error.
+pub(crate) fn error_status(error: &Error) -> GrpcStatus {
+    error.status().code
+}
+
+/// Events of single server call, reported to stats handler, access logger,
+/// channelz and `Server::events` subscribers.
+#[derive(Clone)]
+pub(crate) struct ServerCallStats {
+    handler: Option<Arc<ServerStatsHandler>>,
+    access_logger: Option<Arc<AccessLogger>>,
+    events: ServerEventSubscribers,
+    histogram: Option<Arc<MethodHistogram>>,
+    channelz: Option<Arc<ChannelzCall>>,
+    /// Calls longer than this are logged as slow
+    slow_call_threshold: Option<Duration>,
+    method: Arc<String>,
+    call_id: Option<CallId>,
+    start: Instant,
+    bytes_received: Arc<AtomicUsize>,
+    bytes_sent: Arc<AtomicUsize>,
+}
+
+impl ServerCallStats {
+    pub fn started(
+        shared: &ServerShared,
+        method: &str,
+        call_id: Option<CallId>,
+    ) -> ServerCallStats {
+        if let Some(ref handler) = shared.stats_handler {
+            handler.call_started(method);
+        }
+        if !shared.events.is_empty() {
+            shared.events.publish(ServerEvent::StreamStarted {
+                method: method.to_owned(),
+                call_id: call_id.clone(),
+            });
+        }
+        ServerCallStats {
+            handler: shared.stats_handler.clone(),
+            access_logger: shared.access_logger.clone(),
+            events: shared.events.clone(),
+            histogram: shared.histograms.as_ref().map(|h| h.method(method)),
+            channelz: shared
+                .channelz
+                .as_ref()
+                .map(|c| Arc::new(Channelz::call_started(c, method, call_id.clone()))),
+            slow_call_threshold: shared.conf.slow_call_threshold,
+            method: Arc::new(method.to_owned()),
+            call_id,
+            start: Instant::now(),
+            bytes_received: Arc::new(AtomicUsize::new(0)),
+            bytes_sent: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    pub fn message_received(&self, size: usize) {
+        self.bytes_received.fetch_add(size, Ordering::Relaxed);
+        if let Some(ref histogram) = self.histogram {
+            histogram.message_received(size);
+        }
+        if let Some(ref channelz) = self.channelz {
+            channelz.message_received();
+        }
+        if let Some(ref handler) = self.handler {
+            handler.message_received(&self.method, size);
+        }
+    }
+
+    pub fn message_sent(&self, size: usize) {
+        self.bytes_sent.fetch_add(size, Ordering::Relaxed);
+        if let Some(ref histogram) = self.histogram {
+            histogram.message_sent(size);
+        }
+        if let Some(ref channelz) = self.channelz {
+            channelz.message_sent();
+        }
+        if let Some(ref handler) = self.handler {
+            handler.message_sent(&self.method, size);
+        }
+    }
+
+    pub fn finished(&self, status: GrpcStatus) {
+        let duration = self.start.elapsed();
+        if let Some(ref histogram) = self.histogram {
+            histogram.finished(duration);
+        }
+        if let Some(ref channelz) = self.channelz {
+            channelz.finished(status);
+        }
+        match self.slow_call_threshold {
+            Some(threshold) if duration >= threshold => warn!(
+                "slow call {} took {:?}, status {:?}{}",
+                self.method,
+                duration,
+                status,
+                match self.call_id {
+                    Some(ref call_id) => format!(" call_id={}", call_id),
+                    None => String::new(),
+                }
+            ),
+            _ => {}
+        }
+        if let Some(ref handler) = self.handler {
+            handler.call_finished(&self.method, status, duration);
+        }
+        if let Some(ref access_logger) = self.access_logger {
+            access_logger.log(&AccessLogRecord {
+                method: &self.method,
+                call_id: self.call_id.as_ref().map(CallId::as_str),
+                status,
+                duration,
+                bytes_received: self.bytes_received.load(Ordering::Relaxed) as u64,
+                bytes_sent: self.bytes_sent.load(Ordering::Relaxed) as u64,
+            });
+        }
+        if !self.events.is_empty() {
+            self.events.publish(ServerEvent::StreamFinished {
+                method: (*self.method).clone(),
+                call_id: self.call_id.clone(),
+                status,
+                duration,
+            });
+        }
+    }
+}
+
+/// Events of single client call.
+#[derive(Clone)]
+pub(crate) struct ClientCallStats {
+    handler: Arc<ClientStatsHandler>,
+    method: Arc<String>,
+    start: Instant,
+}
+
+impl ClientCallStats {
+    pub fn started(handler: Arc<ClientStatsHandler>, method: &str) -> ClientCallStats {
+        handler.call_started(method);
+        ClientCallStats {
+            handler,
+            method: Arc::new(method.to_owned()),
+            start: Instant::now(),
+        }
+    }
+
+    pub fn message_sent(&self, size: usize) {
+        self.handler.message_sent(&self.method, size);
+    }
+
+    pub fn message_received(&self, size: usize) {
+        self.handler.message_received(&self.method, size);
+    }
+
+    pub fn finished(&self, status: GrpcStatus) {
+        self.handler
+            .call_finished(&self.method, status, self.start.elapsed());
+    }
+}
+
+/// Totals of messages and bytes sent and received, e. g. to measure throughput.
+///
+/// Can be registered both as server and client stats handler.
+/// Clones share the counters.
+#[derive(Clone, Default, Debug)]
+pub struct StatsCounters {
+    calls_started: Arc<AtomicUsize>,
+    calls_finished: Arc<AtomicUsize>,
+    messages_sent: Arc<AtomicUsize>,
+    messages_received: Arc<AtomicUsize>,
+    bytes_sent: Arc<AtomicUsize>,
+    bytes_received: Arc<AtomicUsize>,
+}
+
+/// Values of `StatsCounters` at some moment.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct StatsCountersSnapshot {
+    pub calls_started: u64,
+    pub calls_finished: u64,
+    pub messages_sent: u64,
+    pub messages_received: u64,
+    /// Serialized size of sent messages, excluding gRPC frame headers.
+    pub bytes_sent: u64,
+    /// Serialized size of received messages, excluding gRPC frame headers.
+    pub bytes_received: u64,
+}
+
+impl StatsCountersSnapshot {
+    /// Counters change since `earlier` snapshot.
+    pub fn since(&self, earlier: &StatsCountersSnapshot) -> StatsCountersSnapshot {
+        StatsCountersSnapshot {
+            calls_started: self.calls_started - earlier.calls_started,
+            calls_finished: self.calls_finished - earlier.calls_finished,
+            messages_sent: self.messages_sent - earlier.messages_sent,
+            messages_received: self.messages_received - earlier.messages_received,
+            bytes_sent: self.bytes_sent - earlier.bytes_sent,
+            bytes_received: self.bytes_received - earlier.bytes_received,
+        }
+    }
+}
+
+impl StatsCounters {
+    pub fn new() -> StatsCounters {
+        Default::default()
+    }
+
+    pub fn snapshot(&self) -> StatsCountersSnapshot {
+        StatsCountersSnapshot {
+            calls_started: self.calls_started.load(Ordering::Relaxed) as u64,
+            calls_finished: self.calls_finished.load(Ordering::Relaxed) as u64,
+            messages_sent: self.messages_sent.load(Ordering::Relaxed) as u64,
+            messages_received: self.messages_received.load(Ordering::Relaxed) as u64,
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed) as u64,
+            bytes_received: self.bytes_received.load(Ordering::Relaxed) as u64,
+        }
+    }
+
+    fn call_started(&self) {
+        self.calls_started.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn message_sent(&self, size: usize) {
+        self.messages_sent.fetch_add(1, Ordering::Relaxed);
+        self.bytes_sent.fetch_add(size, Ordering::Relaxed);
+    }
+
+    fn message_received(&self, size: usize) {
+        self.messages_received.fetch_add(1, Ordering::Relaxed);
+        self.bytes_received.fetch_add(size, Ordering::Relaxed);
+    }
+
+    fn call_finished(&self) {
+        self.calls_finished.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl ServerStatsHandler for StatsCounters {
+    fn call_started(&self, _method: &str) {
+        StatsCounters::call_started(self);
+    }
+
+    fn message_received(&self, _method: &str, size: usize) {
+        StatsCounters::message_received(self, size);
+    }
+
+    fn message_sent(&self, _method: &str, size: usize) {
+        StatsCounters::message_sent(self, size);
+    }
+
+    fn call_finished(&self, _method: &str, _status: GrpcStatus, _duration: Duration) {
+        StatsCounters::call_finished(self);
+    }
+}
+
+impl ClientStatsHandler for StatsCounters {
+    fn call_started(&self, _method: &str) {
+        StatsCounters::call_started(self);
+    }
+
+    fn message_sent(&self, _method: &str, size: usize) {
+        StatsCounters::message_sent(self, size);
+    }
+
+    fn message_received(&self, _method: &str, size: usize) {
+        StatsCounters::message_received(self, size);
+    }
+
+    fn call_finished(&self, _method: &str, _status: GrpcStatus, _duration: Duration) {
+        StatsCounters::call_finished(self);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn counters() {
+        let counters = StatsCounters::new();
+        let handler: Arc<ServerStatsHandler> = Arc::new(counters.clone());
+        handler.call_started("/a/b");
+        let before = counters.snapshot();
+        handler.message_received("/a/b", 10);
+        handler.message_sent("/a/b", 20);
+        handler.call_finished("/a/b", GrpcStatus::Ok, Duration::from_millis(1));
+        assert_eq!(
+            StatsCountersSnapshot {
+                calls_started: 0,
+                calls_finished: 1,
+                messages_sent: 1,
+                messages_received: 1,
+                bytes_sent: 20,
+                bytes_received: 10,
+            },
+            counters.snapshot().since(&before)
+        );
+    }
+}
@@ -154,6 +154,27 @@ impl<T: Send + 'static> GrpcStreamWithTrailingMetadata<T> {
         }))
     }
 
+    /// Transform trailing metadata, keep items
+    pub fn map_trailing_metadata<F>(self, mut f: F) -> GrpcStreamWithTrailingMetadata<T>
+    where
+        F: FnMut(Metadata) -> Metadata + Send + 'static,
+    {
+        self.map_stream(move |stream| {
+            Box::new(stream.map(move |item| match item {
+                ItemOrMetadata::Item(i) => ItemOrMetadata::Item(i),
+                ItemOrMetadata::TrailingMetadata(m) => ItemOrMetadata::TrailingMetadata(f(m)),
+            }))
+        })
+    }
+
+    /// Transform error of the stream
+    pub fn map_err<F>(self, f: F) -> GrpcStreamWithTrailingMetadata<T>
+    where
+        F: FnMut(Error) -> Error + Send + 'static,
+    {
+        self.map_stream(move |stream| Box::new(stream.map_err(f)))
+    }
+
     /// Return raw futures `Stream` without trailing metadata
     pub fn drop_metadata(self) -> GrpcStream<T> {
         Box::new(self.0.filter_map(|item| match item {
@@ -0,0 +1,593 @@
+//! Building blocks of integration tests of services and clients.
+//!
+//! `TestServer` runs services on a localhost port assigned by OS,
+//! `LoopbackServer` on a unix socket, `TestService` builds a service
+//! from closures, `CallRecorder` captures calls on client or server,
+//! `ScriptedServer` answers calls with predefined messages or statuses
+//! after given latency, and `MockMethod` backs mock clients generated
+//! with `mock_client` codegen option.
+
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::future;
+use futures::future::Future;
+use futures::stream;
+
+use client::channel::Channel;
+use client::interceptor::ClientInterceptor;
+use client::Client;
+use client::ClientBuilder;
+use client_stub::ClientStub;
+use error::Error;
+use error::Status;
+use futures_grpc::GrpcStream;
+use marshall::MarshallerString;
+use method::GrpcStreaming;
+use method::MethodDescriptor;
+use or_static::arc::ArcOrStatic;
+use proto::grpc_status::GrpcStatus;
+use proto::metadata::Metadata;
+use req::RequestOptions;
+use resp::SingleResponse;
+use resp::StreamingResponse;
+use result::Result;
+use server::auth::ServerAuthHandler;
+use server::method::MethodHandlerServerStreaming;
+use server::method::MethodHandlerUnary;
+use server::method::ServerMethod;
+use server::Server;
+use server::ServerBuilder;
+use server::ServerServiceDefinition;
+use timer::sleep;
+
+const LOCALHOST: &str = "127.0.0.1";
+
+/// Descriptor of a method with `String` request and response,
+/// handy for tests which do not need protobuf messages.
+pub fn string_method(
+    name: &str,
+    streaming: GrpcStreaming,
+) -> ArcOrStatic<MethodDescriptor<String, String>> {
+    ArcOrStatic::Arc(Arc::new(MethodDescriptor {
+        name: name.into(),
+        streaming,
+        req_marshaller: ArcOrStatic::Static(&MarshallerString),
+        resp_marshaller: ArcOrStatic::Static(&MarshallerString),
+    }))
+}
+
+/// Server listening on `127.0.0.1` port assigned by OS, with a client connected to it.
+pub struct TestServer {
+    client: Arc<Client>,
+    port: u16,
+    // dropped after client
+    server: Server,
+}
+
+impl TestServer {
+    pub fn start(defs: Vec<ServerServiceDefinition>) -> Result<TestServer> {
+        let mut server = ServerBuilder::new_plain();
+        for def in defs {
+            server.add_service(def);
+        }
+        TestServer::start_with(server)
+    }
+
+    /// Start server configured by caller, e. g. with stats handler,
+    /// listening address of the builder is replaced.
+    pub fn start_with(mut server: ServerBuilder) -> Result<TestServer> {
+        server.http.set_addr((LOCALHOST, 0))?;
+        let server = server.build()?;
+        let port = server.local_addr().port()?;
+        let client = ClientBuilder::new(LOCALHOST, port).build()?;
+        Ok(TestServer {
+            client: Arc::new(client),
+            port,
+            server,
+        })
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn server(&self) -> &Server {
+        &self.server
+    }
+
+    /// Client connected to this server.
+    pub fn client(&self) -> Arc<Client> {
+        self.client.clone()
+    }
+
+    /// Builder of another client of this server, e. g. with custom conf.
+    pub fn client_builder(&self) -> ClientBuilder<'static, tls_api_stub::TlsConnector> {
+        ClientBuilder::new(LOCALHOST, self.port)
+    }
+
+    /// Generated client stub connected to this server.
+    pub fn client_stub<C: ClientStub>(&self) -> C {
+        C::with_client(self.client.clone())
+    }
+}
+
+/// Service made of closures.
+pub struct TestService {
+    prefix: String,
+    methods: Vec<ServerMethod>,
+}
+
+impl TestService {
+    /// Service with given name, e. g. `/helloworld.Greeter`.
+    pub fn new(prefix: &str) -> TestService {
+        TestService {
+            prefix: prefix.to_owned(),
+            methods: Vec::new(),
+        }
+    }
+
+    /// Add unary method which replies with the result of `f`.
+    ///
+    /// `Error::Status` returned by `f` is sent to client as is.
+    pub fn unary<Req, Resp, F>(
+        mut self,
+        method: ArcOrStatic<MethodDescriptor<Req, Resp>>,
+        f: F,
+    ) -> TestService
+    where
+        Req: Send + 'static,
+        Resp: Send + 'static,
+        F: Fn(Req) -> Result<Resp> + Send + Sync + 'static,
+    {
+        self.methods.push(ServerMethod::new(
+            method,
+            MethodHandlerUnary::new(move |ctx, req, resp| {
+                let r = SingleResponse::no_metadata(future::result(f(req.message)));
+                ctx.pump_single_response(r, resp);
+                Ok(())
+            }),
+        ));
+        self
+    }
+
+    /// Add server streaming method which replies with messages returned by `f`.
+    pub fn server_streaming<Req, Resp, F>(
+        mut self,
+        method: ArcOrStatic<MethodDescriptor<Req, Resp>>,
+        f: F,
+    ) -> TestService
+    where
+        Req: Send + 'static,
+        Resp: Send + 'static,
+        F: Fn(Req) -> Result<Vec<Resp>> + Send + Sync + 'static,
+    {
+        self.methods.push(ServerMethod::new(
+            method,
+            MethodHandlerServerStreaming::new(move |ctx, req, resp| {
+                let r = match f(req.message) {
+                    Ok(messages) => StreamingResponse::completed(messages),
+                    Err(e) => StreamingResponse::err(e),
+                };
+                ctx.pump_response(r, resp);
+                Ok(())
+            }),
+        ));
+        self
+    }
+
+    pub fn build(self) -> ServerServiceDefinition {
+        ServerServiceDefinition::new(&self.prefix, self.methods)
+    }
+}
+
+/// Call captured by `CallRecorder`.
+#[derive(Debug, Clone)]
+pub struct RecordedCall {
+    /// Full method name, e. g. `/helloworld.Greeter/SayHello`.
+    pub method: String,
+    pub metadata: Metadata,
+}
+
+/// Records method and metadata of each call.
+///
+/// Register it on client with `ClientBuilder::interceptor`
+/// or on server with `ServerBuilder::set_auth_handler`;
+/// calls are never rejected.
+#[derive(Clone, Default)]
+pub struct CallRecorder {
+    calls: Arc<Mutex<Vec<RecordedCall>>>,
+}
+
+impl CallRecorder {
+    pub fn new() -> CallRecorder {
+        Default::default()
+    }
+
+    /// Calls recorded so far, in order they were started.
+    pub fn calls(&self) -> Vec<RecordedCall> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    /// Names of methods called so far.
+    pub fn methods(&self) -> Vec<String> {
+        self.calls
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|c| c.method.clone())
+            .collect()
+    }
+
+    pub fn clear(&self) {
+        self.calls.lock().unwrap().clear();
+    }
+
+    fn record(&self, method: &str, metadata: &Metadata) {
+        self.calls.lock().unwrap().push(RecordedCall {
+            method: method.to_owned(),
+            metadata: metadata.clone(),
+        });
+    }
+}
+
+impl ClientInterceptor for CallRecorder {
+    fn intercept(&self, method: &str, options: &mut RequestOptions) -> Result<()> {
+        self.record(method, &options.metadata);
+        Ok(())
+    }
+}
+
+impl ServerAuthHandler for CallRecorder {
+    fn authorize(&self, method: &str, metadata: &Metadata) -> Result<()> {
+        self.record(method, metadata);
+        Ok(())
+    }
+}
+
+/// Reply of `ScriptedServer` to a single call.
+#[derive(Debug, Clone)]
+pub struct ScriptedReply {
+    latency: Duration,
+    result: ::std::result::Result<Vec<Bytes>, Status>,
+}
+
+impl ScriptedReply {
+    /// Reply with single serialized message.
+    pub fn message<B: Into<Bytes>>(message: B) -> ScriptedReply {
+        ScriptedReply::messages(vec![message.into()])
+    }
+
+    /// Reply with serialized messages of server streaming response.
+    pub fn messages(messages: Vec<Bytes>) -> ScriptedReply {
+        ScriptedReply {
+            latency: Duration::from_secs(0),
+            result: Ok(messages),
+        }
+    }
+
+    /// Fail the call with given status.
+    pub fn status<S: Into<String>>(code: GrpcStatus, message: S) -> ScriptedReply {
+        ScriptedReply {
+            latency: Duration::from_secs(0),
+            result: Err(Status::new(code, message)),
+        }
+    }
+
+    /// Reply after given time.
+    pub fn with_latency(mut self, latency: Duration) -> ScriptedReply {
+        self.latency = latency;
+        self
+    }
+}
+
+type Script = Arc<Mutex<HashMap<String, VecDeque<ScriptedReply>>>>;
+
+/// Server which answers calls of any method with scripted replies,
+/// e. g. to test client retries and timeouts.
+///
+/// Replies of a method are used in order they are added;
+/// calls without a reply fail with `UNIMPLEMENTED`.
+/// Request messages are read and ignored.
+pub struct ScriptedServer {
+    script: Script,
+    recorder: CallRecorder,
+    server: TestServer,
+}
+
+impl ScriptedServer {
+    pub fn start() -> Result<ScriptedServer> {
+        let script = Script::default();
+        let recorder = CallRecorder::new();
+
+        let mut server = ServerBuilder::new_plain();
+        server.set_auth_handler(Arc::new(recorder.clone()));
+        let script_copy = script.clone();
+        server.set_fallback_method(ServerMethod::raw(move |ctx, req, resp| {
+            let reply = script_copy
+                .lock()
+                .unwrap()
+                .get_mut(ctx.method())
+                .and_then(|replies| replies.pop_front());
+            let reply = reply.unwrap_or_else(|| {
+                ScriptedReply::status(
+                    GrpcStatus::Unimplemented,
+                    format!("no scripted reply for {}", ctx.method()),
+                )
+            });
+            req.register_stream_handler_basic(|_message| Ok(()));
+            let result = reply.result;
+            let messages = sleep(reply.latency).then(move |_| -> Result<GrpcStream<Bytes>> {
+                match result {
+                    Ok(messages) => Ok(Box::new(stream::iter_ok(messages))),
+                    Err(status) => Err(Error::Status(status)),
+                }
+            });
+            ctx.pump_response(
+                StreamingResponse::no_metadata(messages.flatten_stream()),
+                resp,
+            );
+            Ok(())
+        }));
+
+        Ok(ScriptedServer {
+            script,
+            recorder,
+            server: TestServer::start_with(server)?,
+        })
+    }
+
+    /// Add reply to the next call of the method, e. g. `/helloworld.Greeter/SayHello`.
+    pub fn script(&self, method: &str, reply: ScriptedReply) {
+        self.script
+            .lock()
+            .unwrap()
+            .entry(method.to_owned())
+            .or_insert_with(VecDeque::new)
+            .push_back(reply);
+    }
+
+    /// Calls received so far.
+    pub fn calls(&self) -> Vec<RecordedCall> {
+        self.recorder.calls()
+    }
+
+    pub fn port(&self) -> u16 {
+        self.server.port()
+    }
+
+    /// Client connected to this server.
+    pub fn client(&self) -> Arc<Client> {
+        self.server.client()
+    }
+}
+
+/// Server listening on a loopback address, with a client connected to it.
+///
+/// On unix server listens on a unix socket in temporary directory,
+/// so tests do not need to allocate TCP ports; elsewhere it listens
+/// on `127.0.0.1` port assigned by OS. Calls go through HTTP/2 over the socket,
+/// there is no in-memory transport.
+pub struct LoopbackServer {
+    client: Arc<Client>,
+    // dropped after client
+    _server: Server,
+}
+
+impl LoopbackServer {
+    pub fn new(def: ServerServiceDefinition) -> Result<LoopbackServer> {
+        LoopbackServer::with_services(vec![def])
+    }
+
+    pub fn with_services(defs: Vec<ServerServiceDefinition>) -> Result<LoopbackServer> {
+        let mut server = ServerBuilder::new_plain();
+        for def in defs {
+            server.add_service(def);
+        }
+        LoopbackServer::start(server)
+    }
+
+    #[cfg(unix)]
+    fn start(mut server: ServerBuilder) -> Result<LoopbackServer> {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+        let path = ::std::env::temp_dir().join(format!(
+            "grpc-rust-loopback-{}-{}",
+            ::std::process::id(),
+            NEXT_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        let path = path
+            .to_str()
+            .ok_or(Error::Other("temp dir path is not UTF-8"))?;
+
+        server.set_unix_addr(path)?;
+        let server = server.build()?;
+        let client = ClientBuilder::new_unix(path).build()?;
+        Ok(LoopbackServer {
+            client: Arc::new(client),
+            _server: server,
+        })
+    }
+
+    #[cfg(not(unix))]
+    fn start(mut server: ServerBuilder) -> Result<LoopbackServer> {
+        server.http.set_addr("127.0.0.1:0")?;
+        let server = server.build()?;
+        let port = server.local_addr().port()?;
+        let client = ClientBuilder::new("127.0.0.1", port).build()?;
+        Ok(LoopbackServer {
+            client: Arc::new(client),
+            _server: server,
+        })
+    }
+
+    /// Client connected to this server.
+    pub fn client(&self) -> Arc<Client> {
+        self.client.clone()
+    }
+
+    /// Channel connected to this server.
+    pub fn channel(&self) -> Channel {
+        Channel::from(self.client.clone())
+    }
+
+    /// Generated client stub connected to this server.
+    pub fn client_stub<C: ClientStub>(&self) -> C {
+        C::with_client(self.client.clone())
+    }
+}
+
+/// Response of a mocked method returned when the call is not expected.
+pub trait MockResponse {
+    fn unexpected(method: &str) -> Self;
+}
+
+fn unexpected_call_error(method: &str) -> Error {
+    Error::Status(Status::new(
+        GrpcStatus::Unimplemented,
+        format!("unexpected call of mocked method {}", method),
+    ))
+}
+
+impl<T: Send + 'static> MockResponse for SingleResponse<T> {
+    fn unexpected(method: &str) -> Self {
+        SingleResponse::err(unexpected_call_error(method))
+    }
+}
+
+impl<T: Send + 'static> MockResponse for StreamingResponse<T> {
+    fn unexpected(method: &str) -> Self {
+        StreamingResponse::err(unexpected_call_error(method))
+    }
+}
+
+/// Method of a mock client generated with `mock_client` codegen option.
+///
+/// `Req` is request message, or `StreamingRequest` of messages sent
+/// to request sink for client streaming and bidi methods,
+/// `R` is `SingleResponse` or `StreamingResponse`.
+///
+/// Calls are answered by expectations in order they are added,
+/// then by `always` handler; other calls fail with `UNIMPLEMENTED`.
+pub struct MockMethod<Req, R> {
+    name: &'static str,
+    expectations: Mutex<VecDeque<Box<FnOnce(RequestOptions, Req) -> R + Send>>>,
+    always: Mutex<Option<Arc<Fn(RequestOptions, Req) -> R + Send + Sync>>>,
+    calls: AtomicUsize,
+}
+
+impl<Req, R: MockResponse> MockMethod<Req, R> {
+    /// Full method name, e. g. `/helloworld.Greeter/SayHello`.
+    pub fn new(name: &'static str) -> MockMethod<Req, R> {
+        MockMethod {
+            name,
+            expectations: Mutex::new(VecDeque::new()),
+            always: Mutex::new(None),
+            calls: AtomicUsize::new(0),
+        }
+    }
+
+    /// Answer the next call with given function.
+    pub fn expect<F>(&self, f: F)
+    where
+        F: FnOnce(RequestOptions, Req) -> R + Send + 'static,
+    {
+        self.expectations.lock().unwrap().push_back(Box::new(f));
+    }
+
+    /// Answer calls not matched by expectations with given function.
+    pub fn always<F>(&self, f: F)
+    where
+        F: Fn(RequestOptions, Req) -> R + Send + Sync + 'static,
+    {
+        *self.always.lock().unwrap() = Some(Arc::new(f));
+    }
+
+    /// Number of calls made so far.
+    pub fn call_count(&self) -> usize {
+        self.calls.load(Ordering::SeqCst)
+    }
+
+    /// Panic if some expectations are not met.
+    pub fn verify(&self) {
+        let pending = self.expectations.lock().unwrap().len();
+        if pending != 0 {
+            panic!("{} expected calls of {} were not made", pending, self.name);
+        }
+    }
+
+    /// Called by generated mock client.
+    pub fn call(&self, o: RequestOptions, req: Req) -> R {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        let expectation = self.expectations.lock().unwrap().pop_front();
+        if let Some(f) = expectation {
+            return f(o, req);
+        }
+        // cloned so handler may call the mock recursively
+        let always = self.always.lock().unwrap().clone();
+        match always {
+            Some(f) => f(o, req),
+            None => R::unexpected(self.name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn mock_method() {
+        let method = MockMethod::<u32, SingleResponse<u32>>::new("/a/b");
+        method.expect(|_o, req| SingleResponse::completed(req + 1));
+        method.always(|_o, req| SingleResponse::completed(req * 10));
+
+        assert_eq!(
+            2,
+            method
+                .call(RequestOptions::new(), 1)
+                .wait_drop_metadata()
+                .unwrap()
+        );
+        assert_eq!(
+            20,
+            method
+                .call(RequestOptions::new(), 2)
+                .wait_drop_metadata()
+                .unwrap()
+        );
+        assert_eq!(2, method.call_count());
+        method.verify();
+
+        let unexpected = MockMethod::<u32, SingleResponse<u32>>::new("/a/c");
+        match unexpected
+            .call(RequestOptions::new(), 1)
+            .wait_drop_metadata()
+        {
+            Err(Error::Status(ref s)) if s.code == GrpcStatus::Unimplemented => {}
+            r => panic!("expecting UNIMPLEMENTED, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn mock_client_streaming_method() {
+        use req::StreamingRequest;
+        use RequestSink;
+
+        let method = MockMethod::<StreamingRequest<u32>, SingleResponse<u32>>::new("/a/sum");
+        method.expect(|_o, reqs| SingleResponse::no_metadata(reqs.fold(0, |a, b| a + b)));
+
+        let (mut sink, reqs) = RequestSink::channel();
+        let resp = method.call(RequestOptions::new(), reqs);
+        sink.send_data(1).unwrap();
+        sink.send_data(2).unwrap();
+        sink.finish().unwrap();
+        assert_eq!(3, resp.wait_drop_metadata().unwrap());
+    }
+}
@@ -0,0 +1,169 @@
+//! Timer used to enforce call deadlines.
+
+use std::sync::mpsc;
+use std::sync::Once;
+use std::thread;
+use std::time::Duration;
+
+use futures::future;
+use futures::future::Future;
+use futures::future::Shared;
+use futures::stream::Stream;
+use futures::sync::oneshot;
+use futures::Async;
+use futures::Poll;
+use tokio_core::reactor::Core;
+use tokio_core::reactor::Remote;
+use tokio_core::reactor::Timeout;
+
+use error::Error;
+use error::Status;
+use proto::grpc_status::GrpcStatus;
+
+/// Event loop of the timer thread, started on first use.
+fn timer_remote() -> Remote {
+    static INIT: Once = Once::new();
+    static mut REMOTE: Option<Remote> = None;
+
+    unsafe {
+        INIT.call_once(|| {
+            let (tx, rx) = mpsc::channel();
+            thread::Builder::new()
+                .name("grpc-timer".to_owned())
+                .spawn(move || {
+                    let mut core = Core::new().expect("Core::new");
+                    tx.send(core.remote()).unwrap();
+                    core.run(future::empty::<(), ()>()).unwrap();
+                })
+                .expect("spawn timer thread");
+            REMOTE = Some(rx.recv().unwrap());
+        });
+        REMOTE.clone().unwrap()
+    }
+}
+
+/// Future resolved after given duration.
+///
+/// Can be cloned to be polled from several futures or streams.
+pub(crate) type Sleep = Shared<oneshot::Receiver<()>>;
+
+pub(crate) fn sleep(duration: Duration) -> Sleep {
+    let (tx, rx) = oneshot::channel();
+    timer_remote().spawn(move |handle| {
+        Timeout::new(duration, handle)
+            .expect("Timeout::new")
+            .then(move |_| {
+                let _ = tx.send(());
+                Ok(())
+            })
+    });
+    rx.shared()
+}
+
+pub(crate) fn deadline_exceeded() -> Error {
+    Error::Status(Status::new(
+        GrpcStatus::DeadlineExceeded,
+        "deadline exceeded",
+    ))
+}
+
+pub(crate) fn is_expired(sleep: &mut Sleep) -> bool {
+    match sleep.poll() {
+        Ok(Async::Ready(..)) => true,
+        // timer thread is gone, never expire
+        Ok(Async::NotReady) | Err(..) => false,
+    }
+}
+
+/// Future or stream which fails with `DEADLINE_EXCEEDED` when sleep completes.
+pub(crate) struct WithDeadline<S> {
+    pub inner: S,
+    pub sleep: Sleep,
+}
+
+impl<F: Future<Error = Error>> Future for WithDeadline<F> {
+    type Item = F::Item;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<F::Item, Error> {
+        if let Async::Ready(r) = self.inner.poll()? {
+            return Ok(Async::Ready(r));
+        }
+        if is_expired(&mut self.sleep) {
+            return Err(deadline_exceeded());
+        }
+        Ok(Async::NotReady)
+    }
+}
+
+impl<S: Stream<Error = Error>> Stream for WithDeadline<S> {
+    type Item = S::Item;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<S::Item>, Error> {
+        if let Async::Ready(r) = self.inner.poll()? {
+            return Ok(Async::Ready(r));
+        }
+        if is_expired(&mut self.sleep) {
+            return Err(deadline_exceeded());
+        }
+        Ok(Async::NotReady)
+    }
+}
+
+/// Stream which fails with `DEADLINE_EXCEEDED` when it is not ready
+/// for `timeout` after it was last ready.
+pub(crate) struct WithItemTimeout<S> {
+    inner: S,
+    timeout: Duration,
+    /// Started lazily when the inner stream is not ready
+    sleep: Option<Sleep>,
+}
+
+impl<S> WithItemTimeout<S> {
+    pub fn new(inner: S, timeout: Duration) -> WithItemTimeout<S> {
+        WithItemTimeout {
+            inner,
+            timeout,
+            sleep: None,
+        }
+    }
+}
+
+impl<S: Stream<Error = Error>> Stream for WithItemTimeout<S> {
+    type Item = S::Item;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<S::Item>, Error> {
+        if let Async::Ready(r) = self.inner.poll()? {
+            self.sleep = None;
+            return Ok(Async::Ready(r));
+        }
+        let timeout = self.timeout;
+        if is_expired(self.sleep.get_or_insert_with(|| sleep(timeout))) {
+            return Err(Error::Status(Status::new(
+                GrpcStatus::DeadlineExceeded,
+                format!("no message received in {:?}", timeout),
+            )));
+        }
+        Ok(Async::NotReady)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn deadline() {
+        let r = WithDeadline {
+            inner: future::empty::<(), Error>(),
+            sleep: sleep(Duration::from_millis(1)),
+        }
+        .wait();
+        match r {
+            Err(Error::Status(e)) => assert_eq!(GrpcStatus::DeadlineExceeded, e.code),
+            r => panic!("{:?}", r),
+        }
+    }
+}
@@ -0,0 +1,347 @@
+//! Propagation of distributed tracing context.
+//!
+//! Context is passed in W3C `traceparent` metadata and in binary
+//! `grpc-trace-bin` metadata used by OpenCensus-based gRPC implementations.
+//! Call id used to correlate client and server logs is passed in `x-call-id` metadata.
+
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::fmt::Write;
+use std::hash::BuildHasher;
+use std::hash::Hasher;
+
+use bytes::Bytes;
+
+use client::interceptor::ClientInterceptor;
+use proto::metadata::Metadata;
+use proto::metadata::MetadataKey;
+use req::RequestOptions;
+use result;
+
+pub(crate) const HEADER_TRACEPARENT: &str = "traceparent";
+pub(crate) const HEADER_GRPC_TRACE_BIN: &str = "grpc-trace-bin";
+pub(crate) const HEADER_CALL_ID: &str = "x-call-id";
+
+/// Trace id, id of the current span and sampling decision.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceContext {
+    pub trace_id: [u8; 16],
+    pub span_id: [u8; 8],
+    pub sampled: bool,
+}
+
+fn random_u64() -> u64 {
+    // `RandomState` is randomly seeded, good enough for ids
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(0);
+    hasher.finish()
+}
+
+fn write_hex(s: &mut String, bytes: &[u8]) {
+    for b in bytes {
+        write!(s, "{:02x}", b).unwrap();
+    }
+}
+
+fn parse_hex(s: &str, out: &mut [u8]) -> Option<()> {
+    if s.len() != out.len() * 2 {
+        return None;
+    }
+    for (i, b) in out.iter_mut().enumerate() {
+        *b = u8::from_str_radix(s.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(())
+}
+
+impl TraceContext {
+    /// Start a new trace.
+    pub fn new_root(sampled: bool) -> TraceContext {
+        let mut trace_id = [0; 16];
+        trace_id[..8].copy_from_slice(&random_u64().to_be_bytes());
+        trace_id[8..].copy_from_slice(&random_u64().to_be_bytes());
+        TraceContext {
+            trace_id,
+            span_id: random_u64().to_be_bytes(),
+            sampled,
+        }
+    }
+
+    /// Context for a new span in the same trace.
+    pub fn child(&self) -> TraceContext {
+        TraceContext {
+            span_id: random_u64().to_be_bytes(),
+            ..*self
+        }
+    }
+
+    /// Format as W3C `traceparent` header value.
+    pub fn to_traceparent(&self) -> String {
+        let mut r = String::from("00-");
+        write_hex(&mut r, &self.trace_id);
+        r.push('-');
+        write_hex(&mut r, &self.span_id);
+        r.push_str(if self.sampled { "-01" } else { "-00" });
+        r
+    }
+
+    /// Parse W3C `traceparent` header value.
+    pub fn parse_traceparent(s: &str) -> Option<TraceContext> {
+        let parts: Vec<&str> = s.trim().split('-').collect();
+        if parts.len() < 4 || parts[0] == "ff" || parts[0].len() != 2 {
+            return None;
+        }
+        let mut r = TraceContext {
+            trace_id: [0; 16],
+            span_id: [0; 8],
+            sampled: false,
+        };
+        parse_hex(parts[1], &mut r.trace_id)?;
+        parse_hex(parts[2], &mut r.span_id)?;
+        let mut flags = [0];
+        parse_hex(parts[3], &mut flags)?;
+        r.sampled = flags[0] & 1 != 0;
+        Some(r)
+    }
+
+    /// Encode in `grpc-trace-bin` format.
+    pub fn to_grpc_trace_bin(&self) -> Vec<u8> {
+        let mut r = Vec::with_capacity(29);
+        // version
+        r.push(0);
+        r.push(0);
+        r.extend_from_slice(&self.trace_id);
+        r.push(1);
+        r.extend_from_slice(&self.span_id);
+        r.push(2);
+        r.push(self.sampled as u8);
+        r
+    }
+
+    /// Parse `grpc-trace-bin` value.
+    pub fn parse_grpc_trace_bin(bytes: &[u8]) -> Option<TraceContext> {
+        if bytes.first() != Some(&0) {
+            return None;
+        }
+        let mut r = TraceContext {
+            trace_id: [0; 16],
+            span_id: [0; 8],
+            sampled: false,
+        };
+        let mut pos = 1;
+        while pos < bytes.len() {
+            let field = bytes[pos];
+            pos += 1;
+            let len = match field {
+                0 => 16,
+                1 => 8,
+                2 => 1,
+                // fields are ordered, so unknown field ends known fields
+                _ => break,
+            };
+            let value = bytes.get(pos..pos + len)?;
+            match field {
+                0 => r.trace_id.copy_from_slice(value),
+                1 => r.span_id.copy_from_slice(value),
+                _ => r.sampled = value[0] & 1 != 0,
+            }
+            pos += len;
+        }
+        Some(r)
+    }
+
+    /// Extract context from request metadata.
+    pub fn from_metadata(metadata: &Metadata) -> Option<TraceContext> {
+        if let Some(value) = metadata.get(HEADER_TRACEPARENT) {
+            if let Some(r) = ::std::str::from_utf8(value)
+                .ok()
+                .and_then(TraceContext::parse_traceparent)
+            {
+                return Some(r);
+            }
+        }
+        metadata
+            .get(HEADER_GRPC_TRACE_BIN)
+            .and_then(TraceContext::parse_grpc_trace_bin)
+    }
+
+    /// Add context to request metadata.
+    pub fn inject(&self, metadata: &mut Metadata) {
+        metadata.add(
+            MetadataKey::from(HEADER_TRACEPARENT),
+            Bytes::from(self.to_traceparent()),
+        );
+        metadata.add(
+            MetadataKey::from(HEADER_GRPC_TRACE_BIN),
+            Bytes::from(self.to_grpc_trace_bin()),
+        );
+    }
+}
+
+/// Id of a single call, attached to log records of both client and server.
+///
+/// Generated ids are 16 hex digits, but any printable ASCII value
+/// received from peer is accepted, so ids of other systems are kept.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CallId(String);
+
+impl CallId {
+    /// Generate a new random id.
+    pub fn generate() -> CallId {
+        let mut r = String::with_capacity(16);
+        write_hex(&mut r, &random_u64().to_be_bytes());
+        CallId(r)
+    }
+
+    /// Use given string as call id, `None` if it is empty or not printable ASCII.
+    pub fn parse(s: &str) -> Option<CallId> {
+        if s.is_empty() || !s.bytes().all(|b| (0x21..=0x7e).contains(&b)) {
+            return None;
+        }
+        Some(CallId(s.to_owned()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Extract call id from request metadata.
+    pub fn from_metadata(metadata: &Metadata) -> Option<CallId> {
+        metadata
+            .get(HEADER_CALL_ID)
+            .and_then(|value| ::std::str::from_utf8(value).ok())
+            .and_then(CallId::parse)
+    }
+
+    /// Add call id to request metadata, replacing previous call id.
+    pub fn inject(&self, metadata: &mut Metadata) {
+        metadata
+            .entries
+            .retain(|e| e.key.as_str() != HEADER_CALL_ID);
+        metadata.add(
+            MetadataKey::from(HEADER_CALL_ID),
+            Bytes::from(self.0.clone()),
+        );
+    }
+}
+
+impl fmt::Display for CallId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Client interceptor which adds trace context returned by a function
+/// (e. g. context of the current span) to requests which do not have it.
+pub struct TraceContextInterceptor<F> {
+    current: F,
+}
+
+impl<F> TraceContextInterceptor<F>
+where
+    F: Fn() -> Option<TraceContext> + Send + Sync + 'static,
+{
+    pub fn new(current: F) -> TraceContextInterceptor<F> {
+        TraceContextInterceptor { current }
+    }
+}
+
+impl<F> ClientInterceptor for TraceContextInterceptor<F>
+where
+    F: Fn() -> Option<TraceContext> + Send + Sync + 'static,
+{
+    fn intercept(&self, _method: &str, options: &mut RequestOptions) -> result::Result<()> {
+        if TraceContext::from_metadata(&options.metadata).is_some() {
+            return Ok(());
+        }
+        if let Some(context) = (self.current)() {
+            context.child().inject(&mut options.metadata);
+        }
+        Ok(())
+    }
+}
+
+/// Stats handler which emits a `tracing` event per finished call.
+#[cfg(feature = "with-tracing")]
+pub struct TracingStatsHandler;
+
+#[cfg(feature = "with-tracing")]
+mod tracing_stats {
+    use std::time::Duration;
+
+    use proto::grpc_status::GrpcStatus;
+    use stats::ClientStatsHandler;
+    use stats::ServerStatsHandler;
+
+    use super::TracingStatsHandler;
+
+    impl ServerStatsHandler for TracingStatsHandler {
+        fn call_finished(&self, method: &str, status: GrpcStatus, duration: Duration) {
+            let span = tracing::info_span!("grpc_server_call", method);
+            let _enter = span.enter();
+            tracing::info!(
+                grpc_status = status.code(),
+                duration_us = duration.as_micros() as u64,
+                "call finished"
+            );
+        }
+    }
+
+    impl ClientStatsHandler for TracingStatsHandler {
+        fn call_finished(&self, method: &str, status: GrpcStatus, duration: Duration) {
+            let span = tracing::info_span!("grpc_client_call", method);
+            let _enter = span.enter();
+            tracing::info!(
+                grpc_status = status.code(),
+                duration_us = duration.as_micros() as u64,
+                "call finished"
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn traceparent() {
+        let s = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+        let context = TraceContext::parse_traceparent(s).unwrap();
+        assert!(context.sampled);
+        assert_eq!(0xb7, context.span_id[0]);
+        assert_eq!(s, context.to_traceparent());
+    }
+
+    #[test]
+    fn grpc_trace_bin() {
+        let context = TraceContext::new_root(true);
+        let bin = context.to_grpc_trace_bin();
+        assert_eq!(29, bin.len());
+        assert_eq!(Some(context), TraceContext::parse_grpc_trace_bin(&bin));
+    }
+
+    #[test]
+    fn child() {
+        let context = TraceContext::new_root(false);
+        let child = context.child();
+        assert_eq!(context.trace_id, child.trace_id);
+        assert_ne!(context.span_id, child.span_id);
+    }
+
+    #[test]
+    fn call_id() {
+        let id = CallId::generate();
+        assert_eq!(16, id.as_str().len());
+        assert_ne!(id, CallId::generate());
+
+        let mut metadata = Metadata::new();
+        CallId::generate().inject(&mut metadata);
+        id.inject(&mut metadata);
+        assert_eq!(1, metadata.entries.len());
+        assert_eq!(Some(id), CallId::from_metadata(&metadata));
+
+        assert_eq!(None, CallId::parse(""));
+        assert_eq!(None, CallId::parse("a b"));
+        assert_eq!("req-1", CallId::parse("req-1").unwrap().as_str());
+    }
+}
@@ -51,3 +51,484 @@ fn server_is_not_running_unix() {
         assert!(result.is_err(), result);
     }
 }
+
+#[test]
+fn connection_pool() {
+    use futures::Future;
+    use grpc::rt::MethodHandlerUnary;
+    use grpc::rt::ServerMethod;
+    use grpc::rt::ServerServiceDefinition;
+
+    fn echo_fn(
+        _: ServerHandlerContext,
+        req: ServerRequestSingle<String>,
+        resp: ServerResponseUnarySink<String>,
+    ) -> grpc::Result<()> {
+        resp.finish(req.message)
+    }
+
+    init_logger();
+
+    let echo = string_string_method("/foo/echo", GrpcStreaming::Unary);
+
+    let mut server = ServerBuilder::new_plain();
+    server.http.set_port(0);
+    server.add_service(ServerServiceDefinition::new(
+        "/foo",
+        vec![ServerMethod::new(
+            echo.clone(),
+            MethodHandlerUnary::new(echo_fn),
+        )],
+    ));
+    let server = server.build().expect("server");
+    let port = server.local_addr().port().expect("port");
+
+    let mut conf = ClientConf::new();
+    conf.max_connections = Some(2);
+    conf.max_streams_per_connection = Some(1);
+    let client = ClientBuilder::new(BIND_HOST, port)
+        .conf(conf)
+        .build()
+        .unwrap();
+
+    let calls: Vec<_> = (0..4)
+        .map(|i| {
+            client
+                .call_unary(RequestOptions::new(), format!("{}", i), echo.clone())
+                .drop_metadata()
+        })
+        .collect();
+
+    assert_eq!(
+        vec!["0", "1", "2", "3"],
+        futures::future::join_all(calls).wait().unwrap()
+    );
+}
+
+#[test]
+fn connect_timeout() {
+    use std::time::Duration;
+    use std::time::Instant;
+
+    init_logger();
+
+    let mut conf = ClientConf::new();
+    conf.connect_timeout = Some(Duration::from_millis(200));
+    // non-routable address, connect would hang without timeout
+    let client = ClientBuilder::new("10.255.255.1", 80)
+        .conf(conf)
+        .build()
+        .unwrap();
+
+    let start = Instant::now();
+    let result = client
+        .call_unary(
+            RequestOptions::new(),
+            "aa".to_owned(),
+            string_string_method("/does/not/matter", GrpcStreaming::Unary),
+        )
+        .wait();
+    assert!(result.is_err(), result);
+    assert!(start.elapsed() < Duration::from_secs(10));
+}
+
+#[test]
+fn deadline_while_connecting() {
+    use std::time::Duration;
+    use std::time::Instant;
+
+    init_logger();
+
+    // non-routable address, without `connect_timeout`
+    // connect hangs until the call deadline
+    let client = ClientBuilder::new("10.255.255.1", 80).build().unwrap();
+
+    let mut options = RequestOptions::new();
+    options.timeout = Some(Duration::from_millis(200));
+    let start = Instant::now();
+    let result = client
+        .call_unary(
+            options,
+            "aa".to_owned(),
+            string_string_method("/does/not/matter", GrpcStreaming::Unary),
+        )
+        .wait();
+    // `DEADLINE_EXCEEDED`, or `UNAVAILABLE` where the network is unreachable
+    assert!(result.is_err(), result);
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn service_config() {
+    use futures::Future;
+    use grpc::rt::MethodHandlerUnary;
+    use grpc::rt::ServerMethod;
+    use grpc::rt::ServerServiceDefinition;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+    use std::time::Duration;
+
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    // fails first two calls
+    fn flaky_fn(
+        _: ServerHandlerContext,
+        req: ServerRequestSingle<String>,
+        resp: ServerResponseUnarySink<String>,
+    ) -> grpc::Result<()> {
+        if CALLS.fetch_add(1, Ordering::SeqCst) < 2 {
+            return resp.send_grpc_error(GrpcStatus::Unavailable, "try again".to_owned());
+        }
+        resp.finish(req.message)
+    }
+
+    init_logger();
+
+    let flaky = string_string_method("/foo/flaky", GrpcStreaming::Unary);
+
+    let mut server = ServerBuilder::new_plain();
+    server.http.set_port(0);
+    server.add_service(ServerServiceDefinition::new(
+        "/foo",
+        vec![ServerMethod::new(
+            flaky.clone(),
+            MethodHandlerUnary::new(flaky_fn),
+        )],
+    ));
+    let server = server.build().expect("server");
+    let port = server.local_addr().port().expect("port");
+
+    let mut conf = ClientConf::new();
+    conf.service_config.set_method_config(
+        "/foo/flaky",
+        MethodConfig {
+            max_response_message_bytes: Some(5),
+            retry_policy: Some(RetryPolicy {
+                max_attempts: 3,
+                initial_backoff: Duration::from_millis(10),
+                max_backoff: Duration::from_millis(100),
+                backoff_multiplier: 2.0,
+                retryable_status_codes: vec![GrpcStatus::Unavailable],
+            }),
+            ..Default::default()
+        },
+    );
+    let client = ClientBuilder::new(BIND_HOST, port)
+        .conf(conf)
+        .build()
+        .unwrap();
+
+    assert_eq!(
+        "abc",
+        client
+            .call_unary(RequestOptions::new(), "abc".to_owned(), flaky.clone())
+            .drop_metadata()
+            .wait()
+            .unwrap()
+    );
+    assert_eq!(3, CALLS.load(Ordering::SeqCst));
+
+    match client
+        .call_unary(RequestOptions::new(), "abcdef".to_owned(), flaky)
+        .drop_metadata()
+        .wait()
+    {
+        Err(Error::Status(e)) => assert_eq!(GrpcStatus::ResourceExhausted, e.code),
+        r => panic!("expecting RESOURCE_EXHAUSTED, got {:?}", r),
+    }
+}
+
+#[test]
+fn connect_to_and_authority() {
+    use std::sync::Arc;
+
+    use futures::future;
+    use futures::Future;
+    use grpc::rt::MethodHandlerUnary;
+    use grpc::rt::ServerMethod;
+    use grpc::rt::ServerServiceDefinition;
+
+    /// Pass authority seen by client to server as metadata.
+    struct AuthorityCredentials;
+
+    impl CallCredentials for AuthorityCredentials {
+        fn get_metadata(&self, context: &CallCredentialsContext) -> GrpcFuture<Metadata> {
+            let mut metadata = Metadata::new();
+            metadata.add(
+                MetadataKey::from("x-authority"),
+                context.authority.to_owned().into(),
+            );
+            Box::new(future::ok(metadata))
+        }
+
+        fn allow_insecure(&self) -> bool {
+            true
+        }
+    }
+
+    fn authority_fn(
+        ctx: ServerHandlerContext,
+        _req: ServerRequestSingle<String>,
+        resp: ServerResponseUnarySink<String>,
+    ) -> grpc::Result<()> {
+        let authority = ctx.metadata.get("x-authority").unwrap_or(b"");
+        resp.finish(String::from_utf8_lossy(authority).into_owned())
+    }
+
+    init_logger();
+
+    let method = string_string_method("/foo/authority", GrpcStreaming::Unary);
+
+    let mut server = ServerBuilder::new_plain();
+    server.http.set_port(0);
+    server.add_service(ServerServiceDefinition::new(
+        "/foo",
+        vec![ServerMethod::new(
+            method.clone(),
+            MethodHandlerUnary::new(authority_fn),
+        )],
+    ));
+    let server = server.build().expect("server");
+    let port = server.local_addr().port().expect("port");
+
+    let call = |conf: ClientConf| {
+        // host does not resolve, so the call succeeds only if `connect_to` is used
+        let client = ClientBuilder::new("backend.invalid", 443)
+            .conf(conf)
+            .call_credentials(Arc::new(AuthorityCredentials))
+            .build()
+            .expect("client");
+        client
+            .call_unary(RequestOptions::new(), String::new(), method.clone())
+            .drop_metadata()
+            .wait()
+            .expect("call")
+    };
+
+    let mut conf = ClientConf::new();
+    conf.connect_to = Some((BIND_HOST.to_owned(), port));
+    assert_eq!("backend.invalid:443", call(conf.clone()));
+
+    conf.authority = Some("override.invalid".to_owned());
+    assert_eq!("override.invalid", call(conf));
+}
+
+#[test]
+fn peer_addrs() {
+    use std::net::SocketAddr;
+
+    use futures::Future;
+    use grpc::rt::MethodHandlerUnary;
+    use grpc::rt::ServerMethod;
+    use grpc::rt::ServerServiceDefinition;
+
+    fn echo_fn(
+        _: ServerHandlerContext,
+        req: ServerRequestSingle<String>,
+        resp: ServerResponseUnarySink<String>,
+    ) -> grpc::Result<()> {
+        resp.finish(req.message)
+    }
+
+    init_logger();
+
+    let echo = string_string_method("/foo/echo", GrpcStreaming::Unary);
+
+    let mut server = ServerBuilder::new_plain();
+    server.http.set_port(0);
+    server.add_service(ServerServiceDefinition::new(
+        "/foo",
+        vec![ServerMethod::new(
+            echo.clone(),
+            MethodHandlerUnary::new(echo_fn),
+        )],
+    ));
+    let server = server.build().expect("server");
+    let port = server.local_addr().port().expect("port");
+
+    let client = ClientBuilder::new(BIND_HOST, port).build().unwrap();
+    // connection is opened by the first call
+    assert!(client.peer_addrs().is_empty());
+
+    assert_eq!(
+        "abc",
+        client
+            .call_unary(RequestOptions::new(), "abc".to_owned(), echo)
+            .drop_metadata()
+            .wait()
+            .unwrap()
+    );
+    let expected: SocketAddr = format!("{}:{}", BIND_HOST, port).parse().unwrap();
+    assert_eq!(vec![expected], client.peer_addrs());
+}
+
+#[test]
+fn testing_test_server_and_recorder() {
+    use std::sync::Arc;
+
+    use futures::Future;
+    use futures::Stream;
+    use grpc::testing::*;
+
+    init_logger();
+
+    let echo = string_method("/foo/echo", GrpcStreaming::Unary);
+    let split = string_method("/foo/split", GrpcStreaming::ServerStreaming);
+    let server = TestServer::start(vec![TestService::new("/foo")
+        .unary(echo.clone(), |req| Ok(req))
+        .server_streaming(split.clone(), |req: String| {
+            Ok(req.split(',').map(|s| s.to_owned()).collect())
+        })
+        .build()])
+    .expect("server");
+
+    let recorder = CallRecorder::new();
+    let client = ClientBuilder::new(BIND_HOST, server.port())
+        .interceptor(Arc::new(recorder.clone()))
+        .build()
+        .unwrap();
+
+    let mut options = RequestOptions::new();
+    options
+        .metadata
+        .add(MetadataKey::from("x-test"), "1".into());
+    assert_eq!(
+        "abc",
+        client
+            .call_unary(options, "abc".to_owned(), echo)
+            .drop_metadata()
+            .wait()
+            .unwrap()
+    );
+    assert_eq!(
+        vec!["a".to_owned(), "b".to_owned()],
+        client
+            .call_server_streaming(RequestOptions::new(), "a,b".to_owned(), split)
+            .drop_metadata()
+            .collect()
+            .wait()
+            .unwrap()
+    );
+
+    let calls = recorder.calls();
+    assert_eq!(vec!["/foo/echo", "/foo/split"], recorder.methods());
+    assert_eq!(Some(&b"1"[..]), calls[0].metadata.get("x-test"));
+}
+
+#[test]
+fn testing_scripted_server() {
+    use std::time::Duration;
+
+    use futures::Future;
+    use grpc::testing::*;
+
+    init_logger();
+
+    let server = ScriptedServer::start().expect("server");
+    server.script(
+        "/foo/get",
+        ScriptedReply::status(GrpcStatus::Unavailable, "try later"),
+    );
+    server.script(
+        "/foo/get",
+        ScriptedReply::message("ok").with_latency(Duration::from_millis(10)),
+    );
+
+    let get = string_method("/foo/get", GrpcStreaming::Unary);
+    let call = || {
+        server
+            .client()
+            .call_unary(RequestOptions::new(), "x".to_owned(), get.clone())
+            .drop_metadata()
+            .wait()
+    };
+    match call() {
+        Err(Error::Status(ref s)) if s.code == GrpcStatus::Unavailable => {}
+        r => panic!("expecting UNAVAILABLE, got {:?}", r),
+    }
+    assert_eq!("ok", call().unwrap());
+    match call() {
+        Err(Error::Status(ref s)) if s.code == GrpcStatus::Unimplemented => {}
+        r => panic!("expecting UNIMPLEMENTED, got {:?}", r),
+    }
+    assert_eq!(3, server.calls().len());
+}
+
+#[test]
+fn resumable_upload() {
+    use std::io::Cursor;
+    use std::str;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    use futures::Future;
+    use futures::Stream;
+    use grpc::rt::MethodHandlerClientStreaming;
+    use grpc::rt::ServerMethod;
+    use grpc::rt::ServerServiceDefinition;
+
+    init_logger();
+
+    let upload = string_string_method("/foo/upload", GrpcStreaming::ClientStreaming);
+
+    // data stored by server, first call fails after the first chunk
+    let stored = Arc::new(Mutex::new(String::new()));
+    let calls = Arc::new(AtomicUsize::new(0));
+
+    let stored_copy = stored.clone();
+    let mut server = ServerBuilder::new_plain();
+    server.http.set_addr((BIND_HOST, 0)).expect("set_addr");
+    server.add_service(ServerServiceDefinition::new(
+        "/foo",
+        vec![ServerMethod::new(
+            upload.clone(),
+            MethodHandlerClientStreaming::new(move |ctx, req, resp| {
+                let offset: usize = req
+                    .metadata()
+                    .get(UPLOAD_OFFSET_METADATA)
+                    .and_then(|v| str::from_utf8(v).ok())
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(0);
+                let fail = calls.fetch_add(1, Ordering::SeqCst) == 0;
+                let stored = stored_copy.clone();
+                stored.lock().unwrap().truncate(offset);
+                let len = req
+                    .into_stream()
+                    .fold(stored, move |stored, chunk: String| {
+                        stored.lock().unwrap().push_str(&chunk);
+                        match fail {
+                            true => Err(Error::Status(Status::new(
+                                GrpcStatus::Unavailable,
+                                "connection lost",
+                            ))),
+                            false => Ok(stored),
+                        }
+                    })
+                    .map(|stored| format!("{}", stored.lock().unwrap().len()));
+                ctx.pump_single_response(SingleResponse::no_metadata(len), resp);
+                Ok(())
+            }),
+        )],
+    ));
+    let server = server.build().expect("server");
+    let port = server.local_addr().port().expect("port");
+    let client = ClientBuilder::new(BIND_HOST, port).build().unwrap();
+
+    let stored_copy = stored.clone();
+    let resp = ResumableUpload::new(
+        &client,
+        upload,
+        move |_: &Client, _: &Error| Ok(stored_copy.lock().unwrap().len() as u64),
+        |_offset, chunk| String::from_utf8(chunk.to_vec()).unwrap(),
+    )
+    .chunk_size(3)
+    .initial_backoff(Duration::from_millis(1))
+    .upload(&mut Cursor::new(b"abcdefgh".to_vec()))
+    .unwrap();
+
+    assert_eq!("8", resp);
+    assert_eq!("abcdefgh", *stored.lock().unwrap());
+}
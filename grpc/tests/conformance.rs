@@ -0,0 +1,467 @@
+//! Protocol conformance: server is called by a raw HTTP/2 client,
+//! and client calls a raw HTTP/2 server, to check behaviors required by
+//! https://github.com/grpc/grpc/blob/master/doc/PROTOCOL-HTTP2.md
+
+#[macro_use]
+extern crate log;
+extern crate bytes;
+extern crate futures;
+extern crate grpc;
+extern crate httpbis;
+extern crate log_ndc_env_logger;
+
+mod test_misc;
+
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use bytes::Bytes;
+use futures::Future;
+use futures::Stream;
+
+use grpc::rt::*;
+use grpc::*;
+
+use test_misc::*;
+
+fn echo_fn(
+    _: ServerHandlerContext,
+    req: ServerRequestSingle<String>,
+    resp: ServerResponseUnarySink<String>,
+) -> grpc::Result<()> {
+    resp.finish(req.message)
+}
+
+fn not_found_fn(
+    _: ServerHandlerContext,
+    _req: ServerRequestSingle<String>,
+    resp: ServerResponseUnarySink<String>,
+) -> grpc::Result<()> {
+    resp.send_grpc_error(GrpcStatus::NotFound, "not found".to_owned())
+}
+
+/// Server with `/foo/echo` and `/foo/not_found` methods
+fn new_server() -> Server {
+    let mut server = ServerBuilder::new_plain();
+    server.http.set_port(0);
+    server.add_service(ServerServiceDefinition::new(
+        "/foo",
+        vec![
+            ServerMethod::new(
+                string_string_method("/foo/echo", GrpcStreaming::Unary),
+                MethodHandlerUnary::new(echo_fn),
+            ),
+            ServerMethod::new(
+                string_string_method("/foo/not_found", GrpcStreaming::Unary),
+                MethodHandlerUnary::new(not_found_fn),
+            ),
+        ],
+    ));
+    server.build().expect("server")
+}
+
+/// Length-prefixed message with given flags byte
+fn grpc_frame(flags: u8, message: &[u8]) -> Bytes {
+    let len = message.len() as u32;
+    let mut frame = vec![
+        flags,
+        (len >> 24) as u8,
+        (len >> 16) as u8,
+        (len >> 8) as u8,
+        len as u8,
+    ];
+    frame.extend_from_slice(message);
+    Bytes::from(frame)
+}
+
+fn request_headers(path: &str, port: u16) -> Vec<httpbis::Header> {
+    vec![
+        httpbis::Header::new(":method", "POST"),
+        httpbis::Header::new(":path", path.to_owned()),
+        httpbis::Header::new(":authority", format!("{}:{}", BIND_HOST, port)),
+        httpbis::Header::new(":scheme", "http"),
+        httpbis::Header::new("content-type", "application/grpc"),
+    ]
+}
+
+/// Response as seen by HTTP/2 client
+struct RawResponse {
+    headers: httpbis::Headers,
+    body: Vec<u8>,
+    trailers: Option<httpbis::Headers>,
+}
+
+impl RawResponse {
+    /// `grpc-status` from trailers or Trailers-Only response headers
+    fn grpc_status(&self) -> Option<&str> {
+        match self.trailers {
+            Some(ref trailers) => trailers.get_opt("grpc-status"),
+            None => self.headers.get_opt("grpc-status"),
+        }
+    }
+}
+
+/// Send a request with a single DATA frame and collect the response.
+fn raw_call(port: u16, headers: Vec<httpbis::Header>, body: Bytes) -> httpbis::Result<RawResponse> {
+    let http = httpbis::Client::new_plain(BIND_HOST, port, Default::default())?;
+    let (_req, resp) = http
+        .start_request(httpbis::Headers::from_vec(headers), Some(body), None, true)
+        .wait()?;
+    let (headers, rem) = resp.0.wait()?;
+    let mut r = RawResponse {
+        headers,
+        body: Vec::new(),
+        trailers: None,
+    };
+    for part in rem.collect().wait()? {
+        match part {
+            httpbis::DataOrTrailers::Data(data, ..) => r.body.extend_from_slice(&data),
+            httpbis::DataOrTrailers::Trailers(trailers) => r.trailers = Some(trailers),
+        }
+    }
+    Ok(r)
+}
+
+/// Request was rejected with given status and message.
+fn assert_rejected(r: httpbis::Result<RawResponse>, grpc_status: &str, message: &str) {
+    let r = r.expect("response");
+    assert_eq!("200", r.headers.get(":status"));
+    assert_eq!(Some(grpc_status), r.grpc_status());
+    let grpc_message = match r.trailers {
+        Some(ref trailers) => trailers.get_opt("grpc-message"),
+        None => r.headers.get_opt("grpc-message"),
+    };
+    assert_eq!(Some(message), grpc_message);
+}
+
+#[test]
+fn server_trailers_only_response() {
+    init_logger();
+
+    let server = new_server();
+    let port = server.local_addr().port().expect("port");
+
+    let r = raw_call(
+        port,
+        request_headers("/foo/unknown", port),
+        grpc_frame(0, b"abc"),
+    )
+    .unwrap();
+
+    // status is sent in the only HEADERS frame
+    assert_eq!("200", r.headers.get(":status"));
+    assert!(r
+        .headers
+        .get("content-type")
+        .starts_with("application/grpc"));
+    assert_eq!(Some("12"), r.headers.get_opt("grpc-status"));
+    assert!(r.body.is_empty());
+    assert!(r.trailers.is_none());
+}
+
+#[test]
+fn server_grpc_status_always_present() {
+    init_logger();
+
+    let server = new_server();
+    let port = server.local_addr().port().expect("port");
+
+    let mut headers = request_headers("/foo/echo", port);
+    headers.push(httpbis::Header::new("te", "trailers"));
+    let r = raw_call(port, headers, grpc_frame(0, b"abc")).unwrap();
+    assert_eq!("200", r.headers.get(":status"));
+    assert_eq!(&grpc_frame(0, b"abc")[..], &r.body[..]);
+    assert_eq!(Some("0"), r.grpc_status());
+
+    let r = raw_call(
+        port,
+        request_headers("/foo/not_found", port),
+        grpc_frame(0, b"abc"),
+    )
+    .unwrap();
+    assert_eq!(Some("5"), r.grpc_status());
+    let message = match r.trailers {
+        Some(ref trailers) => trailers.get_opt("grpc-message"),
+        None => r.headers.get_opt("grpc-message"),
+    };
+    assert_eq!(Some("not found"), message);
+}
+
+#[test]
+fn server_te_header() {
+    init_logger();
+
+    let server = new_server();
+    let port = server.local_addr().port().expect("port");
+
+    // `te: trailers` is sent by conforming clients, but it is not required,
+    // because gRPC-Web clients cannot set it
+    for te in &[Some("trailers"), None] {
+        let mut headers = request_headers("/foo/echo", port);
+        if let Some(te) = *te {
+            headers.push(httpbis::Header::new("te", te));
+        }
+        let r = raw_call(port, headers, grpc_frame(0, b"abc")).unwrap();
+        assert_eq!(Some("0"), r.grpc_status(), "te: {:?}", te);
+    }
+}
+
+#[test]
+fn server_grpc_web_cors() {
+    init_logger();
+
+    let mut server = ServerBuilder::new_plain();
+    server.http.set_port(0);
+    server.conf.grpc_web = true;
+    server.conf.grpc_web_cors_origin = Some("https://example.com".to_owned());
+    server.add_service(ServerServiceDefinition::new(
+        "/foo",
+        vec![ServerMethod::new(
+            string_string_method("/foo/not_found", GrpcStreaming::Unary),
+            MethodHandlerUnary::new(not_found_fn),
+        )],
+    ));
+    let server = server.build().expect("server");
+    let port = server.local_addr().port().expect("port");
+
+    let mut headers = request_headers("/foo/not_found", port);
+    headers[0] = httpbis::Header::new(":method", "OPTIONS");
+    headers.retain(|h| h.name() != "content-type");
+    headers.push(httpbis::Header::new(
+        "access-control-request-headers",
+        "content-type,x-grpc-web",
+    ));
+    let r = raw_call(port, headers, Bytes::new()).unwrap();
+    assert_eq!("204", r.headers.get(":status"));
+    assert_eq!(
+        "https://example.com",
+        r.headers.get("access-control-allow-origin")
+    );
+    assert_eq!(
+        "content-type,x-grpc-web",
+        r.headers.get("access-control-allow-headers")
+    );
+
+    let mut headers = request_headers("/foo/not_found", port);
+    headers.retain(|h| h.name() != "content-type");
+    headers.push(httpbis::Header::new(
+        "content-type",
+        "application/grpc-web+proto",
+    ));
+    let r = raw_call(port, headers, grpc_frame(0, b"abc")).unwrap();
+    assert_eq!(Some("5"), r.grpc_status());
+    assert_eq!(
+        "https://example.com",
+        r.headers.get("access-control-allow-origin")
+    );
+    assert_eq!(
+        "grpc-status,grpc-message",
+        r.headers.get("access-control-expose-headers")
+    );
+}
+
+#[test]
+fn server_rejects_invalid_frame_flags() {
+    init_logger();
+
+    let server = new_server();
+    let port = server.local_addr().port().expect("port");
+
+    // flags other than compressed bit are not defined
+    assert_rejected(
+        raw_call(
+            port,
+            request_headers("/foo/echo", port),
+            grpc_frame(0x02, b"abc"),
+        ),
+        "13",
+        "grpc frame has unknown flags: 0x02",
+    );
+
+    // compressed message without `grpc-encoding`
+    assert_rejected(
+        raw_call(
+            port,
+            request_headers("/foo/echo", port),
+            grpc_frame(0x01, b"abc"),
+        ),
+        "13",
+        "grpc frame is compressed, but compression is not supported",
+    );
+
+    // incomplete frame
+    assert_rejected(
+        raw_call(
+            port,
+            request_headers("/foo/echo", port),
+            grpc_frame(0, b"abc").slice(0, 6),
+        ),
+        "15",
+        "grpc frame is truncated: expected 8 bytes, received 6",
+    );
+}
+
+#[test]
+fn server_unknown_compression() {
+    init_logger();
+
+    let server = new_server();
+    let port = server.local_addr().port().expect("port");
+
+    let mut headers = request_headers("/foo/echo", port);
+    headers.push(httpbis::Header::new("grpc-encoding", "snappy"));
+    let r = raw_call(port, headers, grpc_frame(0x01, b"abc")).unwrap();
+    assert_eq!(Some("12"), r.grpc_status());
+    assert_eq!(Some("identity"), r.headers.get_opt("grpc-accept-encoding"));
+
+    // identity encoding is the same as no encoding
+    let mut headers = request_headers("/foo/echo", port);
+    headers.push(httpbis::Header::new("grpc-encoding", "identity"));
+    let r = raw_call(port, headers, grpc_frame(0, b"abc")).unwrap();
+    assert_eq!(Some("0"), r.grpc_status());
+}
+
+/// HTTP/2 server which replies to gRPC client with hand-made responses,
+/// and remembers headers of the last request.
+struct RawServer {
+    last_request: Arc<Mutex<Option<httpbis::Headers>>>,
+}
+
+impl httpbis::ServerHandler for RawServer {
+    fn start_request(
+        &self,
+        _context: httpbis::ServerHandlerContext,
+        req: httpbis::ServerRequest,
+        mut resp: httpbis::ServerResponse,
+    ) -> httpbis::Result<()> {
+        *self.last_request.lock().unwrap() = Some(req.headers.clone());
+
+        let headers_200 = || {
+            httpbis::Headers::from_vec(vec![
+                httpbis::Header::new(":status", "200"),
+                httpbis::Header::new("content-type", "application/grpc"),
+            ])
+        };
+        match req.headers.path() {
+            "/raw/ok" => {
+                resp.send_headers(headers_200())?;
+                resp.send_data(grpc_frame(0, b"ok"))?;
+                resp.send_trailers(httpbis::Headers::from_vec(vec![httpbis::Header::new(
+                    "grpc-status",
+                    "0",
+                )]))?;
+            }
+            "/raw/no_status" => {
+                resp.send_headers(headers_200())?;
+                resp.send_data(grpc_frame(0, b"ok"))?;
+                resp.send_trailers(httpbis::Headers::from_vec(Vec::new()))?;
+            }
+            "/raw/compressed" => {
+                resp.send_headers(headers_200())?;
+                resp.send_data(grpc_frame(0x01, b"ok"))?;
+                resp.send_trailers(httpbis::Headers::from_vec(vec![httpbis::Header::new(
+                    "grpc-status",
+                    "0",
+                )]))?;
+            }
+            _ => {
+                let mut headers = headers_200();
+                headers.add_header(httpbis::Header::new("grpc-status", "5"));
+                headers.add_header(httpbis::Header::new(
+                    "grpc-message",
+                    "%D0%BD%D0%B5%D1%82 100%25",
+                ));
+                resp.send_message(httpbis::SimpleHttpMessage {
+                    headers,
+                    body: Bytes::new(),
+                })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Raw server and gRPC client connected to it
+fn new_raw_server() -> (
+    httpbis::Server,
+    Client,
+    Arc<Mutex<Option<httpbis::Headers>>>,
+) {
+    let last_request = Arc::new(Mutex::new(None));
+    let mut server: httpbis::ServerBuilder = httpbis::ServerBuilder::new();
+    server.set_port(0);
+    server.service.set_service(
+        "/",
+        Arc::new(RawServer {
+            last_request: last_request.clone(),
+        }),
+    );
+    let server = server.build().expect("server");
+    let port = server.local_addr().port().expect("port");
+    let client = ClientBuilder::new(BIND_HOST, port).build().expect("client");
+    (server, client, last_request)
+}
+
+fn call(client: &Client, name: &str) -> grpc::Result<String> {
+    client
+        .call_unary(
+            RequestOptions::new(),
+            "abc".to_owned(),
+            string_string_method(name, GrpcStreaming::Unary),
+        )
+        .wait_drop_metadata()
+}
+
+fn expect_status(r: grpc::Result<String>) -> Status {
+    match r {
+        Err(Error::Status(status)) => status,
+        r => panic!("expecting error status, got {:?}", r),
+    }
+}
+
+#[test]
+fn client_request_headers() {
+    init_logger();
+
+    let (_server, client, last_request) = new_raw_server();
+
+    assert_eq!("ok", call(&client, "/raw/ok").unwrap());
+
+    let headers = last_request.lock().unwrap().take().expect("request");
+    assert_eq!("POST", headers.get(":method"));
+    assert_eq!("http", headers.get(":scheme"));
+    assert_eq!("/raw/ok", headers.get(":path"));
+    assert_eq!("application/grpc", headers.get("content-type"));
+    assert_eq!("trailers", headers.get("te"));
+}
+
+#[test]
+fn client_requires_grpc_status() {
+    init_logger();
+
+    let (_server, client, _) = new_raw_server();
+
+    let status = expect_status(call(&client, "/raw/no_status"));
+    assert_eq!(GrpcStatus::Internal, status.code);
+}
+
+#[test]
+fn client_trailers_only_response() {
+    init_logger();
+
+    let (_server, client, _) = new_raw_server();
+
+    let status = expect_status(call(&client, "/raw/trailers_only"));
+    assert_eq!(GrpcStatus::NotFound, status.code);
+    assert_eq!("нет 100%", status.message);
+}
+
+#[test]
+fn client_rejects_compressed_response() {
+    init_logger();
+
+    let (_server, client, _) = new_raw_server();
+
+    let status = call(&client, "/raw/compressed").unwrap_err().status();
+    assert_eq!(GrpcStatus::Internal, status.code);
+}
@@ -2,16 +2,22 @@
 extern crate log;
 extern crate log_ndc_env_logger;
 
+extern crate bytes;
 extern crate futures;
 extern crate grpc;
+extern crate httpbis;
+extern crate tokio_core;
 
 mod test_misc;
 
 use grpc::rt::*;
+use grpc::testing::*;
 use grpc::*;
 
 use test_misc::*;
 
+use tokio_core::reactor::Core;
+
 fn echo_fn(
     _: ServerHandlerContext,
     req: ServerRequestSingle<String>,
@@ -77,6 +83,25 @@ fn multiple_services() {
     );
 }
 
+#[test]
+fn duplicate_method() {
+    init_logger();
+
+    let echo = string_string_method("/foo/echo", GrpcStreaming::Unary);
+
+    let mut server = ServerBuilder::new_plain();
+    server.http.set_port(0);
+    server.add_service(ServerServiceDefinition::new(
+        "/foo",
+        vec![
+            ServerMethod::new(echo.clone(), MethodHandlerUnary::new(echo_fn)),
+            ServerMethod::new(echo.clone(), MethodHandlerUnary::new(reverse_fn)),
+        ],
+    ));
+
+    assert!(server.build().is_err());
+}
+
 #[cfg(unix)]
 #[test]
 fn single_service_unix() {
@@ -84,10 +109,7 @@ fn single_service_unix() {
 
     let test_socket_address = "/tmp/grpc_rust_single_service_unix";
     let mut server = ServerBuilder::new_plain();
-    server
-        .http
-        .set_unix_addr(test_socket_address.to_owned())
-        .unwrap();
+    server.set_unix_addr(test_socket_address).unwrap();
 
     let echo = string_string_method("/foo/echo", GrpcStreaming::Unary);
     let reverse = string_string_method("/bar/reverse", GrpcStreaming::Unary);
@@ -108,7 +130,7 @@ fn single_service_unix() {
         )],
     ));
 
-    let _server = server.build().expect("server");
+    let server = server.build().expect("server");
 
     let client = ClientBuilder::new_unix(test_socket_address)
         .build()
@@ -129,4 +151,2120 @@ fn single_service_unix() {
             .wait_drop_metadata()
             .unwrap()
     );
+
+    drop(server);
+    assert!(!std::path::Path::new(test_socket_address).exists());
+}
+
+#[test]
+fn loopback_server() {
+    init_logger();
+
+    let echo = string_string_method("/foo/echo", GrpcStreaming::Unary);
+
+    let server = LoopbackServer::new(ServerServiceDefinition::new(
+        "/foo",
+        vec![ServerMethod::new(
+            echo.clone(),
+            MethodHandlerUnary::new(echo_fn),
+        )],
+    ))
+    .expect("server");
+
+    assert_eq!(
+        "abc".to_owned(),
+        server
+            .client()
+            .call_unary(RequestOptions::new(), "abc".to_owned(), echo)
+            .wait_drop_metadata()
+            .unwrap()
+    );
+}
+
+#[test]
+fn stats_handler() {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use grpc::stats::ServerStatsHandler;
+
+    #[derive(Default)]
+    struct Counts {
+        started: AtomicUsize,
+        received: AtomicUsize,
+        sent: AtomicUsize,
+        finished_ok: AtomicUsize,
+    }
+
+    impl ServerStatsHandler for Counts {
+        fn call_started(&self, _method: &str) {
+            self.started.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn message_received(&self, _method: &str, size: usize) {
+            self.received.fetch_add(size, Ordering::SeqCst);
+        }
+
+        fn message_sent(&self, _method: &str, size: usize) {
+            self.sent.fetch_add(size, Ordering::SeqCst);
+        }
+
+        fn call_finished(&self, method: &str, status: GrpcStatus, _duration: Duration) {
+            assert_eq!("/foo/echo", method);
+            if status == GrpcStatus::Ok {
+                self.finished_ok.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+    }
+
+    init_logger();
+
+    let counts = Arc::new(Counts::default());
+
+    let echo = string_string_method("/foo/echo", GrpcStreaming::Unary);
+
+    let mut server = ServerBuilder::new_plain();
+    server.set_stats_handler(counts.clone());
+    server.add_service(ServerServiceDefinition::new(
+        "/foo",
+        vec![ServerMethod::new(
+            echo.clone(),
+            MethodHandlerUnary::new(echo_fn),
+        )],
+    ));
+    let server = TestServer::start_with(server).expect("server");
+
+    let client = server.client();
+
+    client
+        .call_unary(RequestOptions::new(), "abc".to_owned(), echo)
+        .wait_drop_metadata()
+        .unwrap();
+
+    assert_eq!(1, counts.started.load(Ordering::SeqCst));
+    assert_eq!(3, counts.received.load(Ordering::SeqCst));
+    assert_eq!(3, counts.sent.load(Ordering::SeqCst));
+    assert_eq!(1, counts.finished_ok.load(Ordering::SeqCst));
+}
+
+#[test]
+fn trace_context_propagation() {
+    use std::sync::Arc;
+
+    use grpc::trace::TraceContext;
+    use grpc::trace::TraceContextInterceptor;
+
+    fn trace_id_fn(
+        ctx: ServerHandlerContext,
+        _req: ServerRequestSingle<String>,
+        resp: ServerResponseUnarySink<String>,
+    ) -> grpc::Result<()> {
+        let trace_id = match ctx.trace_context() {
+            Some(context) => context.to_traceparent()[3..35].to_owned(),
+            None => String::new(),
+        };
+        resp.finish(trace_id)
+    }
+
+    init_logger();
+
+    let method = string_string_method("/foo/trace", GrpcStreaming::Unary);
+
+    let mut server = ServerBuilder::new_plain();
+    server.add_service(ServerServiceDefinition::new(
+        "/foo",
+        vec![ServerMethod::new(
+            method.clone(),
+            MethodHandlerUnary::new(trace_id_fn),
+        )],
+    ));
+    let server = TestServer::start_with(server).expect("server");
+
+    let root = TraceContext::new_root(true);
+
+    let client = server
+        .client_builder()
+        .interceptor(Arc::new(TraceContextInterceptor::new(move || Some(root))))
+        .build()
+        .expect("client");
+
+    let trace_id = client
+        .call_unary(RequestOptions::new(), "".to_owned(), method)
+        .wait_drop_metadata()
+        .unwrap();
+
+    assert_eq!(root.to_traceparent()[3..35], trace_id[..]);
+}
+
+#[test]
+fn call_id_propagation() {
+    use grpc::trace::CallId;
+
+    fn call_id_fn(
+        ctx: ServerHandlerContext,
+        _req: ServerRequestSingle<String>,
+        resp: ServerResponseUnarySink<String>,
+    ) -> grpc::Result<()> {
+        let call_id = ctx.call_id().map(|id| id.as_str().to_owned());
+        resp.finish(call_id.unwrap_or_default())
+    }
+
+    init_logger();
+
+    let method = string_string_method("/foo/call_id", GrpcStreaming::Unary);
+
+    let new_server = |generate_call_id| {
+        let mut server = ServerBuilder::new_plain();
+        server.conf.generate_call_id = generate_call_id;
+        server.add_service(ServerServiceDefinition::new(
+            "/foo",
+            vec![ServerMethod::new(
+                method.clone(),
+                MethodHandlerUnary::new(call_id_fn),
+            )],
+        ));
+        TestServer::start_with(server).expect("server")
+    };
+
+    let call = |server: &TestServer, generate_call_id, options| {
+        let mut client = server.client_builder();
+        client.conf.generate_call_id = generate_call_id;
+        client
+            .build()
+            .expect("client")
+            .call_unary(options, "".to_owned(), method.clone())
+            .wait_drop_metadata()
+            .unwrap()
+    };
+
+    let server = new_server(false);
+
+    let options = RequestOptions::new().with_call_id(CallId::parse("req-42").unwrap());
+    assert_eq!("req-42", call(&server, true, options));
+    assert_eq!(16, call(&server, true, RequestOptions::new()).len());
+    assert_eq!("", call(&server, false, RequestOptions::new()));
+
+    let server = new_server(true);
+    assert_eq!(16, call(&server, false, RequestOptions::new()).len());
+}
+
+#[test]
+fn call_credentials() {
+    use futures::future;
+    use std::sync::Arc;
+
+    struct MethodTokenCredentials {
+        allow_insecure: bool,
+    }
+
+    impl CallCredentials for MethodTokenCredentials {
+        fn get_metadata(&self, context: &CallCredentialsContext) -> GrpcFuture<Metadata> {
+            let mut metadata = Metadata::new();
+            metadata.add(
+                MetadataKey::from("authorization"),
+                format!("Bearer {}", context.method).into(),
+            );
+            Box::new(future::ok(metadata))
+        }
+
+        fn allow_insecure(&self) -> bool {
+            self.allow_insecure
+        }
+    }
+
+    fn authorization_fn(
+        ctx: ServerHandlerContext,
+        _req: ServerRequestSingle<String>,
+        resp: ServerResponseUnarySink<String>,
+    ) -> grpc::Result<()> {
+        let authorization = ctx.metadata.get("authorization").unwrap_or(b"");
+        resp.finish(String::from_utf8(authorization.to_vec()).unwrap())
+    }
+
+    init_logger();
+
+    let method = string_string_method("/foo/authorization", GrpcStreaming::Unary);
+
+    let mut server = ServerBuilder::new_plain();
+    server.add_service(ServerServiceDefinition::new(
+        "/foo",
+        vec![ServerMethod::new(
+            method.clone(),
+            MethodHandlerUnary::new(authorization_fn),
+        )],
+    ));
+    let server = TestServer::start_with(server).expect("server");
+
+    // tokens are not sent over plain connection by default
+    let client = server
+        .client_builder()
+        .call_credentials(Arc::new(MethodTokenCredentials {
+            allow_insecure: false,
+        }))
+        .build()
+        .expect("client");
+    match client
+        .call_unary(RequestOptions::new(), "".to_owned(), method.clone())
+        .wait_drop_metadata()
+    {
+        Err(Error::Other(..)) => {}
+        r => panic!("expecting error, got {:?}", r),
+    }
+
+    let client = server
+        .client_builder()
+        .call_credentials(Arc::new(MethodTokenCredentials {
+            allow_insecure: true,
+        }))
+        .build()
+        .expect("client");
+    assert_eq!(
+        "Bearer /foo/authorization",
+        client
+            .call_unary(RequestOptions::new(), "".to_owned(), method)
+            .wait_drop_metadata()
+            .unwrap()
+    );
+}
+
+#[test]
+fn access_logger() {
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
+    struct Records(Mutex<Vec<String>>);
+
+    impl AccessLogger for Records {
+        fn log(&self, record: &AccessLogRecord) {
+            self.0.lock().unwrap().push(format!(
+                "{} {:?} {} {}",
+                record.method, record.status, record.bytes_received, record.bytes_sent
+            ));
+        }
+    }
+
+    init_logger();
+
+    let records = Arc::new(Records(Mutex::new(Vec::new())));
+
+    let echo = string_string_method("/foo/echo", GrpcStreaming::Unary);
+
+    let mut server = ServerBuilder::new_plain();
+    server.set_access_logger(records.clone());
+    server.add_service(ServerServiceDefinition::new(
+        "/foo",
+        vec![ServerMethod::new(
+            echo.clone(),
+            MethodHandlerUnary::new(echo_fn),
+        )],
+    ));
+    let server = TestServer::start_with(server).expect("server");
+
+    let client = server.client();
+
+    client
+        .call_unary(RequestOptions::new(), "abcd".to_owned(), echo)
+        .wait_drop_metadata()
+        .unwrap();
+
+    assert_eq!(
+        vec!["/foo/echo Ok 4 4".to_owned()],
+        *records.0.lock().unwrap()
+    );
+}
+
+#[test]
+fn server_events() {
+    init_logger();
+
+    let echo = string_string_method("/foo/echo", GrpcStreaming::Unary);
+
+    let mut server = ServerBuilder::new_plain();
+    server.add_service(ServerServiceDefinition::new(
+        "/foo",
+        vec![ServerMethod::new(
+            echo.clone(),
+            MethodHandlerUnary::new(echo_fn),
+        )],
+    ));
+    let server = TestServer::start_with(server).expect("server");
+    let events = server.server().events();
+
+    let client = server.client();
+
+    client
+        .call_unary(RequestOptions::new(), "abcd".to_owned(), echo)
+        .wait_drop_metadata()
+        .unwrap();
+
+    let mut events = events.wait();
+    match events.next() {
+        Some(Ok(ServerEvent::StreamStarted { ref method, .. })) => assert_eq!("/foo/echo", method),
+        e => panic!("expecting stream started, got {:?}", e),
+    }
+    match events.next() {
+        Some(Ok(ServerEvent::StreamFinished {
+            ref method, status, ..
+        })) => {
+            assert_eq!("/foo/echo", method);
+            assert_eq!(GrpcStatus::Ok, status);
+        }
+        e => panic!("expecting stream finished, got {:?}", e),
+    }
+
+    drop(server);
+    assert!(events.next().is_none());
+}
+
+#[test]
+fn rate_limiter() {
+    init_logger();
+
+    let echo = string_string_method("/foo/echo", GrpcStreaming::Unary);
+
+    let mut server = ServerBuilder::new_plain();
+    server.set_rate_limiter(
+        RateLimiter::builder()
+            .method("/foo/echo", RateLimit::per_second(1))
+            .build(),
+    );
+    server.add_service(ServerServiceDefinition::new(
+        "/foo",
+        vec![ServerMethod::new(
+            echo.clone(),
+            MethodHandlerUnary::new(echo_fn),
+        )],
+    ));
+    let server = TestServer::start_with(server).expect("server");
+
+    let client = server.client();
+
+    client
+        .call_unary(RequestOptions::new(), "a".to_owned(), echo.clone())
+        .wait_drop_metadata()
+        .unwrap();
+    match client
+        .call_unary(RequestOptions::new(), "b".to_owned(), echo)
+        .wait_drop_metadata()
+    {
+        Err(Error::Status(e)) => assert_eq!(GrpcStatus::ResourceExhausted, e.code),
+        r => panic!("expecting RESOURCE_EXHAUSTED, got {:?}", r),
+    }
+}
+
+#[test]
+fn request_validator() {
+    init_logger();
+
+    let echo = string_string_method("/foo/echo", GrpcStreaming::Unary);
+
+    let mut server = ServerBuilder::new_plain();
+    server.add_service(ServerServiceDefinition::new(
+        "/foo",
+        vec![ServerMethod::new_validated(
+            echo.clone(),
+            MethodHandlerUnary::new(echo_fn),
+            |message: &String| match message.is_empty() {
+                true => Err(Error::Other("message must not be empty")),
+                false => Ok(()),
+            },
+        )],
+    ));
+    let server = TestServer::start_with(server).expect("server");
+
+    let client = server.client();
+
+    assert_eq!(
+        "a",
+        client
+            .call_unary(RequestOptions::new(), "a".to_owned(), echo.clone())
+            .wait_drop_metadata()
+            .unwrap()
+    );
+    match client
+        .call_unary(RequestOptions::new(), "".to_owned(), echo)
+        .wait_drop_metadata()
+    {
+        Err(Error::Status(e)) => {
+            assert_eq!(GrpcStatus::Argument, e.code);
+            assert!(e.message.contains("message must not be empty"));
+        }
+        r => panic!("expecting INVALID_ARGUMENT, got {:?}", r),
+    }
+}
+
+fn assert_unimplemented(r: grpc::Result<String>) {
+    match r {
+        Err(Error::Status(e)) => {
+            assert_eq!(GrpcStatus::Unimplemented, e.code)
+        }
+        r => panic!("expecting UNIMPLEMENTED, got {:?}", r),
+    }
+}
+
+#[test]
+fn unknown_method_and_service() {
+    init_logger();
+
+    let echo = string_string_method("/foo/echo", GrpcStreaming::Unary);
+
+    let mut server = ServerBuilder::new_plain();
+    server.add_service(ServerServiceDefinition::new(
+        "/foo",
+        vec![ServerMethod::new(
+            echo.clone(),
+            MethodHandlerUnary::new(echo_fn),
+        )],
+    ));
+    let server = TestServer::start_with(server).expect("server");
+
+    let client = server.client();
+
+    let unknown_method = string_string_method("/foo/unknown", GrpcStreaming::Unary);
+    assert_unimplemented(
+        client
+            .call_unary(RequestOptions::new(), "abc".to_owned(), unknown_method)
+            .wait_drop_metadata(),
+    );
+
+    let unknown_service = string_string_method("/bar/echo", GrpcStreaming::Unary);
+    assert_unimplemented(
+        client
+            .call_unary(RequestOptions::new(), "abc".to_owned(), unknown_service)
+            .wait_drop_metadata(),
+    );
+
+    // server is still functional
+    assert_eq!(
+        "abc",
+        client
+            .call_unary(RequestOptions::new(), "abc".to_owned(), echo)
+            .wait_drop_metadata()
+            .unwrap()
+    );
+}
+
+#[test]
+fn call_unary_full_and_timeout() {
+    use std::thread;
+    use std::time::Duration;
+
+    fn slow_fn(
+        _: ServerHandlerContext,
+        req: ServerRequestSingle<String>,
+        resp: ServerResponseUnarySink<String>,
+    ) -> grpc::Result<()> {
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(500));
+            let _ = resp.finish(req.message);
+        });
+        Ok(())
+    }
+
+    init_logger();
+
+    let echo = string_string_method("/foo/echo", GrpcStreaming::Unary);
+    let slow = string_string_method("/foo/slow", GrpcStreaming::Unary);
+
+    let mut server = ServerBuilder::new_plain();
+    server.add_service(ServerServiceDefinition::new(
+        "/foo",
+        vec![
+            ServerMethod::new(echo.clone(), MethodHandlerUnary::new(echo_fn)),
+            ServerMethod::new(slow.clone(), MethodHandlerUnary::new(slow_fn)),
+        ],
+    ));
+    let server = TestServer::start_with(server).expect("server");
+
+    let client = server.client();
+
+    let mut options = RequestOptions::new();
+    options.timeout = Some(Duration::from_secs(10));
+    let resp = client
+        .call_unary_full(options, "abc".to_owned(), echo)
+        .wait()
+        .unwrap();
+    assert_eq!("abc", resp.message);
+    assert_eq!(GrpcStatus::Ok, resp.status);
+
+    let mut options = RequestOptions::new();
+    options.timeout = Some(Duration::from_millis(50));
+    match client
+        .call_unary(options, "abc".to_owned(), slow)
+        .wait_drop_metadata()
+    {
+        Err(Error::Status(e)) => {
+            assert_eq!(GrpcStatus::DeadlineExceeded, e.code)
+        }
+        r => panic!("expecting DEADLINE_EXCEEDED, got {:?}", r),
+    }
+}
+
+#[test]
+fn deadline_propagation() {
+    use std::time::Duration;
+    use std::time::Instant;
+
+    fn remaining_fn(
+        ctx: ServerHandlerContext,
+        _req: ServerRequestSingle<String>,
+        resp: ServerResponseUnarySink<String>,
+    ) -> grpc::Result<()> {
+        let remaining = match ctx.deadline() {
+            Some(deadline) => {
+                let now = Instant::now();
+                let remaining = if deadline > now {
+                    deadline - now
+                } else {
+                    Duration::from_secs(0)
+                };
+                format!("{}", remaining.as_secs())
+            }
+            None => "none".to_owned(),
+        };
+        resp.finish(remaining)
+    }
+
+    init_logger();
+
+    let remaining = string_string_method("/backend/remaining", GrpcStreaming::Unary);
+    let forward = string_string_method("/frontend/forward", GrpcStreaming::Unary);
+
+    let mut backend = ServerBuilder::new_plain();
+    backend.add_service(ServerServiceDefinition::new(
+        "/backend",
+        vec![ServerMethod::new(
+            remaining.clone(),
+            MethodHandlerUnary::new(remaining_fn),
+        )],
+    ));
+    let backend = TestServer::start_with(backend).expect("backend");
+    let backend_client = backend.client();
+
+    let mut frontend = ServerBuilder::new_plain();
+    frontend.add_service(ServerServiceDefinition::new(
+        "/frontend",
+        vec![ServerMethod::new(
+            forward.clone(),
+            MethodHandlerUnary::new(
+                move |ctx: ServerHandlerContext,
+                      req: ServerRequestSingle<String>,
+                      resp: ServerResponseUnarySink<String>| {
+                    let response = backend_client.call_unary(
+                        ctx.outgoing_request_options(),
+                        req.message,
+                        remaining.clone(),
+                    );
+                    ctx.pump_single_response(response, resp);
+                    Ok(())
+                },
+            ),
+        )],
+    ));
+    let frontend = TestServer::start_with(frontend).expect("frontend");
+    let client = frontend.client();
+
+    // deadline shrinks, but does not get lost on the second hop
+    let mut options = RequestOptions::new();
+    options.timeout = Some(Duration::from_secs(100));
+    let secs: u64 = client
+        .call_unary(options, "".to_owned(), forward.clone())
+        .wait_drop_metadata()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(secs > 50 && secs < 100, "{}", secs);
+
+    let r = client
+        .call_unary(RequestOptions::new(), "".to_owned(), forward)
+        .wait_drop_metadata()
+        .unwrap();
+    assert_eq!("none", r);
+}
+
+#[test]
+fn service_method_handler() {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+
+    struct Counter {
+        calls: AtomicUsize,
+    }
+
+    impl Counter {
+        fn count(
+            &self,
+            _: ServerHandlerContext,
+            req: ServerRequestSingle<String>,
+            resp: ServerResponseUnarySink<String>,
+        ) -> grpc::Result<()> {
+            let n = self.calls.fetch_add(1, Ordering::SeqCst) + 1;
+            resp.finish(format!("{}{}", req.message, n))
+        }
+    }
+
+    init_logger();
+
+    let count = string_string_method("/foo/count", GrpcStreaming::Unary);
+
+    let counter = Arc::new(Counter {
+        calls: AtomicUsize::new(0),
+    });
+    let server = LoopbackServer::new(ServerServiceDefinition::new(
+        "/foo",
+        vec![ServerMethod::new(
+            count.clone(),
+            MethodHandlerUnary::new_method(counter.clone(), Counter::count),
+        )],
+    ))
+    .expect("server");
+
+    for expected in &["a1", "a2"] {
+        assert_eq!(
+            *expected,
+            server
+                .client()
+                .call_unary(RequestOptions::new(), "a".to_owned(), count.clone())
+                .wait_drop_metadata()
+                .unwrap()
+        );
+    }
+    assert_eq!(2, counter.calls.load(Ordering::SeqCst));
+}
+
+#[test]
+fn debug_frames() {
+    init_logger();
+
+    let echo = string_string_method("/foo/echo", GrpcStreaming::Unary);
+
+    let mut server = ServerBuilder::new_plain();
+    server.conf.debug_frames = true;
+    server.add_service(ServerServiceDefinition::new(
+        "/foo",
+        vec![ServerMethod::new(
+            echo.clone(),
+            MethodHandlerUnary::new(echo_fn),
+        )],
+    ));
+    let server = TestServer::start_with(server).expect("server");
+
+    let mut conf = ClientConf::new();
+    conf.debug_frames = true;
+    let client = server.client_builder().conf(conf).build().expect("client");
+
+    assert_eq!(
+        "abc",
+        client
+            .call_unary(RequestOptions::new(), "abc".to_owned(), echo)
+            .wait_drop_metadata()
+            .unwrap()
+    );
+}
+
+#[test]
+fn multiple_listeners() {
+    init_logger();
+
+    let echo = string_string_method("/foo/echo", GrpcStreaming::Unary);
+
+    let mut server = ServerBuilder::new_plain();
+    let mut second: httpbis::ServerBuilder = httpbis::ServerBuilder::new();
+    second.set_port(0);
+    server.add_listener(second);
+    server.add_service(ServerServiceDefinition::new(
+        "/foo",
+        vec![ServerMethod::new(
+            echo.clone(),
+            MethodHandlerUnary::new(echo_fn),
+        )],
+    ));
+    let server = TestServer::start_with(server).expect("server");
+
+    let addrs = server.server().local_addrs();
+    assert_eq!(2, addrs.len());
+    assert_ne!(addrs[0].port(), addrs[1].port());
+
+    for addr in addrs {
+        let port = addr.port().expect("port");
+        let client = ClientBuilder::new(BIND_HOST, port).build().expect("client");
+        assert_eq!(
+            "abc",
+            client
+                .call_unary(RequestOptions::new(), "abc".to_owned(), echo.clone())
+                .wait_drop_metadata()
+                .unwrap()
+        );
+    }
+}
+
+#[test]
+fn unary_stream_body() {
+    use futures::Stream;
+    use std::thread;
+
+    fn len_fn(
+        _: ServerHandlerContext,
+        req: ServerRequestBody,
+        resp: ServerResponseUnarySink<String>,
+    ) -> grpc::Result<()> {
+        thread::spawn(move || {
+            let body = req
+                .body
+                .fold(0, |len, chunk| Ok::<_, Error>(len + chunk.len()));
+            let _ = match body.wait() {
+                Ok(len) => resp.finish(len.to_string()),
+                Err(e) => resp.send_grpc_error(GrpcStatus::Internal, e.to_string()),
+            };
+        });
+        Ok(())
+    }
+
+    init_logger();
+
+    let len = string_string_method("/foo/len", GrpcStreaming::Unary);
+
+    let mut server = ServerBuilder::new_plain();
+    server.add_service(ServerServiceDefinition::new(
+        "/foo",
+        vec![ServerMethod::new(
+            len.clone(),
+            MethodHandlerUnaryStreamBody::new(len_fn),
+        )],
+    ));
+    let server = TestServer::start_with(server).expect("server");
+
+    let client = server.client();
+
+    // larger than default flow control window
+    let message = "x".repeat(1_000_000);
+    assert_eq!(
+        "1000000",
+        client
+            .call_unary(RequestOptions::new(), message, len.clone())
+            .wait_drop_metadata()
+            .unwrap()
+    );
+
+    assert_eq!(
+        "0",
+        client
+            .call_unary(RequestOptions::new(), String::new(), len)
+            .wait_drop_metadata()
+            .unwrap()
+    );
+}
+
+#[test]
+fn raw_fallback_proxy() {
+    use futures::Future;
+    use futures::Stream;
+
+    init_logger();
+
+    let echo = string_string_method("/foo/echo", GrpcStreaming::Unary);
+
+    let mut backend = ServerBuilder::new_plain();
+    backend.add_service(ServerServiceDefinition::new(
+        "/foo",
+        vec![ServerMethod::new(
+            echo.clone(),
+            MethodHandlerUnary::new(echo_fn),
+        )],
+    ));
+    let backend = TestServer::start_with(backend).expect("backend");
+    let backend_client = backend.client();
+
+    let mut proxy = ServerBuilder::new_plain();
+    proxy.set_fallback_method(ServerMethod::raw(move |ctx, req, resp| {
+        let mut options = RequestOptions::new();
+        options.metadata = ctx.metadata.clone();
+        let call = backend_client.call_raw(options, ctx.method());
+        let requests = req.into_stream();
+        ctx.loop_remote().spawn(move |_handle| {
+            call.and_then(move |(upstream_req, upstream_resp)| {
+                let forward_requests = requests
+                    .fold(upstream_req, |mut sink, message| {
+                        sink.send_data(message).map(|()| sink)
+                    })
+                    .and_then(|mut sink| sink.finish());
+                forward_requests.join(upstream_resp.drop_metadata().collect())
+            })
+            .then(move |r| {
+                let mut resp = resp;
+                match r {
+                    Ok(((), messages)) => {
+                        for message in messages {
+                            resp.send_data(message)?;
+                        }
+                        resp.send_trailers(Metadata::new())
+                    }
+                    Err(e) => {
+                        let status = Status::from(e);
+                        resp.send_grpc_error(status.code, status.message)
+                    }
+                }
+            })
+            .map_err(|e| warn!("proxy error: {:?}", e))
+        });
+        Ok(())
+    }));
+    let proxy = TestServer::start_with(proxy).expect("proxy");
+    let client = proxy.client();
+
+    assert_eq!(
+        "abc",
+        client
+            .call_unary(RequestOptions::new(), "abc".to_owned(), echo)
+            .wait_drop_metadata()
+            .unwrap()
+    );
+
+    let unknown_method = string_string_method("/foo/unknown", GrpcStreaming::Unary);
+    assert_unimplemented(
+        client
+            .call_unary(RequestOptions::new(), "abc".to_owned(), unknown_method)
+            .wait_drop_metadata(),
+    );
+}
+
+#[test]
+fn auth_handler() {
+    use std::sync::Arc;
+
+    struct SecretAuthHandler;
+
+    impl ServerAuthHandler for SecretAuthHandler {
+        fn authorize(&self, _method: &str, metadata: &Metadata) -> grpc::Result<()> {
+            match metadata.get("authorization") {
+                Some(b"secret") => Ok(()),
+                Some(..) => Err(Error::Status(Status::new(
+                    GrpcStatus::PermissionDenied,
+                    "wrong secret",
+                ))),
+                None => Err(Error::Other("no secret")),
+            }
+        }
+    }
+
+    fn call_with_secret(
+        client: &Client,
+        method: &ArcOrStatic<MethodDescriptor<String, String>>,
+        secret: Option<&'static str>,
+    ) -> grpc::Result<String> {
+        let mut options = RequestOptions::new();
+        if let Some(secret) = secret {
+            options
+                .metadata
+                .add(MetadataKey::from("authorization"), secret.into());
+        }
+        client
+            .call_unary(options, "abc".to_owned(), method.clone())
+            .wait_drop_metadata()
+    }
+
+    fn assert_status(r: grpc::Result<String>, status: GrpcStatus) {
+        match r {
+            Err(Error::Status(e)) => assert_eq!(status, e.code),
+            r => panic!("expecting {:?}, got {:?}", status, r),
+        }
+    }
+
+    init_logger();
+
+    let echo = string_string_method("/foo/echo", GrpcStreaming::Unary);
+
+    let mut server = ServerBuilder::new_plain();
+    server.set_auth_handler(Arc::new(SecretAuthHandler));
+    server.add_service(ServerServiceDefinition::new(
+        "/foo",
+        vec![ServerMethod::new(
+            echo.clone(),
+            MethodHandlerUnary::new(echo_fn),
+        )],
+    ));
+    let server = TestServer::start_with(server).expect("server");
+
+    let client = server.client();
+
+    assert_eq!(
+        "abc",
+        call_with_secret(&client, &echo, Some("secret")).unwrap()
+    );
+    assert_status(
+        call_with_secret(&client, &echo, Some("guess")),
+        GrpcStatus::PermissionDenied,
+    );
+    assert_status(
+        call_with_secret(&client, &echo, None),
+        GrpcStatus::Unauthenticated,
+    );
+}
+
+#[test]
+fn panic_handler() {
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
+    struct Panics(Mutex<Vec<String>>);
+
+    impl ServerPanicHandler for Panics {
+        fn panicked(&self, method: &str, message: &str) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("{} {}", method, message));
+        }
+    }
+
+    fn panic_fn(
+        _: ServerHandlerContext,
+        _: ServerRequestSingle<String>,
+        _: ServerResponseUnarySink<String>,
+    ) -> grpc::Result<()> {
+        panic!("handler failed")
+    }
+
+    fn panic_pump_fn(
+        ctx: ServerHandlerContext,
+        _: ServerRequestSingle<String>,
+        resp: ServerResponseUnarySink<String>,
+    ) -> grpc::Result<()> {
+        ctx.pump_future(
+            futures::future::lazy(|| -> grpc::Result<String> { panic!("future failed") }),
+            resp,
+        );
+        Ok(())
+    }
+
+    init_logger();
+
+    let panics = Arc::new(Panics(Mutex::new(Vec::new())));
+
+    let method = string_string_method("/foo/panic", GrpcStreaming::Unary);
+    let pump_method = string_string_method("/foo/panic_pump", GrpcStreaming::Unary);
+
+    let mut server = ServerBuilder::new_plain();
+    server.set_panic_handler(panics.clone());
+    server.add_service(ServerServiceDefinition::new(
+        "/foo",
+        vec![
+            ServerMethod::new(method.clone(), MethodHandlerUnary::new(panic_fn)),
+            ServerMethod::new(pump_method.clone(), MethodHandlerUnary::new(panic_pump_fn)),
+        ],
+    ));
+    let server = TestServer::start_with(server).expect("server");
+
+    let client = server.client();
+
+    for method in vec![method, pump_method] {
+        match client
+            .call_unary(RequestOptions::new(), "abc".to_owned(), method)
+            .wait_drop_metadata()
+        {
+            Err(Error::Status(e)) => assert_eq!(GrpcStatus::Internal, e.code),
+            r => panic!("expecting INTERNAL, got {:?}", r),
+        }
+    }
+
+    assert_eq!(
+        vec![
+            "/foo/panic handler failed".to_owned(),
+            "/foo/panic_pump future failed".to_owned(),
+        ],
+        *panics.0.lock().unwrap()
+    );
+}
+
+#[test]
+fn channel_shared_by_stubs() {
+    use std::sync::Arc;
+
+    struct FooClient {
+        grpc_client: Arc<Client>,
+    }
+
+    impl ClientStub for FooClient {
+        fn with_client(grpc_client: Arc<Client>) -> Self {
+            FooClient { grpc_client }
+        }
+    }
+
+    struct BarClient {
+        grpc_client: Arc<Client>,
+    }
+
+    impl ClientStub for BarClient {
+        fn with_client(grpc_client: Arc<Client>) -> Self {
+            BarClient { grpc_client }
+        }
+    }
+
+    init_logger();
+
+    let echo = string_string_method("/foo/echo", GrpcStreaming::Unary);
+    let reverse = string_string_method("/bar/reverse", GrpcStreaming::Unary);
+
+    let mut server = ServerBuilder::new_plain();
+    server.add_service(ServerServiceDefinition::new(
+        "/foo",
+        vec![ServerMethod::new(
+            echo.clone(),
+            MethodHandlerUnary::new(echo_fn),
+        )],
+    ));
+    server.add_service(ServerServiceDefinition::new(
+        "/bar",
+        vec![ServerMethod::new(
+            reverse.clone(),
+            MethodHandlerUnary::new(reverse_fn),
+        )],
+    ));
+    let server = TestServer::start_with(server).expect("server");
+
+    let channel = server.client_builder().build_channel().expect("channel");
+
+    let foo: FooClient = channel.stub();
+    let bar: BarClient = channel.clone().stub();
+
+    assert!(Arc::ptr_eq(&foo.grpc_client, &bar.grpc_client));
+
+    assert_eq!(
+        "abc",
+        foo.grpc_client
+            .call_unary(RequestOptions::new(), "abc".to_owned(), echo)
+            .wait_drop_metadata()
+            .unwrap()
+    );
+    assert_eq!(
+        "zyx",
+        bar.grpc_client
+            .call_unary(RequestOptions::new(), "xyz".to_owned(), reverse)
+            .wait_drop_metadata()
+            .unwrap()
+    );
+}
+
+#[test]
+fn single_threaded_event_loop() {
+    init_logger();
+
+    let mut core = Core::new().expect("core");
+
+    let echo = string_string_method("/foo/echo", GrpcStreaming::Unary);
+
+    let mut server = ServerBuilder::new_plain();
+    server.set_event_loop(core.remote());
+    server.add_service(ServerServiceDefinition::new(
+        "/foo",
+        vec![ServerMethod::new(
+            echo.clone(),
+            MethodHandlerUnary::new(echo_fn),
+        )],
+    ));
+    let server = TestServer::start_with(server).expect("server");
+
+    let client = server
+        .client_builder()
+        .with_event_loop(core.remote())
+        .build()
+        .expect("client");
+
+    let r = core
+        .run(
+            client
+                .call_unary(RequestOptions::new(), "abc".to_owned(), echo)
+                .drop_metadata(),
+        )
+        .expect("call");
+    assert_eq!("abc", r);
+}
+
+#[test]
+fn delayed_initial_metadata() {
+    use futures::future;
+    use futures::sync::oneshot;
+    use futures::Future;
+    use std::thread;
+    use std::time::Duration;
+
+    fn lookup_fn(
+        ctx: ServerHandlerContext,
+        req: ServerRequestSingle<String>,
+        resp: ServerResponseUnarySink<String>,
+    ) -> grpc::Result<()> {
+        // metadata is known only after a slow lookup
+        let (tx, rx) = oneshot::channel();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            let mut metadata = Metadata::new();
+            metadata.add(MetadataKey::from("x-lookup"), "found".into());
+            let _ = tx.send(metadata);
+        });
+
+        let mut trailing = Metadata::new();
+        trailing.add(MetadataKey::from("x-trailing"), "done".into());
+
+        let message = req.message;
+        let response = SingleResponse::new(rx.map_err(|_| Error::Other("lookup canceled")).map(
+            move |metadata| {
+                let result: GrpcFuture<(String, Metadata)> =
+                    Box::new(future::ok((message, trailing)));
+                (metadata, result)
+            },
+        ));
+        ctx.pump_single_response(response, resp);
+        Ok(())
+    }
+
+    init_logger();
+
+    let method = string_string_method("/foo/lookup", GrpcStreaming::Unary);
+
+    let mut server = ServerBuilder::new_plain();
+    server.add_service(ServerServiceDefinition::new(
+        "/foo",
+        vec![ServerMethod::new(
+            method.clone(),
+            MethodHandlerUnary::new(lookup_fn),
+        )],
+    ));
+    let server = TestServer::start_with(server).expect("server");
+
+    let client = server.client();
+
+    let resp = client
+        .call_unary_full(RequestOptions::new(), "abc".to_owned(), method)
+        .wait()
+        .unwrap();
+    assert_eq!("abc", resp.message);
+    assert_eq!(Some(&b"found"[..]), resp.initial_metadata.get("x-lookup"));
+    assert_eq!(Some(&b"done"[..]), resp.trailing_metadata.get("x-trailing"));
+}
+
+#[test]
+fn http_fallback() {
+    use bytes::Bytes;
+    use futures::Future;
+    use std::sync::Arc;
+
+    struct Healthz;
+
+    impl httpbis::ServerHandler for Healthz {
+        fn start_request(
+            &self,
+            _context: httpbis::ServerHandlerContext,
+            req: httpbis::ServerRequest,
+            mut resp: httpbis::ServerResponse,
+        ) -> httpbis::Result<()> {
+            let (status, body) = match req.headers.path() {
+                "/healthz" => ("200", "ok"),
+                _ => ("404", "not found"),
+            };
+            resp.send_message(httpbis::SimpleHttpMessage {
+                headers: httpbis::Headers::from_vec(vec![
+                    httpbis::Header::new(":status", status),
+                    httpbis::Header::new("content-type", "text/plain"),
+                ]),
+                body: Bytes::from(body),
+            })?;
+            Ok(())
+        }
+    }
+
+    init_logger();
+
+    let echo = string_string_method("/foo/echo", GrpcStreaming::Unary);
+
+    let mut server = ServerBuilder::new_plain();
+    server.set_http_fallback(Arc::new(Healthz));
+    server.add_service(ServerServiceDefinition::new(
+        "/foo",
+        vec![ServerMethod::new(
+            echo.clone(),
+            MethodHandlerUnary::new(echo_fn),
+        )],
+    ));
+    let server = TestServer::start_with(server).expect("server");
+
+    // gRPC calls are not affected
+    let client = server.client();
+    assert_eq!(
+        "abc",
+        client
+            .call_unary(RequestOptions::new(), "abc".to_owned(), echo)
+            .wait_drop_metadata()
+            .unwrap()
+    );
+
+    let port = server.port();
+    let http = httpbis::Client::new_plain(BIND_HOST, port, Default::default()).expect("client");
+    let authority = format!("{}:{}", BIND_HOST, port);
+
+    let healthz = http
+        .start_get("/healthz", &authority)
+        .collect()
+        .wait()
+        .unwrap();
+    assert_eq!("200", healthz.headers.get(":status"));
+    assert_eq!(&b"ok"[..], &healthz.body[..]);
+
+    // non-gRPC request to a service path
+    let other = http
+        .start_get("/foo/echo", &authority)
+        .collect()
+        .wait()
+        .unwrap();
+    assert_eq!("404", other.headers.get(":status"));
+}
+
+#[test]
+fn async_service_method() {
+    use std::sync::Arc;
+
+    struct Greeter;
+
+    impl Greeter {
+        fn greet(
+            &self,
+            ctx: ServerHandlerContext,
+            req: ServerRequestSingle<String>,
+        ) -> SingleResponse<String> {
+            let mut metadata = Metadata::new();
+            metadata.add(MetadataKey::from("x-method"), ctx.method().into());
+            SingleResponse::completed_with_metadata(metadata, format!("hello {}", req.message))
+        }
+
+        fn fail(
+            &self,
+            _ctx: ServerHandlerContext,
+            _req: ServerRequestSingle<String>,
+        ) -> SingleResponse<String> {
+            SingleResponse::err(Error::Status(Status::new(GrpcStatus::NotFound, "nobody")))
+        }
+    }
+
+    init_logger();
+
+    let greet = string_string_method("/foo/greet", GrpcStreaming::Unary);
+    let fail = string_string_method("/foo/fail", GrpcStreaming::Unary);
+
+    let service = Arc::new(Greeter);
+
+    let mut server = ServerBuilder::new_plain();
+    server.add_service(ServerServiceDefinition::new(
+        "/foo",
+        vec![
+            ServerMethod::new(
+                greet.clone(),
+                MethodHandlerUnary::new_async_method(service.clone(), Greeter::greet),
+            ),
+            ServerMethod::new(
+                fail.clone(),
+                MethodHandlerUnary::new_async_method(service.clone(), Greeter::fail),
+            ),
+        ],
+    ));
+    let server = TestServer::start_with(server).expect("server");
+
+    let client = server.client();
+
+    let resp = client
+        .call_unary_full(RequestOptions::new(), "world".to_owned(), greet)
+        .wait()
+        .unwrap();
+    assert_eq!("hello world", resp.message);
+    assert_eq!(
+        Some(&b"/foo/greet"[..]),
+        resp.initial_metadata.get("x-method")
+    );
+
+    match client
+        .call_unary(RequestOptions::new(), "world".to_owned(), fail)
+        .wait_drop_metadata()
+    {
+        Err(Error::Status(e)) => assert_eq!(GrpcStatus::NotFound, e.code),
+        r => panic!("expecting NOT_FOUND, got {:?}", r),
+    }
+}
+
+#[test]
+fn service_descriptors() {
+    init_logger();
+
+    let echo = string_string_method("/foo/echo", GrpcStreaming::Unary);
+    let reverse = string_string_method("/foo/reverse", GrpcStreaming::Unary);
+
+    let mut foo = ServerServiceDefinition::new(
+        "/foo",
+        vec![
+            ServerMethod::new(echo.clone(), MethodHandlerUnary::new(echo_fn)),
+            ServerMethod::new(reverse.clone(), MethodHandlerUnary::new(reverse_fn)),
+        ],
+    );
+    assert_eq!(2, foo.methods().count());
+
+    // disabled by feature flag
+    assert!(foo.remove_method("/foo/reverse").is_some());
+
+    let mut server = ServerBuilder::new_plain();
+    server.add_service(foo);
+    let server = TestServer::start_with(server).expect("server");
+
+    assert_eq!(
+        vec![ServiceDescriptor {
+            name: "/foo".to_owned(),
+            methods: vec![ServiceMethodDescriptor {
+                name: "/foo/echo".to_owned(),
+                streaming: GrpcStreaming::Unary,
+            }],
+        }],
+        server.server().services()
+    );
+
+    let client = server.client();
+    match client
+        .call_unary(RequestOptions::new(), "abc".to_owned(), reverse)
+        .wait_drop_metadata()
+    {
+        Err(Error::Status(e)) => assert_eq!(GrpcStatus::Unimplemented, e.code),
+        r => panic!("expecting UNIMPLEMENTED, got {:?}", r),
+    }
+}
+
+#[test]
+fn add_remove_service_at_runtime() {
+    init_logger();
+
+    let echo = string_string_method("/foo/echo", GrpcStreaming::Unary);
+
+    let server = TestServer::start(Vec::new()).expect("server");
+
+    let client = server.client();
+
+    let call = |client: &Client| {
+        client
+            .call_unary(RequestOptions::new(), "abc".to_owned(), echo.clone())
+            .wait_drop_metadata()
+    };
+
+    match call(&client) {
+        Err(Error::Status(e)) => assert_eq!(GrpcStatus::Unimplemented, e.code),
+        r => panic!("expecting UNIMPLEMENTED, got {:?}", r),
+    }
+
+    let foo = || {
+        ServerServiceDefinition::new(
+            "/foo",
+            vec![ServerMethod::new(
+                echo.clone(),
+                MethodHandlerUnary::new(echo_fn),
+            )],
+        )
+    };
+    server.server().add_service(foo()).expect("add_service");
+    assert!(server.server().add_service(foo()).is_err());
+    assert_eq!(1, server.server().services().len());
+
+    assert_eq!("abc", call(&client).expect("call"));
+
+    assert!(server.server().remove_service("foo"));
+    assert!(!server.server().remove_service("foo"));
+    assert!(server.server().services().is_empty());
+
+    match call(&client) {
+        Err(Error::Status(e)) => assert_eq!(GrpcStatus::Unimplemented, e.code),
+        r => panic!("expecting UNIMPLEMENTED, got {:?}", r),
+    }
+}
+
+#[test]
+fn event_loop_and_worker_threads() {
+    use std::thread;
+
+    init_logger();
+
+    fn thread_name_fn(
+        _: ServerHandlerContext,
+        _req: ServerRequestSingle<String>,
+        resp: ServerResponseUnarySink<String>,
+    ) -> grpc::Result<()> {
+        resp.finish(thread::current().name().unwrap_or("").to_owned())
+    }
+
+    let method = string_string_method("/foo/thread", GrpcStreaming::Unary);
+
+    let mut server = ServerBuilder::new_plain();
+    server.conf.event_loop_threads = Some(2);
+    server.conf.worker_threads = Some(2);
+    server.add_service(ServerServiceDefinition::new(
+        "/foo",
+        vec![ServerMethod::new(
+            method.clone(),
+            MethodHandlerUnary::new(thread_name_fn),
+        )],
+    ));
+    let server = TestServer::start_with(server).expect("server");
+    // additional loop listens on the same port, on Linux only
+    let loops = if cfg!(target_os = "linux") { 2 } else { 1 };
+    assert_eq!(loops, server.server().local_addrs().len());
+
+    let mut conf = ClientConf::new();
+    conf.event_loop_threads = Some(2);
+    conf.max_connections = Some(2);
+    conf.max_streams_per_connection = Some(1);
+    let client = server.client_builder().conf(conf).build().expect("client");
+
+    for _ in 0..4 {
+        let thread_name = client
+            .call_unary(RequestOptions::new(), String::new(), method.clone())
+            .wait_drop_metadata()
+            .expect("call");
+        assert!(
+            thread_name.starts_with("grpc-server-worker-"),
+            "{}",
+            thread_name
+        );
+    }
+}
+
+#[test]
+fn worker_thread_handler_error() {
+    fn not_found_fn(
+        _: ServerHandlerContext,
+        req: ServerRequestSingle<String>,
+        _resp: ServerResponseUnarySink<String>,
+    ) -> grpc::Result<()> {
+        Err(Error::Status(Status::new(
+            GrpcStatus::NotFound,
+            format!("no such key: {}", req.message),
+        )))
+    }
+
+    init_logger();
+
+    let method = string_string_method("/foo/get", GrpcStreaming::Unary);
+
+    let mut server = ServerBuilder::new_plain();
+    server.conf.worker_threads = Some(1);
+    server.add_service(ServerServiceDefinition::new(
+        "/foo",
+        vec![ServerMethod::new(
+            method.clone(),
+            MethodHandlerUnary::new(not_found_fn),
+        )],
+    ));
+    let server = TestServer::start_with(server).expect("server");
+
+    let client = server.client();
+
+    match client
+        .call_unary(RequestOptions::new(), "abc".to_owned(), method)
+        .wait_drop_metadata()
+    {
+        Err(Error::Status(e)) => {
+            assert_eq!(GrpcStatus::NotFound, e.code);
+            assert_eq!("no such key: abc", e.message);
+        }
+        r => panic!("expecting NOT_FOUND, got {:?}", r),
+    }
+}
+
+#[test]
+fn reuse_port() {
+    init_logger();
+
+    let echo = string_string_method("/foo/echo", GrpcStreaming::Unary);
+    let new_server = |port| {
+        let mut server = ServerBuilder::new_plain();
+        server.http.set_port(port);
+        server.conf.reuse_port = true;
+        server.add_service(ServerServiceDefinition::new(
+            "/foo",
+            vec![ServerMethod::new(
+                echo.clone(),
+                MethodHandlerUnary::new(echo_fn),
+            )],
+        ));
+        server.build().expect("server")
+    };
+
+    let server1 = new_server(0);
+    let port = server1.local_addr().port().expect("port");
+    // second process (or server) may bind the same port
+    let server2 = new_server(port);
+    assert_eq!(Some(port), server2.local_addr().port());
+
+    let client = ClientBuilder::new(BIND_HOST, port).build().expect("client");
+    let r = client
+        .call_unary(RequestOptions::new(), "abc".to_owned(), echo.clone())
+        .wait_drop_metadata();
+    assert_eq!("abc", r.expect("call"));
+}
+
+#[test]
+fn request_stream_limits() {
+    use futures::Future;
+    use futures::Stream;
+    use std::time::Duration;
+
+    fn count_fn(
+        ctx: ServerHandlerContext,
+        req: ServerRequest<String>,
+        resp: ServerResponseUnarySink<String>,
+    ) -> grpc::Result<()> {
+        let count = req
+            .into_stream()
+            .fold(0, |n, _| Ok::<_, Error>(n + 1))
+            .map(|n: u32| format!("{}", n));
+        ctx.pump_single_response(SingleResponse::no_metadata(count), resp);
+        Ok(())
+    }
+
+    fn expect_status(r: grpc::Result<String>) -> GrpcStatus {
+        match r {
+            Err(Error::Status(e)) => e.code,
+            r => panic!("expecting error status, got {:?}", r),
+        }
+    }
+
+    init_logger();
+
+    let count = string_string_method("/foo/count", GrpcStreaming::ClientStreaming);
+
+    let mut server = ServerBuilder::new_plain();
+    server.conf.max_request_messages = Some(2);
+    server.conf.request_message_timeout = Some(Duration::from_millis(200));
+    server.add_service(ServerServiceDefinition::new(
+        "/foo",
+        vec![ServerMethod::new(
+            count.clone(),
+            MethodHandlerClientStreaming::new(count_fn),
+        )],
+    ));
+    let server = TestServer::start_with(server).expect("server");
+
+    let client = server.client();
+
+    let (mut tx, resp) = client.call_client_streaming(RequestOptions::new(), count.clone());
+    tx.send_data("a".to_owned()).unwrap();
+    tx.send_data("b".to_owned()).unwrap();
+    tx.finish().unwrap();
+    assert_eq!("2", resp.wait_drop_metadata().unwrap());
+
+    // stream is failed after the limit, client may not be able to send the rest
+    let (mut tx, resp) = client.call_client_streaming(RequestOptions::new(), count.clone());
+    for m in &["a", "b", "c"] {
+        let _ = tx.send_data(m.to_string());
+    }
+    let _ = tx.finish();
+    assert_eq!(
+        GrpcStatus::ResourceExhausted,
+        expect_status(resp.wait_drop_metadata())
+    );
+
+    // client opens a stream and stops sending
+    let (mut tx, resp) = client.call_client_streaming(RequestOptions::new(), count);
+    tx.send_data("a".to_owned()).unwrap();
+    assert_eq!(
+        GrpcStatus::DeadlineExceeded,
+        expect_status(resp.wait_drop_metadata())
+    );
+    drop(tx);
+}
+
+#[test]
+fn custom_response_headers() {
+    init_logger();
+
+    let echo = string_method("/foo/echo", GrpcStreaming::Unary);
+
+    let mut server = ServerBuilder::new_plain();
+    server
+        .conf
+        .custom_response_headers
+        .add(MetadataKey::from("server"), "grpc-rust-test".into());
+    server.conf.advertise_accept_encoding = true;
+    server.add_service(
+        TestService::new("/foo")
+            .unary(echo.clone(), |req| Ok(req))
+            .build(),
+    );
+    let server = TestServer::start_with(server).expect("server");
+
+    let (metadata, message, _) = server
+        .client()
+        .call_unary(RequestOptions::new(), "abc".to_owned(), echo)
+        .wait()
+        .unwrap();
+    assert_eq!("abc", message);
+    assert_eq!(Some(&b"grpc-rust-test"[..]), metadata.get("server"));
+}
+
+#[test]
+fn method_histograms() {
+    use std::time::Duration;
+
+    init_logger();
+
+    let echo = string_method("/foo/echo", GrpcStreaming::Unary);
+
+    let mut server = ServerBuilder::new_plain();
+    server.conf.method_histograms = true;
+    server.conf.slow_call_threshold = Some(Duration::from_secs(0));
+    server.add_service(
+        TestService::new("/foo")
+            .unary(echo.clone(), |req| Ok(req))
+            .build(),
+    );
+    let server = TestServer::start_with(server).expect("server");
+
+    for _ in 0..3 {
+        server
+            .client()
+            .call_unary(RequestOptions::new(), "abc".to_owned(), echo.clone())
+            .wait_drop_metadata()
+            .unwrap();
+    }
+
+    let histograms = server.server().method_histograms().unwrap().snapshot();
+    let echo = &histograms["/foo/echo"];
+    assert_eq!(3, echo.latency_micros.count);
+    assert_eq!(3, echo.request_message_bytes.count);
+    assert_eq!(9, echo.request_message_bytes.sum);
+    assert_eq!(
+        Some(64),
+        echo.response_message_bytes.quantile_upper_bound(1.0)
+    );
+}
+
+#[cfg(feature = "with-serde")]
+#[test]
+fn channelz_service() {
+    use std::sync::Arc;
+
+    use bytes::Bytes;
+    use grpc::for_test::MarshallerBytes;
+    use grpc::stats::StatsCounters;
+
+    init_logger();
+
+    let echo = string_method("/foo/echo", GrpcStreaming::Unary);
+    let channelz_method = |name: &str| {
+        ArcOrStatic::Arc(Arc::new(MethodDescriptor {
+            name: name.to_owned().into(),
+            streaming: GrpcStreaming::Unary,
+            req_marshaller: ArcOrStatic::Static(&MarshallerBytes),
+            resp_marshaller: ArcOrStatic::Static(&MarshallerBytes),
+        }))
+    };
+
+    let channelz = Arc::new(Channelz::new());
+    let stats = StatsCounters::new();
+    let mut server = ServerBuilder::new_plain();
+    server.set_channelz(channelz.clone());
+    server.set_stats_handler(Arc::new(stats.clone()));
+    server.add_service(Channelz::service(&channelz));
+    server.add_service(
+        TestService::new("/foo")
+            .unary(echo.clone(), |req| Ok(req))
+            .build(),
+    );
+    let server = TestServer::start_with(server).expect("server");
+
+    for _ in 0..2 {
+        server
+            .client()
+            .call_unary(RequestOptions::new(), "abc".to_owned(), echo.clone())
+            .wait_drop_metadata()
+            .unwrap();
+    }
+
+    let echo = &channelz.snapshot()["/foo/echo"];
+    assert_eq!(2, echo.calls_succeeded);
+    assert_eq!(0, echo.calls_in_progress());
+    assert!(channelz.streams().is_empty());
+    // stats handler is not displaced by channelz
+    assert_eq!(2, stats.snapshot().calls_finished);
+
+    let json = server
+        .client()
+        .call_unary(
+            RequestOptions::new(),
+            Bytes::new(),
+            channelz_method("/grpc.rust.Channelz/GetMethodCounters"),
+        )
+        .wait_drop_metadata()
+        .unwrap();
+    let json = String::from_utf8(json.to_vec()).unwrap();
+    assert!(
+        json.contains("\"/foo/echo\":{\"calls_failed\":0,"),
+        "{}",
+        json
+    );
+
+    // the call listing streams is itself in progress
+    let json = server
+        .client()
+        .call_unary(
+            RequestOptions::new(),
+            Bytes::new(),
+            channelz_method("/grpc.rust.Channelz/GetStreams"),
+        )
+        .wait_drop_metadata()
+        .unwrap();
+    let json = String::from_utf8(json.to_vec()).unwrap();
+    assert!(
+        json.contains("\"method\":\"/grpc.rust.Channelz/GetStreams\""),
+        "{}",
+        json
+    );
+}
+
+#[test]
+fn max_request_buffer_bytes() {
+    init_logger();
+
+    let len = string_method("/foo/len", GrpcStreaming::Unary);
+
+    let mut server = ServerBuilder::new_plain();
+    server.conf.max_request_buffer_bytes = Some(1024);
+    server.add_service(
+        TestService::new("/foo")
+            .unary(len.clone(), |req: String| Ok(req.len().to_string()))
+            .build(),
+    );
+    let server = TestServer::start_with(server).expect("server");
+
+    let resp = server
+        .client()
+        .call_unary(RequestOptions::new(), "abc".to_owned(), len.clone())
+        .wait_drop_metadata();
+    assert_eq!("3", resp.unwrap());
+
+    // message spans several DATA frames, so it is buffered before it is complete
+    let resp = server
+        .client()
+        .call_unary(RequestOptions::new(), "a".repeat(100_000), len)
+        .wait_drop_metadata();
+    match resp {
+        Err(ref e) if e.status().code == GrpcStatus::ResourceExhausted => {}
+        r => panic!("expecting RESOURCE_EXHAUSTED, got {:?}", r),
+    }
+}
+
+#[test]
+fn draining() {
+    use std::thread;
+    use std::time::Duration;
+
+    fn count_fn(
+        ctx: ServerHandlerContext,
+        req: ServerRequest<String>,
+        resp: ServerResponseUnarySink<String>,
+    ) -> grpc::Result<()> {
+        let count = req
+            .into_stream()
+            .fold(0, |n, _| Ok::<_, Error>(n + 1))
+            .map(|n: u32| format!("{}", n));
+        ctx.pump_single_response(SingleResponse::no_metadata(count), resp);
+        Ok(())
+    }
+
+    init_logger();
+
+    let count = string_method("/foo/count", GrpcStreaming::ClientStreaming);
+
+    let server = TestServer::start(vec![ServerServiceDefinition::new(
+        "/foo",
+        vec![ServerMethod::new(
+            count.clone(),
+            MethodHandlerClientStreaming::new(count_fn),
+        )],
+    )])
+    .expect("server");
+    let client = server.client();
+
+    let (mut tx, in_progress) = client.call_client_streaming(RequestOptions::new(), count.clone());
+    tx.send_data("a".to_owned()).unwrap();
+    // wait for the call to reach the server
+    while server.server().in_flight_requests().get() == 0 {
+        thread::sleep(Duration::from_millis(1));
+    }
+
+    assert_eq!(1, client.peer_addrs().len());
+
+    server.server().set_draining(true);
+    assert!(server.server().is_draining());
+
+    let (mut rejected_tx, rejected) =
+        client.call_client_streaming(RequestOptions::new(), count.clone());
+    let _ = rejected_tx.finish();
+    match rejected.wait_drop_metadata() {
+        Err(ref e) if e.status().code == GrpcStatus::Unavailable => {}
+        r => panic!("expecting UNAVAILABLE, got {:?}", r),
+    }
+    // connection of draining server is not used for new calls
+    assert!(client.peer_addrs().is_empty());
+
+    // call started before draining completes
+    tx.send_data("b".to_owned()).unwrap();
+    tx.finish().unwrap();
+    assert_eq!("2", in_progress.wait_drop_metadata().unwrap());
+
+    server.server().set_draining(false);
+    let (mut tx, resp) = client.call_client_streaming(RequestOptions::new(), count);
+    tx.finish().unwrap();
+    assert_eq!("0", resp.wait_drop_metadata().unwrap());
+    assert_eq!(1, client.peer_addrs().len());
+}
+
+#[test]
+fn handler_watchdog() {
+    use std::sync::Arc;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    use futures::future;
+
+    struct Stuck(Mutex<Vec<String>>);
+
+    impl ServerWatchdogHandler for Stuck {
+        fn stuck(&self, method: &str, _timeout: Duration) {
+            self.0.lock().unwrap().push(method.to_owned());
+        }
+    }
+
+    fn stuck_fn(
+        ctx: ServerHandlerContext,
+        _: ServerRequestSingle<String>,
+        resp: ServerResponseUnarySink<String>,
+    ) -> grpc::Result<()> {
+        ctx.pump_single_response(
+            SingleResponse::no_metadata(future::empty::<String, Error>()),
+            resp,
+        );
+        Ok(())
+    }
+
+    fn echo_fn(
+        ctx: ServerHandlerContext,
+        req: ServerRequestSingle<String>,
+        resp: ServerResponseUnarySink<String>,
+    ) -> grpc::Result<()> {
+        ctx.pump_single_response(SingleResponse::completed(req.message), resp);
+        Ok(())
+    }
+
+    init_logger();
+
+    let stuck_handlers = Arc::new(Stuck(Mutex::new(Vec::new())));
+
+    let stuck = string_method("/foo/stuck", GrpcStreaming::Unary);
+    let echo = string_method("/foo/echo", GrpcStreaming::Unary);
+
+    let mut server = ServerBuilder::new_plain();
+    server.conf.handler_watchdog_timeout = Some(Duration::from_secs(10));
+    server.set_watchdog_handler(stuck_handlers.clone());
+    server.add_service(ServerServiceDefinition::new(
+        "/foo",
+        vec![
+            ServerMethod::new(stuck.clone(), MethodHandlerUnary::new(stuck_fn))
+                .with_watchdog_timeout(Duration::from_millis(50)),
+            ServerMethod::new(echo.clone(), MethodHandlerUnary::new(echo_fn)),
+        ],
+    ));
+    let server = TestServer::start_with(server).expect("server");
+    let client = server.client();
+
+    match client
+        .call_unary(RequestOptions::new(), "abc".to_owned(), stuck)
+        .wait_drop_metadata()
+    {
+        Err(ref e) if e.status().code == GrpcStatus::DeadlineExceeded => {}
+        r => panic!("expecting DEADLINE_EXCEEDED, got {:?}", r),
+    }
+    assert_eq!(
+        vec!["/foo/stuck".to_owned()],
+        *stuck_handlers.0.lock().unwrap()
+    );
+
+    let resp = client
+        .call_unary(RequestOptions::new(), "abc".to_owned(), echo)
+        .wait_drop_metadata();
+    assert_eq!("abc", resp.unwrap());
+}
+
+#[test]
+fn dropped_response_cancels_call() {
+    use std::sync::mpsc;
+    use std::sync::Mutex;
+    use std::thread;
+    use std::time::Duration;
+
+    use futures::future;
+    use futures::Future;
+    use tokio_core::reactor::Timeout;
+
+    struct NotifyOnDrop(Mutex<mpsc::Sender<()>>);
+
+    impl Drop for NotifyOnDrop {
+        fn drop(&mut self) {
+            let _ = self.0.lock().unwrap().send(());
+        }
+    }
+
+    init_logger();
+
+    let (dropped_tx, dropped_rx) = mpsc::channel();
+    let dropped_tx = Mutex::new(dropped_tx);
+
+    let never = string_method("/foo/never", GrpcStreaming::Unary);
+
+    let server = TestServer::start(vec![ServerServiceDefinition::new(
+        "/foo",
+        vec![ServerMethod::new(
+            never.clone(),
+            MethodHandlerUnary::new(
+                move |ctx: ServerHandlerContext,
+                      _: ServerRequestSingle<String>,
+                      resp: ServerResponseUnarySink<String>| {
+                    let guard = NotifyOnDrop(Mutex::new(dropped_tx.lock().unwrap().clone()));
+                    let response = future::empty::<String, Error>().map(move |message| {
+                        let _ = &guard;
+                        message
+                    });
+                    ctx.pump_future(response, resp);
+                    Ok(())
+                },
+            ),
+        )],
+    )])
+    .expect("server");
+
+    let mut core = Core::new().expect("core");
+    let timeout = Timeout::new(Duration::from_millis(100), &core.handle()).expect("timeout");
+    let resp = server
+        .client()
+        .call_unary(RequestOptions::new(), "abc".to_owned(), never)
+        .drop_metadata();
+    let resp = match core.run(resp.select2(timeout)) {
+        Ok(future::Either::B((_, resp))) => resp,
+        _ => panic!("expecting response to wait forever"),
+    };
+    // wait for the call to reach the server
+    while server.server().in_flight_requests().get() == 0 {
+        thread::sleep(Duration::from_millis(1));
+    }
+
+    drop(resp);
+    dropped_rx
+        .recv_timeout(Duration::from_secs(5))
+        .expect("handler future is dropped");
+}
+
+#[test]
+fn deadline_cancels_call() {
+    use std::sync::mpsc;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    use futures::future;
+    use futures::Future;
+
+    struct NotifyOnDrop(Mutex<mpsc::Sender<()>>);
+
+    impl Drop for NotifyOnDrop {
+        fn drop(&mut self) {
+            let _ = self.0.lock().unwrap().send(());
+        }
+    }
+
+    init_logger();
+
+    let (dropped_tx, dropped_rx) = mpsc::channel();
+    let dropped_tx = Mutex::new(dropped_tx);
+
+    let never = string_method("/foo/never", GrpcStreaming::Unary);
+
+    let server = TestServer::start(vec![ServerServiceDefinition::new(
+        "/foo",
+        vec![ServerMethod::new(
+            never.clone(),
+            MethodHandlerUnary::new(
+                move |ctx: ServerHandlerContext,
+                      _: ServerRequestSingle<String>,
+                      resp: ServerResponseUnarySink<String>| {
+                    let guard = NotifyOnDrop(Mutex::new(dropped_tx.lock().unwrap().clone()));
+                    let response = future::empty::<String, Error>().map(move |message| {
+                        let _ = &guard;
+                        message
+                    });
+                    ctx.pump_future(response, resp);
+                    Ok(())
+                },
+            ),
+        )],
+    )])
+    .expect("server");
+
+    let mut options = RequestOptions::new();
+    options.timeout = Some(Duration::from_millis(200));
+    let result = server
+        .client()
+        .call_unary(options, "abc".to_owned(), never)
+        .drop_metadata()
+        .wait();
+    match result {
+        Err(Error::Status(ref s)) if s.code == GrpcStatus::DeadlineExceeded => {}
+        r => panic!("expecting DEADLINE_EXCEEDED, got {:?}", r),
+    }
+
+    // deadline is enforced by the client, which resets the stream
+    dropped_rx
+        .recv_timeout(Duration::from_secs(5))
+        .expect("handler future is dropped");
+}
+
+#[test]
+fn user_agent() {
+    fn user_agent_fn(
+        ctx: ServerHandlerContext,
+        _: ServerRequestSingle<String>,
+        resp: ServerResponseUnarySink<String>,
+    ) -> grpc::Result<()> {
+        resp.finish(ctx.user_agent().unwrap_or("").to_owned())
+    }
+
+    init_logger();
+
+    let method = string_method("/foo/user_agent", GrpcStreaming::Unary);
+
+    let server = TestServer::start(vec![ServerServiceDefinition::new(
+        "/foo",
+        vec![ServerMethod::new(
+            method.clone(),
+            MethodHandlerUnary::new(user_agent_fn),
+        )],
+    )])
+    .expect("server");
+
+    let user_agent = server
+        .client()
+        .call_unary(RequestOptions::new(), "".to_owned(), method.clone())
+        .wait_drop_metadata()
+        .unwrap();
+    assert!(user_agent.starts_with("grpc-rust/"), user_agent);
+
+    let mut conf = ClientConf::new();
+    conf.user_agent_prefix = Some("my-app/1.2".to_owned());
+    let client = server.client_builder().conf(conf).build().expect("client");
+    let user_agent = client
+        .call_unary(RequestOptions::new(), "".to_owned(), method)
+        .wait_drop_metadata()
+        .unwrap();
+    assert!(user_agent.starts_with("my-app/1.2 grpc-rust/"), user_agent);
 }
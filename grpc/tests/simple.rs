@@ -9,8 +9,10 @@ extern crate log_ndc_env_logger;
 mod test_misc;
 
 use std::sync::Arc;
+use std::sync::Mutex;
 
 use futures::future::*;
+use futures::sink::Sink;
 use futures::stream::Stream;
 
 use grpc::rt::*;
@@ -84,6 +86,21 @@ where
     new_server(service, method, MethodHandlerClientStreaming::new(handler))
 }
 
+/// Single bidi method server
+fn new_server_bidi<H>(service: &str, method: &str, handler: H) -> Server
+where
+    H: Fn(
+            ServerHandlerContext,
+            ServerRequest<String>,
+            ServerResponseSink<String>,
+        ) -> grpc::Result<()>
+        + Sync
+        + Send
+        + 'static,
+{
+    new_server(service, method, MethodHandlerBidi::new(handler))
+}
+
 /// Tester for unary methods
 struct TesterUnary {
     name: String,
@@ -131,9 +148,7 @@ impl TesterUnary {
 
     fn call_expect_grpc_error<F: FnOnce(&str) -> bool>(&self, param: &str, expect: F) {
         self.call_expect_error(param, |e| match e {
-            &Error::GrpcMessage(GrpcMessageError {
-                ref grpc_message, ..
-            }) if expect(&grpc_message) => true,
+            &Error::Status(Status { ref message, .. }) if expect(&message) => true,
             _ => false,
         });
     }
@@ -209,14 +224,11 @@ impl TesterClientStreaming {
         }
     }
 
-    fn call(&self) -> (ClientRequestSink<String>, SingleResponse<String>) {
-        self.client
-            .call_client_streaming(
-                RequestOptions::new(),
-                string_string_method(&self.name, GrpcStreaming::ClientStreaming),
-            )
-            .wait()
-            .unwrap()
+    fn call(&self) -> (RequestSink<String>, SingleResponse<String>) {
+        self.client.call_client_streaming(
+            RequestOptions::new(),
+            string_string_method(&self.name, GrpcStreaming::ClientStreaming),
+        )
     }
 }
 
@@ -238,6 +250,39 @@ fn error_in_handler() {
     tester.call_expect_grpc_error_contain("aa", "grpc server handler did not close the sender");
 }
 
+#[test]
+fn error_message_non_ascii() {
+    init_logger();
+
+    let message = "ошибка: 100% \"failed\"\n";
+    let tester = TesterUnary::new(move |_m, _req, resp| {
+        resp.send_grpc_error(GrpcStatus::NotFound, message.to_owned())
+    });
+
+    tester.call_expect_grpc_error("aa", |m| m == message);
+}
+
+#[test]
+#[allow(deprecated)]
+fn error_message_grpc_message_error() {
+    init_logger();
+
+    let tester = TesterUnary::new(|_m, _req, resp| {
+        resp.send_grpc_error(GrpcStatus::NotFound, "not found".to_owned())
+    });
+
+    tester.call_expect_error("aa", |e| match e {
+        &Error::Status(ref status) => match GrpcMessageError::from(status.clone()) {
+            GrpcMessageError {
+                grpc_status: 5,
+                ref grpc_message,
+            } => grpc_message == "not found",
+            _ => false,
+        },
+        _ => false,
+    });
+}
+
 // TODO
 //#[test]
 fn _panic_in_handler() {
@@ -280,6 +325,59 @@ fn server_streaming() {
     assert!(rs.next().is_none());
 }
 
+#[test]
+fn server_streaming_buffer_hint() {
+    init_logger();
+
+    let tester = TesterServerStreaming::new(|_m, req, mut resp| {
+        let flags = WriteFlags { buffer_hint: true };
+        for i in 0..100 {
+            resp.send_data_with_flags(format!("{}{}", req.message, i), flags)?;
+        }
+        // buffered messages are sent before trailers
+        resp.send_trailers(Metadata::new())
+    });
+
+    let messages = tester.call("x").collect().wait().unwrap();
+    let expected: Vec<String> = (0..100).map(|i| format!("x{}", i)).collect();
+    assert_eq!(expected, messages);
+}
+
+#[test]
+fn server_streaming_metadata_then() {
+    init_logger();
+
+    let (first_tx, first_rx) = futures::sync::oneshot::channel::<String>();
+    let first_rx = Mutex::new(Some(first_rx));
+
+    let tester = TesterServerStreaming::new(move |ctx, _req, resp| {
+        let mut metadata = Metadata::new();
+        metadata.add(MetadataKey::from("x-early"), "yes".into());
+        let first = first_rx.lock().unwrap().take().expect("single call");
+        // message is not available until client has seen headers
+        let stream = first
+            .map_err(|_| Error::Other("canceled"))
+            .map(|m| -> GrpcStream<String> { Box::new(futures::stream::once(Ok(m))) });
+        ctx.pump_response(StreamingResponse::metadata_then(metadata, stream), resp);
+        Ok(())
+    });
+
+    let (metadata, stream) = tester
+        .client
+        .call_server_streaming(
+            RequestOptions::new(),
+            "x".to_owned(),
+            string_string_method(&tester.name, GrpcStreaming::ServerStreaming),
+        )
+        .into_parts();
+    let metadata = metadata.wait().unwrap();
+    assert_eq!(Some(&b"yes"[..]), metadata.get("x-early"));
+
+    first_tx.send("first".to_owned()).unwrap();
+    let messages = stream.collect().wait().unwrap();
+    assert_eq!(vec!["first".to_owned()], messages);
+}
+
 #[test]
 fn client_streaming() {
     init_logger();
@@ -308,3 +406,72 @@ fn client_streaming() {
 
     assert_eq!("aabbcc", result.wait().unwrap().1);
 }
+
+#[test]
+fn client_streaming_send_all() {
+    init_logger();
+
+    let tester = TesterClientStreaming::new(move |m, req, resp| {
+        let request_stream = req.into_stream();
+        m.ctx.loop_remote().spawn(move |_handle| {
+            request_stream
+                .fold(0, |len, message| {
+                    futures::finished::<_, Error>(len + message.len())
+                })
+                .map(|len| {
+                    resp.finish(format!("{}", len)).unwrap();
+                })
+                .map_err(|_| ())
+        });
+        Ok(())
+    });
+
+    // larger than default flow control window
+    let messages = (0..100).map(|_| "x".repeat(10000));
+
+    let (tx, result) = tester.call();
+    tx.send_all(futures::stream::iter_ok(messages))
+        .wait()
+        .expect("send_all");
+
+    assert_eq!("1000000", result.wait().unwrap().1);
+}
+
+#[test]
+fn bidi_ping_pong() {
+    init_logger();
+
+    // echo each message as soon as it is received
+    let server = new_server_bidi("/test", "/Bidi", |m, req, resp| {
+        m.ctx.loop_remote().spawn(move |_handle| {
+            req.into_stream()
+                .fold(resp, |mut resp, message| {
+                    resp.send_data(message)?;
+                    Ok::<_, Error>(resp)
+                })
+                .and_then(|mut resp| resp.send_trailers(Metadata::new()))
+                .map_err(|_| ())
+        });
+        Ok(())
+    });
+    let port = server.local_addr().port().expect("port");
+    let client = ClientBuilder::new(BIND_HOST, port).build().unwrap();
+
+    let (mut req, resp) = client.call_bidi(
+        RequestOptions::new(),
+        string_string_method("/test/Bidi", GrpcStreaming::Bidi),
+    );
+    let mut resp = resp.drop_metadata();
+
+    // next message is sent only after the previous one is echoed
+    for i in 0..3 {
+        req = req.send(format!("ping {}", i)).wait().expect("send");
+        let (message, rem) = resp.into_future().wait().map_err(|(e, _)| e).expect("next");
+        assert_eq!(Some(format!("ping {}", i)), message);
+        resp = rem;
+    }
+
+    req.finish().expect("finish");
+    let (message, _) = resp.into_future().wait().map_err(|(e, _)| e).expect("next");
+    assert_eq!(None, message);
+}
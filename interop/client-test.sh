@@ -17,6 +17,12 @@ cargo build --bin grpc-rust-interop-client
 ../target/debug/grpc-rust-interop-client --test_case ping_pong
 ../target/debug/grpc-rust-interop-client --test_case empty_stream
 ../target/debug/grpc-rust-interop-client --test_case custom_metadata
+../target/debug/grpc-rust-interop-client --test_case status_code_and_message
+../target/debug/grpc-rust-interop-client --test_case unimplemented_method
+../target/debug/grpc-rust-interop-client --test_case unimplemented_service
+../target/debug/grpc-rust-interop-client --test_case cancel_after_begin
+../target/debug/grpc-rust-interop-client --test_case cancel_after_first_response
+../target/debug/grpc-rust-interop-client --test_case timeout_on_sleeping_server
 
 kill_server
 
@@ -20,6 +20,7 @@ tests=(
     custom_metadata
     status_code_and_message
     unimplemented_method
+    unimplemented_service
     cancel_after_first_response
 )
 
@@ -7,10 +7,11 @@ extern crate grpc;
 extern crate grpc_interop;
 extern crate log;
 extern crate protobuf;
+extern crate tls_api_native_tls;
+
+use std::sync::Arc;
 
-use grpc::prelude::*;
 use grpc_interop::interop_client;
-use grpc_interop::test_grpc::TestServiceClient;
 
 use clap::App;
 use clap::Arg;
@@ -92,19 +93,62 @@ fn main() {
         .value_of("server_port")
         .map(|s| s.parse().unwrap())
         .unwrap_or(DEFAULT_PORT);
-
-    let client =
-        TestServiceClient::new_plain(hostname, serverport, Default::default()).expect("init");
+    let use_tls = options.value_of("use_tls") == Some("true");
+    let use_test_ca = options.value_of("use_test_ca") == Some("true");
 
     let testcase = options.value_of("test_case").unwrap_or("");
-    'b: loop {
-        for &(t, f) in interop_client::TESTS {
-            if t == testcase {
-                f(client);
-                break 'b;
-            }
+    let auth_args = interop_client::AuthArgs {
+        default_service_account: options
+            .value_of("default_service_account")
+            .map(|s| s.to_owned()),
+        oauth_scope: options.value_of("oauth_scope").map(|s| s.to_owned()),
+        service_account_key_file: options
+            .value_of("service_account_key_file")
+            .map(|s| s.to_owned()),
+    };
+    let credentials = interop_client::call_credentials(testcase, &auth_args);
+
+    let client = if use_tls {
+        let mut tls_conf = grpc::ClientTlsConf::new();
+        tls_conf.sni_host = options
+            .value_of("server_host_override")
+            .map(|s| s.to_owned());
+        if use_test_ca {
+            tls_conf.add_root_certificate_der(interop_client::test_ca_der());
+        }
+        let mut builder = grpc::ClientBuilder::new(hostname, serverport)
+            .tls_conf::<tls_api_native_tls::TlsConnector>(tls_conf)
+            .expect("tls");
+        if let Some(credentials) = credentials {
+            builder = builder.call_credentials(credentials);
+        }
+        builder.build().expect("init")
+    } else {
+        let mut builder = grpc::ClientBuilder::new(hostname, serverport);
+        if let Some(credentials) = credentials {
+            builder = builder.call_credentials(credentials);
         }
+        builder.build().expect("init")
+    };
+    let client = Arc::new(client);
 
-        panic!("no test_case specified or unknown test case");
+    if let Some(&(_, f)) = interop_client::TESTS.iter().find(|&&(t, _)| t == testcase) {
+        f(client);
+    } else if let Some(&(_, f)) = interop_client::AUTH_TESTS
+        .iter()
+        .find(|&&(t, _)| t == testcase)
+    {
+        f(client, &auth_args);
+    } else {
+        let names: Vec<&str> = interop_client::TESTS
+            .iter()
+            .map(|&(t, _)| t)
+            .chain(interop_client::AUTH_TESTS.iter().map(|&(t, _)| t))
+            .collect();
+        panic!(
+            "no test_case specified or unknown test case {:?}, known test cases: {}",
+            testcase,
+            names.join(", ")
+        );
     }
 }
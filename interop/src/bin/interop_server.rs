@@ -1,19 +1,28 @@
 extern crate bytes;
+extern crate clap;
 extern crate env_logger;
 extern crate futures;
 extern crate futures_cpupool;
 extern crate grpc;
+extern crate native_tls;
 extern crate protobuf;
+extern crate tls_api;
+extern crate tls_api_native_tls;
 #[macro_use]
 extern crate log;
 
 extern crate grpc_interop;
 use grpc_interop::*;
 
+use std::collections::VecDeque;
+use std::fs;
 use std::thread;
 
 use bytes::Bytes;
 
+use clap::App;
+use clap::Arg;
+
 use futures::stream;
 use futures::stream::Stream;
 
@@ -82,11 +91,13 @@ impl TestService for TestServerImpl {
                 "requested to send grpc error {}",
                 req.message.get_response_status().get_code()
             );
-            return resp.send_grpc_error(
+            resp.send_metadata(echo_custom_metadata(&req.metadata))?;
+            return resp.send_grpc_error_with_trailers(
                 GrpcStatus::from_code_or_unknown(
                     req.message.get_response_status().get_code() as u32
                 ),
                 req.message.get_response_status().message.clone(),
+                echo_custom_trailing(&req.metadata),
             );
         }
 
@@ -173,10 +184,16 @@ impl TestService for TestServerImpl {
         debug!("sending custom metadata");
         resp.send_metadata(echo_custom_metadata(&metadata))?;
         let mut req = req.into_stream();
+        // responses are sent only when flow control window allows
+        let mut pending = VecDeque::new();
         o.spawn_poll_fn(move || loop {
             if let Async::NotReady = resp.poll()? {
                 return Ok(Async::NotReady);
             }
+            if let Some(response) = pending.pop_front() {
+                resp.send_data(response)?;
+                continue;
+            }
             match req.poll()? {
                 Async::Ready(Some(m)) => {
                     if m.get_response_status().get_code() != 0 {
@@ -184,22 +201,21 @@ impl TestService for TestServerImpl {
                             "requested to send grpc error {}",
                             m.get_response_status().get_code()
                         );
-                        resp.send_grpc_error(
+                        resp.send_grpc_error_with_trailers(
                             GrpcStatus::from_code_or_unknown(m.get_response_status().code as u32),
                             m.get_response_status().message.clone(),
+                            echo_custom_trailing(&metadata),
                         )?;
                         return Ok(Async::Ready(()));
                     }
 
                     for p in &m.response_parameters {
                         debug!("requested to send data of size {}", p.size);
-                        resp.send_data({
-                            let mut response = StreamingOutputCallResponse::new();
-                            let mut payload = Payload::new();
-                            payload.set_body(make_string(p.size as usize));
-                            response.set_payload(payload);
-                            response
-                        })?;
+                        let mut response = StreamingOutputCallResponse::new();
+                        let mut payload = Payload::new();
+                        payload.set_body(make_string(p.size as usize));
+                        response.set_payload(payload);
+                        pending.push_back(response);
                     }
                 }
                 Async::Ready(None) => {
@@ -228,13 +244,81 @@ impl TestService for TestServerImpl {
     }
 }
 
+/// Builder of acceptor with gRPC test server certificate,
+/// `testdata/server1.pem` and `testdata/server1.key`.
+///
+/// The files are not shipped with the crate, copy them from
+/// https://github.com/grpc/grpc/tree/master/src/core/tsi/test_creds
+fn test_tls_acceptor_builder() -> tls_api_native_tls::TlsAcceptorBuilder {
+    let read = |name: &str| {
+        let path = format!("{}/testdata/{}", env!("CARGO_MANIFEST_DIR"), name);
+        fs::read(&path).unwrap_or_else(|e| panic!("read {}: {}", path, e))
+    };
+    let identity = native_tls::Identity::from_pkcs8(&read("server1.pem"), &read("server1.key"))
+        .expect("server1 identity");
+    tls_api_native_tls::TlsAcceptorBuilder(native_tls::TlsAcceptor::builder(identity))
+}
+
+fn build_server<A: tls_api::TlsAcceptor>(mut server: ServerBuilder<A>, port: u16) -> Server {
+    server.http.set_port(port);
+    server.add_service(TestServiceServer::new_service_def(TestServerImpl {}));
+    server.build().expect("server")
+}
+
+// The flags we use are defined in the gRPC Interopability doc
+// https://github.com/grpc/grpc/blob/master/doc/interop-test-descriptions.md
 fn main() {
     env_logger::init();
 
-    let mut server = ServerBuilder::new_plain();
-    server.http.set_port(DEFAULT_PORT);
-    server.add_service(TestServiceServer::new_service_def(TestServerImpl {}));
-    let _server = server.build().expect("server");
+    let options = App::new("gRPC interopability server")
+        .version("0.1")
+        .about("Interoperability Test Server for grpc-rust")
+        .arg(
+            Arg::with_name("port")
+                .long("port")
+                .help("The server port to listen on. For example, \"8080\"")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("use_tls") // boolean
+                .long("use_tls")
+                .help("Whether to use a plaintext or encrypted connection")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("use_test_ca") // boolean
+                .long("use_test_ca")
+                .help(
+                    "Accepted for compatibility with interop runners, \
+                     server does not verify client certificates",
+                )
+                .takes_value(true),
+        )
+        .get_matches();
+
+    let port = options
+        .value_of("port")
+        .map(|s| s.parse().expect("port"))
+        .unwrap_or(DEFAULT_PORT);
+    let use_tls = options.value_of("use_tls") == Some("true");
+
+    let _server = if use_tls {
+        let mut server = ServerBuilder::<tls_api_native_tls::TlsAcceptor>::new();
+        let mut tls_conf = ServerTlsConf::new();
+        tls_conf.require_alpn_h2 = true;
+        server
+            .tls_conf(tls_conf, test_tls_acceptor_builder())
+            .expect("tls");
+        build_server(server, port)
+    } else {
+        build_server(ServerBuilder::new_plain(), port)
+    };
+
+    println!(
+        "interop server started on port {} {}",
+        port,
+        if use_tls { "with tls" } else { "without tls" }
+    );
 
     loop {
         thread::park();
@@ -1,3 +1,4 @@
+extern crate base64;
 extern crate bytes;
 extern crate chrono;
 extern crate env_logger;
@@ -6,6 +7,7 @@ extern crate futures_cpupool;
 extern crate grpc;
 extern crate grpc_protobuf;
 extern crate log;
+extern crate native_tls;
 extern crate protobuf;
 extern crate tls_api;
 
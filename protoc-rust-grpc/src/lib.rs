@@ -26,6 +26,8 @@ pub struct Args<'a> {
     pub rust_protobuf: bool,
     /// Customize rust-protobuf codegen
     pub rust_protobuf_customize: protoc_rust::Customize,
+    /// Customize rust-grpc codegen
+    pub customize: grpc_compiler::codegen::Customize,
 }
 
 pub fn run(args: Args) -> Result<()> {
@@ -90,7 +92,11 @@ pub fn run(args: Args) -> Result<()> {
         ));
     }
 
-    let gen_result = grpc_compiler::codegen::gen(fds.get_file(), &files_to_generate);
+    let gen_result = grpc_compiler::codegen::gen_with_customize(
+        fds.get_file(),
+        &files_to_generate,
+        &args.customize,
+    );
 
     for r in gen_result {
         let r: protobuf::compiler_plugin::GenResult = r;